@@ -1,3 +1,22 @@
 fn main() {
-    winres::WindowsResource::new().compile().unwrap();
+    let mut res = winres::WindowsResource::new();
+    // Names for the performance counters in `src/perfcounters.rs`.
+    res.append_rc_content(
+        r#"
+STRINGTABLE
+BEGIN
+    1000 "Shawl Service"
+    1001 "Commands that Shawl runs as Windows services"
+    1002 "Restarts"
+    1003 "Number of times the command has restarted since the service started"
+    1004 "Command Uptime"
+    1005 "Seconds since the command was launched"
+    1006 "Command Working Set"
+    1007 "Bytes of memory that the command is using"
+    1008 "Output Lines/sec"
+    1009 "Rate at which the command writes lines of output"
+END
+"#,
+    );
+    res.compile().unwrap();
 }
@@ -0,0 +1,277 @@
+use windows::{
+    core::{HSTRING, PCWSTR, PWSTR},
+    Win32::{
+        Foundation::{CloseHandle, LocalFree, BOOL, HANDLE, HLOCAL},
+        Security::{
+            Authorization::{
+                ConvertSecurityDescriptorToStringSecurityDescriptorW, ConvertSidToStringSidW,
+                ConvertStringSecurityDescriptorToSecurityDescriptorW, GetNamedSecurityInfoW, SetNamedSecurityInfoW,
+                SDDL_REVISION_1, SE_FILE_OBJECT,
+            },
+            GetSecurityDescriptorDacl, GetTokenInformation, LookupAccountNameW, TokenUser, ACL,
+            DACL_SECURITY_INFORMATION, OWNER_SECURITY_INFORMATION, PROTECTED_DACL_SECURITY_INFORMATION,
+            PSECURITY_DESCRIPTOR, PSID, SECURITY_ATTRIBUTES, SID_NAME_USE, TOKEN_QUERY, TOKEN_USER,
+        },
+        System::Threading::{GetCurrentProcess, OpenProcessToken},
+    },
+};
+
+/// SDDL alias for the Local System account, which services use by default.
+pub const LOCAL_SYSTEM: &str = "SY";
+
+/// Accounts that may own or change files that Shawl runs services from:
+/// Local System, the administrators, and TrustedInstaller, by alias and by SID.
+const ADMIN_SIDS: [&str; 5] = [
+    LOCAL_SYSTEM,
+    "BA",
+    "S-1-5-18",
+    "S-1-5-32-544",
+    "S-1-5-80-956008885-3418522649-1831038044-1853292631-2271478464",
+];
+
+/// Access rights that let an account change a file's content or permissions.
+const WRITE_ACCESS: u32 = 0x2 | 0x4 | 0x40000 | 0x80000 | 0x1000_0000 | 0x4000_0000;
+
+/// A DACL with full control for the administrators and one account, which files
+/// in the directory inherit. Permissions from the parent directory are not inherited.
+fn private_sddl(sid: &str) -> String {
+    format!("D:P(A;OICI;FA;;;BA)(A;OICI;FA;;;{})", sid)
+}
+
+unsafe fn sid_to_string(sid: PSID) -> windows::core::Result<String> {
+    let mut text = PWSTR::null();
+    ConvertSidToStringSidW(sid, &mut text)?;
+    let result = text.to_string();
+    let _ = LocalFree(HLOCAL(text.0 as _));
+    Ok(result.unwrap_or_default())
+}
+
+/// The SID of the account that Shawl is running as.
+pub fn current_account_sid() -> Result<String, String> {
+    unsafe {
+        let mut token = HANDLE::default();
+        OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token)
+            .map_err(|e| format!("Unable to open the process token: {}", e))?;
+        let mut size = 0;
+        let _ = GetTokenInformation(token, TokenUser, None, 0, &mut size);
+        // Use u64 for the alignment of the SID pointer in `TOKEN_USER`.
+        let mut buffer = vec![0u64; (size as usize).div_ceil(8)];
+        let result = GetTokenInformation(token, TokenUser, Some(buffer.as_mut_ptr() as *mut _), size, &mut size);
+        let _ = CloseHandle(token);
+        result.map_err(|e| format!("Unable to determine the current account: {}", e))?;
+        let user = &*(buffer.as_ptr() as *const TOKEN_USER);
+        sid_to_string(user.User.Sid).map_err(|e| format!("Unable to determine the current account: {}", e))
+    }
+}
+
+/// The SID of an account like `NT AUTHORITY\NetworkService` or `.\someone`.
+pub fn account_sid(account: &str) -> Result<String, String> {
+    let error = |e: windows::core::Error| format!("Unable to find the account {}: {}", account, e);
+    let name = HSTRING::from(account.strip_prefix(".\\").unwrap_or(account));
+    unsafe {
+        let mut sid_size = 0;
+        let mut domain_size = 0;
+        let mut kind = SID_NAME_USE::default();
+        let _ = LookupAccountNameW(
+            PCWSTR::null(),
+            &name,
+            PSID::default(),
+            &mut sid_size,
+            PWSTR::null(),
+            &mut domain_size,
+            &mut kind,
+        );
+        let mut sid = vec![0u64; (sid_size as usize).div_ceil(8)];
+        let mut domain = vec![0u16; domain_size as usize];
+        LookupAccountNameW(
+            PCWSTR::null(),
+            &name,
+            PSID(sid.as_mut_ptr() as _),
+            &mut sid_size,
+            PWSTR(domain.as_mut_ptr()),
+            &mut domain_size,
+            &mut kind,
+        )
+        .map_err(error)?;
+        sid_to_string(PSID(sid.as_mut_ptr() as _)).map_err(error)
+    }
+}
+
+/// Allow only the administrators and the account to access the directory and its files,
+/// including the ones that already exist.
+pub fn restrict(dir: &std::path::Path, sid: &str) -> Result<(), String> {
+    let error = |e: windows::core::Error| format!("Unable to restrict access to {}: {}", dir.display(), e);
+    unsafe {
+        let mut descriptor = PSECURITY_DESCRIPTOR::default();
+        ConvertStringSecurityDescriptorToSecurityDescriptorW(
+            &HSTRING::from(private_sddl(sid)),
+            SDDL_REVISION_1,
+            &mut descriptor,
+            None,
+        )
+        .map_err(error)?;
+
+        let mut present = BOOL::default();
+        let mut dacl: *mut ACL = std::ptr::null_mut();
+        let mut defaulted = BOOL::default();
+        let result = GetSecurityDescriptorDacl(descriptor, &mut present, &mut dacl, &mut defaulted).and_then(|_| {
+            SetNamedSecurityInfoW(
+                &HSTRING::from(dir.as_os_str()),
+                SE_FILE_OBJECT,
+                DACL_SECURITY_INFORMATION | PROTECTED_DACL_SECURITY_INFORMATION,
+                PSID::default(),
+                PSID::default(),
+                Some(dacl),
+                None,
+            )
+            .ok()
+        });
+        let _ = LocalFree(HLOCAL(descriptor.0));
+        result.map_err(error)
+    }
+}
+
+/// Call `f` with security attributes for a new object that has an SDDL security descriptor.
+pub fn with_security_attributes<T>(sddl: &str, f: impl FnOnce(&SECURITY_ATTRIBUTES) -> T) -> Result<T, String> {
+    unsafe {
+        let mut descriptor = PSECURITY_DESCRIPTOR::default();
+        ConvertStringSecurityDescriptorToSecurityDescriptorW(
+            &HSTRING::from(sddl),
+            SDDL_REVISION_1,
+            &mut descriptor,
+            None,
+        )
+        .map_err(|e| format!("Invalid security descriptor {}: {}", sddl, e))?;
+        let attributes = SECURITY_ATTRIBUTES {
+            nLength: std::mem::size_of::<SECURITY_ATTRIBUTES>() as u32,
+            lpSecurityDescriptor: descriptor.0,
+            bInheritHandle: BOOL(0),
+        };
+        let result = f(&attributes);
+        let _ = LocalFree(HLOCAL(descriptor.0));
+        Ok(result)
+    }
+}
+
+/// Convert the rights of an SDDL access entry, like `FA` or `0x1200a9`, to an access mask.
+/// Rights that can't grant write access are left out.
+fn sddl_rights(rights: &str) -> u32 {
+    if let Some(hex) = rights.strip_prefix("0x").or_else(|| rights.strip_prefix("0X")) {
+        // An unreadable mask might allow anything.
+        return u32::from_str_radix(hex, 16).unwrap_or(u32::MAX);
+    }
+    let mut mask = 0;
+    let mut chars = rights.chars();
+    while let (Some(a), Some(b)) = (chars.next(), chars.next()) {
+        mask |= match (a, b) {
+            ('G', 'A') => 0x1000_0000,
+            ('G', 'W') => 0x4000_0000,
+            ('F', 'A') => 0x1F01FF,
+            ('F', 'W') => 0x120116,
+            ('K', 'A') => 0xF003F,
+            ('K', 'W') => 0x20006,
+            ('W', 'D') => 0x40000,
+            ('W', 'O') => 0x80000,
+            ('D', 'C') => 0x2,
+            ('L', 'C') => 0x4,
+            _ => 0,
+        };
+    }
+    mask
+}
+
+/// Whether a security descriptor in SDDL form is owned by the administrators or Local System,
+/// and only lets them (or TrustedInstaller) change the object.
+fn is_admin_only_sddl(sddl: &str) -> bool {
+    let Some(rest) = sddl.strip_prefix("O:") else {
+        return false;
+    };
+    let owner_end = ["G:", "D:", "S:"]
+        .iter()
+        .filter_map(|x| rest.find(x))
+        .min()
+        .unwrap_or(rest.len());
+    if !ADMIN_SIDS.contains(&&rest[..owner_end]) {
+        return false;
+    }
+    let Some(dacl) = rest.find("D:").map(|i| &rest[i + 2..]) else {
+        return false;
+    };
+    if dacl.starts_with("NO_ACCESS_CONTROL") {
+        return false;
+    }
+    dacl.split('(').skip(1).all(|ace| {
+        let fields: Vec<_> = ace.trim_end_matches(')').split(';').collect();
+        let [kind, flags, rights, _, _, sid, ..] = fields[..] else {
+            return false;
+        };
+        let allows = kind == "A" || kind == "OA";
+        let applies = !flags.as_bytes().chunks(2).any(|x| x == b"IO");
+        !allows || !applies || sddl_rights(rights) & WRITE_ACCESS == 0 || ADMIN_SIDS.contains(&sid)
+    })
+}
+
+/// Whether a file is owned by the administrators or Local System and nobody else can change it.
+pub fn is_admin_only(path: &std::path::Path) -> Result<bool, String> {
+    let error = |e: windows::core::Error| format!("Unable to check the permissions of {}: {}", path.display(), e);
+    unsafe {
+        let info = OWNER_SECURITY_INFORMATION | DACL_SECURITY_INFORMATION;
+        let mut descriptor = PSECURITY_DESCRIPTOR::default();
+        GetNamedSecurityInfoW(
+            &HSTRING::from(path.as_os_str()),
+            SE_FILE_OBJECT,
+            info,
+            None,
+            None,
+            None,
+            None,
+            &mut descriptor,
+        )
+        .ok()
+        .map_err(error)?;
+        let mut text = PWSTR::null();
+        let result =
+            ConvertSecurityDescriptorToStringSecurityDescriptorW(descriptor, SDDL_REVISION_1, info, &mut text, None);
+        let _ = LocalFree(HLOCAL(descriptor.0));
+        result.map_err(error)?;
+        let sddl = text.to_string().unwrap_or_default();
+        let _ = LocalFree(HLOCAL(text.0 as _));
+        Ok(is_admin_only_sddl(&sddl))
+    }
+}
+
+#[cfg(test)]
+speculate::speculate! {
+    describe "is_admin_only_sddl" {
+        it "accepts files that only the administrators can change" {
+            assert!(is_admin_only_sddl("O:BAG:SYD:PAI(A;;FA;;;SY)(A;;FA;;;BA)(A;;0x1200a9;;;BU)"));
+            assert!(is_admin_only_sddl("O:SYD:AI(A;ID;FA;;;S-1-5-18)(A;ID;FR;;;WD)(A;OICIIOID;GA;;;CO)"));
+        }
+
+        it "rejects files owned by someone else" {
+            assert!(!is_admin_only_sddl("O:S-1-5-21-1-2-3-1001D:(A;;FA;;;SY)(A;;FA;;;BA)"));
+            assert!(!is_admin_only_sddl("D:(A;;FA;;;BA)"));
+        }
+
+        it "rejects files that someone else can change" {
+            assert!(!is_admin_only_sddl("O:BAD:(A;;FA;;;BA)(A;ID;0x1301bf;;;AU)"));
+            assert!(!is_admin_only_sddl("O:BAD:(A;;FA;;;BA)(A;;FW;;;BU)"));
+            assert!(!is_admin_only_sddl("O:BAD:(A;;FA;;;BA)(A;;CCDCLCSWRPWPDTLOCRSDRCWDWO;;;BU)"));
+            assert!(!is_admin_only_sddl("O:BAD:NO_ACCESS_CONTROL"));
+            assert!(!is_admin_only_sddl("O:BA"));
+        }
+    }
+
+    describe "private_sddl" {
+        it "allows only the administrators and the account" {
+            assert_eq!(
+                private_sddl("S-1-5-20"),
+                "D:P(A;OICI;FA;;;BA)(A;OICI;FA;;;S-1-5-20)",
+            );
+            assert_eq!(
+                private_sddl(LOCAL_SYSTEM),
+                "D:P(A;OICI;FA;;;BA)(A;OICI;FA;;;SY)",
+            );
+        }
+    }
+}
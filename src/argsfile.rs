@@ -0,0 +1,183 @@
+/// Split an arguments file into its options and, after a `--` line, the command.
+/// Each line is one argument, so values can contain spaces and quotes without escaping.
+/// Blank lines and lines starting with `#` are skipped.
+fn parse(content: &str) -> (Vec<String>, Option<Vec<String>>) {
+    let mut lines = content
+        .lines()
+        .map(str::trim)
+        .filter(|x| !x.is_empty() && !x.starts_with('#'));
+    let options: Vec<_> = lines.by_ref().take_while(|x| *x != "--").map(String::from).collect();
+    let command: Vec<_> = lines.map(String::from).collect();
+    let has_command = content.lines().any(|x| x.trim() == "--");
+    (options, has_command.then_some(command))
+}
+
+/// Get the path from `--args-file path` or `--args-file=path`.
+fn file_option(
+    arg: &std::ffi::OsStr,
+    args: &mut impl Iterator<Item = std::ffi::OsString>,
+) -> Result<Option<std::ffi::OsString>, String> {
+    if arg == "--args-file" {
+        return args
+            .next()
+            .map(Some)
+            .ok_or_else(|| "--args-file requires a path".to_string());
+    }
+    let path = arg.to_str().and_then(|x| x.strip_prefix("--args-file="));
+    Ok(path.map(Into::into))
+}
+
+/// Insert the arguments from each `--args-file` before the `--` after that option,
+/// which is kept so that the options show where they came from.
+/// A file's command goes at the end, so there can only be one command in total.
+/// Only `shawl run` reads the files, so that `shawl add` keeps referring to them
+/// instead of copying their arguments into the service's command line.
+fn expand_with(
+    args: Vec<std::ffi::OsString>,
+    read: impl Fn(&std::path::Path) -> std::io::Result<String>,
+) -> Result<Vec<std::ffi::OsString>, String> {
+    if args.get(1).is_none_or(|x| x != "run") {
+        return Ok(args);
+    }
+    let mut args = args.into_iter();
+    let mut expanded: Vec<_> = args.by_ref().take(2).collect();
+    let mut command: Option<Vec<std::ffi::OsString>> = None;
+    let mut set_command = |new: Vec<std::ffi::OsString>| {
+        if command.replace(new).is_some() {
+            return Err("The command can only be given once, in an arguments file or after `--`".to_string());
+        }
+        Ok(())
+    };
+
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            set_command(args.by_ref().collect())?;
+            break;
+        }
+        let Some(path) = file_option(&arg, &mut args)? else {
+            expanded.push(arg);
+            continue;
+        };
+        let mut option = std::ffi::OsString::from("--args-file=");
+        option.push(&path);
+        expanded.push(option);
+        let path = std::path::PathBuf::from(path);
+        let content = read(&path).map_err(|e| format!("Unable to read arguments file {}: {}", path.display(), e))?;
+        let (options, file_command) = parse(&content);
+        expanded.extend(options.into_iter().map(Into::into));
+        if let Some(file_command) = file_command {
+            set_command(file_command.into_iter().map(Into::into).collect())?;
+        }
+    }
+
+    if let Some(command) = command {
+        expanded.push("--".into());
+        expanded.extend(command);
+    }
+    Ok(expanded)
+}
+
+/// Read the arguments files for `--args-file`, which is useful for
+/// long lists of options that would exceed the command line limit.
+pub fn expand(args: Vec<std::ffi::OsString>) -> Result<Vec<std::ffi::OsString>, String> {
+    expand_with(args, |path| std::fs::read_to_string(path))
+}
+
+#[cfg(test)]
+speculate::speculate! {
+    fn s(text: &str) -> String {
+        text.to_string()
+    }
+
+    fn os(args: &[&str]) -> Vec<std::ffi::OsString> {
+        args.iter().map(std::ffi::OsString::from).collect()
+    }
+
+    fn read(path: &std::path::Path) -> std::io::Result<String> {
+        match path.to_string_lossy().as_ref() {
+            "opts.txt" => Ok(s("# Shared options\n--env\nGREETING=hello world\n\n--stop-timeout=5000\n")),
+            "cmd.txt" => Ok(s("--restart\r\n--\r\nnode\r\nserver.js\r\n")),
+            _ => Err(std::io::Error::new(std::io::ErrorKind::NotFound, "not found")),
+        }
+    }
+
+    describe "parse" {
+        it "reads one argument per line" {
+            assert_eq!(
+                parse("# comment\n--env\nA=1 2\n\n  --no-restart  \n"),
+                (vec![s("--env"), s("A=1 2"), s("--no-restart")], None),
+            );
+        }
+
+        it "reads the command after --" {
+            assert_eq!(
+                parse("--restart\n--\nnode\nserver.js\n"),
+                (vec![s("--restart")], Some(vec![s("node"), s("server.js")])),
+            );
+        }
+    }
+
+    describe "expand_with" {
+        it "inserts the options in place" {
+            assert_eq!(
+                expand_with(os(&["shawl", "run", "--name", "app", "--args-file", "opts.txt", "--", "foo"]), read),
+                Ok(os(&[
+                    "shawl",
+                    "run",
+                    "--name",
+                    "app",
+                    "--args-file=opts.txt",
+                    "--env",
+                    "GREETING=hello world",
+                    "--stop-timeout=5000",
+                    "--",
+                    "foo",
+                ])),
+            );
+            assert_eq!(
+                expand_with(os(&["shawl", "run", "--args-file", "opts.txt", "--", "foo"]), read),
+                expand_with(os(&["shawl", "run", "--args-file=opts.txt", "--", "foo"]), read),
+            );
+        }
+
+        it "moves the file's command to the end" {
+            assert_eq!(
+                expand_with(os(&["shawl", "run", "--args-file", "cmd.txt", "--name", "app"]), read),
+                Ok(os(&["shawl", "run", "--args-file=cmd.txt", "--restart", "--name", "app", "--", "node", "server.js"])),
+            );
+        }
+
+        it "leaves the command alone" {
+            let args = os(&["shawl", "run", "--", "foo", "--args-file", "opts.txt"]);
+            assert_eq!(expand_with(args.clone(), read), Ok(args));
+        }
+
+        it "leaves other subcommands alone" {
+            let args = os(&["shawl", "add", "--args-file", "cmd.txt", "--name", "app"]);
+            assert_eq!(expand_with(args.clone(), read), Ok(args));
+        }
+
+        it "does not read arguments that start with @" {
+            let args = os(&["shawl", "run", "--env", "@opts.txt", "--", "foo"]);
+            assert_eq!(expand_with(args.clone(), read), Ok(args));
+        }
+
+        it "rejects a second command" {
+            assert_eq!(
+                expand_with(os(&["shawl", "run", "--args-file", "cmd.txt", "--", "foo"]), read),
+                Err(s("The command can only be given once, in an arguments file or after `--`")),
+            );
+        }
+
+        it "reports a missing file" {
+            assert_eq!(
+                expand_with(os(&["shawl", "run", "--args-file=missing.txt"]), read),
+                Err(s("Unable to read arguments file missing.txt: not found")),
+            );
+            assert_eq!(
+                expand_with(os(&["shawl", "run", "--args-file"]), read),
+                Err(s("--args-file requires a path")),
+            );
+        }
+    }
+}
@@ -1,18 +1,282 @@
 use clap::Parser;
 
 pub fn evaluate_cli() -> Cli {
-    Cli::parse()
+    let mut args: Vec<std::ffi::OsString> = std::env::args_os().collect();
+    if let Some(elevate) = wizard_requested(&args) {
+        // Elevate before prompting, so that the answers aren't lost.
+        elevate_if_requested(&args, elevate);
+        match crate::wizard::run() {
+            Ok(Some(wizard_args)) => {
+                args.splice(2.., wizard_args.into_iter().map(Into::into));
+            }
+            Ok(None) => {
+                println!("The service was not added");
+                std::process::exit(0);
+            }
+            Err(e) => clap::Error::raw(clap::error::ErrorKind::Io, e).exit(),
+        }
+    }
+    let args = crate::argsfile::expand(args)
+        .and_then(|args| crate::defaults::load().and_then(|defaults| crate::defaults::apply(args, &defaults)))
+        .unwrap_or_else(|e| clap::Error::raw(clap::error::ErrorKind::Io, e).exit());
+    let mut cli = Cli::parse_from(&args);
+    if let Subcommand::Add {
+        name,
+        cwd,
+        common,
+        elevate,
+        ..
+    } = &mut cli.sub
+    {
+        elevate_if_requested(&args, *elevate);
+        if let Some(path) = cwd {
+            *path = prepare_add_cwd(path, common.cwd_create)
+                .unwrap_or_else(|e| clap::Error::raw(clap::error::ErrorKind::Io, e).exit());
+        }
+        common
+            .expand_templates(&template_variables(name, cwd.as_deref()))
+            .unwrap_or_else(|e| clap::Error::raw(clap::error::ErrorKind::Io, e).exit());
+    }
+    cli
+}
+
+/// Check for `shawl add --wizard`, which may also have `--elevate`,
+/// returning whether to elevate.
+fn wizard_requested(args: &[std::ffi::OsString]) -> Option<bool> {
+    let options = args.get(2..)?;
+    let wizard = args[1] == "add"
+        && options.iter().any(|x| x == "--wizard")
+        && options.iter().all(|x| x == "--wizard" || x == "--elevate");
+    wizard.then(|| options.iter().any(|x| x == "--elevate"))
+}
+
+/// `shawl add` usually needs to run as an administrator. With `--elevate`, relaunch Shawl
+/// through a UAC prompt and exit with its result. Otherwise, carry on and let the service manager decide.
+fn elevate_if_requested(args: &[std::ffi::OsString], elevate: bool) {
+    if !elevate || crate::elevation::is_elevated() {
+        return;
+    }
+    let code = crate::control::AddError::AccessDenied.exit_code();
+    let args: Vec<_> = args
+        .iter()
+        .skip(1)
+        .map(|x| crate::control::quote_exactly(&x.to_string_lossy()))
+        .collect();
+    match crate::elevation::relaunch(&args) {
+        Ok(code) => std::process::exit(code),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(code);
+        }
+    }
+}
+
+/// The long options for `shawl run`, along with whether each one takes a value.
+pub fn run_options() -> Vec<(String, bool)> {
+    use clap::CommandFactory;
+    Cli::command()
+        .find_subcommand("run")
+        .map(|run| {
+            run.get_arguments()
+                .filter_map(|arg| Some((arg.get_long()?.to_string(), arg.get_action().takes_values())))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Pairs of long options for `shawl run` that can't be used together.
+pub fn run_conflicts() -> Vec<(String, String)> {
+    use clap::CommandFactory;
+    let mut cli = Cli::command();
+    cli.build();
+    let Some(run) = cli.find_subcommand("run") else {
+        return vec![];
+    };
+    run.get_arguments()
+        .flat_map(|arg| {
+            run.get_arg_conflicts_with(arg)
+                .into_iter()
+                .filter_map(move |other| Some((arg.get_long()?.to_string(), other.get_long()?.to_string())))
+        })
+        .collect()
 }
 
 fn parse_canonical_path(path: &str) -> Result<String, std::io::Error> {
+    if path.contains('%') {
+        // Leave environment variable references for `--expand-env` to resolve at runtime.
+        return Ok(path.to_string());
+    }
     Ok(std::fs::canonicalize(path)?.to_string_lossy().to_string())
 }
 
+/// Placeholders that `shawl add` fills in for `--log-dir`, `--env` values, and the command.
+const TEMPLATE_VARIABLES: [&str; 3] = ["{name}", "{cwd}", "{shawl_dir}"];
+
+/// `{{` and `}}` stand for literal braces, so that text like `{name}` can be passed through.
+const TEMPLATE_ESCAPES: [&str; 2] = ["{{", "}}"];
+
+fn has_template(text: &str) -> bool {
+    TEMPLATE_VARIABLES
+        .iter()
+        .chain(TEMPLATE_ESCAPES.iter())
+        .any(|x| text.contains(x))
+}
+
+/// Fill in the placeholders in one pass, so that escaped braces are never mistaken for one.
+/// Escapes are kept as-is unless `unescape` is set,
+/// since `shawl add` leaves the launch placeholders and escapes for `shawl run`.
+fn expand_template(text: &str, variables: &[(&str, String)], unescape: bool) -> String {
+    let mut expanded = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if let Some(escape) = TEMPLATE_ESCAPES.iter().find(|x| rest.starts_with(*x)) {
+            expanded.push_str(if unescape { &escape[..1] } else { escape });
+            rest = &rest[escape.len()..];
+        } else if let Some((placeholder, value)) = variables.iter().find(|(x, _)| rest.starts_with(x)) {
+            expanded.push_str(value);
+            rest = &rest[placeholder.len()..];
+        } else {
+            expanded.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    expanded
+}
+
+/// Values for the placeholders in `shawl add`.
+/// Without `--cwd`, `{cwd}` is the directory where `shawl add` was run.
+fn template_variables(name: &str, cwd: Option<&str>) -> Vec<(&'static str, String)> {
+    let cwd = match cwd {
+        Some(cwd) => cwd.to_string(),
+        None => std::env::current_dir()
+            .map(|x| x.to_string_lossy().to_string())
+            .unwrap_or_default(),
+    };
+    let shawl_dir = std::env::current_exe()
+        .ok()
+        .and_then(|x| x.parent().map(|x| x.to_string_lossy().to_string()))
+        .unwrap_or_default();
+    vec![("{name}", name.to_string()), ("{cwd}", cwd), ("{shawl_dir}", shawl_dir)]
+}
+
+/// Like `parse_canonical_path`, but a missing directory is made absolute
+/// so that `prepare_add_cwd` can decide what to do once `--cwd-create` is known.
+fn parse_cwd(path: &str) -> Result<String, std::io::Error> {
+    match parse_canonical_path(path) {
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            Ok(std::env::current_dir()?.join(path).to_string_lossy().to_string())
+        }
+        result => result,
+    }
+}
+
+/// Make sure that the `--cwd` for `shawl add` exists, creating it for `--cwd-create`.
+/// If it can't be created yet, such as on a drive that isn't mounted,
+/// the service creates it when the command launches instead.
+fn prepare_add_cwd(cwd: &str, create: bool) -> Result<String, String> {
+    let path = std::path::Path::new(cwd);
+    if cwd.contains('%') || path.exists() {
+        return Ok(cwd.to_string());
+    }
+    if !create {
+        return Err(format!(
+            "The --cwd directory does not exist: {} (use --cwd-create to create it)",
+            cwd
+        ));
+    }
+    Ok(std::fs::create_dir_all(path)
+        .and_then(|_| std::fs::canonicalize(path))
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_else(|_| cwd.to_string()))
+}
+
 fn parse_ensured_directory(path: &str) -> Result<String, std::io::Error> {
+    if has_template(path) {
+        // Leave placeholders for `shawl add` to fill in before creating the directory.
+        return Ok(path.to_string());
+    }
     std::fs::create_dir_all(path)?;
     Ok(std::fs::canonicalize(path)?.to_string_lossy().to_string())
 }
 
+/// A subdirectory name must stay inside the log directory, since `--log-private` restricts it.
+fn parse_log_subdir(name: &str) -> Result<String, String> {
+    let mut components = std::path::Path::new(name).components();
+    match (components.next(), components.next()) {
+        (Some(std::path::Component::Normal(_)), None) => Ok(name.to_string()),
+        _ => Err("must be a single directory name".to_string()),
+    }
+}
+
+fn parse_output_file(path: &str) -> Result<String, std::io::Error> {
+    let path = std::path::Path::new(path);
+    let Some(name) = path.file_name() else {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "path must include a file name",
+        ));
+    };
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => std::path::Path::new("."),
+    };
+    std::fs::create_dir_all(parent)?;
+    Ok(std::fs::canonicalize(parent)?.join(name).to_string_lossy().to_string())
+}
+
+fn parse_log_name_pattern(pattern: &str) -> Result<String, String> {
+    if pattern.is_empty() || pattern.contains(['/', '\\']) {
+        return Err(format!("invalid log name pattern: {}", pattern));
+    }
+    Ok(pattern.to_string())
+}
+
+fn parse_regex(value: &str) -> Result<String, regex::Error> {
+    regex::Regex::new(value)?;
+    Ok(value.to_string())
+}
+
+const DURATION_UNITS: &[(&str, u64)] = &[("h", 60 * 60 * 1000), ("m", 60 * 1000), ("s", 1000), ("ms", 1)];
+
+pub fn parse_duration(value: &str) -> Result<std::time::Duration, String> {
+    let value = value.trim();
+    let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+
+    let number = number
+        .parse::<u64>()
+        .map_err(|e| format!("Unable to parse duration '{}': {:?}", value, e))?;
+    let factor = match unit {
+        "" => 1,
+        _ => match DURATION_UNITS.iter().find(|(suffix, _)| *suffix == unit) {
+            Some((_, factor)) => *factor,
+            None => return Err(format!("Unknown duration unit '{}' in '{}'", unit, value)),
+        },
+    };
+
+    number
+        .checked_mul(factor)
+        .map(std::time::Duration::from_millis)
+        .ok_or_else(|| format!("Duration is too long: '{}'", value))
+}
+
+fn parse_nonzero_duration(value: &str) -> Result<std::time::Duration, String> {
+    match parse_duration(value)? {
+        duration if duration.is_zero() => Err(format!("Duration must be greater than zero: '{}'", value)),
+        duration => Ok(duration),
+    }
+}
+
+pub fn duration_to_cli(duration: std::time::Duration) -> String {
+    let ms = duration.as_millis() as u64;
+    for (suffix, factor) in DURATION_UNITS {
+        if ms > 0 && ms.is_multiple_of(*factor) {
+            return format!("{}{}", ms / factor, suffix);
+        }
+    }
+    format!("{}ms", ms)
+}
+
 macro_rules! possible_values {
     ($t: ty, $options: ident) => {{
         use clap::builder::{PossibleValuesParser, TypedValueParser};
@@ -23,6 +287,8 @@ macro_rules! possible_values {
 #[derive(Debug)]
 pub enum CliError {
     InvalidEnvVar { specification: String },
+    InvalidControlMapping { specification: String },
+    InvalidControlLogLevel { specification: String, reason: String },
 }
 
 impl std::error::Error for CliError {}
@@ -33,6 +299,17 @@ impl std::fmt::Display for CliError {
             Self::InvalidEnvVar { specification } => {
                 write!(f, "Invalid KEY=value formatting in '{}'", specification)
             }
+            Self::InvalidControlMapping { specification } => {
+                write!(
+                    f,
+                    "Invalid CODE=action formatting in '{}' (code must be 128-255, action must be `restart`, `run:<command>`, `stdin:<text>`, or `log-level:<level>` with a level of {})",
+                    specification,
+                    LogLevel::ALL.join(", ")
+                )
+            }
+            Self::InvalidControlLogLevel { specification, reason } => {
+                write!(f, "Invalid CODE=action formatting in '{}': {}", specification, reason)
+            }
         }
     }
 }
@@ -94,278 +371,3524 @@ impl std::str::FromStr for Priority {
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
-pub enum LogRotation {
-    Bytes(u64),
-    Daily,
-    Hourly,
+pub enum ResumeAction {
+    Restart,
+    Signal,
 }
 
-impl LogRotation {
+impl ResumeAction {
+    pub const ALL: &'static [&'static str] = &["restart", "signal"];
+}
+
+impl ResumeAction {
     pub fn to_cli(self) -> String {
         match self {
-            LogRotation::Bytes(bytes) => format!("bytes={}", bytes),
-            LogRotation::Daily => "daily".to_string(),
-            LogRotation::Hourly => "hourly".to_string(),
+            Self::Restart => "restart",
+            Self::Signal => "signal",
         }
+        .to_string()
     }
 }
 
-impl std::str::FromStr for LogRotation {
+impl std::str::FromStr for ResumeAction {
     type Err = String;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
-            "daily" => return Ok(Self::Daily),
-            "hourly" => return Ok(Self::Hourly),
-            _ => {}
+            "restart" => Ok(Self::Restart),
+            "signal" => Ok(Self::Signal),
+            _ => Err(format!("invalid resume action: {}", s)),
         }
+    }
+}
 
-        if s.starts_with("bytes=") {
-            let parts: Vec<_> = s.splitn(2, '=').collect();
-            match parts[1].parse::<u64>() {
-                Ok(bytes) => return Ok(Self::Bytes(bytes)),
-                Err(e) => return Err(format!("Unable to parse log rotation as bytes: {:?}", e)),
-            }
-        }
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum KillMethod {
+    #[default]
+    Process,
+    Tree,
+    Job,
+}
 
-        Err(format!("Unable to parse log rotation: {}", s))
-    }
+impl KillMethod {
+    pub const ALL: &'static [&'static str] = &["process", "tree", "job"];
 }
 
-impl Default for LogRotation {
-    fn default() -> Self {
-        Self::Bytes(1024 * 1024 * 2)
+impl KillMethod {
+    pub fn to_cli(self) -> String {
+        match self {
+            Self::Process => "process",
+            Self::Tree => "tree",
+            Self::Job => "job",
+        }
+        .to_string()
     }
 }
 
-fn parse_env_var(value: &str) -> Result<(String, String), CliError> {
-    let parts: Vec<&str> = value.splitn(2, '=').collect();
-    if parts.len() != 2 {
-        return Err(CliError::InvalidEnvVar {
-            specification: value.to_string(),
-        });
+impl std::str::FromStr for KillMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "process" => Ok(Self::Process),
+            "tree" => Ok(Self::Tree),
+            "job" => Ok(Self::Job),
+            _ => Err(format!("invalid kill method: {}", s)),
+        }
     }
-    Ok((parts[0].to_string(), parts[1].to_string()))
 }
 
-fn styles() -> clap::builder::styling::Styles {
-    use clap::builder::styling::{AnsiColor, Effects, Styles};
-
-    Styles::styled()
-        .header(AnsiColor::Yellow.on_default() | Effects::BOLD)
-        .usage(AnsiColor::Yellow.on_default() | Effects::BOLD)
-        .literal(AnsiColor::Green.on_default() | Effects::BOLD)
-        .placeholder(AnsiColor::Green.on_default())
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum StopMethod {
+    #[default]
+    CtrlC,
+    Kill,
 }
 
-#[derive(clap::Parser, Clone, Debug, Default, PartialEq, Eq)]
-pub struct CommonOpts {
-    /// Exit codes that should be considered successful (comma-separated) [default: 0]
-    #[clap(
-        long,
-        value_name = "codes",
-        value_delimiter = ',',
-        number_of_values = 1,
-        allow_hyphen_values(true)
-    )]
-    pub pass: Option<Vec<i32>>,
+impl StopMethod {
+    pub const ALL: &'static [&'static str] = &["ctrl-c", "kill"];
+}
 
-    /// Always restart the command regardless of the exit code
-    #[clap(
-        long,
-        conflicts_with("no_restart"),
-        conflicts_with("restart_if"),
-        conflicts_with("restart_if_not")
-    )]
-    pub restart: bool,
+impl StopMethod {
+    pub fn to_cli(self) -> String {
+        match self {
+            Self::CtrlC => "ctrl-c",
+            Self::Kill => "kill",
+        }
+        .to_string()
+    }
+}
 
-    /// Never restart the command regardless of the exit code
-    #[clap(
-        long,
-        conflicts_with("restart"),
-        conflicts_with("restart_if"),
-        conflicts_with("restart_if_not")
-    )]
-    pub no_restart: bool,
+impl std::str::FromStr for StopMethod {
+    type Err = String;
 
-    /// Restart the command if the exit code is one of these (comma-separated)
-    #[clap(
-        long,
-        conflicts_with("restart"),
-        conflicts_with("no_restart"),
-        conflicts_with("restart_if_not"),
-        value_name = "codes",
-        value_delimiter = ',',
-        number_of_values = 1,
-        allow_hyphen_values(true)
-    )]
-    pub restart_if: Vec<i32>,
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ctrl-c" => Ok(Self::CtrlC),
+            "kill" => Ok(Self::Kill),
+            _ => Err(format!("invalid stop method: {}", s)),
+        }
+    }
+}
 
-    /// Restart the command if the exit code is not one of these (comma-separated)
-    #[clap(
-        long,
-        conflicts_with("restart"),
-        conflicts_with("no_restart"),
-        conflicts_with("restart_if"),
-        value_name = "codes",
-        value_delimiter = ',',
-        number_of_values = 1,
-        allow_hyphen_values(true)
-    )]
-    pub restart_if_not: Vec<i32>,
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LogCmdTarget {
+    EventLog,
+    EventLogStderr,
+}
 
-    /// How long to wait before restarting the wrapped process
-    #[clap(long, value_name = "ms")]
-    pub restart_delay: Option<u64>,
+impl LogCmdTarget {
+    pub const ALL: &'static [&'static str] = &["eventlog", "eventlog-stderr"];
+}
 
-    /// How long to wait in milliseconds between sending the wrapped process
-    /// a ctrl-C event and forcibly killing it [default: 3000]
-    #[clap(long, value_name = "ms")]
-    pub stop_timeout: Option<u64>,
+impl LogCmdTarget {
+    pub fn to_cli(self) -> String {
+        match self {
+            Self::EventLog => "eventlog",
+            Self::EventLogStderr => "eventlog-stderr",
+        }
+        .to_string()
+    }
+}
 
-    /// Disable all of Shawl's logging
-    #[clap(long)]
-    pub no_log: bool,
+impl std::str::FromStr for LogCmdTarget {
+    type Err = String;
 
-    /// Disable logging of output from the command running as a service
-    #[clap(long)]
-    pub no_log_cmd: bool,
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "eventlog" => Ok(Self::EventLog),
+            "eventlog-stderr" => Ok(Self::EventLogStderr),
+            _ => Err(format!("invalid command log target: {}", s)),
+        }
+    }
+}
 
-    /// Write log file to a custom directory. This directory will be created if it doesn't exist.
-    #[clap(long, value_name = "path", value_parser = parse_ensured_directory)]
-    pub log_dir: Option<String>,
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DuplicateAction {
+    Adopt,
+    Kill,
+    Refuse,
+}
 
-    /// Use a different name for the main log file.
-    /// Set this to just the desired base name of the log file.
-    /// For example, `--log-as shawl` would result in a log file named `shawl_rCURRENT.log`
-    /// instead of the normal `shawl_for_<name>_rCURRENT.log` pattern.
-    #[clap(long)]
-    pub log_as: Option<String>,
+impl DuplicateAction {
+    pub const ALL: &'static [&'static str] = &["adopt", "kill", "refuse"];
+}
 
-    /// Use a separate log file for the wrapped command's stdout and stderr.
-    /// Set this to just the desired base name of the log file.
-    /// For example, `--log-cmd-as foo` would result in a log file named `foo_rCURRENT.log`.
-    /// The output will be logged as-is without any additional log template.
-    #[clap(long)]
-    pub log_cmd_as: Option<String>,
+impl DuplicateAction {
+    pub fn to_cli(self) -> String {
+        match self {
+            Self::Adopt => "adopt",
+            Self::Kill => "kill",
+            Self::Refuse => "refuse",
+        }
+        .to_string()
+    }
+}
 
-    /// Threshold for rotating log files. Valid options:
-    /// `daily`, `hourly`, `bytes=n` (every N bytes)
-    /// [default: bytes=2097152]
-    #[clap(long)]
-    pub log_rotate: Option<LogRotation>,
+impl std::str::FromStr for DuplicateAction {
+    type Err = String;
 
-    /// How many old log files to retain [default: 2]
-    #[clap(long)]
-    pub log_retain: Option<usize>,
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "adopt" => Ok(Self::Adopt),
+            "kill" => Ok(Self::Kill),
+            "refuse" => Ok(Self::Refuse),
+            _ => Err(format!("invalid duplicate action: {}", s)),
+        }
+    }
+}
 
-    /// Append the service start arguments to the command
-    #[clap(long)]
-    pub pass_start_args: bool,
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Shell {
+    Cmd,
+    Powershell,
+}
 
-    /// Additional environment variable in the format 'KEY=value' (repeatable)
-    #[clap(long, number_of_values = 1, value_parser = parse_env_var)]
-    pub env: Vec<(String, String)>,
+impl Shell {
+    pub const ALL: &'static [&'static str] = &["cmd", "powershell"];
+}
 
-    /// Additional directory to append to the PATH environment variable (repeatable)
-    #[clap(long, number_of_values = 1, value_parser = parse_canonical_path)]
-    pub path: Vec<String>,
+impl Shell {
+    pub fn to_cli(self) -> String {
+        match self {
+            Self::Cmd => "cmd",
+            Self::Powershell => "powershell",
+        }
+        .to_string()
+    }
+}
 
-    /// Additional directory to prepend to the PATH environment variable (repeatable)
-    #[clap(long, value_name = "path", number_of_values = 1, value_parser = parse_canonical_path)]
-    pub path_prepend: Vec<String>,
+impl std::str::FromStr for Shell {
+    type Err = String;
 
-    /// Process priority of the command to run as a service
-    #[clap(long, value_parser = possible_values!(Priority, ALL))]
-    pub priority: Option<Priority>,
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cmd" => Ok(Self::Cmd),
+            "powershell" => Ok(Self::Powershell),
+            _ => Err(format!("invalid shell: {}", s)),
+        }
+    }
+}
 
-    /// Command to run as a service
-    #[clap(required(true), last(true))]
-    pub command: Vec<String>,
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Mitigation {
+    Dep,
+    ForceAslr,
+    NoChildProcess,
+    MicrosoftSignedOnly,
 }
 
-#[derive(clap::Subcommand, Clone, Debug, PartialEq, Eq)]
-pub enum Subcommand {
-    #[clap(about = "Add a new service")]
-    Add {
-        #[clap(flatten)]
-        common: CommonOpts,
+impl Mitigation {
+    pub const ALL: &'static [&'static str] = &["dep", "force-aslr", "no-child-process", "microsoft-signed-only"];
+}
 
-        /// Working directory in which to run the command. You may provide a
-        /// relative path, and it will be converted to an absolute one
-        #[clap(long, value_name = "path", value_parser = parse_canonical_path)]
-        cwd: Option<String>,
+impl Mitigation {
+    pub fn to_cli(self) -> String {
+        match self {
+            Self::Dep => "dep",
+            Self::ForceAslr => "force-aslr",
+            Self::NoChildProcess => "no-child-process",
+            Self::MicrosoftSignedOnly => "microsoft-signed-only",
+        }
+        .to_string()
+    }
+}
 
-        /// Other services that must be started first (comma-separated)
-        #[clap(long, value_delimiter = ',')]
-        dependencies: Vec<String>,
+impl std::str::FromStr for Mitigation {
+    type Err = String;
 
-        /// Name of the service to create
-        #[clap(long)]
-        name: String,
-    },
-    #[clap(about = "Run a command as a service; only works when launched by the Windows service manager")]
-    Run {
-        #[clap(flatten)]
-        common: CommonOpts,
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dep" => Ok(Self::Dep),
+            "force-aslr" => Ok(Self::ForceAslr),
+            "no-child-process" => Ok(Self::NoChildProcess),
+            "microsoft-signed-only" => Ok(Self::MicrosoftSignedOnly),
+            _ => Err(format!("invalid mitigation: {}", s)),
+        }
+    }
+}
 
-        /// Working directory in which to run the command. Must be an absolute path
-        #[clap(long, value_name = "path")]
-        cwd: Option<String>,
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SessionChangeAction {
+    Log,
+    Restart,
+    Run(String),
+}
 
-        /// Name of the service; used in logging, but does not need to match real name
-        #[clap(long, default_value = "Shawl")]
-        name: String,
-    },
+impl SessionChangeAction {
+    pub fn to_cli(&self) -> String {
+        match self {
+            Self::Log => "log".to_string(),
+            Self::Restart => "restart".to_string(),
+            Self::Run(command) => format!("run:{}", command),
+        }
+    }
 }
 
-#[derive(clap::Parser, Clone, Debug, PartialEq, Eq)]
-#[clap(
-    name = "shawl",
-    version,
-    about = "Wrap arbitrary commands as Windows services",
-    max_term_width = 100,
-    subcommand_negates_reqs = true,
-    next_line_help = true,
-    styles = styles()
-)]
-pub struct Cli {
-    #[clap(subcommand)]
-    pub sub: Subcommand,
+/// Parse the shared `run:<command>` action syntax.
+fn parse_run_action(s: &str) -> Option<String> {
+    s.strip_prefix("run:")
+        .filter(|command| !command.is_empty())
+        .map(str::to_string)
 }
 
-#[cfg(test)]
-speculate::speculate! {
-    fn check_args(args: &[&str], expected: Cli) {
-        assert_eq!(
-            expected,
-            Cli::parse_from(args)
-        );
+impl std::str::FromStr for SessionChangeAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "log" => return Ok(Self::Log),
+            "restart" => return Ok(Self::Restart),
+            _ => {}
+        }
+
+        if let Some(command) = parse_run_action(s) {
+            return Ok(Self::Run(command));
+        }
+
+        Err(format!("invalid session change action: {}", s))
     }
+}
 
-    fn check_args_err(args: &[&str], error: clap::error::ErrorKind) {
-        let result = Cli::try_parse_from(args);
-        assert!(result.is_err());
-        assert_eq!(result.unwrap_err().kind(), error);
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ControlAction {
+    Restart,
+    Run(String),
+    Stdin(String),
+    LogLevel(LogLevel),
+}
+
+impl ControlAction {
+    pub fn to_cli(&self) -> String {
+        match self {
+            Self::Restart => "restart".to_string(),
+            Self::Run(command) => format!("run:{}", command),
+            Self::Stdin(text) => format!("stdin:{}", text),
+            Self::LogLevel(level) => format!("log-level:{}", level.to_cli()),
+        }
     }
+}
 
-    fn s(text: &str) -> String {
-        text.to_string()
+impl std::str::FromStr for ControlAction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "restart" {
+            return Ok(Self::Restart);
+        }
+        if let Some(command) = parse_run_action(s) {
+            return Ok(Self::Run(command));
+        }
+        if let Some(text) = s.strip_prefix("stdin:") {
+            return Ok(Self::Stdin(text.to_string()));
+        }
+        if let Some(level) = s.strip_prefix("log-level:") {
+            return level.parse().map(Self::LogLevel).map_err(|_| {
+                format!(
+                    "invalid log level for control action: {} (must be one of {})",
+                    level,
+                    LogLevel::ALL.join(", ")
+                )
+            });
+        }
+
+        Err(format!("invalid control action: {}", s))
     }
+}
 
-    fn p(path: &str) -> String {
-        std::fs::canonicalize(&path).unwrap().to_string_lossy().to_string()
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum SidecarRestart {
+    #[default]
+    Always,
+    OnFailure,
+    Never,
+}
+
+impl SidecarRestart {
+    pub fn to_cli(self) -> &'static str {
+        match self {
+            Self::Always => "always",
+            Self::OnFailure => "on-failure",
+            Self::Never => "never",
+        }
     }
+}
 
-    describe "run subcommand" {
-        it "works with minimal arguments" {
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Sidecar {
+    pub restart: SidecarRestart,
+    pub command: String,
+}
+
+impl Sidecar {
+    pub fn to_cli(&self) -> String {
+        format!("{}:{}", self.restart.to_cli(), self.command)
+    }
+}
+
+impl std::str::FromStr for Sidecar {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (restart, command) = match s.split_once(':') {
+            Some(("always", command)) => (SidecarRestart::Always, command),
+            Some(("on-failure", command)) => (SidecarRestart::OnFailure, command),
+            Some(("never", command)) => (SidecarRestart::Never, command),
+            _ => (SidecarRestart::default(), s),
+        };
+
+        if command.trim().is_empty() {
+            return Err("Sidecar command must not be empty".to_string());
+        }
+
+        Ok(Self {
+            restart,
+            command: command.to_string(),
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LogRotation {
+    Bytes(u64),
+    Daily,
+    Hourly,
+    DailyOrBytes(u64),
+    HourlyOrBytes(u64),
+}
+
+impl LogRotation {
+    pub fn to_cli(self) -> String {
+        match self {
+            LogRotation::Bytes(bytes) => format!("bytes={}", bytes),
+            LogRotation::Daily => "daily".to_string(),
+            LogRotation::Hourly => "hourly".to_string(),
+            LogRotation::DailyOrBytes(bytes) => format!("daily,bytes={}", bytes),
+            LogRotation::HourlyOrBytes(bytes) => format!("hourly,bytes={}", bytes),
+        }
+    }
+}
+
+impl std::str::FromStr for LogRotation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        // An age and a size can be combined, such as `daily,bytes=n`.
+        let mut age = None;
+        let mut bytes = None;
+        for part in s.split(',') {
+            match part.trim() {
+                "daily" if age.is_none() => age = Some(Self::Daily),
+                "hourly" if age.is_none() => age = Some(Self::Hourly),
+                part if part.starts_with("bytes=") && bytes.is_none() => {
+                    let parts: Vec<_> = part.splitn(2, '=').collect();
+                    match parts[1].parse::<u64>() {
+                        Ok(parsed) => bytes = Some(parsed),
+                        Err(e) => return Err(format!("Unable to parse log rotation as bytes: {:?}", e)),
+                    }
+                }
+                _ => return Err(format!("Unable to parse log rotation: {}", s)),
+            }
+        }
+
+        match (age, bytes) {
+            (Some(age), None) => Ok(age),
+            (None, Some(bytes)) => Ok(Self::Bytes(bytes)),
+            (Some(Self::Daily), Some(bytes)) => Ok(Self::DailyOrBytes(bytes)),
+            (Some(Self::Hourly), Some(bytes)) => Ok(Self::HourlyOrBytes(bytes)),
+            _ => Err(format!("Unable to parse log rotation: {}", s)),
+        }
+    }
+}
+
+impl Default for LogRotation {
+    fn default() -> Self {
+        Self::Bytes(1024 * 1024 * 2)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl LogFormat {
+    pub const ALL: &'static [&'static str] = &["text", "json"];
+}
+
+impl LogFormat {
+    pub fn to_cli(self) -> String {
+        match self {
+            Self::Text => "text",
+            Self::Json => "json",
+        }
+        .to_string()
+    }
+}
+
+impl std::str::FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("invalid log format: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum GelfProtocol {
+    Udp,
+    Tcp,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct GelfEndpoint {
+    pub protocol: GelfProtocol,
+    /// Host and port, like `graylog:12201`.
+    pub address: String,
+}
+
+impl GelfEndpoint {
+    pub fn to_cli(&self) -> String {
+        match self.protocol {
+            GelfProtocol::Udp => format!("udp://{}", self.address),
+            GelfProtocol::Tcp => format!("tcp://{}", self.address),
+        }
+    }
+}
+
+impl std::str::FromStr for GelfEndpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || {
+            format!(
+                "invalid GELF endpoint (expected udp://host:port or tcp://host:port): {}",
+                s
+            )
+        };
+
+        let (protocol, address) = s.split_once("://").ok_or_else(error)?;
+        let protocol = match protocol.to_lowercase().as_str() {
+            "udp" => GelfProtocol::Udp,
+            "tcp" => GelfProtocol::Tcp,
+            _ => return Err(error()),
+        };
+        match address.rsplit_once(':') {
+            Some((host, port)) if !host.is_empty() && port.parse::<u16>().is_ok() => Ok(Self {
+                protocol,
+                address: address.to_string(),
+            }),
+            _ => Err(error()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WebhookUrl {
+    pub secure: bool,
+    pub host: String,
+    pub port: u16,
+    /// Path and query, like `/services/T00/B00/XXX`.
+    pub path: String,
+}
+
+impl WebhookUrl {
+    pub fn to_cli(&self) -> String {
+        let (scheme, default_port) = if self.secure { ("https", 443) } else { ("http", 80) };
+        if self.port == default_port {
+            format!("{}://{}{}", scheme, self.host, self.path)
+        } else {
+            format!("{}://{}:{}{}", scheme, self.host, self.port, self.path)
+        }
+    }
+
+    /// Hide the path, which usually contains the webhook's secret token.
+    pub fn redacted(&self) -> Self {
+        Self {
+            path: format!("/{}", REDACTED),
+            ..self.clone()
+        }
+    }
+}
+
+impl std::str::FromStr for WebhookUrl {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || format!("invalid webhook URL (expected https://host/path): {}", s);
+
+        let (scheme, rest) = s.split_once("://").ok_or_else(error)?;
+        let secure = match scheme.to_lowercase().as_str() {
+            "https" => true,
+            "http" => false,
+            _ => return Err(error()),
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(i) => rest.split_at(i),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse::<u16>().map_err(|_| error())?),
+            None => (authority, if secure { 443 } else { 80 }),
+        };
+        if host.is_empty() {
+            return Err(error());
+        }
+        Ok(Self {
+            secure,
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    #[default]
+    Debug,
+    Trace,
+}
+
+impl LogLevel {
+    pub const ALL: &'static [&'static str] = &["error", "warn", "info", "debug", "trace"];
+}
+
+impl LogLevel {
+    pub fn to_cli(self) -> String {
+        match self {
+            Self::Error => "error",
+            Self::Warn => "warn",
+            Self::Info => "info",
+            Self::Debug => "debug",
+            Self::Trace => "trace",
+        }
+        .to_string()
+    }
+
+    pub fn to_log(self) -> log::Level {
+        match self {
+            Self::Error => log::Level::Error,
+            Self::Warn => log::Level::Warn,
+            Self::Info => log::Level::Info,
+            Self::Debug => log::Level::Debug,
+            Self::Trace => log::Level::Trace,
+        }
+    }
+}
+
+impl std::str::FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(Self::Error),
+            "warn" => Ok(Self::Warn),
+            "info" => Ok(Self::Info),
+            "debug" => Ok(Self::Debug),
+            "trace" => Ok(Self::Trace),
+            _ => Err(format!("invalid log level: {}", s)),
+        }
+    }
+}
+
+fn parse_env_var(value: &str) -> Result<(String, String), CliError> {
+    let parts: Vec<&str> = value.splitn(2, '=').collect();
+    if parts.len() != 2 {
+        return Err(CliError::InvalidEnvVar {
+            specification: value.to_string(),
+        });
+    }
+    Ok((parts[0].to_string(), parts[1].to_string()))
+}
+
+fn parse_codepage(value: &str) -> Result<u32, String> {
+    match value.to_lowercase().as_str() {
+        "utf-8" | "utf8" => Ok(65001),
+        x => x.parse::<u32>().map_err(|_| format!("invalid code page: {}", value)),
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum CmdEncoding {
+    #[default]
+    Utf8,
+    Oem,
+    Ansi,
+    Auto,
+    CodePage(u32),
+}
+
+impl CmdEncoding {
+    pub fn to_cli(self) -> String {
+        match self {
+            Self::Utf8 => "utf-8".to_string(),
+            Self::Oem => "oem".to_string(),
+            Self::Ansi => "ansi".to_string(),
+            Self::Auto => "auto".to_string(),
+            Self::CodePage(codepage) => codepage.to_string(),
+        }
+    }
+}
+
+impl std::str::FromStr for CmdEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "oem" => Ok(Self::Oem),
+            "ansi" => Ok(Self::Ansi),
+            "auto" => Ok(Self::Auto),
+            _ => match parse_codepage(s) {
+                Ok(65001) => Ok(Self::Utf8),
+                Ok(codepage) => Ok(Self::CodePage(codepage)),
+                Err(_) => Err(format!("invalid command encoding: {}", s)),
+            },
+        }
+    }
+}
+
+/// One exit code, or an inclusive range like `100-199`.
+/// Ranges compare codes as unsigned, the way Windows usually shows them,
+/// so `0-0xFFFFFFFF` includes every code and negative codes sort above positive ones.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ExitCodeRange {
+    pub start: i32,
+    pub end: i32,
+}
+
+impl ExitCodeRange {
+    pub fn contains(self, code: i32) -> bool {
+        (self.start as u32..=self.end as u32).contains(&(code as u32))
+    }
+
+    /// Check whether the code is in any of the ranges.
+    pub fn any_contains(ranges: &[Self], code: i32) -> bool {
+        ranges.iter().any(|x| x.contains(code))
+    }
+
+    pub fn to_cli(self) -> String {
+        if self.start == self.end {
+            self.start.to_string()
+        } else {
+            format!("{}-{}", self.start, self.end)
+        }
+    }
+}
+
+impl From<i32> for ExitCodeRange {
+    fn from(code: i32) -> Self {
+        Self { start: code, end: code }
+    }
+}
+
+/// Parse an exit code in decimal or hex, like `-1` or `0xC0000005`.
+/// Hex values are read as unsigned, the way Windows usually shows them.
+fn parse_exit_code(value: &str) -> Option<i32> {
+    match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(hex) => u32::from_str_radix(hex, 16).ok().map(|x| x as i32),
+        None => value.parse::<i32>().ok(),
+    }
+}
+
+impl std::str::FromStr for ExitCodeRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if let Some(code) = parse_exit_code(s) {
+            return Ok(code.into());
+        }
+        // Either end may be negative, like `-10--1`, so try each hyphen as the separator.
+        for (i, _) in s.match_indices('-').filter(|(i, _)| *i > 0) {
+            if let (Some(start), Some(end)) = (parse_exit_code(&s[..i]), parse_exit_code(&s[i + 1..])) {
+                if start as u32 > end as u32 {
+                    return Err(format!("exit code range must not be backwards: {}", s));
+                }
+                return Ok(Self { start, end });
+            }
+        }
+        Err(format!("invalid exit code or range: {}", s))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum FirewallProtocol {
+    #[default]
+    Tcp,
+    Udp,
+}
+
+impl FirewallProtocol {
+    pub fn to_cli(self) -> &'static str {
+        match self {
+            Self::Tcp => "tcp",
+            Self::Udp => "udp",
+        }
+    }
+}
+
+/// Inbound traffic to allow through Windows Firewall for the command's program.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FirewallAllow {
+    /// Any port that the program listens on.
+    Program,
+    Ports {
+        start: u16,
+        end: u16,
+        protocol: FirewallProtocol,
+    },
+}
+
+impl FirewallAllow {
+    pub fn to_cli(self) -> String {
+        match self {
+            Self::Program => "program".to_string(),
+            Self::Ports { start, end, protocol } if start == end => format!("{}/{}", start, protocol.to_cli()),
+            Self::Ports { start, end, protocol } => format!("{}-{}/{}", start, end, protocol.to_cli()),
+        }
+    }
+}
+
+impl std::str::FromStr for FirewallAllow {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s == "program" {
+            return Ok(Self::Program);
+        }
+
+        let (ports, protocol) = match s.split_once('/') {
+            Some((ports, "tcp")) => (ports, FirewallProtocol::Tcp),
+            Some((ports, "udp")) => (ports, FirewallProtocol::Udp),
+            Some(_) => return Err(format!("firewall protocol must be tcp or udp: {}", s)),
+            None => (s, FirewallProtocol::default()),
+        };
+        let port = |x: &str| x.parse::<u16>().ok().filter(|x| *x > 0);
+        let (start, end) = match ports.split_once('-') {
+            Some((start, end)) => (port(start), port(end)),
+            None => (port(ports), port(ports)),
+        };
+        match (start, end) {
+            (Some(start), Some(end)) if start <= end => Ok(Self::Ports { start, end, protocol }),
+            _ => Err(format!("invalid port or range: {}", s)),
+        }
+    }
+}
+
+fn parse_urlacl(value: &str) -> Result<String, String> {
+    let lower = value.to_lowercase();
+    if !(lower.starts_with("http://") || lower.starts_with("https://")) || !value.ends_with('/') {
+        return Err("URL must start with http:// or https:// and end with /".to_string());
+    }
+    Ok(value.to_string())
+}
+
+fn parse_control_mapping(value: &str) -> Result<(u32, ControlAction), CliError> {
+    let error = || CliError::InvalidControlMapping {
+        specification: value.to_string(),
+    };
+
+    let (code, action) = value.split_once('=').ok_or_else(error)?;
+    let code = code.trim().parse::<u32>().map_err(|_| error())?;
+    if !(128..=255).contains(&code) {
+        return Err(error());
+    }
+    let action = action.parse::<ControlAction>().map_err(|reason| {
+        if action.starts_with("log-level:") {
+            CliError::InvalidControlLogLevel {
+                specification: value.to_string(),
+                reason,
+            }
+        } else {
+            error()
+        }
+    })?;
+
+    Ok((code, action))
+}
+
+fn styles() -> clap::builder::styling::Styles {
+    use clap::builder::styling::{AnsiColor, Effects, Styles};
+
+    Styles::styled()
+        .header(AnsiColor::Yellow.on_default() | Effects::BOLD)
+        .usage(AnsiColor::Yellow.on_default() | Effects::BOLD)
+        .literal(AnsiColor::Green.on_default() | Effects::BOLD)
+        .placeholder(AnsiColor::Green.on_default())
+}
+
+#[derive(clap::Parser, Clone, Debug, Default, PartialEq, Eq)]
+pub struct CommonOpts {
+    /// Use the options from the `[profile.<name>]` table in `shawl.toml`,
+    /// which is read each time the service starts. Other options override the profile
+    #[clap(long, value_name = "name")]
+    pub profile: Option<String>,
+
+    /// Use default options from machine environment variables like `SHAWL_STOP_TIMEOUT`.
+    /// Only options for timeouts, restart delays, and the log format can be set this way
+    #[clap(long)]
+    pub defaults_from_env: bool,
+
+    /// File with more options for `shawl run`, one per line, and optionally the command after a `--` line.
+    /// The service reads it each time it starts, so its options don't count against the command line limit
+    #[clap(long, value_name = "path", value_parser = parse_canonical_path, number_of_values = 1)]
+    pub args_file: Vec<String>,
+
+    /// Exit codes that should be considered successful (comma-separated) [default: 0].
+    /// Codes may be in hex (e.g., `0xC0000005`) or ranges (e.g., `0-9`)
+    #[clap(
+        long,
+        value_name = "codes",
+        value_delimiter = ',',
+        number_of_values = 1,
+        allow_hyphen_values(true)
+    )]
+    pub pass: Option<Vec<ExitCodeRange>>,
+
+    /// Always restart the command regardless of the exit code
+    #[clap(
+        long,
+        conflicts_with("no_restart"),
+        conflicts_with("restart_if"),
+        conflicts_with("restart_if_not")
+    )]
+    pub restart: bool,
+
+    /// Never restart the command regardless of the exit code
+    #[clap(
+        long,
+        conflicts_with("restart"),
+        conflicts_with("restart_if"),
+        conflicts_with("restart_if_not")
+    )]
+    pub no_restart: bool,
+
+    /// Restart the command if the exit code is one of these (comma-separated).
+    /// Codes may be in hex (e.g., `0xC0000005`) or ranges (e.g., `100-199`)
+    #[clap(
+        long,
+        conflicts_with("restart"),
+        conflicts_with("no_restart"),
+        conflicts_with("restart_if_not"),
+        value_name = "codes",
+        value_delimiter = ',',
+        number_of_values = 1,
+        allow_hyphen_values(true)
+    )]
+    pub restart_if: Vec<ExitCodeRange>,
+
+    /// Restart the command if the exit code is not one of these (comma-separated).
+    /// Codes may be in hex (e.g., `0xC0000005`) or ranges (e.g., `0-9`)
+    #[clap(
+        long,
+        conflicts_with("restart"),
+        conflicts_with("no_restart"),
+        conflicts_with("restart_if"),
+        value_name = "codes",
+        value_delimiter = ',',
+        number_of_values = 1,
+        allow_hyphen_values(true)
+    )]
+    pub restart_if_not: Vec<ExitCodeRange>,
+
+    /// Stop the service without error if the command exits with one of these codes
+    /// (comma-separated), regardless of the restart options.
+    /// Codes may be in hex or ranges, like for `--restart-if`.
+    /// Use this when the command has its own way for an operator to shut it down
+    #[clap(
+        long,
+        value_name = "codes",
+        value_delimiter = ',',
+        number_of_values = 1,
+        allow_hyphen_values(true)
+    )]
+    pub stop_if: Vec<ExitCodeRange>,
+
+    /// How long to wait before restarting the wrapped process
+    #[clap(long, value_name = "ms")]
+    pub restart_delay: Option<u64>,
+
+    /// Restart the command once it has been running for this long,
+    /// using the same stop sequence as when the service is stopped.
+    /// The value may use a unit suffix of `ms`, `s`, `m`, or `h` (e.g., `12h`);
+    /// a bare number is interpreted as milliseconds
+    #[clap(long, value_name = "duration", value_parser = parse_duration)]
+    pub restart_every: Option<std::time::Duration>,
+
+    /// Run the command repeatedly, waiting this long after each run finishes
+    /// before starting the next one, until the service is stopped.
+    /// Failed runs are logged but do not stop the service.
+    /// The value may use a unit suffix of `ms`, `s`, `m`, or `h` (e.g., `15m`);
+    /// a bare number is interpreted as milliseconds
+    #[clap(
+        long,
+        value_name = "duration",
+        value_parser = parse_duration,
+        conflicts_with("restart"),
+        conflicts_with("no_restart"),
+        conflicts_with("restart_if"),
+        conflicts_with("restart_if_not"),
+        conflicts_with("restart_delay"),
+        conflicts_with("restart_every")
+    )]
+    pub interval: Option<std::time::Duration>,
+
+    /// Run this many copies of the command at once.
+    /// Each copy receives its number in the `SHAWL_INSTANCE` environment variable,
+    /// starting from 1, and is restarted on its own according to the restart options.
+    /// Copies other than the first are stopped with ctrl-break instead of ctrl-C,
+    /// and the service stops them all once the first copy is done
+    #[clap(long, value_name = "n", value_parser = clap::value_parser!(u32).range(1..), conflicts_with("interval"))]
+    pub instances: Option<u32>,
+
+    /// How many times to retry launching the command if it fails to start
+    /// (e.g., because the executable is briefly locked or missing during a deployment).
+    /// Retries wait 1 second at first, doubling each time up to 30 seconds
+    /// [default: 0]
+    #[clap(long, value_name = "count")]
+    pub spawn_retries: Option<u32>,
+
+    /// Restart the command when its executable file is replaced,
+    /// using the same stop sequence as when the service is stopped
+    #[clap(long)]
+    pub restart_on_update: bool,
+
+    /// Restart the command when this file changes (repeatable),
+    /// using the same stop sequence as when the service is stopped
+    #[clap(long, value_name = "path", number_of_values = 1, value_parser = parse_canonical_path)]
+    pub watch: Vec<String>,
+
+    /// How long to wait after the last change to a watched file before restarting the command,
+    /// for `--watch` and `--restart-on-update`.
+    /// The value may use a unit suffix of `ms`, `s`, `m`, or `h`
+    /// [default: 1s]
+    #[clap(long, value_name = "duration", value_parser = parse_duration)]
+    pub watch_debounce: Option<std::time::Duration>,
+
+    /// How long to wait in milliseconds between sending the wrapped process
+    /// a ctrl-C event and forcibly killing it [default: 3000]
+    #[clap(long, value_name = "ms")]
+    pub stop_timeout: Option<u64>,
+
+    /// Accept the preshutdown notification when Windows is shutting down,
+    /// then wait this long in milliseconds for the wrapped process to stop
+    /// before forcibly killing it (instead of using `--stop-timeout`).
+    /// The `add` command also sets the service's preshutdown timeout to match
+    #[clap(long, value_name = "ms")]
+    pub preshutdown_timeout: Option<u64>,
+
+    /// How long to wait in milliseconds after the service is asked to stop
+    /// before sending ctrl-C to the command, such as to let a load balancer
+    /// stop sending it requests. This does not apply to restarts
+    #[clap(long, value_name = "ms")]
+    pub stop_delay: Option<u64>,
+
+    /// How to ask the command to stop.
+    /// `ctrl-c` sends it a ctrl-C event and waits for the stop timeout,
+    /// while `kill` ends it right away using `--kill-method`.
+    /// With `kill`, Shawl does not need a console, which is not available in some environments
+    /// like Nano Server, unless another option relies on one
+    /// [default: ctrl-c]
+    #[clap(long, value_name = "method", value_parser = possible_values!(StopMethod, ALL))]
+    pub stop_method: Option<StopMethod>,
+
+    /// How to forcibly end the command if it does not exit within the stop timeout.
+    /// `process` kills only the command's own process,
+    /// `tree` runs `taskkill /T /F` to kill the command and its descendants,
+    /// and `job` terminates a job object that the command is placed into at launch
+    /// [default: process]
+    #[clap(long, value_name = "method", value_parser = possible_values!(KillMethod, ALL))]
+    pub kill_method: Option<KillMethod>,
+
+    /// What to do if the command from a previous run of the service is still running
+    /// when the service starts, such as after Shawl itself was killed.
+    /// `adopt` supervises the old process instead of launching a new one,
+    /// `kill` ends the old process, and `refuse` stops the service with an error.
+    /// An adopted process's output is not logged, and it is killed when the service stops
+    /// since it cannot receive a ctrl-C from Shawl.
+    /// Shawl recognizes the old process by its ID, executable path, and start time,
+    /// which it records in a `.pid` file next to its log
+    #[clap(long, value_name = "action", value_parser = possible_values!(DuplicateAction, ALL))]
+    pub on_duplicate: Option<DuplicateAction>,
+
+    /// Disable all of Shawl's logging
+    #[clap(long)]
+    pub no_log: bool,
+
+    /// Disable logging of output from the command running as a service
+    #[clap(long)]
+    pub no_log_cmd: bool,
+
+    /// Write log file to a custom directory. This directory will be created if it doesn't exist.
+    #[clap(long, value_name = "path", value_parser = parse_ensured_directory)]
+    pub log_dir: Option<String>,
+
+    /// Put the log files in a subdirectory of the log directory,
+    /// which is named after the service unless you specify a name.
+    /// The name may include `{service_name}` for the service name,
+    /// so that many services can share this option in `shawl.toml`
+    #[clap(long, value_name = "name", num_args = 0..=1, value_parser = parse_log_subdir)]
+    pub log_subdir: Option<Option<String>>,
+
+    /// Allow only administrators and the service's account to access the log subdirectory,
+    /// since the command's output may contain sensitive data.
+    /// This requires `--log-subdir`, so that the rest of the log directory is left alone
+    #[clap(long, requires("log_subdir"))]
+    pub log_private: bool,
+
+    /// Use a different name for the main log file.
+    /// Set this to just the desired base name of the log file.
+    /// For example, `--log-as shawl` would result in a log file named `shawl_rCURRENT.log`
+    /// instead of the normal `shawl_for_<name>_rCURRENT.log` pattern.
+    #[clap(long)]
+    pub log_as: Option<String>,
+
+    /// Name the log files with this pattern of `strftime` placeholders instead,
+    /// where `{service_name}` is the service name. For example, `--log-name-pattern {service_name}-%Y-%m-%d`
+    /// would result in a log file named `myservice-2024-05-01.log`.
+    /// Each file is named when it is started, so pair this with a matching `--log-rotate`, like `daily`.
+    /// `--log-retain` counts the files that match the pattern
+    #[clap(long, value_name = "pattern", value_parser = parse_log_name_pattern, conflicts_with("log_as"))]
+    pub log_name_pattern: Option<String>,
+
+    /// Use a separate log file for the wrapped command's stdout and stderr.
+    /// Set this to just the desired base name of the log file.
+    /// For example, `--log-cmd-as foo` would result in a log file named `foo_rCURRENT.log`.
+    /// The output will be logged as-is without any additional log template.
+    #[clap(long)]
+    pub log_cmd_as: Option<String>,
+
+    /// Write the command's stdout and stderr to separate files,
+    /// each rotated on its own. For example, `--log-cmd-as foo --log-cmd-split`
+    /// would result in `foo.out_rCURRENT.log` and `foo.err_rCURRENT.log`
+    #[clap(long, requires("log_cmd_as"))]
+    pub log_cmd_split: bool,
+
+    /// Log level for Shawl's own messages.
+    /// The command's output is controlled separately by `--log-stdout-level` and `--log-stderr-level`
+    /// [default: debug, or the `RUST_LOG` environment variable if set]
+    #[clap(long, value_name = "level", value_parser = possible_values!(LogLevel, ALL))]
+    pub log_level: Option<LogLevel>,
+
+    /// Log level for lines of the command's stdout [default: debug]
+    #[clap(long, value_name = "level", value_parser = possible_values!(LogLevel, ALL))]
+    pub log_stdout_level: Option<LogLevel>,
+
+    /// Log level for lines of the command's stderr [default: debug]
+    #[clap(long, value_name = "level", value_parser = possible_values!(LogLevel, ALL))]
+    pub log_stderr_level: Option<LogLevel>,
+
+    /// Only log lines of the command's output that match this regular expression.
+    /// If repeated, a line must match at least one of them
+    #[clap(long, value_name = "regex", number_of_values = 1, value_parser = parse_regex)]
+    pub log_filter_include: Vec<String>,
+
+    /// Don't log lines of the command's output that match this regular expression (repeatable).
+    /// This takes precedence over `--log-filter-include`
+    #[clap(long, value_name = "regex", number_of_values = 1, value_parser = parse_regex)]
+    pub log_filter_exclude: Vec<String>,
+
+    /// Replace matches of this regular expression with `***` in the log files (repeatable).
+    /// This applies to the command's output and to Shawl's own messages.
+    /// The pattern is saved in the service's configuration, so use `--log-redact-secret` for literal secrets
+    #[clap(long, value_name = "regex", number_of_values = 1, value_parser = parse_regex)]
+    pub log_redact: Vec<String>,
+
+    /// Replace the secret of this generic credential in the Windows Credential Manager
+    /// with `***` in the log files (repeatable), like `--log-redact`,
+    /// without saving the secret in the service's configuration.
+    /// The credential is read when the service starts and must belong to the account that the service runs as
+    #[clap(long, value_name = "credential", number_of_values = 1)]
+    pub log_redact_secret: Vec<String>,
+
+    /// Also send logs to a Graylog server using GELF,
+    /// as `udp://host:port` or `tcp://host:port`.
+    /// Messages are kept and resent after reconnecting if the server is unavailable
+    #[clap(long, value_name = "endpoint")]
+    pub log_gelf: Option<GelfEndpoint>,
+
+    /// Log at most this many lines of the command's output per `--log-rate-interval`.
+    /// Extra lines are dropped, with a note of how many were dropped
+    #[clap(long, value_name = "lines")]
+    pub log_rate_limit: Option<u32>,
+
+    /// Interval for `--log-rate-limit`.
+    /// The value may use a unit suffix of `ms`, `s`, `m`, or `h`
+    /// [default: 1s]
+    #[clap(long, value_name = "duration", value_parser = parse_duration, requires("log_rate_limit"))]
+    pub log_rate_interval: Option<std::time::Duration>,
+
+    /// Log consecutive identical lines of the command's output once,
+    /// followed by a note of how many more times it was repeated
+    #[clap(long)]
+    pub log_collapse_repeats: bool,
+
+    /// Log lines of the command's output that start with whitespace,
+    /// such as the frames of a stack trace, together with the line before them
+    /// as a single record
+    #[clap(long)]
+    pub log_group_indented: bool,
+
+    /// Log lines of the command's output that match this regular expression
+    /// together with the line before them as a single record (repeatable),
+    /// such as `^Caused by:`
+    #[clap(long, value_name = "regex", number_of_values = 1, value_parser = parse_regex)]
+    pub log_group_pattern: Vec<String>,
+
+    /// Also write each line of the wrapped command's output to the Windows Application event log,
+    /// using the service name as the source.
+    /// `eventlog` forwards stdout as information events and stderr as error events,
+    /// while `eventlog-stderr` only forwards stderr
+    #[clap(
+        long,
+        value_name = "target",
+        value_parser = possible_values!(LogCmdTarget, ALL),
+        conflicts_with("no_log_cmd")
+    )]
+    pub log_cmd_to: Option<LogCmdTarget>,
+
+    /// Also write each line of the wrapped command's output to Shawl's stderr as it arrives,
+    /// so that it can be watched live when running Shawl from a console
+    #[clap(long, conflicts_with("no_log_cmd"))]
+    pub echo_output: bool,
+
+    /// Threshold for rotating log files. Valid options:
+    /// `daily`, `hourly`, `bytes=n` (every N bytes),
+    /// or an age and size together like `daily,bytes=n` (whichever comes first)
+    /// [default: bytes=2097152]
+    #[clap(long)]
+    pub log_rotate: Option<LogRotation>,
+
+    /// How many old log files to retain [default: 2]
+    #[clap(long)]
+    pub log_retain: Option<usize>,
+
+    /// Delete the oldest rotated log files when the logs for this service
+    /// (including --log-cmd-as) exceed this many megabytes in total
+    #[clap(long, value_name = "MB")]
+    pub log_max_total: Option<u64>,
+
+    /// Buffer log writes and flush them at this interval instead of writing each record immediately,
+    /// which helps when the command writes a lot of output. Logs are also flushed when Shawl exits.
+    /// The value may use a unit suffix of `ms`, `s`, `m`, or `h`
+    #[clap(long, value_name = "duration", value_parser = parse_duration)]
+    pub log_flush_interval: Option<std::time::Duration>,
+
+    /// Format of each line in the log files.
+    /// `json` writes an object with the `timestamp`, `level`, `service`, and `message`,
+    /// plus the `stream` (`stdout` or `stderr`) for lines of the command's output
+    /// [default: text]
+    #[clap(long, value_name = "format", value_parser = possible_values!(LogFormat, ALL))]
+    pub log_format: Option<LogFormat>,
+
+    /// Write log timestamps in UTC instead of local time.
+    /// This also applies to the `--log-cmd-as` file when using `--log-format json`
+    #[clap(long)]
+    pub log_utc: bool,
+
+    /// Write log timestamps in RFC 3339 format with milliseconds and the UTC offset
+    /// (e.g., `2025-01-16T08:30:00.123-05:00`).
+    /// JSON logs always use this format
+    #[clap(long)]
+    pub log_rfc3339: bool,
+
+    /// Append the service start arguments to the command
+    #[clap(long)]
+    pub pass_start_args: bool,
+
+    /// Additional environment variable in the format 'KEY=value' (repeatable)
+    #[clap(long, number_of_values = 1, value_parser = parse_env_var)]
+    pub env: Vec<(String, String)>,
+
+    /// File of `KEY=value` lines to load as environment variables,
+    /// re-read each time the command launches.
+    /// Supports `#` comments and quoted values. `--env` takes precedence
+    #[clap(long, value_name = "path", value_parser = parse_canonical_path)]
+    pub env_file: Option<String>,
+
+    /// Additional environment variable in the format 'KEY=credential' (repeatable),
+    /// where the value is read at launch from the named generic credential
+    /// in the Windows Credential Manager (e.g., one created with `cmdkey /generic`).
+    /// The credential must belong to the account that the service runs as,
+    /// and its secret must be UTF-16 text, which is how Windows tools store it
+    #[clap(long, value_name = "KEY=credential", number_of_values = 1, value_parser = parse_env_var)]
+    pub env_secret: Vec<(String, String)>,
+
+    /// Launch the command with a minimal environment instead of inheriting Shawl's.
+    /// Only `SystemRoot` and `SystemDrive` are kept, `PATH` is built from `--path`
+    /// and `--path-prepend`, and other variables come from `--env` and similar options
+    #[clap(long, conflicts_with("refresh_env"))]
+    pub clean_env: bool,
+
+    /// Variable to pass through from Shawl's environment with `--clean-env` (repeatable).
+    /// Names are case-insensitive and may use `*` and `?` wildcards (e.g., `APP_*`).
+    /// Inheriting `PATH` replaces the empty one that `--path` would add to
+    #[clap(long, value_name = "name", number_of_values = 1, requires("clean_env"))]
+    pub inherit_env: Vec<String>,
+
+    /// Inherited environment variable to remove before launching the command (repeatable).
+    /// Names are case-insensitive and may use `*` and `?` wildcards (e.g., `*_PROXY`).
+    /// Variables set by `--env` and similar options are not removed
+    #[clap(long, value_name = "name", number_of_values = 1)]
+    pub unset_env: Vec<String>,
+
+    /// Re-read the system and user environment variables from the registry
+    /// before each launch of the command, so that changes like new PATH entries
+    /// take effect without restarting Windows. `--env` and `--path` are applied on top
+    #[clap(long)]
+    pub refresh_env: bool,
+
+    /// Load the user profile of the account that the service runs as,
+    /// and launch the command with that account's environment variables
+    /// (e.g., `USERPROFILE` and `APPDATA`), which Windows does not provide
+    /// to services running as a named account. `--env` and `--path` are applied on top
+    #[clap(long, conflicts_with("clean_env"))]
+    pub load_profile: bool,
+
+    /// Expand `%VAR%` environment variable references in the command,
+    /// `--cwd`, `--env` values, and `--path`/`--path-prepend` when the service launches.
+    /// Paths containing `%` are converted to absolute paths after they are expanded,
+    /// instead of by the `add` command
+    #[clap(long)]
+    pub expand_env: bool,
+
+    /// Create the `--cwd` directory if it doesn't exist, instead of rejecting it.
+    /// The service also creates it each time the command launches,
+    /// so it can be added before another step lays down the directory
+    #[clap(long)]
+    pub cwd_create: bool,
+
+    /// Additional directory to append to the PATH environment variable (repeatable)
+    #[clap(long, number_of_values = 1, value_parser = parse_canonical_path)]
+    pub path: Vec<String>,
+
+    /// Additional directory to prepend to the PATH environment variable (repeatable)
+    #[clap(long, value_name = "path", number_of_values = 1, value_parser = parse_canonical_path)]
+    pub path_prepend: Vec<String>,
+
+    /// Run the command through a shell, so that it can use built-ins,
+    /// redirection, and operators like `&&`.
+    /// The command's arguments are joined with spaces and passed to
+    /// `cmd /C` or `powershell -NoProfile -Command`
+    #[clap(long, value_parser = possible_values!(Shell, ALL))]
+    pub shell: Option<Shell>,
+
+    /// Don't automatically run `.bat`, `.cmd`, `.ps1`, and `.py` commands
+    /// through `cmd`, `powershell -File`, and `py` respectively
+    #[clap(long)]
+    pub no_interpreter: bool,
+
+    /// File whose content to write to the command's stdin when it launches
+    #[clap(long, value_name = "path", value_parser = parse_canonical_path, conflicts_with("stdin_text"))]
+    pub stdin_file: Option<String>,
+
+    /// Line of text to write to the command's stdin when it launches,
+    /// such as to answer a confirmation prompt
+    #[clap(long, value_name = "text")]
+    pub stdin_text: Option<String>,
+
+    /// Code page for the console that the command shares,
+    /// as either `utf-8` or a number (e.g., `437`).
+    /// Use this when the command's output appears garbled in Shawl's log
+    #[clap(long, value_name = "codepage", value_parser = parse_codepage)]
+    pub console_codepage: Option<u32>,
+
+    /// Encoding of the command's output: `utf-8`, `oem` or `ansi` (the system's code pages),
+    /// a code page number (e.g., `936`), or `auto` to use UTF-8 when the line is valid UTF-8
+    /// and the OEM code page otherwise.
+    /// Characters that cannot be decoded are replaced
+    /// [default: utf-8]
+    #[clap(long, value_name = "encoding")]
+    pub cmd_encoding: Option<CmdEncoding>,
+
+    /// Write the command's stdout to this file byte-for-byte instead of logging it,
+    /// for commands that write binary data. Stderr is still logged as usual.
+    /// The file is rotated according to `--capture-raw-rotate` and `--log-retain`
+    #[clap(long, value_name = "path", value_parser = parse_output_file)]
+    pub capture_raw: Option<String>,
+
+    /// Rotate the `--capture-raw` file before it would exceed this many bytes,
+    /// renaming old files with a numbered suffix like `.1`
+    /// [default: 2097152]
+    #[clap(long, value_name = "bytes", requires("capture_raw"))]
+    pub capture_raw_rotate: Option<u64>,
+
+    /// Hide the console window that the command and its child processes share.
+    /// Windows that the command creates itself are not affected
+    #[clap(long)]
+    pub hide_window: bool,
+
+    /// Launch the command in the session of the user logged on at the console,
+    /// so that its windows appear on that user's desktop.
+    /// Shawl must run as LocalSystem. The command's output is not logged,
+    /// and it cannot receive ctrl-C, so it is killed when the service stops
+    #[clap(long, conflicts_with("stdin_file"), conflicts_with("stdin_text"))]
+    pub interactive_session: bool,
+
+    /// Mitigation policy to apply to the command when it launches.
+    /// `dep` enables data execution prevention,
+    /// `force-aslr` relocates images even if they were not built for it,
+    /// `no-child-process` prevents the command from creating processes,
+    /// and `microsoft-signed-only` blocks DLLs that are not signed by Microsoft.
+    /// Repeat this option to apply several policies
+    #[clap(long, value_name = "policy", value_parser = possible_values!(Mitigation, ALL))]
+    pub mitigation: Vec<Mitigation>,
+
+    /// Directory where Windows Error Reporting should write a minidump
+    /// if the command crashes. The dump's path is logged when the command
+    /// exits with an error status like 0xC0000005.
+    /// This directory will be created if it doesn't exist.
+    /// Windows applies the setting to every program with the same executable name,
+    /// so it's only made while the command runs, and not for shells or script interpreters
+    #[clap(long, value_name = "path", value_parser = parse_ensured_directory)]
+    pub crash_dump_dir: Option<String>,
+
+    /// Report the command's lifecycle (launch, exit, crash, restart, stop, and kill)
+    /// to the Windows Application event log, using the service name as the source
+    #[clap(long)]
+    pub event_log: bool,
+
+    /// Post the command's lifecycle events to a Slack incoming webhook,
+    /// with the service name, host, exit code, and restart count
+    #[clap(long, value_name = "url")]
+    pub notify_slack: Option<WebhookUrl>,
+
+    /// Post the command's lifecycle events to a Microsoft Teams webhook as an Adaptive Card,
+    /// with the service name, host, exit code, and restart count
+    #[clap(long, value_name = "url")]
+    pub notify_teams: Option<WebhookUrl>,
+
+    /// URL to ping every `--heartbeat-interval` while the command is running,
+    /// such as a check on healthchecks.io. When the command crashes, fails,
+    /// or can't be launched, the URL with `/fail` appended is pinged instead
+    #[clap(long, value_name = "url")]
+    pub heartbeat_url: Option<WebhookUrl>,
+
+    /// How often to ping `--heartbeat-url`. Default: 60s.
+    /// The value may use a unit suffix of `ms`, `s`, `m`, or `h`;
+    /// a bare number is interpreted as milliseconds
+    #[clap(long, value_name = "duration", value_parser = parse_nonzero_duration, requires("heartbeat_url"))]
+    pub heartbeat_interval: Option<std::time::Duration>,
+
+    /// Send the command's lifecycle events as log records to an OpenTelemetry collector
+    /// over OTLP/HTTP, with `service.name` set to the service name.
+    /// This is the collector's base URL, like `http://localhost:4318`,
+    /// to which `/v1/logs` is added
+    #[clap(long, value_name = "url")]
+    pub otlp_endpoint: Option<WebhookUrl>,
+
+    /// Also send each line of the command's output to `--otlp-endpoint`
+    #[clap(long, requires("otlp_endpoint"))]
+    pub otlp_output: bool,
+
+    /// Publish the command's restart count, uptime, memory, and output rate
+    /// as Windows performance counters in the "Shawl Service" set,
+    /// with an instance named after the service.
+    /// The set is registered with `lodctr` when the service is added
+    #[clap(long)]
+    pub perf_counters: bool,
+
+    /// Allow inbound traffic to the command's program through Windows Firewall
+    /// on a port or range, like `8080`, `8080/tcp`, or `5000-5010/udp` (repeatable),
+    /// or on any port with `program`.
+    /// The rule is created when the service is added and is named after the service,
+    /// and `shawl remove` deletes it
+    #[clap(long, value_name = "port/protocol", number_of_values = 1)]
+    pub firewall_allow: Vec<FirewallAllow>,
+
+    /// Reserve an HTTP.sys URL for the service's account (repeatable),
+    /// like `http://+:8080/api/`, so that programs using `HttpListener`
+    /// can listen on it without running as an administrator.
+    /// The reservation is made with `netsh http add urlacl` when the service is added,
+    /// and `shawl remove` deletes it
+    #[clap(long, value_name = "url", number_of_values = 1, value_parser = parse_urlacl)]
+    pub urlacl: Vec<String>,
+
+    /// Write the service's state, the command's PID, uptime, restart count,
+    /// last exit code, and last output time to this file as JSON every 5 seconds,
+    /// for monitoring systems that watch files
+    #[clap(long, value_name = "path", value_parser = parse_output_file)]
+    pub status_file: Option<String>,
+
+    /// Append one JSON object per line to this file for each lifecycle event
+    /// (the command's launch, exit, crash, scheduled restart, stop request, and kill),
+    /// for deployment and orchestration tools that follow the service
+    #[clap(long, value_name = "path", value_parser = parse_output_file)]
+    pub event_file: Option<String>,
+
+    /// Process priority of the command to run as a service
+    #[clap(long, value_parser = possible_values!(Priority, ALL))]
+    pub priority: Option<Priority>,
+
+    /// What to do when the system resumes from sleep.
+    /// `restart` will restart the command using the normal stop sequence,
+    /// while `signal` will send the command a ctrl-break event
+    #[clap(long, value_parser = possible_values!(ResumeAction, ALL))]
+    pub on_resume: Option<ResumeAction>,
+
+    /// What to do when a user session changes (e.g., logon, logoff, lock, unlock).
+    /// Valid options: `log` (only log the event),
+    /// `restart` (restart the command on logon and logoff),
+    /// `run:<command>` (run a command through `cmd /C` with the environment variables
+    /// `SHAWL_SESSION_EVENT` and `SHAWL_SESSION_ID`)
+    #[clap(long, value_name = "action")]
+    pub on_session_change: Option<SessionChangeAction>,
+
+    /// Action to take when the service receives a custom control code (128-255),
+    /// such as from `sc control <name> 130`. Format: `CODE=action` (repeatable).
+    /// Valid actions: `restart` (restart the command),
+    /// `run:<command>` (run a command through `cmd /C`),
+    /// `stdin:<text>` (write a line of text to the command's stdin),
+    /// `log-level:<level>` (change Shawl's log level until the service stops,
+    /// like `--log-level`)
+    #[clap(long, value_name = "mapping", number_of_values = 1, value_parser = parse_control_mapping)]
+    pub on_control: Vec<(u32, ControlAction)>,
+
+    /// Additional command line to run alongside the main command (repeatable).
+    /// Sidecars are launched before the main command and stopped after it, in reverse order.
+    /// They run with the same working directory and environment as the main command,
+    /// including `--cwd`, `--env`, and `--path`.
+    /// You may prefix the command line with a restart policy of
+    /// `always:`, `on-failure:`, or `never:` [default: always]
+    #[clap(long, value_name = "command", number_of_values = 1)]
+    pub sidecar: Vec<Sidecar>,
+
+    /// Command line to run to completion before launching the main command (repeatable).
+    /// Init commands run in order, and the service fails to start if any of them
+    /// exits with a nonzero code
+    #[clap(long, value_name = "command", number_of_values = 1)]
+    pub init: Vec<String>,
+
+    /// Script to run on lifecycle events, with the event as its argument:
+    /// `pre-start` before each launch, `post-exit` when the command exits,
+    /// and `state-change` when the state in `shawl status --live` changes.
+    /// Details like `SHAWL_EXIT_CODE` and `SHAWL_RESTARTS` are in its environment,
+    /// and for `post-exit`, it can print `restart` or `no-restart` as its last line
+    /// to decide instead of the restart options
+    #[clap(long, value_name = "path", value_parser = parse_canonical_path)]
+    pub hook_script: Option<String>,
+
+    /// Program to run on the same lifecycle events as `--hook-script`,
+    /// with a JSON object on stdin that describes the event
+    /// (e.g., `event`, `exit_code`, `restarts`, and `timestamp`).
+    /// Its output is logged, and it is killed if it takes longer than 30 seconds
+    #[clap(long, value_name = "path", value_parser = parse_canonical_path)]
+    pub hook_exec: Option<String>,
+
+    /// Wait until the network is usable (i.e., there is a default route)
+    /// before the first launch of the command.
+    /// You may specify a timeout, after which the service will fail to start
+    /// (e.g., `--wait-for-network 2m`); otherwise, Shawl waits indefinitely
+    #[clap(long, value_name = "timeout", value_parser = parse_duration, num_args = 0..=1)]
+    pub wait_for_network: Option<Option<std::time::Duration>>,
+
+    /// Wait until this file or directory is accessible before the first launch
+    /// of the command (repeatable). This is useful for network shares that
+    /// become available late during startup
+    #[clap(long, value_name = "path", number_of_values = 1)]
+    pub wait_for_path: Vec<String>,
+
+    /// How long to wait for `--wait-for-path` before the service fails to start.
+    /// The value may use a unit suffix of `ms`, `s`, `m`, or `h`
+    /// [default: wait indefinitely]
+    #[clap(long, value_name = "duration", value_parser = parse_duration, requires("wait_for_path"))]
+    pub wait_for_path_timeout: Option<std::time::Duration>,
+
+    /// Wait until this other service is running before the first launch
+    /// of the command (repeatable)
+    #[clap(long, value_name = "name", number_of_values = 1)]
+    pub wait_for_service: Vec<String>,
+
+    /// How long to wait for `--wait-for-service` before the service fails to start.
+    /// The value may use a unit suffix of `ms`, `s`, `m`, or `h`
+    /// [default: wait indefinitely]
+    #[clap(long, value_name = "duration", value_parser = parse_duration, requires("wait_for_service"))]
+    pub wait_for_service_timeout: Option<std::time::Duration>,
+
+    /// Wait until this other service is running before launching the command,
+    /// and stop the command whenever that service stops (repeatable).
+    /// The command is launched again once the service is running
+    #[clap(long, value_name = "name", number_of_values = 1)]
+    pub bind_to_service: Vec<String>,
+
+    /// Full command line to run as a service, instead of the arguments after `--`.
+    /// The program is taken from the start of the string,
+    /// and the rest is passed to it exactly as written, for programs like `msiexec`
+    /// that parse their command line in their own way
+    #[clap(long, value_name = "string", conflicts_with_all = ["command", "pass_start_args"])]
+    pub command_line: Option<String>,
+
+    /// Command to run as a service
+    #[clap(required_unless_present_any = ["command_line", "args_file"], last(true))]
+    pub command: Vec<String>,
+}
+
+/// Placeholder for sensitive values when options are logged.
+const REDACTED: &str = "***";
+
+impl CommonOpts {
+    /// The directory for the log files: `--log-dir` or else the one that contains Shawl,
+    /// then the `--log-subdir` if there is one.
+    pub fn log_directory(&self, name: &str) -> std::io::Result<std::path::PathBuf> {
+        let mut dir = match &self.log_dir {
+            Some(dir) => std::path::PathBuf::from(dir),
+            None => {
+                let mut exe_dir = std::env::current_exe()?;
+                exe_dir.pop();
+                exe_dir
+            }
+        };
+        if let Some(subdir) = &self.log_subdir {
+            dir.push(match subdir {
+                Some(subdir) => subdir.replace("{service_name}", name),
+                None => name.to_string(),
+            });
+        }
+        Ok(dir)
+    }
+
+    /// Fill in placeholders like `{name}` for `shawl add`,
+    /// then create the log directory if its path had any.
+    pub fn expand_templates(&mut self, variables: &[(&str, String)]) -> Result<(), String> {
+        if let Some(log_dir) = self.log_dir.as_deref().filter(|x| has_template(x)) {
+            // `shawl run` does not fill in the log directory, so this is its final form.
+            let log_dir = expand_template(log_dir, variables, true);
+            self.log_dir = Some(
+                parse_ensured_directory(&log_dir)
+                    .map_err(|e| format!("Unable to create log directory {}: {}", log_dir, e))?,
+            );
+        }
+        self.fill(variables, false);
+        Ok(())
+    }
+
+    /// Fill in placeholders in the `--env` values and the command when it launches,
+    /// turning `{{` and `}}` into literal braces.
+    pub fn fill_placeholders(&mut self, variables: &[(&str, String)]) {
+        self.fill(variables, true);
+    }
+
+    fn fill(&mut self, variables: &[(&str, String)], unescape: bool) {
+        for (_, value) in &mut self.env {
+            *value = expand_template(value, variables, unescape);
+        }
+        for arg in &mut self.command {
+            *arg = expand_template(arg, variables, unescape);
+        }
+        if let Some(command_line) = &mut self.command_line {
+            *command_line = expand_template(command_line, variables, unescape);
+        }
+    }
+
+    /// Copy the options with sensitive values masked, so that they can be logged.
+    /// `--env` values, `--stdin-text`, and `--on-control` stdin text may contain passwords,
+    /// and webhook and heartbeat URLs contain tokens.
+    pub fn redacted(&self) -> Self {
+        Self {
+            env: self
+                .env
+                .iter()
+                .map(|(key, _)| (key.clone(), REDACTED.to_string()))
+                .collect(),
+            stdin_text: self.stdin_text.as_ref().map(|_| REDACTED.to_string()),
+            on_control: self
+                .on_control
+                .iter()
+                .map(|(code, action)| match action {
+                    ControlAction::Stdin(_) => (*code, ControlAction::Stdin(REDACTED.to_string())),
+                    _ => (*code, action.clone()),
+                })
+                .collect(),
+            notify_slack: self.notify_slack.as_ref().map(WebhookUrl::redacted),
+            notify_teams: self.notify_teams.as_ref().map(WebhookUrl::redacted),
+            heartbeat_url: self.heartbeat_url.as_ref().map(WebhookUrl::redacted),
+            ..self.clone()
+        }
+    }
+}
+
+#[derive(clap::Subcommand, Clone, Debug, PartialEq, Eq)]
+pub enum Subcommand {
+    #[clap(about = "Add a new service")]
+    Add {
+        #[clap(flatten)]
+        common: CommonOpts,
+
+        /// Working directory in which to run the command. You may provide a
+        /// relative path, and it will be converted to an absolute one.
+        /// It must already exist, unless you use `--cwd-create`
+        #[clap(long, value_name = "path", value_parser = parse_cwd)]
+        cwd: Option<String>,
+
+        /// Other services that must be started first (comma-separated)
+        #[clap(long, value_delimiter = ',')]
+        dependencies: Vec<String>,
+
+        /// Name of the service to create
+        #[clap(long)]
+        name: String,
+
+        /// Create the service even if the command's program can't be found,
+        /// such as when it will be installed later
+        #[clap(long)]
+        no_verify: bool,
+
+        /// Account for the service to run as instead of Local System,
+        /// such as `NT AUTHORITY\NetworkService` or a virtual account like `NT SERVICE\<name>`.
+        /// Accounts that need a password can be set with `sc config` afterward
+        #[clap(long, value_name = "name")]
+        account: Option<String>,
+
+        /// Description of the service to show in the Services app
+        #[clap(long, value_name = "text")]
+        description: Option<String>,
+
+        /// Answer prompts for the main options instead, then review them before adding the service.
+        /// This can only be combined with `--elevate`
+        #[clap(long, exclusive = true)]
+        wizard: bool,
+
+        /// If Shawl isn't running as an administrator, which is needed to add a service,
+        /// run it again through a UAC prompt. The elevated Shawl opens in its own window
+        #[clap(long)]
+        elevate: bool,
+    },
+    #[clap(
+        about = "Run a command as a service; only works when launched by the Windows service manager",
+        // Options from `shawl.toml` come first, so let the service's own options replace them.
+        args_override_self = true
+    )]
+    Run {
+        #[clap(flatten)]
+        common: CommonOpts,
+
+        /// Working directory in which to run the command. Must be an absolute path
+        #[clap(long, value_name = "path")]
+        cwd: Option<String>,
+
+        /// Run in the current console instead of under the Windows service manager,
+        /// to try a configuration without installing it.
+        /// Ctrl-C stops the command like stopping the service would
+        #[clap(long)]
+        foreground: bool,
+
+        /// Name of the service; used in logging, but does not need to match real name
+        #[clap(long, default_value = "Shawl")]
+        name: String,
+    },
+    #[clap(
+        about = "Stop and delete a service, along with the firewall rules and URL reservations that `shawl add` created for it"
+    )]
+    Remove {
+        /// Name of the service
+        name: String,
+    },
+    #[clap(about = "Show the status of an installed service")]
+    Status {
+        /// Name of the service
+        name: String,
+
+        /// Also ask the running service for the command's PID, uptime, last exit code,
+        /// and time since its last output, along with the restarts, failures,
+        /// and uptime since the service started
+        #[clap(long)]
+        live: bool,
+    },
+    #[clap(about = "Run an installed service's configuration in this console, then stop it and report the outcome")]
+    Test {
+        /// Name of the service, which must be stopped
+        name: String,
+
+        /// How long to run the command before stopping it [default: 10s]
+        #[clap(long, value_name = "duration", value_parser = parse_duration)]
+        duration: Option<std::time::Duration>,
+    },
+}
+
+#[derive(clap::Parser, Clone, Debug, PartialEq, Eq)]
+#[clap(
+    name = "shawl",
+    version,
+    about = "Wrap arbitrary commands as Windows services",
+    max_term_width = 100,
+    subcommand_negates_reqs = true,
+    next_line_help = true,
+    styles = styles()
+)]
+pub struct Cli {
+    #[clap(subcommand)]
+    pub sub: Subcommand,
+}
+
+impl Cli {
+    /// Copy the CLI with sensitive option values masked, so that it can be logged.
+    pub fn redacted(&self) -> Self {
+        let mut cli = self.clone();
+        match &mut cli.sub {
+            Subcommand::Add { common, .. } | Subcommand::Run { common, .. } => *common = common.redacted(),
+            Subcommand::Remove { .. } | Subcommand::Status { .. } | Subcommand::Test { .. } => (),
+        }
+        cli
+    }
+}
+
+#[cfg(test)]
+speculate::speculate! {
+    fn check_args(args: &[&str], expected: Cli) {
+        assert_eq!(
+            expected,
+            Cli::parse_from(args)
+        );
+    }
+
+    fn check_args_err(args: &[&str], error: clap::error::ErrorKind) {
+        let result = Cli::try_parse_from(args);
+        assert!(result.is_err());
+        assert_eq!(result.unwrap_err().kind(), error);
+    }
+
+    fn s(text: &str) -> String {
+        text.to_string()
+    }
+
+    fn p(path: &str) -> String {
+        std::fs::canonicalize(path).unwrap().to_string_lossy().to_string()
+    }
+
+    describe "run subcommand" {
+        it "works with minimal arguments" {
+            check_args(
+                &["shawl", "run", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "requires a command" {
+            check_args_err(
+                &["shawl", "run"],
+                clap::error::ErrorKind::MissingRequiredArgument,
+            );
+        }
+
+        it "accepts --command-line" {
+            check_args(
+                &["shawl", "run", "--command-line", r#"msiexec /i "C:\temp\app.msi" INSTALLDIR="C:\Program Files\App""#],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            command_line: Some(s(r#"msiexec /i "C:\temp\app.msi" INSTALLDIR="C:\Program Files\App""#)),
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "rejects --command-line with a command" {
+            check_args_err(
+                &["shawl", "run", "--command-line", "foo", "--", "foo"],
+                clap::error::ErrorKind::ArgumentConflict,
+            );
+            check_args_err(
+                &["shawl", "run", "--command-line", "foo", "--pass-start-args"],
+                clap::error::ErrorKind::ArgumentConflict,
+            );
+        }
+
+        it "accepts --pass" {
+            check_args(
+                &["shawl", "run", "--pass", "1,2", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            pass: Some(vec![1.into(), 2.into()]),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --pass with leading negative" {
+            check_args(
+                &["shawl", "run", "--pass", "-1", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            pass: Some(vec![(-1).into()]),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "rejects --pass without value" {
+            check_args_err(
+                &["shawl", "run", "--pass", "--", "foo"],
+                clap::error::ErrorKind::UnknownArgument,
+            );
+        }
+
+        it "accepts --restart" {
+            check_args(
+                &["shawl", "run", "--restart", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            restart: true,
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "rejects --restart with conflicting options" {
+            for case in [
+                vec!["shawl", "run", "--restart", "--no-restart", "--", "foo"],
+                vec!["shawl", "run", "--restart", "--restart-if", "1", "--", "foo"],
+                vec!["shawl", "run", "--restart", "--restart-if-not", "1", "--", "foo"],
+            ] {
+                check_args_err(
+                    &case,
+                    clap::error::ErrorKind::ArgumentConflict,
+                );
+            }
+        }
+
+        it "accepts --no-restart" {
+            check_args(
+                &["shawl", "run", "--no-restart", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            no_restart: true,
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "rejects --no-restart with conflicting options" {
+            for case in [
+                vec!["shawl", "run", "--no-restart", "--restart", "--", "foo"],
+                vec!["shawl", "run", "--no-restart", "--restart-if", "1", "--", "foo"],
+                vec!["shawl", "run", "--no-restart", "--restart-if-not", "1", "--", "foo"],
+            ] {
+                check_args_err(
+                    &case,
+                    clap::error::ErrorKind::ArgumentConflict,
+                );
+            }
+        }
+
+        it "accepts --restart-if" {
+            check_args(
+                &["shawl", "run", "--restart-if", "1,2", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            restart_if: vec![1.into(), 2.into()],
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --restart-if with leading negative" {
+            check_args(
+                &["shawl", "run", "--restart-if", "-1", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            restart_if: vec![(-1).into()],
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --restart-if with ranges and hex" {
+            check_args(
+                &["shawl", "run", "--restart-if", "100-199,0xC0000005", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            restart_if: vec![ExitCodeRange { start: 100, end: 199 }, (0xC0000005_u32 as i32).into()],
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "rejects --restart-if without value" {
+            check_args_err(
+                &["shawl", "run", "--restart-if", "--", "foo"],
+                clap::error::ErrorKind::UnknownArgument,
+            );
+        }
+
+        it "rejects --restart-if with conflicting options" {
+            for case in [
+                vec!["shawl", "run", "--restart-if", "0", "--restart", "--", "foo"],
+                vec!["shawl", "run", "--restart-if", "0", "--no-restart", "--", "foo"],
+                vec!["shawl", "run", "--restart-if", "0", "--restart-if-not", "1", "--", "foo"],
+            ] {
+                check_args_err(
+                    &case,
+                    clap::error::ErrorKind::ArgumentConflict,
+                );
+            }
+        }
+
+        it "accepts --restart-if-not" {
+            check_args(
+                &["shawl", "run", "--restart-if-not", "1,2", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            restart_if_not: vec![1.into(), 2.into()],
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --restart-if-not with leading negative" {
+            check_args(
+                &["shawl", "run", "--restart-if-not", "-1", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            restart_if_not: vec![(-1).into()],
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --stop-if with --restart" {
+            check_args(
+                &["shawl", "run", "--restart", "--stop-if", "10,-1", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            restart: true,
+                            stop_if: vec![10.into(), (-1).into()],
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "rejects --restart-if-not without value" {
+            check_args_err(
+                &["shawl", "run", "--restart-if-not", "--", "foo"],
+                clap::error::ErrorKind::UnknownArgument,
+            );
+        }
+
+        it "rejects --restart-if-not with conflicting options" {
+            for case in [
+                vec!["shawl", "run", "--restart-if-not", "0", "--restart", "--", "foo"],
+                vec!["shawl", "run", "--restart-if-not", "0", "--no-restart", "--", "foo"],
+                vec!["shawl", "run", "--restart-if-not", "0", "--restart-if", "1", "--", "foo"],
+            ] {
+                check_args_err(
+                    &case,
+                    clap::error::ErrorKind::ArgumentConflict,
+                );
+            }
+        }
+
+        it "accepts --restart-delay" {
+            check_args(
+                &["shawl", "run", "--restart-delay", "1500", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            restart_delay: Some(1500),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --restart-every" {
+            check_args(
+                &["shawl", "run", "--restart-every", "12h", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            restart_every: Some(std::time::Duration::from_secs(12 * 60 * 60)),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "rejects --restart-every with an unknown unit" {
+            check_args_err(
+                &["shawl", "run", "--restart-every", "12d", "--", "foo"],
+                clap::error::ErrorKind::ValueValidation,
+            );
+        }
+
+        it "accepts --interval" {
+            check_args(
+                &["shawl", "run", "--interval", "15m", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            interval: Some(std::time::Duration::from_secs(15 * 60)),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "rejects --interval with --restart" {
+            check_args_err(
+                &["shawl", "run", "--interval", "15m", "--restart", "--", "foo"],
+                clap::error::ErrorKind::ArgumentConflict,
+            );
+        }
+
+        it "accepts --instances" {
+            check_args(
+                &["shawl", "run", "--instances", "5", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            instances: Some(5),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "rejects --instances of zero" {
+            check_args_err(
+                &["shawl", "run", "--instances", "0", "--", "foo"],
+                clap::error::ErrorKind::ValueValidation,
+            );
+        }
+
+        it "accepts --spawn-retries" {
+            check_args(
+                &["shawl", "run", "--spawn-retries", "5", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            spawn_retries: Some(5),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --restart-on-update" {
+            check_args(
+                &["shawl", "run", "--restart-on-update", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            restart_on_update: true,
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --watch" {
+            let path = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
+            check_args(
+                &["shawl", "run", "--watch", path, "--watch-debounce", "500ms", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            watch: vec![p(path)],
+                            watch_debounce: Some(std::time::Duration::from_millis(500)),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --stop-timeout" {
+            check_args(
+                &["shawl", "run", "--stop-timeout", "500", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            stop_timeout: Some(500),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --preshutdown-timeout" {
+            check_args(
+                &["shawl", "run", "--preshutdown-timeout", "60000", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            preshutdown_timeout: Some(60000),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --stop-delay" {
+            check_args(
+                &["shawl", "run", "--stop-delay", "5000", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            stop_delay: Some(5000),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --kill-method" {
+            check_args(
+                &["shawl", "run", "--kill-method", "job", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            kill_method: Some(KillMethod::Job),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --on-duplicate" {
+            check_args(
+                &["shawl", "run", "--on-duplicate", "kill", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            on_duplicate: Some(DuplicateAction::Kill),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --stop-method" {
+            check_args(
+                &["shawl", "run", "--stop-method", "kill", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            stop_method: Some(StopMethod::Kill),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "rejects invalid --kill-method" {
+            check_args_err(
+                &["shawl", "run", "--kill-method", "nuke", "--", "foo"],
+                clap::error::ErrorKind::InvalidValue,
+            );
+        }
+
+        it "accepts --name" {
+            check_args(
+                &["shawl", "run", "--name", "custom-name", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("custom-name"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+    }
+
+    describe "add subcommand" {
+        it "works with minimal arguments" {
+            check_args(
+                &["shawl", "add", "--name", "custom-name", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Add {
+                        name: s("custom-name"),
+                        cwd: None,
+                        dependencies: vec![],
+                        no_verify: false,
+                        account: None,
+                        description: None,
+                        wizard: false,
+                        elevate: false,
+                        common: CommonOpts {
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --account and --description" {
+            check_args(
+                &[
+                    "shawl", "add",
+                    "--account", "NT AUTHORITY\\NetworkService",
+                    "--description", "Our website",
+                    "--name", "web", "--", "foo",
+                ],
+                Cli {
+                    sub: Subcommand::Add {
+                        name: s("web"),
+                        cwd: None,
+                        dependencies: vec![],
+                        no_verify: false,
+                        account: Some(s("NT AUTHORITY\\NetworkService")),
+                        description: Some(s("Our website")),
+                        wizard: false,
+                        elevate: false,
+                        common: CommonOpts {
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --elevate" {
+            check_args(
+                &["shawl", "add", "--elevate", "--name", "web", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Add {
+                        name: s("web"),
+                        cwd: None,
+                        dependencies: vec![],
+                        no_verify: false,
+                        account: None,
+                        description: None,
+                        wizard: false,
+                        elevate: true,
+                        common: CommonOpts {
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "recognizes --wizard with --elevate" {
+            let os = |args: &[&str]| args.iter().map(std::ffi::OsString::from).collect::<Vec<_>>();
+            assert_eq!(wizard_requested(&os(&["shawl", "add", "--wizard"])), Some(false));
+            assert_eq!(wizard_requested(&os(&["shawl", "add", "--elevate", "--wizard"])), Some(true));
+            assert_eq!(wizard_requested(&os(&["shawl", "add", "--wizard", "--name", "web"])), None);
+            assert_eq!(wizard_requested(&os(&["shawl", "add", "--elevate"])), None);
+            assert_eq!(wizard_requested(&os(&["shawl", "run", "--wizard"])), None);
+            assert_eq!(wizard_requested(&os(&["shawl"])), None);
+        }
+
+        it "rejects --wizard with other options" {
+            check_args_err(
+                &["shawl", "add", "--wizard", "--name", "web", "--", "foo"],
+                clap::error::ErrorKind::ArgumentConflict,
+            );
+        }
+
+        it "accepts --no-verify" {
+            check_args(
+                &["shawl", "add", "--no-verify", "--name", "custom-name", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Add {
+                        name: s("custom-name"),
+                        cwd: None,
+                        dependencies: vec![],
+                        no_verify: true,
+                        account: None,
+                        description: None,
+                        wizard: false,
+                        elevate: false,
+                        common: CommonOpts {
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "requires a command" {
+            check_args_err(
+                &["shawl", "add", "--name", "foo"],
+                clap::error::ErrorKind::MissingRequiredArgument,
+            );
+        }
+
+        it "requires a name" {
+            check_args_err(
+                &["shawl", "add", "--", "foo"],
+                clap::error::ErrorKind::MissingRequiredArgument,
+            );
+        }
+
+        it "accepts --pass" {
+            check_args(
+                &["shawl", "add", "--pass", "1,2", "--name", "foo", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Add {
+                        name: s("foo"),
+                        cwd: None,
+                        dependencies: vec![],
+                        no_verify: false,
+                        account: None,
+                        description: None,
+                        wizard: false,
+                        elevate: false,
+                        common: CommonOpts {
+                            pass: Some(vec![1.into(), 2.into()]),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --restart" {
+            check_args(
+                &["shawl", "add", "--restart", "--name", "foo", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Add {
+                        name: s("foo"),
+                        cwd: None,
+                        dependencies: vec![],
+                        no_verify: false,
+                        account: None,
+                        description: None,
+                        wizard: false,
+                        elevate: false,
+                        common: CommonOpts {
+                            restart: true,
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --no-restart" {
+            check_args(
+                &["shawl", "add", "--no-restart", "--name", "foo", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Add {
+                        name: s("foo"),
+                        cwd: None,
+                        dependencies: vec![],
+                        no_verify: false,
+                        account: None,
+                        description: None,
+                        wizard: false,
+                        elevate: false,
+                        common: CommonOpts {
+                            no_restart: true,
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --restart-if" {
+            check_args(
+                &["shawl", "add", "--restart-if", "1,2", "--name", "foo", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Add {
+                        name: s("foo"),
+                        cwd: None,
+                        dependencies: vec![],
+                        no_verify: false,
+                        account: None,
+                        description: None,
+                        wizard: false,
+                        elevate: false,
+                        common: CommonOpts {
+                            restart_if: vec![1.into(), 2.into()],
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --restart-if-not" {
+            check_args(
+                &["shawl", "add", "--restart-if-not", "1,2", "--name", "foo", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Add {
+                        name: s("foo"),
+                        cwd: None,
+                        dependencies: vec![],
+                        no_verify: false,
+                        account: None,
+                        description: None,
+                        wizard: false,
+                        elevate: false,
+                        common: CommonOpts {
+                            restart_if_not: vec![1.into(), 2.into()],
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --stop-timeout" {
+            check_args(
+                &["shawl", "add", "--stop-timeout", "500", "--name", "foo", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Add {
+                        name: s("foo"),
+                        cwd: None,
+                        dependencies: vec![],
+                        no_verify: false,
+                        account: None,
+                        description: None,
+                        wizard: false,
+                        elevate: false,
+                        common: CommonOpts {
+                            stop_timeout: Some(500),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --no-log" {
+            check_args(
+                &["shawl", "run", "--no-log", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            no_log: true,
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --no-log-cmd" {
+            check_args(
+                &["shawl", "run", "--no-log-cmd", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            no_log_cmd: true,
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --log-subdir with or without a name" {
+            check_args(
+                &["shawl", "run", "--log-subdir", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            log_subdir: Some(None),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+            check_args(
+                &["shawl", "run", "--log-subdir", "apps-{service_name}", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            log_subdir: Some(Some(s("apps-{service_name}"))),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --log-private" {
+            check_args(
+                &["shawl", "run", "--log-subdir", "--log-private", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            log_subdir: Some(None),
+                            log_private: true,
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "rejects a --log-subdir outside of the log directory" {
+            for subdir in ["..", ".", "a/b", r"C:\Windows"] {
+                check_args_err(
+                    &["shawl", "run", "--log-subdir", subdir, "--", "foo"],
+                    clap::error::ErrorKind::ValueValidation,
+                );
+            }
+        }
+
+        it "rejects --log-private without --log-subdir" {
+            check_args_err(
+                &["shawl", "run", "--log-dir", ".", "--log-private", "--", "foo"],
+                clap::error::ErrorKind::MissingRequiredArgument,
+            );
+        }
+
+        it "accepts --log-as" {
+            check_args(
+                &["shawl", "run", "--log-as", "foo", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            log_as: Some("foo".to_string()),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --log-name-pattern" {
+            check_args(
+                &["shawl", "run", "--log-name-pattern", "{service_name}-%Y-%m-%d", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            log_name_pattern: Some(s("{service_name}-%Y-%m-%d")),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "rejects invalid --log-name-pattern" {
+            check_args_err(
+                &["shawl", "run", "--log-name-pattern", "logs/%Y", "--", "foo"],
+                clap::error::ErrorKind::ValueValidation,
+            );
+            check_args_err(
+                &["shawl", "run", "--log-name-pattern", "%Y", "--log-as", "foo", "--", "foo"],
+                clap::error::ErrorKind::ArgumentConflict,
+            );
+        }
+
+        it "accepts --log-cmd-as" {
+            check_args(
+                &["shawl", "run", "--log-cmd-as", "foo", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            log_cmd_as: Some("foo".to_string()),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --log-cmd-split" {
+            check_args(
+                &["shawl", "run", "--log-cmd-as", "foo", "--log-cmd-split", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            log_cmd_as: Some("foo".to_string()),
+                            log_cmd_split: true,
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "rejects --log-cmd-split without --log-cmd-as" {
+            check_args_err(
+                &["shawl", "run", "--log-cmd-split", "--", "foo"],
+                clap::error::ErrorKind::MissingRequiredArgument,
+            );
+        }
+
+        it "accepts --log-level" {
+            check_args(
+                &["shawl", "run", "--log-level", "trace", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            log_level: Some(LogLevel::Trace),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --log-stdout-level and --log-stderr-level" {
+            check_args(
+                &[
+                    "shawl",
+                    "run",
+                    "--log-stdout-level",
+                    "info",
+                    "--log-stderr-level",
+                    "warn",
+                    "--",
+                    "foo",
+                ],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            log_stdout_level: Some(LogLevel::Info),
+                            log_stderr_level: Some(LogLevel::Warn),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --log-filter-include and --log-filter-exclude" {
+            check_args(
+                &[
+                    "shawl",
+                    "run",
+                    "--log-filter-include",
+                    "ERROR",
+                    "--log-filter-exclude",
+                    "^heartbeat",
+                    "--log-filter-exclude",
+                    "ping",
+                    "--",
+                    "foo",
+                ],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            log_filter_include: vec![s("ERROR")],
+                            log_filter_exclude: vec![s("^heartbeat"), s("ping")],
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "rejects an invalid --log-filter-exclude" {
+            check_args_err(
+                &["shawl", "run", "--log-filter-exclude", "(", "--", "foo"],
+                clap::error::ErrorKind::ValueValidation,
+            );
+        }
+
+        it "accepts --log-redact-secret" {
+            check_args(
+                &["shawl", "run", "--log-redact-secret", "my-app/token", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            log_redact_secret: vec![s("my-app/token")],
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --log-redact" {
+            check_args(
+                &["shawl", "run", "--log-redact", "token=\\w+", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            log_redact: vec![s("token=\\w+")],
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --log-gelf" {
+            check_args(
+                &["shawl", "run", "--log-gelf", "udp://graylog:12201", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            log_gelf: Some(GelfEndpoint {
+                                protocol: GelfProtocol::Udp,
+                                address: s("graylog:12201"),
+                            }),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "rejects invalid --log-gelf" {
+            for value in ["graylog:12201", "http://graylog:12201", "tcp://graylog", "tcp://:12201"] {
+                check_args_err(
+                    &["shawl", "run", "--log-gelf", value, "--", "foo"],
+                    clap::error::ErrorKind::ValueValidation,
+                );
+            }
+        }
+
+        it "accepts --log-rate-limit and --log-collapse-repeats" {
+            check_args(
+                &[
+                    "shawl",
+                    "run",
+                    "--log-rate-limit",
+                    "100",
+                    "--log-rate-interval",
+                    "10s",
+                    "--log-collapse-repeats",
+                    "--",
+                    "foo",
+                ],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            log_rate_limit: Some(100),
+                            log_rate_interval: Some(std::time::Duration::from_secs(10)),
+                            log_collapse_repeats: true,
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "rejects --log-rate-interval without --log-rate-limit" {
+            check_args_err(
+                &["shawl", "run", "--log-rate-interval", "10s", "--", "foo"],
+                clap::error::ErrorKind::MissingRequiredArgument,
+            );
+        }
+
+        it "accepts --log-group-indented and --log-group-pattern" {
+            check_args(
+                &[
+                    "shawl",
+                    "run",
+                    "--log-group-indented",
+                    "--log-group-pattern",
+                    "^Caused by:",
+                    "--",
+                    "foo",
+                ],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            log_group_indented: true,
+                            log_group_pattern: vec![s("^Caused by:")],
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --log-cmd-to" {
+            check_args(
+                &["shawl", "run", "--log-cmd-to", "eventlog-stderr", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            log_cmd_to: Some(LogCmdTarget::EventLogStderr),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "rejects --log-cmd-to with --no-log-cmd" {
+            check_args_err(
+                &["shawl", "run", "--log-cmd-to", "eventlog", "--no-log-cmd", "--", "foo"],
+                clap::error::ErrorKind::ArgumentConflict,
+            );
+        }
+
+
+        it "accepts --echo-output" {
+            check_args(
+                &["shawl", "run", "--echo-output", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            echo_output: true,
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "rejects --echo-output with --no-log-cmd" {
+            check_args_err(
+                &["shawl", "run", "--echo-output", "--no-log-cmd", "--", "foo"],
+                clap::error::ErrorKind::ArgumentConflict,
+            );
+        }
+
+        it "accepts --log-rotate bytes=n" {
+            check_args(
+                &["shawl", "run", "--log-rotate", "bytes=123", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            log_rotate: Some(LogRotation::Bytes(123)),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --log-rotate daily" {
+            check_args(
+                &["shawl", "run", "--log-rotate", "daily", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            log_rotate: Some(LogRotation::Daily),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --log-rotate hourly" {
+            check_args(
+                &["shawl", "run", "--log-rotate", "hourly", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            log_rotate: Some(LogRotation::Hourly),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --log-rotate with an age and size" {
+            check_args(
+                &["shawl", "run", "--log-rotate", "daily,bytes=10485760", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            log_rotate: Some(LogRotation::DailyOrBytes(10485760)),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+            check_args(
+                &["shawl", "run", "--log-rotate", "bytes=123,hourly", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            log_rotate: Some(LogRotation::HourlyOrBytes(123)),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "rejects --log-rotate with two ages" {
+            check_args_err(
+                &["shawl", "run", "--log-rotate", "daily,hourly", "--", "foo"],
+                clap::error::ErrorKind::ValueValidation,
+            );
+        }
+
+        it "accepts --log-retain" {
+            check_args(
+                &["shawl", "run", "--log-retain", "5", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            log_retain: Some(5),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --log-max-total" {
+            check_args(
+                &["shawl", "run", "--log-max-total", "100", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            log_max_total: Some(100),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --log-flush-interval" {
+            check_args(
+                &["shawl", "run", "--log-flush-interval", "2s", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            log_flush_interval: Some(std::time::Duration::from_secs(2)),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --log-format" {
+            check_args(
+                &["shawl", "run", "--log-format", "json", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            log_format: Some(LogFormat::Json),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --log-utc and --log-rfc3339" {
+            check_args(
+                &["shawl", "run", "--log-utc", "--log-rfc3339", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            log_utc: true,
+                            log_rfc3339: true,
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --log-dir" {
+            let path = env!("CARGO_MANIFEST_DIR");
+            check_args(
+                &["shawl", "run", "--log-dir", path, "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            log_dir: Some(p(path)),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --pass-start-args" {
+            check_args(
+                &["shawl", "run", "--pass-start-args", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            pass_start_args: true,
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --env" {
+            check_args(
+                &["shawl", "add", "--env", "FOO=bar", "--name", "foo", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Add {
+                        name: s("foo"),
+                        cwd: None,
+                        dependencies: vec![],
+                        no_verify: false,
+                        account: None,
+                        description: None,
+                        wizard: false,
+                        elevate: false,
+                        common: CommonOpts {
+                            env: vec![(s("FOO"), s("bar"))],
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --env multiple times" {
+            check_args(
+                &["shawl", "add", "--env", "FOO=1", "--env", "BAR=2", "--name", "foo", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Add {
+                        name: s("foo"),
+                        cwd: None,
+                        dependencies: vec![],
+                        no_verify: false,
+                        account: None,
+                        description: None,
+                        wizard: false,
+                        elevate: false,
+                        common: CommonOpts {
+                            env: vec![(s("FOO"), s("1")), (s("BAR"), s("2"))],
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --env-file" {
+            let path = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
+            check_args(
+                &["shawl", "add", "--env-file", path, "--name", "foo", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Add {
+                        name: s("foo"),
+                        cwd: None,
+                        dependencies: vec![],
+                        no_verify: false,
+                        account: None,
+                        description: None,
+                        wizard: false,
+                        elevate: false,
+                        common: CommonOpts {
+                            env_file: Some(p(path)),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --env-secret" {
+            check_args(
+                &["shawl", "add", "--env-secret", "DB_PASSWORD=myapp/db", "--name", "foo", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Add {
+                        name: s("foo"),
+                        cwd: None,
+                        dependencies: vec![],
+                        no_verify: false,
+                        account: None,
+                        description: None,
+                        wizard: false,
+                        elevate: false,
+                        common: CommonOpts {
+                            env_secret: vec![(s("DB_PASSWORD"), s("myapp/db"))],
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --refresh-env" {
+            check_args(
+                &["shawl", "run", "--refresh-env", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            refresh_env: true,
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --load-profile" {
+            check_args(
+                &["shawl", "run", "--load-profile", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            load_profile: true,
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "accepts --clean-env" {
+            check_args(
+                &["shawl", "run", "--clean-env", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            clean_env: true,
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "rejects --clean-env with --refresh-env" {
+            check_args_err(
+                &["shawl", "run", "--clean-env", "--refresh-env", "--", "foo"],
+                clap::error::ErrorKind::ArgumentConflict,
+            );
+        }
+
+        it "accepts --inherit-env with --clean-env" {
+            check_args(
+                &["shawl", "run", "--clean-env", "--inherit-env", "TEMP", "--inherit-env", "APP_*", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            clean_env: true,
+                            inherit_env: vec![s("TEMP"), s("APP_*")],
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
+        }
+
+        it "rejects --inherit-env without --clean-env" {
+            check_args_err(
+                &["shawl", "run", "--inherit-env", "TEMP", "--", "foo"],
+                clap::error::ErrorKind::MissingRequiredArgument,
+            );
+        }
+
+        it "accepts --unset-env multiple times" {
             check_args(
-                &["shawl", "run", "--", "foo"],
+                &["shawl", "run", "--unset-env", "JAVA_TOOL_OPTIONS", "--unset-env", "*_PROXY", "--", "foo"],
                 Cli {
                     sub: Subcommand::Run {
                         name: s("Shawl"),
                         cwd: None,
+                        foreground: false,
                         common: CommonOpts {
+                            unset_env: vec![s("JAVA_TOOL_OPTIONS"), s("*_PROXY")],
                             command: vec![s("foo")],
                             ..Default::default()
                         }
@@ -374,22 +3897,47 @@ speculate::speculate! {
             );
         }
 
-        it "requires a command" {
-            check_args_err(
-                &["shawl", "run"],
-                clap::error::ErrorKind::MissingRequiredArgument,
+        it "accepts --path" {
+            let path = env!("CARGO_MANIFEST_DIR");
+            check_args(
+                &["shawl", "add", "--path", path, "--name", "foo", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Add {
+                        name: s("foo"),
+                        cwd: None,
+                        dependencies: vec![],
+                        no_verify: false,
+                        account: None,
+                        description: None,
+                        wizard: false,
+                        elevate: false,
+                        common: CommonOpts {
+                            path: vec![p(path)],
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
             );
         }
 
-        it "accepts --pass" {
+        it "accepts --path multiple times" {
+            let path1 = format!("{}/target", env!("CARGO_MANIFEST_DIR"));
+            let path2 = format!("{}/src", env!("CARGO_MANIFEST_DIR"));
             check_args(
-                &["shawl", "run", "--pass", "1,2", "--", "foo"],
+                &["shawl", "add", "--path", &path1, "--path", &path2, "--name", "foo", "--", "foo"],
                 Cli {
-                    sub: Subcommand::Run {
-                        name: s("Shawl"),
+                    sub: Subcommand::Add {
+                        name: s("foo"),
                         cwd: None,
+                        dependencies: vec![],
+                        no_verify: false,
+                        account: None,
+                        description: None,
+                        wizard: false,
+                        elevate: false,
                         common: CommonOpts {
-                            pass: Some(vec![1, 2]),
+                            path: vec![p(&path1), p(&path2)],
                             command: vec![s("foo")],
                             ..Default::default()
                         }
@@ -398,15 +3946,22 @@ speculate::speculate! {
             );
         }
 
-        it "accepts --pass with leading negative" {
+        it "accepts --path-prepend" {
+            let path = env!("CARGO_MANIFEST_DIR");
             check_args(
-                &["shawl", "run", "--pass", "-1", "--", "foo"],
+                &["shawl", "add", "--path-prepend", path, "--name", "foo", "--", "foo"],
                 Cli {
-                    sub: Subcommand::Run {
-                        name: s("Shawl"),
+                    sub: Subcommand::Add {
+                        name: s("foo"),
                         cwd: None,
+                        dependencies: vec![],
+                        no_verify: false,
+                        account: None,
+                        description: None,
+                        wizard: false,
+                        elevate: false,
                         common: CommonOpts {
-                            pass: Some(vec![-1]),
+                            path_prepend: vec![p(path)],
                             command: vec![s("foo")],
                             ..Default::default()
                         }
@@ -415,22 +3970,41 @@ speculate::speculate! {
             );
         }
 
-        it "rejects --pass without value" {
-            check_args_err(
-                &["shawl", "run", "--pass", "--", "foo"],
-                clap::error::ErrorKind::UnknownArgument,
+        it "accepts --path-prepend multiple times" {
+            let path1 = format!("{}/target", env!("CARGO_MANIFEST_DIR"));
+            let path2 = format!("{}/src", env!("CARGO_MANIFEST_DIR"));
+            check_args(
+                &["shawl", "add", "--path-prepend", &path1, "--path-prepend", &path2, "--name", "foo", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Add {
+                        name: s("foo"),
+                        cwd: None,
+                        dependencies: vec![],
+                        no_verify: false,
+                        account: None,
+                        description: None,
+                        wizard: false,
+                        elevate: false,
+                        common: CommonOpts {
+                            path_prepend: vec![p(&path1), p(&path2)],
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
             );
         }
 
-        it "accepts --restart" {
+        it "accepts --hide-window" {
             check_args(
-                &["shawl", "run", "--restart", "--", "foo"],
+                &["shawl", "run", "--hide-window", "--", "foo"],
                 Cli {
                     sub: Subcommand::Run {
                         name: s("Shawl"),
                         cwd: None,
+                        foreground: false,
                         common: CommonOpts {
-                            restart: true,
+                            hide_window: true,
                             command: vec![s("foo")],
                             ..Default::default()
                         }
@@ -439,28 +4013,16 @@ speculate::speculate! {
             );
         }
 
-        it "rejects --restart with conflicting options" {
-            for case in [
-                vec!["shawl", "run", "--restart", "--no-restart", "--", "foo"],
-                vec!["shawl", "run", "--restart", "--restart-if", "1", "--", "foo"],
-                vec!["shawl", "run", "--restart", "--restart-if-not", "1", "--", "foo"],
-            ] {
-                check_args_err(
-                    &case,
-                    clap::error::ErrorKind::ArgumentConflict,
-                );
-            }
-        }
-
-        it "accepts --no-restart" {
+        it "accepts --interactive-session" {
             check_args(
-                &["shawl", "run", "--no-restart", "--", "foo"],
+                &["shawl", "run", "--interactive-session", "--", "foo"],
                 Cli {
                     sub: Subcommand::Run {
                         name: s("Shawl"),
                         cwd: None,
+                        foreground: false,
                         common: CommonOpts {
-                            no_restart: true,
+                            interactive_session: true,
                             command: vec![s("foo")],
                             ..Default::default()
                         }
@@ -469,28 +4031,35 @@ speculate::speculate! {
             );
         }
 
-        it "rejects --no-restart with conflicting options" {
-            for case in [
-                vec!["shawl", "run", "--no-restart", "--restart", "--", "foo"],
-                vec!["shawl", "run", "--no-restart", "--restart-if", "1", "--", "foo"],
-                vec!["shawl", "run", "--no-restart", "--restart-if-not", "1", "--", "foo"],
-            ] {
-                check_args_err(
-                    &case,
-                    clap::error::ErrorKind::ArgumentConflict,
-                );
-            }
+        it "accepts --mitigation" {
+            check_args(
+                &["shawl", "run", "--mitigation", "force-aslr", "--mitigation", "microsoft-signed-only", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            mitigation: vec![Mitigation::ForceAslr, Mitigation::MicrosoftSignedOnly],
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
         }
 
-        it "accepts --restart-if" {
+        it "accepts --crash-dump-dir" {
+            let path = env!("CARGO_MANIFEST_DIR");
             check_args(
-                &["shawl", "run", "--restart-if", "1,2", "--", "foo"],
+                &["shawl", "run", "--crash-dump-dir", path, "--", "foo"],
                 Cli {
                     sub: Subcommand::Run {
                         name: s("Shawl"),
                         cwd: None,
+                        foreground: false,
                         common: CommonOpts {
-                            restart_if: vec![1, 2],
+                            crash_dump_dir: Some(p(path)),
                             command: vec![s("foo")],
                             ..Default::default()
                         }
@@ -499,15 +4068,16 @@ speculate::speculate! {
             );
         }
 
-        it "accepts --restart-if with leading negative" {
+        it "accepts --event-log" {
             check_args(
-                &["shawl", "run", "--restart-if", "-1", "--", "foo"],
+                &["shawl", "run", "--event-log", "--", "foo"],
                 Cli {
                     sub: Subcommand::Run {
                         name: s("Shawl"),
                         cwd: None,
+                        foreground: false,
                         common: CommonOpts {
-                            restart_if: vec![-1],
+                            event_log: true,
                             command: vec![s("foo")],
                             ..Default::default()
                         }
@@ -516,35 +4086,80 @@ speculate::speculate! {
             );
         }
 
-        it "rejects --restart-if without value" {
-            check_args_err(
-                &["shawl", "run", "--restart-if", "--", "foo"],
-                clap::error::ErrorKind::UnknownArgument,
+
+        it "accepts --notify-slack and --notify-teams" {
+            check_args(
+                &[
+                    "shawl",
+                    "run",
+                    "--notify-slack",
+                    "https://hooks.slack.com/services/T00/B00/XXX",
+                    "--notify-teams",
+                    "https://example.webhook.office.com:8443/hook",
+                    "--",
+                    "foo",
+                ],
+                Cli {
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
+                        cwd: None,
+                        foreground: false,
+                        common: CommonOpts {
+                            notify_slack: Some(WebhookUrl {
+                                secure: true,
+                                host: s("hooks.slack.com"),
+                                port: 443,
+                                path: s("/services/T00/B00/XXX"),
+                            }),
+                            notify_teams: Some(WebhookUrl {
+                                secure: true,
+                                host: s("example.webhook.office.com"),
+                                port: 8443,
+                                path: s("/hook"),
+                            }),
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
             );
         }
 
-        it "rejects --restart-if with conflicting options" {
-            for case in [
-                vec!["shawl", "run", "--restart-if", "0", "--restart", "--", "foo"],
-                vec!["shawl", "run", "--restart-if", "0", "--no-restart", "--", "foo"],
-                vec!["shawl", "run", "--restart-if", "0", "--restart-if-not", "1", "--", "foo"],
-            ] {
+        it "rejects invalid webhook URLs" {
+            for value in ["hooks.slack.com/services/x", "ftp://example.com/hook", "https://:443/hook", "https://host:x/hook"] {
                 check_args_err(
-                    &case,
-                    clap::error::ErrorKind::ArgumentConflict,
+                    &["shawl", "run", "--notify-slack", value, "--", "foo"],
+                    clap::error::ErrorKind::ValueValidation,
                 );
             }
         }
 
-        it "accepts --restart-if-not" {
+
+        it "accepts --heartbeat-url and --heartbeat-interval" {
             check_args(
-                &["shawl", "run", "--restart-if-not", "1,2", "--", "foo"],
+                &[
+                    "shawl",
+                    "run",
+                    "--heartbeat-url",
+                    "https://hc-ping.com/1234",
+                    "--heartbeat-interval",
+                    "5m",
+                    "--",
+                    "foo",
+                ],
                 Cli {
                     sub: Subcommand::Run {
                         name: s("Shawl"),
                         cwd: None,
+                        foreground: false,
                         common: CommonOpts {
-                            restart_if_not: vec![1, 2],
+                            heartbeat_url: Some(WebhookUrl {
+                                secure: true,
+                                host: s("hc-ping.com"),
+                                port: 443,
+                                path: s("/1234"),
+                            }),
+                            heartbeat_interval: Some(std::time::Duration::from_secs(300)),
                             command: vec![s("foo")],
                             ..Default::default()
                         }
@@ -553,15 +4168,36 @@ speculate::speculate! {
             );
         }
 
-        it "accepts --restart-if-not with leading negative" {
+        it "rejects a --heartbeat-interval of zero" {
+            check_args_err(
+                &["shawl", "run", "--heartbeat-url", "https://example.com/ping", "--heartbeat-interval", "0s", "--", "foo"],
+                clap::error::ErrorKind::ValueValidation,
+            );
+        }
+
+        it "requires --heartbeat-url for --heartbeat-interval" {
+            check_args_err(
+                &["shawl", "run", "--heartbeat-interval", "5m", "--", "foo"],
+                clap::error::ErrorKind::MissingRequiredArgument,
+            );
+        }
+
+        it "accepts --otlp-endpoint and --otlp-output" {
             check_args(
-                &["shawl", "run", "--restart-if-not", "-1", "--", "foo"],
+                &["shawl", "run", "--otlp-endpoint", "http://localhost:4318", "--otlp-output", "--", "foo"],
                 Cli {
                     sub: Subcommand::Run {
                         name: s("Shawl"),
                         cwd: None,
+                        foreground: false,
                         common: CommonOpts {
-                            restart_if_not: vec![-1],
+                            otlp_endpoint: Some(WebhookUrl {
+                                secure: false,
+                                host: s("localhost"),
+                                port: 4318,
+                                path: s("/"),
+                            }),
+                            otlp_output: true,
                             command: vec![s("foo")],
                             ..Default::default()
                         }
@@ -570,35 +4206,51 @@ speculate::speculate! {
             );
         }
 
-        it "rejects --restart-if-not without value" {
+        it "requires --otlp-endpoint for --otlp-output" {
             check_args_err(
-                &["shawl", "run", "--restart-if-not", "--", "foo"],
-                clap::error::ErrorKind::UnknownArgument,
+                &["shawl", "run", "--otlp-output", "--", "foo"],
+                clap::error::ErrorKind::MissingRequiredArgument,
             );
         }
 
-        it "rejects --restart-if-not with conflicting options" {
-            for case in [
-                vec!["shawl", "run", "--restart-if-not", "0", "--restart", "--", "foo"],
-                vec!["shawl", "run", "--restart-if-not", "0", "--no-restart", "--", "foo"],
-                vec!["shawl", "run", "--restart-if-not", "0", "--restart-if", "1", "--", "foo"],
-            ] {
-                check_args_err(
-                    &case,
-                    clap::error::ErrorKind::ArgumentConflict,
-                );
-            }
+        it "accepts --perf-counters" {
+            check_args(
+                &["shawl", "add", "--perf-counters", "--name", "Shawl", "--", "foo"],
+                Cli {
+                    sub: Subcommand::Add {
+                        name: s("Shawl"),
+                        cwd: None,
+                        dependencies: vec![],
+                        no_verify: false,
+                        account: None,
+                        description: None,
+                        wizard: false,
+                        elevate: false,
+                        common: CommonOpts {
+                            perf_counters: true,
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
+            );
         }
 
-        it "accepts --restart-delay" {
+        it "accepts --profile" {
             check_args(
-                &["shawl", "run", "--restart-delay", "1500", "--", "foo"],
+                &["shawl", "add", "--profile", "java-app", "--name", "Shawl", "--", "foo"],
                 Cli {
-                    sub: Subcommand::Run {
+                    sub: Subcommand::Add {
                         name: s("Shawl"),
                         cwd: None,
+                        dependencies: vec![],
+                        no_verify: false,
+                        account: None,
+                        description: None,
+                        wizard: false,
+                        elevate: false,
                         common: CommonOpts {
-                            restart_delay: Some(1500),
+                            profile: Some(s("java-app")),
                             command: vec![s("foo")],
                             ..Default::default()
                         }
@@ -607,15 +4259,20 @@ speculate::speculate! {
             );
         }
 
-        it "accepts --stop-timeout" {
+        it "accepts --status-file" {
+            let dir = env!("CARGO_MANIFEST_DIR");
+            let path = format!("{}/status.json", dir);
             check_args(
-                &["shawl", "run", "--stop-timeout", "500", "--", "foo"],
+                &["shawl", "run", "--status-file", &path, "--", "foo"],
                 Cli {
                     sub: Subcommand::Run {
                         name: s("Shawl"),
                         cwd: None,
+                        foreground: false,
                         common: CommonOpts {
-                            stop_timeout: Some(500),
+                            status_file: Some(
+                                std::path::Path::new(&p(dir)).join("status.json").to_string_lossy().to_string()
+                            ),
                             command: vec![s("foo")],
                             ..Default::default()
                         }
@@ -624,13 +4281,14 @@ speculate::speculate! {
             );
         }
 
-        it "accepts --name" {
+        it "accepts --foreground for run" {
             check_args(
-                &["shawl", "run", "--name", "custom-name", "--", "foo"],
+                &["shawl", "run", "--foreground", "--", "foo"],
                 Cli {
                     sub: Subcommand::Run {
-                        name: s("custom-name"),
+                        name: s("Shawl"),
                         cwd: None,
+                        foreground: true,
                         common: CommonOpts {
                             command: vec![s("foo")],
                             ..Default::default()
@@ -639,18 +4297,25 @@ speculate::speculate! {
                 },
             );
         }
-    }
 
-    describe "add subcommand" {
-        it "works with minimal arguments" {
+        it "accepts --firewall-allow multiple times" {
             check_args(
-                &["shawl", "add", "--name", "custom-name", "--", "foo"],
+                &["shawl", "add", "--firewall-allow", "8080", "--firewall-allow", "program", "--name", "web", "--", "foo"],
                 Cli {
                     sub: Subcommand::Add {
-                        name: s("custom-name"),
+                        name: s("web"),
                         cwd: None,
                         dependencies: vec![],
+                        no_verify: false,
+                        account: None,
+                        description: None,
+                        wizard: false,
+                        elevate: false,
                         common: CommonOpts {
+                            firewall_allow: vec![
+                                FirewallAllow::Ports { start: 8080, end: 8080, protocol: FirewallProtocol::Tcp },
+                                FirewallAllow::Program,
+                            ],
                             command: vec![s("foo")],
                             ..Default::default()
                         }
@@ -659,30 +4324,59 @@ speculate::speculate! {
             );
         }
 
-        it "requires a command" {
-            check_args_err(
-                &["shawl", "add", "--name", "foo"],
-                clap::error::ErrorKind::MissingRequiredArgument,
+        it "accepts --urlacl multiple times" {
+            check_args(
+                &[
+                    "shawl", "add",
+                    "--urlacl", "http://+:8080/api/",
+                    "--urlacl", "https://+:8443/",
+                    "--name", "web", "--", "foo",
+                ],
+                Cli {
+                    sub: Subcommand::Add {
+                        name: s("web"),
+                        cwd: None,
+                        dependencies: vec![],
+                        no_verify: false,
+                        account: None,
+                        description: None,
+                        wizard: false,
+                        elevate: false,
+                        common: CommonOpts {
+                            urlacl: vec![s("http://+:8080/api/"), s("https://+:8443/")],
+                            command: vec![s("foo")],
+                            ..Default::default()
+                        }
+                    }
+                },
             );
         }
 
-        it "requires a name" {
+        it "rejects --urlacl without a scheme or trailing slash" {
             check_args_err(
-                &["shawl", "add", "--", "foo"],
-                clap::error::ErrorKind::MissingRequiredArgument,
+                &["shawl", "add", "--urlacl", "+:8080/", "--name", "web", "--", "foo"],
+                clap::error::ErrorKind::ValueValidation,
+            );
+            check_args_err(
+                &["shawl", "add", "--urlacl", "http://+:8080/api", "--name", "web", "--", "foo"],
+                clap::error::ErrorKind::ValueValidation,
             );
         }
 
-        it "accepts --pass" {
+        it "accepts --event-file" {
+            let dir = env!("CARGO_MANIFEST_DIR");
+            let path = format!("{}/events.jsonl", dir);
             check_args(
-                &["shawl", "add", "--pass", "1,2", "--name", "foo", "--", "foo"],
+                &["shawl", "run", "--event-file", &path, "--", "foo"],
                 Cli {
-                    sub: Subcommand::Add {
-                        name: s("foo"),
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
                         cwd: None,
-                        dependencies: vec![],
+                        foreground: false,
                         common: CommonOpts {
-                            pass: Some(vec![1, 2]),
+                            event_file: Some(
+                                std::path::Path::new(&p(dir)).join("events.jsonl").to_string_lossy().to_string()
+                            ),
                             command: vec![s("foo")],
                             ..Default::default()
                         }
@@ -691,16 +4385,17 @@ speculate::speculate! {
             );
         }
 
-        it "accepts --restart" {
+        it "lets later options replace earlier ones for run" {
             check_args(
-                &["shawl", "add", "--restart", "--name", "foo", "--", "foo"],
+                &["shawl", "run", "--stop-timeout=5000", "--no-restart", "--stop-timeout", "1000", "--no-restart", "--", "foo"],
                 Cli {
-                    sub: Subcommand::Add {
-                        name: s("foo"),
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
                         cwd: None,
-                        dependencies: vec![],
+                        foreground: false,
                         common: CommonOpts {
-                            restart: true,
+                            stop_timeout: Some(1000),
+                            no_restart: true,
                             command: vec![s("foo")],
                             ..Default::default()
                         }
@@ -709,16 +4404,82 @@ speculate::speculate! {
             );
         }
 
-        it "accepts --no-restart" {
+        it "accepts --console-codepage" {
+            for (value, codepage) in [("utf-8", 65001), ("UTF8", 65001), ("437", 437)] {
+                check_args(
+                    &["shawl", "run", "--console-codepage", value, "--", "foo"],
+                    Cli {
+                        sub: Subcommand::Run {
+                            name: s("Shawl"),
+                            cwd: None,
+                            foreground: false,
+                            common: CommonOpts {
+                                console_codepage: Some(codepage),
+                                command: vec![s("foo")],
+                                ..Default::default()
+                            }
+                        }
+                    },
+                );
+            }
+        }
+
+        it "rejects invalid --console-codepage" {
+            check_args_err(
+                &["shawl", "run", "--console-codepage", "latin-1", "--", "foo"],
+                clap::error::ErrorKind::ValueValidation,
+            );
+        }
+
+        it "accepts --cmd-encoding" {
+            for (value, encoding) in [
+                ("utf-8", CmdEncoding::Utf8),
+                ("65001", CmdEncoding::Utf8),
+                ("OEM", CmdEncoding::Oem),
+                ("ansi", CmdEncoding::Ansi),
+                ("auto", CmdEncoding::Auto),
+                ("936", CmdEncoding::CodePage(936)),
+            ] {
+                check_args(
+                    &["shawl", "run", "--cmd-encoding", value, "--", "foo"],
+                    Cli {
+                        sub: Subcommand::Run {
+                            name: s("Shawl"),
+                            cwd: None,
+                            foreground: false,
+                            common: CommonOpts {
+                                cmd_encoding: Some(encoding),
+                                command: vec![s("foo")],
+                                ..Default::default()
+                            }
+                        }
+                    },
+                );
+            }
+        }
+
+        it "rejects invalid --cmd-encoding" {
+            check_args_err(
+                &["shawl", "run", "--cmd-encoding", "latin-1", "--", "foo"],
+                clap::error::ErrorKind::ValueValidation,
+            );
+        }
+
+        it "accepts --capture-raw and --capture-raw-rotate" {
+            let dir = env!("CARGO_MANIFEST_DIR");
+            let path = format!("{}/raw.bin", dir);
             check_args(
-                &["shawl", "add", "--no-restart", "--name", "foo", "--", "foo"],
+                &["shawl", "run", "--capture-raw", &path, "--capture-raw-rotate", "1000", "--", "foo"],
                 Cli {
-                    sub: Subcommand::Add {
-                        name: s("foo"),
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
                         cwd: None,
-                        dependencies: vec![],
+                        foreground: false,
                         common: CommonOpts {
-                            no_restart: true,
+                            capture_raw: Some(
+                                std::path::Path::new(&p(dir)).join("raw.bin").to_string_lossy().to_string()
+                            ),
+                            capture_raw_rotate: Some(1000),
                             command: vec![s("foo")],
                             ..Default::default()
                         }
@@ -727,16 +4488,23 @@ speculate::speculate! {
             );
         }
 
-        it "accepts --restart-if" {
+        it "rejects --capture-raw-rotate without --capture-raw" {
+            check_args_err(
+                &["shawl", "run", "--capture-raw-rotate", "1000", "--", "foo"],
+                clap::error::ErrorKind::MissingRequiredArgument,
+            );
+        }
+
+        it "accepts --stdin-text" {
             check_args(
-                &["shawl", "add", "--restart-if", "1,2", "--name", "foo", "--", "foo"],
+                &["shawl", "run", "--stdin-text", "Y", "--", "foo"],
                 Cli {
-                    sub: Subcommand::Add {
-                        name: s("foo"),
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
                         cwd: None,
-                        dependencies: vec![],
+                        foreground: false,
                         common: CommonOpts {
-                            restart_if: vec![1, 2],
+                            stdin_text: Some(s("Y")),
                             command: vec![s("foo")],
                             ..Default::default()
                         }
@@ -745,16 +4513,17 @@ speculate::speculate! {
             );
         }
 
-        it "accepts --restart-if-not" {
+        it "accepts --stdin-file" {
+            let path = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
             check_args(
-                &["shawl", "add", "--restart-if-not", "1,2", "--name", "foo", "--", "foo"],
+                &["shawl", "run", "--stdin-file", path, "--", "foo"],
                 Cli {
-                    sub: Subcommand::Add {
-                        name: s("foo"),
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
                         cwd: None,
-                        dependencies: vec![],
+                        foreground: false,
                         common: CommonOpts {
-                            restart_if_not: vec![1, 2],
+                            stdin_file: Some(p(path)),
                             command: vec![s("foo")],
                             ..Default::default()
                         }
@@ -763,17 +4532,25 @@ speculate::speculate! {
             );
         }
 
-        it "accepts --stop-timeout" {
+        it "rejects --stdin-file with --stdin-text" {
+            let path = concat!(env!("CARGO_MANIFEST_DIR"), "/Cargo.toml");
+            check_args_err(
+                &["shawl", "run", "--stdin-file", path, "--stdin-text", "Y", "--", "foo"],
+                clap::error::ErrorKind::ArgumentConflict,
+            );
+        }
+
+        it "accepts --shell" {
             check_args(
-                &["shawl", "add", "--stop-timeout", "500", "--name", "foo", "--", "foo"],
+                &["shawl", "run", "--shell", "cmd", "--", "echo hi && foo.exe"],
                 Cli {
-                    sub: Subcommand::Add {
-                        name: s("foo"),
+                    sub: Subcommand::Run {
+                        name: s("Shawl"),
                         cwd: None,
-                        dependencies: vec![],
+                        foreground: false,
                         common: CommonOpts {
-                            stop_timeout: Some(500),
-                            command: vec![s("foo")],
+                            shell: Some(Shell::Cmd),
+                            command: vec![s("echo hi && foo.exe")],
                             ..Default::default()
                         }
                     }
@@ -781,16 +4558,24 @@ speculate::speculate! {
             );
         }
 
-        it "accepts --no-log" {
+        it "rejects invalid --shell" {
+            check_args_err(
+                &["shawl", "run", "--shell", "bash", "--", "foo"],
+                clap::error::ErrorKind::InvalidValue,
+            );
+        }
+
+        it "accepts --no-interpreter" {
             check_args(
-                &["shawl", "run", "--no-log", "--", "foo"],
+                &["shawl", "run", "--no-interpreter", "--", "foo.bat"],
                 Cli {
                     sub: Subcommand::Run {
                         name: s("Shawl"),
                         cwd: None,
+                        foreground: false,
                         common: CommonOpts {
-                            no_log: true,
-                            command: vec![s("foo")],
+                            no_interpreter: true,
+                            command: vec![s("foo.bat")],
                             ..Default::default()
                         }
                     }
@@ -798,15 +4583,21 @@ speculate::speculate! {
             );
         }
 
-        it "accepts --no-log-cmd" {
+        it "accepts --on-resume" {
             check_args(
-                &["shawl", "run", "--no-log-cmd", "--", "foo"],
+                &["shawl", "add", "--on-resume", "restart", "--name", "foo", "--", "foo"],
                 Cli {
-                    sub: Subcommand::Run {
-                        name: s("Shawl"),
+                    sub: Subcommand::Add {
+                        name: s("foo"),
                         cwd: None,
+                        dependencies: vec![],
+                        no_verify: false,
+                        account: None,
+                        description: None,
+                        wizard: false,
+                        elevate: false,
                         common: CommonOpts {
-                            no_log_cmd: true,
+                            on_resume: Some(ResumeAction::Restart),
                             command: vec![s("foo")],
                             ..Default::default()
                         }
@@ -815,15 +4606,69 @@ speculate::speculate! {
             );
         }
 
-        it "accepts --log-as" {
+        it "rejects invalid --on-resume" {
+            check_args_err(
+                &["shawl", "add", "--on-resume", "reboot", "--name", "foo", "--", "foo"],
+                clap::error::ErrorKind::InvalidValue,
+            );
+        }
+
+        it "accepts --on-session-change" {
+            for (value, action) in [
+                ("log", SessionChangeAction::Log),
+                ("restart", SessionChangeAction::Restart),
+                ("run:notify.bat --user", SessionChangeAction::Run(s("notify.bat --user"))),
+            ] {
+                check_args(
+                    &["shawl", "add", "--on-session-change", value, "--name", "foo", "--", "foo"],
+                    Cli {
+                        sub: Subcommand::Add {
+                            name: s("foo"),
+                            cwd: None,
+                            dependencies: vec![],
+                            no_verify: false,
+                            account: None,
+                            description: None,
+                            wizard: false,
+                            elevate: false,
+                            common: CommonOpts {
+                                on_session_change: Some(action),
+                                command: vec![s("foo")],
+                                ..Default::default()
+                            }
+                        }
+                    },
+                );
+            }
+        }
+
+        it "accepts --on-control multiple times" {
             check_args(
-                &["shawl", "run", "--log-as", "foo", "--", "foo"],
+                &[
+                    "shawl", "add",
+                    "--on-control", "130=run:app.exe reload",
+                    "--on-control", "131=stdin:y",
+                    "--on-control", "132=restart",
+                    "--on-control", "133=log-level:info",
+                    "--name", "foo", "--", "foo",
+                ],
                 Cli {
-                    sub: Subcommand::Run {
-                        name: s("Shawl"),
+                    sub: Subcommand::Add {
+                        name: s("foo"),
                         cwd: None,
+                        dependencies: vec![],
+                        no_verify: false,
+                        account: None,
+                        description: None,
+                        wizard: false,
+                        elevate: false,
                         common: CommonOpts {
-                            log_as: Some("foo".to_string()),
+                            on_control: vec![
+                                (130, ControlAction::Run(s("app.exe reload"))),
+                                (131, ControlAction::Stdin(s("y"))),
+                                (132, ControlAction::Restart),
+                                (133, ControlAction::LogLevel(LogLevel::Info)),
+                            ],
                             command: vec![s("foo")],
                             ..Default::default()
                         }
@@ -832,15 +4677,47 @@ speculate::speculate! {
             );
         }
 
-        it "accepts --log-cmd-as" {
+        it "names the valid log levels for --on-control" {
+            let error = parse_control_mapping("130=log-level:verbose").unwrap_err().to_string();
+            assert!(error.contains("verbose"));
+            assert!(error.contains("error, warn, info, debug, trace"));
+            assert!(CliError::InvalidControlMapping { specification: s("130=reboot") }
+                .to_string()
+                .contains("`log-level:<level>`"));
+        }
+
+        it "rejects invalid --on-control" {
+            for value in ["130", "127=restart", "256=restart", "130=reboot", "130=run:", "130=log-level:verbose"] {
+                check_args_err(
+                    &["shawl", "add", "--on-control", value, "--name", "foo", "--", "foo"],
+                    clap::error::ErrorKind::ValueValidation,
+                );
+            }
+        }
+
+        it "accepts --sidecar multiple times" {
             check_args(
-                &["shawl", "run", "--log-cmd-as", "foo", "--", "foo"],
+                &[
+                    "shawl", "add",
+                    "--sidecar", "exporter.exe --port 9100",
+                    "--sidecar", "on-failure:C:/tools/agent.exe",
+                    "--name", "foo", "--", "foo",
+                ],
                 Cli {
-                    sub: Subcommand::Run {
-                        name: s("Shawl"),
+                    sub: Subcommand::Add {
+                        name: s("foo"),
                         cwd: None,
+                        dependencies: vec![],
+                        no_verify: false,
+                        account: None,
+                        description: None,
+                        wizard: false,
+                        elevate: false,
                         common: CommonOpts {
-                            log_cmd_as: Some("foo".to_string()),
+                            sidecar: vec![
+                                Sidecar { restart: SidecarRestart::Always, command: s("exporter.exe --port 9100") },
+                                Sidecar { restart: SidecarRestart::OnFailure, command: s("C:/tools/agent.exe") },
+                            ],
                             command: vec![s("foo")],
                             ..Default::default()
                         }
@@ -849,15 +4726,24 @@ speculate::speculate! {
             );
         }
 
-        it "accepts --log-rotate bytes=n" {
+        it "does not mistake a drive letter for a --sidecar restart policy" {
+            assert_eq!(
+                "C:/tools/agent.exe".parse::<Sidecar>(),
+                Ok(Sidecar { restart: SidecarRestart::Always, command: s("C:/tools/agent.exe") }),
+            );
+        }
+
+        it "accepts --hook-script" {
+            let path = env!("CARGO_MANIFEST_DIR");
             check_args(
-                &["shawl", "run", "--log-rotate", "bytes=123", "--", "foo"],
+                &["shawl", "run", "--hook-script", path, "--", "foo"],
                 Cli {
                     sub: Subcommand::Run {
                         name: s("Shawl"),
                         cwd: None,
+                        foreground: false,
                         common: CommonOpts {
-                            log_rotate: Some(LogRotation::Bytes(123)),
+                            hook_script: Some(p(path)),
                             command: vec![s("foo")],
                             ..Default::default()
                         }
@@ -866,15 +4752,17 @@ speculate::speculate! {
             );
         }
 
-        it "accepts --log-rotate daily" {
+        it "accepts --hook-exec" {
+            let path = env!("CARGO_MANIFEST_DIR");
             check_args(
-                &["shawl", "run", "--log-rotate", "daily", "--", "foo"],
+                &["shawl", "run", "--hook-exec", path, "--", "foo"],
                 Cli {
                     sub: Subcommand::Run {
                         name: s("Shawl"),
                         cwd: None,
+                        foreground: false,
                         common: CommonOpts {
-                            log_rotate: Some(LogRotation::Daily),
+                            hook_exec: Some(p(path)),
                             command: vec![s("foo")],
                             ..Default::default()
                         }
@@ -883,15 +4771,26 @@ speculate::speculate! {
             );
         }
 
-        it "accepts --log-rotate hourly" {
+        it "accepts --init multiple times" {
             check_args(
-                &["shawl", "run", "--log-rotate", "hourly", "--", "foo"],
+                &[
+                    "shawl", "add",
+                    "--init", "migrate.exe --up",
+                    "--init", "warmup.bat",
+                    "--name", "foo", "--", "foo",
+                ],
                 Cli {
-                    sub: Subcommand::Run {
-                        name: s("Shawl"),
+                    sub: Subcommand::Add {
+                        name: s("foo"),
                         cwd: None,
+                        dependencies: vec![],
+                        no_verify: false,
+                        account: None,
+                        description: None,
+                        wizard: false,
+                        elevate: false,
                         common: CommonOpts {
-                            log_rotate: Some(LogRotation::Hourly),
+                            init: vec![s("migrate.exe --up"), s("warmup.bat")],
                             command: vec![s("foo")],
                             ..Default::default()
                         }
@@ -900,15 +4799,21 @@ speculate::speculate! {
             );
         }
 
-        it "accepts --log-retain" {
+        it "accepts --wait-for-network without timeout" {
             check_args(
-                &["shawl", "run", "--log-retain", "5", "--", "foo"],
+                &["shawl", "add", "--wait-for-network", "--name", "foo", "--", "foo"],
                 Cli {
-                    sub: Subcommand::Run {
-                        name: s("Shawl"),
+                    sub: Subcommand::Add {
+                        name: s("foo"),
                         cwd: None,
+                        dependencies: vec![],
+                        no_verify: false,
+                        account: None,
+                        description: None,
+                        wizard: false,
+                        elevate: false,
                         common: CommonOpts {
-                            log_retain: Some(5),
+                            wait_for_network: Some(None),
                             command: vec![s("foo")],
                             ..Default::default()
                         }
@@ -917,16 +4822,21 @@ speculate::speculate! {
             );
         }
 
-        it "accepts --log-dir" {
-            let path = env!("CARGO_MANIFEST_DIR");
+        it "accepts --wait-for-network with timeout" {
             check_args(
-                &["shawl", "run", "--log-dir", path, "--", "foo"],
+                &["shawl", "add", "--wait-for-network", "90s", "--name", "foo", "--", "foo"],
                 Cli {
-                    sub: Subcommand::Run {
-                        name: s("Shawl"),
+                    sub: Subcommand::Add {
+                        name: s("foo"),
                         cwd: None,
+                        dependencies: vec![],
+                        no_verify: false,
+                        account: None,
+                        description: None,
+                        wizard: false,
+                        elevate: false,
                         common: CommonOpts {
-                            log_dir: Some(p(path)),
+                            wait_for_network: Some(Some(std::time::Duration::from_secs(90))),
                             command: vec![s("foo")],
                             ..Default::default()
                         }
@@ -935,15 +4845,17 @@ speculate::speculate! {
             );
         }
 
-        it "accepts --pass-start-args" {
+        it "accepts --wait-for-service-timeout" {
             check_args(
-                &["shawl", "run", "--pass-start-args", "--", "foo"],
+                &["shawl", "run", "--wait-for-service", "db", "--wait-for-service-timeout", "90s", "--", "foo"],
                 Cli {
                     sub: Subcommand::Run {
                         name: s("Shawl"),
                         cwd: None,
+                        foreground: false,
                         common: CommonOpts {
-                            pass_start_args: true,
+                            wait_for_service: vec![s("db")],
+                            wait_for_service_timeout: Some(std::time::Duration::from_secs(90)),
                             command: vec![s("foo")],
                             ..Default::default()
                         }
@@ -952,16 +4864,34 @@ speculate::speculate! {
             );
         }
 
-        it "accepts --env" {
+        it "requires --wait-for-service for --wait-for-service-timeout" {
+            check_args_err(
+                &["shawl", "run", "--wait-for-service-timeout", "90s", "--", "foo"],
+                clap::error::ErrorKind::MissingRequiredArgument,
+            );
+        }
+
+        it "accepts --wait-for-path" {
             check_args(
-                &["shawl", "add", "--env", "FOO=bar", "--name", "foo", "--", "foo"],
+                &[
+                    "shawl", "add",
+                    "--wait-for-path", "\\\\server\\share",
+                    "--wait-for-path-timeout", "5m",
+                    "--name", "foo", "--", "foo",
+                ],
                 Cli {
                     sub: Subcommand::Add {
                         name: s("foo"),
                         cwd: None,
                         dependencies: vec![],
+                        no_verify: false,
+                        account: None,
+                        description: None,
+                        wizard: false,
+                        elevate: false,
                         common: CommonOpts {
-                            env: vec![(s("FOO"), s("bar"))],
+                            wait_for_path: vec![s("\\\\server\\share")],
+                            wait_for_path_timeout: Some(std::time::Duration::from_secs(5 * 60)),
                             command: vec![s("foo")],
                             ..Default::default()
                         }
@@ -970,16 +4900,34 @@ speculate::speculate! {
             );
         }
 
-        it "accepts --env multiple times" {
+        it "rejects --wait-for-path-timeout without --wait-for-path" {
+            check_args_err(
+                &["shawl", "add", "--wait-for-path-timeout", "5m", "--name", "foo", "--", "foo"],
+                clap::error::ErrorKind::MissingRequiredArgument,
+            );
+        }
+
+        it "accepts --wait-for-service and --bind-to-service" {
             check_args(
-                &["shawl", "add", "--env", "FOO=1", "--env", "BAR=2", "--name", "foo", "--", "foo"],
+                &[
+                    "shawl", "add",
+                    "--wait-for-service", "Dnscache",
+                    "--bind-to-service", "postgresql",
+                    "--name", "foo", "--", "foo",
+                ],
                 Cli {
                     sub: Subcommand::Add {
                         name: s("foo"),
                         cwd: None,
                         dependencies: vec![],
+                        no_verify: false,
+                        account: None,
+                        description: None,
+                        wizard: false,
+                        elevate: false,
                         common: CommonOpts {
-                            env: vec![(s("FOO"), s("1")), (s("BAR"), s("2"))],
+                            wait_for_service: vec![s("Dnscache")],
+                            bind_to_service: vec![s("postgresql")],
                             command: vec![s("foo")],
                             ..Default::default()
                         }
@@ -988,17 +4936,35 @@ speculate::speculate! {
             );
         }
 
-        it "accepts --path" {
-            let path = env!("CARGO_MANIFEST_DIR");
+        it "rejects invalid --on-session-change" {
+            for value in ["reboot", "run:", "run=notify.bat"] {
+                check_args_err(
+                    &["shawl", "add", "--on-session-change", value, "--name", "foo", "--", "foo"],
+                    clap::error::ErrorKind::ValueValidation,
+                );
+            }
+        }
+
+        it "accepts --expand-env" {
             check_args(
-                &["shawl", "add", "--path", path, "--name", "foo", "--", "foo"],
+                &[
+                    "shawl", "add",
+                    "--expand-env",
+                    "--cwd", "%ProgramData%\\myapp",
+                    "--name", "foo", "--", "foo",
+                ],
                 Cli {
                     sub: Subcommand::Add {
                         name: s("foo"),
-                        cwd: None,
+                        cwd: Some(s("%ProgramData%\\myapp")),
                         dependencies: vec![],
+                        no_verify: false,
+                        account: None,
+                        description: None,
+                        wizard: false,
+                        elevate: false,
                         common: CommonOpts {
-                            path: vec![p(path)],
+                            expand_env: true,
                             command: vec![s("foo")],
                             ..Default::default()
                         }
@@ -1007,18 +4973,22 @@ speculate::speculate! {
             );
         }
 
-        it "accepts --path multiple times" {
-            let path1 = format!("{}/target", env!("CARGO_MANIFEST_DIR"));
-            let path2 = format!("{}/src", env!("CARGO_MANIFEST_DIR"));
+        it "accepts --cwd-create with a missing --cwd" {
+            let cwd = std::env::current_dir().unwrap().join("shawl-missing-dir");
             check_args(
-                &["shawl", "add", "--path", &path1, "--path", &path2, "--name", "foo", "--", "foo"],
+                &["shawl", "add", "--cwd-create", "--cwd", "shawl-missing-dir", "--name", "foo", "--", "foo"],
                 Cli {
                     sub: Subcommand::Add {
                         name: s("foo"),
-                        cwd: None,
+                        cwd: Some(cwd.to_string_lossy().to_string()),
                         dependencies: vec![],
+                        no_verify: false,
+                        account: None,
+                        description: None,
+                        wizard: false,
+                        elevate: false,
                         common: CommonOpts {
-                            path: vec![p(&path1), p(&path2)],
+                            cwd_create: true,
                             command: vec![s("foo")],
                             ..Default::default()
                         }
@@ -1027,41 +4997,289 @@ speculate::speculate! {
             );
         }
 
-        it "accepts --path-prepend" {
-            let path = env!("CARGO_MANIFEST_DIR");
+        it "rejects a missing --cwd without --cwd-create" {
+            assert_eq!(
+                prepare_add_cwd("C:\\shawl-missing-dir", false),
+                Err(s("The --cwd directory does not exist: C:\\shawl-missing-dir (use --cwd-create to create it)")),
+            );
+            assert_eq!(prepare_add_cwd("%ProgramData%\\myapp", false), Ok(s("%ProgramData%\\myapp")));
+        }
+    }
+
+    describe "remove subcommand" {
+        it "requires a name" {
+            check_args_err(&["shawl", "remove"], clap::error::ErrorKind::MissingRequiredArgument);
+        }
+
+        it "accepts a name" {
+            check_args(&["shawl", "remove", "my-app"], Cli { sub: Subcommand::Remove { name: s("my-app") } });
+        }
+    }
+
+    describe "status subcommand" {
+        it "requires a name" {
+            check_args_err(&["shawl", "status"], clap::error::ErrorKind::MissingRequiredArgument);
+        }
+
+        it "accepts --live" {
             check_args(
-                &["shawl", "add", "--path-prepend", path, "--name", "foo", "--", "foo"],
+                &["shawl", "status", "my-app", "--live"],
                 Cli {
-                    sub: Subcommand::Add {
-                        name: s("foo"),
-                        cwd: None,
-                        dependencies: vec![],
-                        common: CommonOpts {
-                            path_prepend: vec![p(path)],
-                            command: vec![s("foo")],
-                            ..Default::default()
-                        }
-                    }
+                    sub: Subcommand::Status {
+                        name: s("my-app"),
+                        live: true,
+                    },
                 },
             );
         }
+    }
 
-        it "accepts --path-prepend multiple times" {
-            let path1 = format!("{}/target", env!("CARGO_MANIFEST_DIR"));
-            let path2 = format!("{}/src", env!("CARGO_MANIFEST_DIR"));
+    describe "test subcommand" {
+        it "requires a name" {
+            check_args_err(&["shawl", "test"], clap::error::ErrorKind::MissingRequiredArgument);
+        }
+
+        it "accepts --duration" {
             check_args(
-                &["shawl", "add", "--path-prepend", &path1, "--path-prepend", &path2, "--name", "foo", "--", "foo"],
+                &["shawl", "test", "my-app", "--duration", "30s"],
+                Cli {
+                    sub: Subcommand::Test {
+                        name: s("my-app"),
+                        duration: Some(std::time::Duration::from_secs(30)),
+                    },
+                },
+            );
+        }
+    }
+
+    describe "firewall_allow" {
+        it "parses ports, ranges, and protocols" {
+            assert_eq!(
+                "8080".parse::<FirewallAllow>(),
+                Ok(FirewallAllow::Ports { start: 8080, end: 8080, protocol: FirewallProtocol::Tcp }),
+            );
+            assert_eq!(
+                "5000-5010/udp".parse::<FirewallAllow>(),
+                Ok(FirewallAllow::Ports { start: 5000, end: 5010, protocol: FirewallProtocol::Udp }),
+            );
+            assert_eq!("program".parse::<FirewallAllow>(), Ok(FirewallAllow::Program));
+        }
+
+        it "rejects invalid values" {
+            assert!("0".parse::<FirewallAllow>().is_err());
+            assert!("70000".parse::<FirewallAllow>().is_err());
+            assert!("5010-5000".parse::<FirewallAllow>().is_err());
+            assert!("8080/icmp".parse::<FirewallAllow>().is_err());
+        }
+
+        it "round-trips through the CLI format" {
+            for value in ["8080/tcp", "5000-5010/udp", "program"] {
+                assert_eq!(value.parse::<FirewallAllow>().unwrap().to_cli(), value);
+            }
+        }
+    }
+
+    describe "durations" {
+        it "parses each unit" {
+            assert_eq!(parse_duration("250"), Ok(std::time::Duration::from_millis(250)));
+            assert_eq!(parse_duration("250ms"), Ok(std::time::Duration::from_millis(250)));
+            assert_eq!(parse_duration("30s"), Ok(std::time::Duration::from_secs(30)));
+            assert_eq!(parse_duration("5m"), Ok(std::time::Duration::from_secs(5 * 60)));
+            assert_eq!(parse_duration("2h"), Ok(std::time::Duration::from_secs(2 * 60 * 60)));
+        }
+
+        it "rejects invalid values" {
+            assert!(parse_duration("").is_err());
+            assert!(parse_duration("h").is_err());
+            assert!(parse_duration("5 days").is_err());
+            assert!(parse_duration("18446744073709551615h").is_err());
+        }
+
+        it "formats with the largest whole unit" {
+            assert_eq!(duration_to_cli(std::time::Duration::from_secs(2 * 60 * 60)), "2h");
+            assert_eq!(duration_to_cli(std::time::Duration::from_secs(90)), "90s");
+            assert_eq!(duration_to_cli(std::time::Duration::from_millis(1500)), "1500ms");
+            assert_eq!(duration_to_cli(std::time::Duration::from_millis(0)), "0ms");
+        }
+    }
+
+    describe "exit code ranges" {
+        it "parses codes and ranges" {
+            assert_eq!("5".parse(), Ok(ExitCodeRange::from(5)));
+            assert_eq!("-1".parse(), Ok(ExitCodeRange::from(-1)));
+            assert_eq!("0xC0000005".parse(), Ok(ExitCodeRange::from(0xC0000005_u32 as i32)));
+            assert_eq!("100-199".parse(), Ok(ExitCodeRange { start: 100, end: 199 }));
+            assert_eq!("-10--1".parse(), Ok(ExitCodeRange { start: -10, end: -1 }));
+            assert_eq!("0x10-0x1F".parse(), Ok(ExitCodeRange { start: 16, end: 31 }));
+            assert_eq!("0-0xFFFFFFFF".parse(), Ok(ExitCodeRange { start: 0, end: -1 }));
+            assert_eq!("0xC0000000-0xCFFFFFFF".parse(), Ok(ExitCodeRange { start: 0xC0000000_u32 as i32, end: 0xCFFFFFFF_u32 as i32 }));
+        }
+
+        it "rejects invalid values" {
+            assert!("".parse::<ExitCodeRange>().is_err());
+            assert!("1-".parse::<ExitCodeRange>().is_err());
+            assert!("9-0".parse::<ExitCodeRange>().is_err());
+            assert!("-1-5".parse::<ExitCodeRange>().is_err());
+            assert!("0xZ".parse::<ExitCodeRange>().is_err());
+        }
+
+        it "checks whether a code is included" {
+            let ranges = vec![ExitCodeRange::from(0), ExitCodeRange { start: 100, end: 199 }];
+            assert!(ExitCodeRange::any_contains(&ranges, 0));
+            assert!(ExitCodeRange::any_contains(&ranges, 150));
+            assert!(!ExitCodeRange::any_contains(&ranges, 1));
+            assert!(!ExitCodeRange::any_contains(&ranges, 200));
+        }
+
+        it "compares codes as unsigned" {
+            let all = vec![ExitCodeRange { start: 0, end: -1 }];
+            assert!(ExitCodeRange::any_contains(&all, 0));
+            assert!(ExitCodeRange::any_contains(&all, -1));
+            assert!(ExitCodeRange::any_contains(&all, 0xC0000005_u32 as i32));
+
+            let ntstatus = vec![ExitCodeRange { start: 0xC0000000_u32 as i32, end: 0xCFFFFFFF_u32 as i32 }];
+            assert!(ExitCodeRange::any_contains(&ntstatus, 0xC0000005_u32 as i32));
+            assert!(!ExitCodeRange::any_contains(&ntstatus, -1));
+            assert!(!ExitCodeRange::any_contains(&ntstatus, 1));
+        }
+
+        it "formats for the command line" {
+            assert_eq!(ExitCodeRange::from(-1).to_cli(), "-1");
+            assert_eq!(ExitCodeRange { start: 100, end: 199 }.to_cli(), "100-199");
+        }
+    }
+
+    describe "log_directory" {
+        it "adds the subdirectory" {
+            let mut opts = CommonOpts {
+                log_dir: Some(s("logs")),
+                ..Default::default()
+            };
+            assert_eq!(opts.log_directory("web").unwrap(), std::path::PathBuf::from("logs"));
+            opts.log_subdir = Some(None);
+            assert_eq!(opts.log_directory("web").unwrap(), std::path::Path::new("logs").join("web"));
+            opts.log_subdir = Some(Some(s("apps-{service_name}")));
+            assert_eq!(opts.log_directory("web").unwrap(), std::path::Path::new("logs").join("apps-web"));
+        }
+    }
+
+    describe "redacted" {
+        it "masks env values and stdin text" {
+            let opts = CommonOpts {
+                env: vec![(s("USER"), s("app")), (s("PASSWORD"), s("hunter2"))],
+                stdin_text: Some(s("Y")),
+                command: vec![s("foo")],
+                ..Default::default()
+            };
+            assert_eq!(
+                opts.redacted(),
+                CommonOpts {
+                    env: vec![(s("USER"), s("***")), (s("PASSWORD"), s("***"))],
+                    stdin_text: Some(s("***")),
+                    command: vec![s("foo")],
+                    ..Default::default()
+                },
+            );
+        }
+
+
+        it "masks webhook paths" {
+            let opts = CommonOpts {
+                notify_slack: Some("https://hooks.slack.com/services/T00/B00/XXX".parse().unwrap()),
+                ..Default::default()
+            };
+            assert_eq!(
+                opts.redacted().notify_slack.map(|x| x.to_cli()),
+                Some(s("https://hooks.slack.com/***")),
+            );
+        }
+
+        it "masks stdin text for control codes" {
+            let opts = CommonOpts {
+                on_control: vec![(130, ControlAction::Stdin(s("hunter2"))), (131, ControlAction::Restart)],
+                ..Default::default()
+            };
+            assert_eq!(
+                opts.redacted().on_control,
+                vec![(130, ControlAction::Stdin(s("***"))), (131, ControlAction::Restart)],
+            );
+        }
+
+        it "masks options of either subcommand" {
+            let cli = Cli {
+                sub: Subcommand::Run {
+                    name: s("shawl"),
+                    cwd: None,
+                    foreground: false,
+                    common: CommonOpts {
+                        env: vec![(s("TOKEN"), s("abc123"))],
+                        ..Default::default()
+                    },
+                },
+            };
+            assert!(!format!("{:?}", cli.redacted()).contains("abc123"));
+        }
+    }
+
+    describe "run_options" {
+        it "lists the options with whether they take a value" {
+            let options = run_options();
+            assert!(options.contains(&(s("stop-timeout"), true)));
+            assert!(options.contains(&(s("log-dir"), true)));
+            assert!(options.contains(&(s("no-restart"), false)));
+        }
+    }
+
+    describe "expand_templates" {
+        it "fills in the placeholders for add" {
+            let mut opts = CommonOpts {
+                env: vec![(s("APP_HOME"), s("{cwd}")), (s("APP_NAME"), s("{name}-{name}"))],
+                command: vec![s("{shawl_dir}\\app.exe"), s("--id={name}"), s("{other}")],
+                ..Default::default()
+            };
+            let variables = vec![
+                ("{name}", s("web")),
+                ("{cwd}", s("C:\\Apps\\web")),
+                ("{shawl_dir}", s("C:\\Tools")),
+            ];
+            opts.expand_templates(&variables).unwrap();
+            assert_eq!(
+                opts.env,
+                vec![(s("APP_HOME"), s("C:\\Apps\\web")), (s("APP_NAME"), s("web-web"))],
+            );
+            assert_eq!(opts.command, vec![s("C:\\Tools\\app.exe"), s("--id=web"), s("{other}")]);
+        }
+
+        it "keeps escaped braces for the launch" {
+            let mut opts = CommonOpts {
+                command: vec![s("app.exe"), s("--format={{name}}"), s("{{{name}}}")],
+                ..Default::default()
+            };
+            opts.expand_templates(&[("{name}", s("web"))]).unwrap();
+            assert_eq!(opts.command, vec![s("app.exe"), s("--format={{name}}"), s("{{web}}")]);
+            opts.fill_placeholders(&[("{service_name}", s("web"))]);
+            assert_eq!(opts.command, vec![s("app.exe"), s("--format={name}"), s("{web}")]);
+        }
+
+        it "leaves placeholders in --log-dir for add" {
+            check_args(
+                &["shawl", "add", "--log-dir", "C:\\Logs\\{name}", "--name", "Shawl", "--", "foo"],
                 Cli {
                     sub: Subcommand::Add {
-                        name: s("foo"),
+                        name: s("Shawl"),
                         cwd: None,
                         dependencies: vec![],
+                        no_verify: false,
+                        account: None,
+                        description: None,
+                        wizard: false,
+                        elevate: false,
                         common: CommonOpts {
-                            path_prepend: vec![p(&path1), p(&path2)],
+                            log_dir: Some(s("C:\\Logs\\{name}")),
                             command: vec![s("foo")],
                             ..Default::default()
-                        }
-                    }
+                        },
+                    },
                 },
             );
         }
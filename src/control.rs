@@ -1,8 +1,74 @@
-use crate::cli::CommonOpts;
-use log::error;
+use crate::cli::{CommonOpts, FirewallAllow};
+use log::{error, warn};
 use std::io::Write;
 
-pub fn add_service(name: String, cwd: Option<String>, dependencies: &[String], opts: CommonOpts) -> Result<(), ()> {
+/// Why `shawl add` failed, which determines its exit code so that scripts can react.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AddError {
+    /// Any failure without a more specific code.
+    Other,
+    /// The options are invalid, like when clap rejects them.
+    InvalidArguments,
+    /// Shawl needs to run as an administrator.
+    AccessDenied,
+    /// There is already a service with the same name.
+    Duplicate,
+    /// The service manager can't be reached.
+    ScmUnavailable,
+}
+
+impl AddError {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Self::Other => 1,
+            Self::InvalidArguments => 2,
+            Self::AccessDenied => 3,
+            Self::Duplicate => 4,
+            Self::ScmUnavailable => 5,
+        }
+    }
+
+    /// Classify a Win32 error code, which is also what `sc` exits with.
+    fn from_win32(code: u32) -> Self {
+        match code {
+            // ERROR_ACCESS_DENIED
+            5 => Self::AccessDenied,
+            // ERROR_INVALID_PARAMETER, ERROR_INVALID_NAME, ERROR_INVALID_SERVICE_ACCOUNT,
+            // ERROR_CIRCULAR_DEPENDENCY, ERROR_SERVICE_DEPENDENCY_DELETED
+            87 | 123 | 1057 | 1059 | 1075 => Self::InvalidArguments,
+            // ERROR_SERVICE_MARKED_FOR_DELETE, ERROR_SERVICE_EXISTS, ERROR_DUPLICATE_SERVICE_NAME
+            1072 | 1073 | 1078 => Self::Duplicate,
+            // ERROR_SERVICE_DATABASE_LOCKED, ERROR_SHUTDOWN_IN_PROGRESS,
+            // RPC_S_SERVER_UNAVAILABLE, EPT_S_NOT_REGISTERED
+            1055 | 1115 | 1722 | 1753 => Self::ScmUnavailable,
+            _ => Self::Other,
+        }
+    }
+
+    fn from_service_error(error: &windows_service::Error) -> Self {
+        match error {
+            windows_service::Error::Winapi(e) => e.raw_os_error().map_or(Self::Other, |x| Self::from_win32(x as u32)),
+            _ => Self::Other,
+        }
+    }
+
+    fn from_windows_error(error: &windows::core::Error) -> Self {
+        crate::exitcode::win32_from_hresult(error.code().0).map_or(Self::Other, Self::from_win32)
+    }
+}
+
+/// Create a service that runs the current executable with `run` and the options,
+/// which must be done as an administrator.
+/// If any setup after creating the service fails, the service is deleted again.
+pub fn add_service(
+    name: String,
+    cwd: Option<String>,
+    dependencies: &[String],
+    account: Option<String>,
+    description: Option<String>,
+    no_verify: bool,
+    opts: CommonOpts,
+) -> Result<(), AddError> {
     let shawl_path = quote(
         &std::env::current_exe()
             .expect("Unable to determine Shawl location")
@@ -10,6 +76,60 @@ pub fn add_service(name: String, cwd: Option<String>, dependencies: &[String], o
     );
     let shawl_args = construct_shawl_run_args(&name, &cwd, &opts);
     let prepared_command = prepare_command(&opts.command);
+    // Only used here, so they're left out of the service's command line.
+    let mut firewall_allow = opts.firewall_allow.clone();
+    let mut urlacl = opts.urlacl.clone();
+    let mut command_line = format!("{} {}", shawl_path, shawl_args.join(" "));
+    if !prepared_command.is_empty() {
+        command_line.push_str(&format!(" -- {}", prepared_command.join(" ")));
+    }
+    let opts = match effective_options(&command_line) {
+        Ok(opts) => opts,
+        Err(e) => {
+            error!("Invalid options for the service: {}", e);
+            return Err(AddError::InvalidArguments);
+        }
+    };
+    for allow in &opts.firewall_allow {
+        if !firewall_allow.contains(allow) {
+            firewall_allow.push(*allow);
+        }
+    }
+    for url in &opts.urlacl {
+        if !urlacl.contains(url) {
+            urlacl.push(url.clone());
+        }
+    }
+
+    for path in &opts.args_file {
+        if let Ok(false) = crate::acl::is_admin_only(std::path::Path::new(path)) {
+            warn!(
+                "The service reads {} each time it starts, but accounts other than the administrators can change it",
+                path
+            );
+        }
+    }
+
+    let program = match &opts.command_line {
+        Some(command_line) => crate::service::split_program(command_line).0,
+        None => opts.command.first().cloned().unwrap_or_default(),
+    };
+    if let Err(e) = verify_program(&program, cwd.as_deref(), &opts) {
+        if no_verify {
+            warn!("{}", e);
+        } else {
+            error!("{} (use --no-verify to create the service anyway)", e);
+            return Err(AddError::InvalidArguments);
+        }
+    }
+    let firewall_program = firewall_program(&program, cwd.as_deref(), &opts);
+    if firewall_program.is_none() && !firewall_allow.is_empty() {
+        if firewall_allow.contains(&FirewallAllow::Program) {
+            error!("--firewall-allow program requires the command's program to be an executable that can be found");
+            return Err(AddError::InvalidArguments);
+        }
+        warn!("The command's program is a script or can't be found, so the firewall rules will allow any program on those ports");
+    }
 
     let mut cmd = std::process::Command::new("sc");
     cmd.arg("create").arg(&name);
@@ -18,44 +138,560 @@ pub fn add_service(name: String, cwd: Option<String>, dependencies: &[String], o
         cmd.arg("depend=");
         cmd.arg(quote(&dependencies.join("/")));
     }
+    if let Some(account) = &account {
+        cmd.arg("obj=").arg(account);
+    }
 
     let output = cmd
         .arg("binPath=")
-        .arg(format!(
-            "{} {} -- {}",
-            shawl_path,
-            shawl_args.join(" "),
-            prepared_command.join(" ")
-        ))
+        .arg(command_line)
         .output()
         .expect("Failed to create the service");
     match output.status.code() {
-        Some(0) => Ok(()),
+        Some(0) => {
+            let configure = || {
+                if let Some(description) = &description {
+                    if let Err(e) = set_description(&name, description) {
+                        error!("Failed to set the service's description: {:?}", e);
+                        return Err(AddError::from_service_error(&e));
+                    }
+                }
+                if let Some(timeout) = opts.preshutdown_timeout {
+                    if let Err(e) = set_preshutdown_timeout(&name, timeout) {
+                        error!("Failed to set the service's preshutdown timeout: {:?}", e);
+                        return Err(AddError::from_service_error(&e));
+                    }
+                }
+                if opts.event_log || opts.log_cmd_to.is_some() {
+                    if let Err(e) = crate::eventlog::register_source(&name) {
+                        error!("Failed to register the service as an event log source: {:?}", e);
+                        return Err(AddError::from_windows_error(&e));
+                    }
+                }
+                if opts.log_private {
+                    if let Err(e) = restrict_log_directory(&name, account.as_deref(), &opts) {
+                        error!("Failed to restrict access to the log directory: {}", e);
+                        return Err(AddError::Other);
+                    }
+                }
+                if opts.perf_counters {
+                    if let Err(e) = crate::perfcounters::register() {
+                        error!("Failed to register the performance counters: {}", e);
+                        return Err(AddError::Other);
+                    }
+                }
+                if let Err(e) = add_firewall_rules(&name, firewall_program.as_deref(), &firewall_allow) {
+                    error!("Failed to create the firewall rule: {}", e);
+                    return Err(AddError::Other);
+                }
+                if let Err(e) = reserve_urls(&name, account.as_deref(), &urlacl) {
+                    error!("Failed to reserve the URL: {}", e);
+                    return Err(AddError::Other);
+                }
+                Ok(())
+            };
+            let result = configure();
+            if result.is_err() {
+                // Don't leave behind a service that's only partly set up.
+                match remove_service(&name) {
+                    Ok(()) => warn!("Deleted the service since it could not be fully set up"),
+                    Err(e) => error!("Failed to delete the partly set up service: {}", e),
+                }
+            }
+            result
+        }
         Some(x) => {
             error!("Failed to create the service. Error code: {}.", x);
             error!("SC stdout:\n{}", String::from_utf8_lossy(&output.stdout));
             error!("SC stderr:\n{}", String::from_utf8_lossy(&output.stderr));
-            Err(())
+            let error = AddError::from_win32(x as u32);
+            if error == AddError::AccessDenied {
+                error!("Adding a service requires running Shawl as an administrator. Try again from an elevated terminal or with --elevate");
+            }
+            Err(error)
         }
         None => {
             error!("Failed to create the service. Output:");
             std::io::stderr().write_all(&output.stdout).unwrap();
             std::io::stderr().write_all(&output.stderr).unwrap();
-            Err(())
+            Err(AddError::Other)
+        }
+    }
+}
+
+/// Stop and delete a service, along with the firewall rules and URL reservations
+/// that `shawl add` created for it,
+/// which must be done as an administrator.
+/// The service is deleted even if the cleanup fails, which is then reported in the error.
+pub fn remove_service(name: &str) -> Result<(), String> {
+    use windows_service::{
+        service::{ServiceAccess, ServiceState},
+        service_manager::{ServiceManager, ServiceManagerAccess},
+    };
+
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)
+        .map_err(|e| format!("Unable to connect to the service manager: {}", e))?;
+    let service = manager
+        .open_service(
+            name,
+            ServiceAccess::QUERY_STATUS | ServiceAccess::STOP | ServiceAccess::DELETE,
+        )
+        .map_err(|e| format!("Unable to open service {}: {}", name, e))?;
+
+    let urls = recorded_urls(name);
+
+    let stopped = |service: &windows_service::service::Service| {
+        service
+            .query_status()
+            .map(|status| status.current_state == ServiceState::Stopped)
+            .map_err(|e| format!("Unable to query service {}: {}", name, e))
+    };
+    if !stopped(&service)? {
+        println!("Stopping {}", name);
+        if let Err(e) = service.stop() {
+            // It may have stopped on its own in the meantime.
+            if !stopped(&service)? {
+                return Err(format!("Unable to stop service {}: {}", name, e));
+            }
+        }
+        let deadline = std::time::Instant::now() + REMOVE_STOP_TIMEOUT;
+        while !stopped(&service)? {
+            if std::time::Instant::now() >= deadline {
+                return Err(format!("Timed out waiting for service {} to stop", name));
+            }
+            std::thread::sleep(std::time::Duration::from_millis(250));
+        }
+    }
+    service
+        .delete()
+        .map_err(|e| format!("Unable to delete service {}: {}", name, e))?;
+
+    let mut failures = vec![];
+    if let Err(e) = remove_firewall_rules(name) {
+        failures.push(format!("Failed to delete the firewall rules: {}", e));
+    }
+    if let Err(e) = urls.map_err(|e| e.to_string()).and_then(|urls| release_urls(&urls)) {
+        failures.push(format!("Failed to delete the URL reservations: {}", e));
+    }
+    if failures.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "Deleted service {}, but not everything that was set up for it:\n{}",
+            name,
+            failures.join("\n")
+        ))
+    }
+}
+
+/// How long `shawl remove` waits for the service to stop before giving up.
+const REMOVE_STOP_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(60);
+
+fn set_preshutdown_timeout(name: &str, timeout: u64) -> windows_service::Result<()> {
+    use windows_service::{
+        service::ServiceAccess,
+        service_manager::{ServiceManager, ServiceManagerAccess},
+    };
+
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+    let service = manager.open_service(name, ServiceAccess::CHANGE_CONFIG)?;
+    // Leave some room for Shawl itself to report the final status.
+    service.set_preshutdown_timeout(std::time::Duration::from_millis(timeout + 1000))
+}
+
+fn set_description(name: &str, description: &str) -> windows_service::Result<()> {
+    use windows_service::{
+        service::ServiceAccess,
+        service_manager::{ServiceManager, ServiceManagerAccess},
+    };
+
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+    let service = manager.open_service(name, ServiceAccess::CHANGE_CONFIG)?;
+    service.set_description(description)
+}
+
+/// The SID of the service's account, which may be a virtual account
+/// that only exists once the service has been created.
+fn service_account_sid(account: Option<&str>) -> Result<String, String> {
+    match account {
+        Some(account) => crate::acl::account_sid(account),
+        None => Ok(crate::acl::LOCAL_SYSTEM.to_string()),
+    }
+}
+
+/// Apply `--log-private` for the service's account, which only restricts the service's log subdirectory.
+fn restrict_log_directory(name: &str, account: Option<&str>, opts: &CommonOpts) -> Result<(), String> {
+    if opts.log_subdir.is_none() {
+        return Err("--log-private requires --log-subdir".to_string());
+    }
+    let sid = service_account_sid(account)?;
+    let dir = opts
+        .log_directory(name)
+        .map_err(|e| format!("Unable to determine the log directory: {}", e))?;
+    crate::acl::restrict(&dir, &sid)
+}
+
+/// Name of the firewall rules for `--firewall-allow`, which all share it so that they can be deleted together.
+pub fn firewall_rule_name(name: &str) -> String {
+    format!("{} (Shawl)", name)
+}
+
+/// Arguments for `netsh` to create an inbound rule, scoped to the program when it's known.
+fn firewall_rule_args(name: &str, program: Option<&std::path::Path>, allow: FirewallAllow) -> Vec<String> {
+    let mut args: Vec<String> = ["advfirewall", "firewall", "add", "rule"]
+        .iter()
+        .map(|x| x.to_string())
+        .collect();
+    args.push(format!("name={}", firewall_rule_name(name)));
+    args.extend(["dir=in", "action=allow", "enable=yes"].iter().map(|x| x.to_string()));
+    if let Some(program) = program {
+        args.push(format!("program={}", program.display()));
+    }
+    if let FirewallAllow::Ports { start, end, protocol } = allow {
+        args.push(format!("protocol={}", protocol.to_cli()));
+        if start == end {
+            args.push(format!("localport={}", start));
+        } else {
+            args.push(format!("localport={}-{}", start, end));
+        }
+    }
+    args
+}
+
+/// The executable to scope the firewall rules to. Scripts listen through their interpreter,
+/// which other scripts share, so they can only be allowed by port.
+fn firewall_program(program: &str, cwd: Option<&str>, opts: &CommonOpts) -> Option<std::path::PathBuf> {
+    if opts.shell.is_some() || program.contains('%') || has_launch_placeholder(program) {
+        return None;
+    }
+    let (path, extensions) = program_search(opts);
+    crate::env::resolve_program(program, cwd, Some(&path), &extensions)
+        .filter(|x| crate::service::script_kind(&x.to_string_lossy()).is_none())
+}
+
+fn netsh(args: &[String]) -> Result<(), String> {
+    let output = std::process::Command::new("netsh")
+        .args(args)
+        .output()
+        .map_err(|e| format!("Unable to run netsh: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "netsh failed with code {:?}: {}",
+            output.status.code(),
+            String::from_utf8_lossy(&output.stdout).trim()
+        ));
+    }
+    Ok(())
+}
+
+fn add_firewall_rules(name: &str, program: Option<&std::path::Path>, allows: &[FirewallAllow]) -> Result<(), String> {
+    for allow in allows {
+        netsh(&firewall_rule_args(name, program, *allow))?;
+    }
+    Ok(())
+}
+
+/// Arguments for `netsh` to show or delete all of the service's firewall rules.
+fn firewall_rules_args(name: &str, action: &str) -> Vec<String> {
+    vec![
+        "advfirewall".to_string(),
+        "firewall".to_string(),
+        action.to_string(),
+        "rule".to_string(),
+        format!("name={}", firewall_rule_name(name)),
+    ]
+}
+
+fn remove_firewall_rules(name: &str) -> Result<(), String> {
+    // `show` fails when no rule has the name, which is the case for most services.
+    if netsh(&firewall_rules_args(name, "show")).is_err() {
+        return Ok(());
+    }
+    netsh(&firewall_rules_args(name, "delete"))
+}
+
+/// Arguments for `netsh` to let an account listen on a URL through HTTP.sys.
+/// The account is given by SID so that virtual accounts and `.\name` work too.
+fn urlacl_args(url: &str, sid: &str) -> Vec<String> {
+    vec![
+        "http".to_string(),
+        "add".to_string(),
+        "urlacl".to_string(),
+        format!("url={}", url),
+        format!("sddl=D:(A;;GX;;;{})", sid),
+    ]
+}
+
+/// Apply `--urlacl` for the service's account, recording each reservation
+/// in the service's registry key so that `shawl remove` can delete it.
+fn reserve_urls(name: &str, account: Option<&str>, urls: &[String]) -> Result<(), String> {
+    if urls.is_empty() {
+        return Ok(());
+    }
+    let sid = service_account_sid(account)?;
+    let mut reserved = vec![];
+    for url in urls {
+        let result = netsh(&urlacl_args(url, &sid));
+        if result.is_ok() {
+            reserved.push(url.clone());
+        }
+        if let Err(e) = record_urls(name, &reserved) {
+            return Err(format!("Unable to record the URL reservations for removal: {}", e));
+        }
+        result?;
+    }
+    Ok(())
+}
+
+/// Delete the URL reservations that `shawl add` recorded for the service.
+fn release_urls(urls: &[String]) -> Result<(), String> {
+    for url in urls {
+        netsh(&[
+            "http".to_string(),
+            "delete".to_string(),
+            "urlacl".to_string(),
+            format!("url={}", url),
+        ])?;
+    }
+    Ok(())
+}
+
+/// Registry value under the service's key that lists the URLs reserved for it,
+/// which Windows deletes along with the service.
+const URLACL_VALUE: &str = "ShawlUrlAcl";
+
+fn service_key(name: &str) -> String {
+    format!("SYSTEM\\CurrentControlSet\\Services\\{}", name)
+}
+
+fn record_urls(name: &str, urls: &[String]) -> windows::core::Result<()> {
+    use windows::{
+        core::HSTRING,
+        Win32::System::Registry::{
+            RegCloseKey, RegOpenKeyExW, RegSetValueExW, HKEY, HKEY_LOCAL_MACHINE, KEY_SET_VALUE, REG_MULTI_SZ,
+        },
+    };
+
+    unsafe {
+        let mut key = HKEY::default();
+        RegOpenKeyExW(
+            HKEY_LOCAL_MACHINE,
+            &HSTRING::from(service_key(name)),
+            0,
+            KEY_SET_VALUE,
+            &mut key,
+        )
+        .ok()?;
+        let data: Vec<u8> = encode_multi_sz(urls)
+            .into_iter()
+            .flat_map(|x| x.to_le_bytes())
+            .collect();
+        let result = RegSetValueExW(key, &HSTRING::from(URLACL_VALUE), 0, REG_MULTI_SZ, Some(&data)).ok();
+        let _ = RegCloseKey(key);
+        result
+    }
+}
+
+fn recorded_urls(name: &str) -> windows::core::Result<Vec<String>> {
+    use windows::{
+        core::HSTRING,
+        Win32::{
+            Foundation::ERROR_FILE_NOT_FOUND,
+            System::Registry::{RegGetValueW, HKEY_LOCAL_MACHINE, RRF_RT_REG_MULTI_SZ},
+        },
+    };
+
+    let key = HSTRING::from(service_key(name));
+    let value = HSTRING::from(URLACL_VALUE);
+    unsafe {
+        let mut size = 0u32;
+        let result = RegGetValueW(
+            HKEY_LOCAL_MACHINE,
+            &key,
+            &value,
+            RRF_RT_REG_MULTI_SZ,
+            None,
+            None,
+            Some(&mut size),
+        );
+        if result == ERROR_FILE_NOT_FOUND {
+            return Ok(vec![]);
+        }
+        result.ok()?;
+        let mut data = vec![0u16; (size as usize).div_ceil(2)];
+        RegGetValueW(
+            HKEY_LOCAL_MACHINE,
+            &key,
+            &value,
+            RRF_RT_REG_MULTI_SZ,
+            None,
+            Some(data.as_mut_ptr().cast()),
+            Some(&mut size),
+        )
+        .ok()?;
+        data.truncate(size as usize / 2);
+        Ok(decode_multi_sz(&data))
+    }
+}
+
+/// Encode strings as a `REG_MULTI_SZ`, each ending in a null, followed by another null.
+fn encode_multi_sz(items: &[String]) -> Vec<u16> {
+    let mut data: Vec<u16> = items
+        .iter()
+        .flat_map(|x| x.encode_utf16().chain(std::iter::once(0)))
+        .collect();
+    if data.is_empty() {
+        data.push(0);
+    }
+    data.push(0);
+    data
+}
+
+fn decode_multi_sz(data: &[u16]) -> Vec<String> {
+    data.split(|x| *x == 0)
+        .filter(|x| !x.is_empty())
+        .map(String::from_utf16_lossy)
+        .collect()
+}
+
+/// The options that the service will run with, including any from `shawl.toml`,
+/// since some of them need setup when the service is added.
+fn effective_options(command_line: &str) -> Result<CommonOpts, String> {
+    use clap::Parser;
+
+    let args = split_command_line(command_line).into_iter().map(Into::into).collect();
+    let args = crate::argsfile::expand(args)?;
+    let args = crate::defaults::load().and_then(|defaults| crate::defaults::apply(args, &defaults))?;
+    match crate::cli::Cli::try_parse_from(args).map_err(|e| e.to_string())?.sub {
+        crate::cli::Subcommand::Run { common, .. } => Ok(common),
+        _ => Err("not a `shawl run` command line".to_string()),
+    }
+}
+
+/// The `PATH` and `PATHEXT` that the service will search for the command's program.
+fn program_search(opts: &CommonOpts) -> (String, String) {
+    let mut path = opts.path_prepend.clone();
+    path.extend(std::env::var("PATH").ok());
+    path.extend(opts.path.iter().cloned());
+    let extensions = std::env::var("PATHEXT").unwrap_or_else(|_| ".COM;.EXE;.BAT;.CMD".to_string());
+    (path.join(";"), extensions)
+}
+
+/// Check for placeholders that `shawl run` fills in when the command launches.
+fn has_launch_placeholder(text: &str) -> bool {
+    crate::service::LAUNCH_PLACEHOLDERS.iter().any(|x| text.contains(x))
+}
+
+/// Find the command's program the same way as when the service starts,
+/// so that a typo is caught now instead of when the service fails to start.
+fn verify_program(program: &str, cwd: Option<&str>, opts: &CommonOpts) -> Result<(), String> {
+    if opts.shell.is_some() || program.contains('%') || has_launch_placeholder(program) {
+        // Shell built-ins, environment variables, and placeholders can only be resolved by the service.
+        return Ok(());
+    }
+
+    let (path, extensions) = program_search(opts);
+    let Some(resolved) = crate::env::resolve_program(program, cwd, Some(&path), &extensions) else {
+        let mut message = format!("Unable to find the command's program: {}", program);
+        if let Some(script) = crate::env::resolve_program(program, cwd, Some(&path), ".PS1;.PY;.PYW") {
+            message.push_str(&format!(
+                "; did you mean {}? Scripts need their extension so that they run through their interpreter",
+                script.display()
+            ));
+        }
+        return Err(message);
+    };
+
+    let Some(kind) = crate::service::script_kind(&resolved.to_string_lossy()) else {
+        return Ok(());
+    };
+    if opts.no_interpreter {
+        if kind != crate::service::ScriptKind::Batch {
+            return Err(format!(
+                "{} is a {:?} script, which Windows cannot run directly without its interpreter, so remove --no-interpreter",
+                resolved.display(),
+                kind
+            ));
         }
+    } else if crate::env::resolve_program(kind.interpreter(), None, Some(&path), &extensions).is_none() {
+        return Err(format!(
+            "{} is a {:?} script, but its interpreter, {}, cannot be found",
+            resolved.display(),
+            kind,
+            kind.interpreter()
+        ));
     }
+    Ok(())
 }
 
 fn construct_shawl_run_args(name: &str, cwd: &Option<String>, opts: &CommonOpts) -> Vec<String> {
     let mut shawl_args = vec!["run".to_string(), "--name".to_string(), quote(name)];
+    if let Some(profile) = &opts.profile {
+        shawl_args.push("--profile".to_string());
+        shawl_args.push(quote(profile));
+    }
+    if opts.defaults_from_env {
+        shawl_args.push("--defaults-from-env".to_string());
+    }
+    for path in &opts.args_file {
+        shawl_args.push("--args-file".to_string());
+        shawl_args.push(join_command_line(std::slice::from_ref(path)));
+    }
     if let Some(delay) = opts.restart_delay {
         shawl_args.push("--restart-delay".to_string());
         shawl_args.push(delay.to_string());
     }
+    if let Some(every) = opts.restart_every {
+        shawl_args.push("--restart-every".to_string());
+        shawl_args.push(crate::cli::duration_to_cli(every));
+    }
+    if let Some(interval) = opts.interval {
+        shawl_args.push("--interval".to_string());
+        shawl_args.push(crate::cli::duration_to_cli(interval));
+    }
+    if let Some(instances) = opts.instances {
+        shawl_args.push("--instances".to_string());
+        shawl_args.push(instances.to_string());
+    }
+    if opts.restart_on_update {
+        shawl_args.push("--restart-on-update".to_string());
+    }
+    for path in &opts.watch {
+        shawl_args.push("--watch".to_string());
+        shawl_args.push(quote(path));
+    }
+    if let Some(debounce) = opts.watch_debounce {
+        shawl_args.push("--watch-debounce".to_string());
+        shawl_args.push(crate::cli::duration_to_cli(debounce));
+    }
+    if let Some(retries) = opts.spawn_retries {
+        shawl_args.push("--spawn-retries".to_string());
+        shawl_args.push(retries.to_string());
+    }
+    if let Some(delay) = opts.stop_delay {
+        shawl_args.push("--stop-delay".to_string());
+        shawl_args.push(delay.to_string());
+    }
+    if let Some(action) = opts.on_duplicate {
+        shawl_args.push("--on-duplicate".to_string());
+        shawl_args.push(action.to_cli());
+    }
+    if let Some(method) = opts.stop_method {
+        shawl_args.push("--stop-method".to_string());
+        shawl_args.push(method.to_cli());
+    }
+    if let Some(method) = opts.kill_method {
+        shawl_args.push("--kill-method".to_string());
+        shawl_args.push(method.to_cli());
+    }
     if let Some(st) = opts.stop_timeout {
         shawl_args.push("--stop-timeout".to_string());
         shawl_args.push(st.to_string());
     }
+    if let Some(timeout) = opts.preshutdown_timeout {
+        shawl_args.push("--preshutdown-timeout".to_string());
+        shawl_args.push(timeout.to_string());
+    }
     if opts.restart {
         shawl_args.push("--restart".to_string());
     }
@@ -67,7 +703,7 @@ fn construct_shawl_run_args(name: &str, cwd: &Option<String>, opts: &CommonOpts)
         shawl_args.push(
             opts.restart_if
                 .iter()
-                .map(|x| x.to_string())
+                .map(|x| x.to_cli())
                 .collect::<Vec<String>>()
                 .join(","),
         );
@@ -77,14 +713,24 @@ fn construct_shawl_run_args(name: &str, cwd: &Option<String>, opts: &CommonOpts)
         shawl_args.push(
             opts.restart_if_not
                 .iter()
-                .map(|x| x.to_string())
+                .map(|x| x.to_cli())
                 .collect::<Vec<String>>()
                 .join(","),
         );
     };
+    if !opts.stop_if.is_empty() {
+        shawl_args.push("--stop-if".to_string());
+        shawl_args.push(
+            opts.stop_if
+                .iter()
+                .map(|x| x.to_cli())
+                .collect::<Vec<String>>()
+                .join(","),
+        );
+    }
     if let Some(pass) = &opts.pass {
         shawl_args.push("--pass".to_string());
-        shawl_args.push(pass.iter().map(|x| x.to_string()).collect::<Vec<String>>().join(","));
+        shawl_args.push(pass.iter().map(|x| x.to_cli()).collect::<Vec<String>>().join(","));
     }
     if let Some(cwd) = &cwd {
         shawl_args.push("--cwd".to_string());
@@ -100,14 +746,87 @@ fn construct_shawl_run_args(name: &str, cwd: &Option<String>, opts: &CommonOpts)
         shawl_args.push("--log-dir".to_string());
         shawl_args.push(quote(log_dir));
     }
+    if let Some(subdir) = &opts.log_subdir {
+        shawl_args.push("--log-subdir".to_string());
+        if let Some(subdir) = subdir {
+            shawl_args.push(quote(subdir));
+        }
+    }
+    if opts.log_private {
+        shawl_args.push("--log-private".to_string());
+    }
     if let Some(log_as) = &opts.log_as {
         shawl_args.push("--log-as".to_string());
         shawl_args.push(quote(log_as));
     }
+    if let Some(pattern) = &opts.log_name_pattern {
+        shawl_args.push("--log-name-pattern".to_string());
+        shawl_args.push(quote(pattern));
+    }
     if let Some(log_cmd_as) = &opts.log_cmd_as {
         shawl_args.push("--log-cmd-as".to_string());
         shawl_args.push(quote(log_cmd_as));
     }
+    if opts.log_cmd_split {
+        shawl_args.push("--log-cmd-split".to_string());
+    }
+    if let Some(level) = opts.log_level {
+        shawl_args.push("--log-level".to_string());
+        shawl_args.push(level.to_cli());
+    }
+    if let Some(level) = opts.log_stdout_level {
+        shawl_args.push("--log-stdout-level".to_string());
+        shawl_args.push(level.to_cli());
+    }
+    if let Some(level) = opts.log_stderr_level {
+        shawl_args.push("--log-stderr-level".to_string());
+        shawl_args.push(level.to_cli());
+    }
+    for pattern in &opts.log_filter_include {
+        shawl_args.push("--log-filter-include".to_string());
+        shawl_args.push(join_command_line(std::slice::from_ref(pattern)));
+    }
+    for pattern in &opts.log_filter_exclude {
+        shawl_args.push("--log-filter-exclude".to_string());
+        shawl_args.push(join_command_line(std::slice::from_ref(pattern)));
+    }
+    for pattern in &opts.log_redact {
+        shawl_args.push("--log-redact".to_string());
+        shawl_args.push(join_command_line(std::slice::from_ref(pattern)));
+    }
+    for credential in &opts.log_redact_secret {
+        shawl_args.push("--log-redact-secret".to_string());
+        shawl_args.push(join_command_line(std::slice::from_ref(credential)));
+    }
+    if let Some(endpoint) = &opts.log_gelf {
+        shawl_args.push("--log-gelf".to_string());
+        shawl_args.push(endpoint.to_cli());
+    }
+    if let Some(limit) = opts.log_rate_limit {
+        shawl_args.push("--log-rate-limit".to_string());
+        shawl_args.push(limit.to_string());
+    }
+    if let Some(interval) = opts.log_rate_interval {
+        shawl_args.push("--log-rate-interval".to_string());
+        shawl_args.push(crate::cli::duration_to_cli(interval));
+    }
+    if opts.log_collapse_repeats {
+        shawl_args.push("--log-collapse-repeats".to_string());
+    }
+    if opts.log_group_indented {
+        shawl_args.push("--log-group-indented".to_string());
+    }
+    for pattern in &opts.log_group_pattern {
+        shawl_args.push("--log-group-pattern".to_string());
+        shawl_args.push(join_command_line(std::slice::from_ref(pattern)));
+    }
+    if let Some(target) = opts.log_cmd_to {
+        shawl_args.push("--log-cmd-to".to_string());
+        shawl_args.push(target.to_cli());
+    }
+    if opts.echo_output {
+        shawl_args.push("--echo-output".to_string());
+    }
     if let Some(log_rotate) = &opts.log_rotate {
         shawl_args.push("--log-rotate".to_string());
         shawl_args.push(log_rotate.to_cli());
@@ -116,15 +835,68 @@ fn construct_shawl_run_args(name: &str, cwd: &Option<String>, opts: &CommonOpts)
         shawl_args.push("--log-retain".to_string());
         shawl_args.push(log_retain.to_string());
     }
+    if let Some(log_max_total) = &opts.log_max_total {
+        shawl_args.push("--log-max-total".to_string());
+        shawl_args.push(log_max_total.to_string());
+    }
+    if let Some(interval) = opts.log_flush_interval {
+        shawl_args.push("--log-flush-interval".to_string());
+        shawl_args.push(crate::cli::duration_to_cli(interval));
+    }
+    if let Some(log_format) = opts.log_format {
+        shawl_args.push("--log-format".to_string());
+        shawl_args.push(log_format.to_cli());
+    }
+    if opts.log_utc {
+        shawl_args.push("--log-utc".to_string());
+    }
+    if opts.log_rfc3339 {
+        shawl_args.push("--log-rfc3339".to_string());
+    }
+    if opts.expand_env {
+        shawl_args.push("--expand-env".to_string());
+    }
+    if opts.cwd_create {
+        shawl_args.push("--cwd-create".to_string());
+    }
     if opts.pass_start_args {
         shawl_args.push("--pass-start-args".to_string());
     }
+    if let Some(command_line) = &opts.command_line {
+        shawl_args.push("--command-line".to_string());
+        shawl_args.push(quote_exactly(command_line));
+    }
     if !opts.env.is_empty() {
         for (x, y) in &opts.env {
             shawl_args.push("--env".to_string());
             shawl_args.push(quote(&format!("{}={}", x, y)));
         }
     }
+    if let Some(env_file) = &opts.env_file {
+        shawl_args.push("--env-file".to_string());
+        shawl_args.push(quote(env_file));
+    }
+    for name in &opts.unset_env {
+        shawl_args.push("--unset-env".to_string());
+        shawl_args.push(quote(name));
+    }
+    if opts.clean_env {
+        shawl_args.push("--clean-env".to_string());
+    }
+    for name in &opts.inherit_env {
+        shawl_args.push("--inherit-env".to_string());
+        shawl_args.push(quote(name));
+    }
+    if opts.refresh_env {
+        shawl_args.push("--refresh-env".to_string());
+    }
+    if opts.load_profile {
+        shawl_args.push("--load-profile".to_string());
+    }
+    for (key, credential) in &opts.env_secret {
+        shawl_args.push("--env-secret".to_string());
+        shawl_args.push(quote(&format!("{}={}", key, credential)));
+    }
     if !opts.path.is_empty() {
         for path in &opts.path {
             shawl_args.push("--path".to_string());
@@ -137,10 +909,146 @@ fn construct_shawl_run_args(name: &str, cwd: &Option<String>, opts: &CommonOpts)
             shawl_args.push(quote(path));
         }
     }
+    if let Some(shell) = opts.shell {
+        shawl_args.push("--shell".to_string());
+        shawl_args.push(shell.to_cli());
+    }
+    if opts.no_interpreter {
+        shawl_args.push("--no-interpreter".to_string());
+    }
+    if let Some(stdin_file) = &opts.stdin_file {
+        shawl_args.push("--stdin-file".to_string());
+        shawl_args.push(quote(stdin_file));
+    }
+    if let Some(stdin_text) = &opts.stdin_text {
+        shawl_args.push("--stdin-text".to_string());
+        shawl_args.push(quote(stdin_text));
+    }
+    if let Some(encoding) = opts.cmd_encoding {
+        shawl_args.push("--cmd-encoding".to_string());
+        shawl_args.push(encoding.to_cli());
+    }
+    if let Some(path) = &opts.capture_raw {
+        shawl_args.push("--capture-raw".to_string());
+        shawl_args.push(quote(path));
+    }
+    if let Some(bytes) = opts.capture_raw_rotate {
+        shawl_args.push("--capture-raw-rotate".to_string());
+        shawl_args.push(bytes.to_string());
+    }
+    if let Some(codepage) = opts.console_codepage {
+        shawl_args.push("--console-codepage".to_string());
+        shawl_args.push(codepage.to_string());
+    }
+    if opts.hide_window {
+        shawl_args.push("--hide-window".to_string());
+    }
+    if opts.interactive_session {
+        shawl_args.push("--interactive-session".to_string());
+    }
+    for mitigation in &opts.mitigation {
+        shawl_args.push("--mitigation".to_string());
+        shawl_args.push(mitigation.to_cli());
+    }
+    if let Some(crash_dump_dir) = &opts.crash_dump_dir {
+        shawl_args.push("--crash-dump-dir".to_string());
+        shawl_args.push(quote(crash_dump_dir));
+    }
+    if opts.event_log {
+        shawl_args.push("--event-log".to_string());
+    }
+    if let Some(url) = &opts.notify_slack {
+        shawl_args.push("--notify-slack".to_string());
+        shawl_args.push(quote(&url.to_cli()));
+    }
+    if let Some(url) = &opts.notify_teams {
+        shawl_args.push("--notify-teams".to_string());
+        shawl_args.push(quote(&url.to_cli()));
+    }
+    if let Some(url) = &opts.heartbeat_url {
+        shawl_args.push("--heartbeat-url".to_string());
+        shawl_args.push(quote(&url.to_cli()));
+    }
+    if let Some(interval) = opts.heartbeat_interval {
+        shawl_args.push("--heartbeat-interval".to_string());
+        shawl_args.push(crate::cli::duration_to_cli(interval));
+    }
+    if let Some(url) = &opts.otlp_endpoint {
+        shawl_args.push("--otlp-endpoint".to_string());
+        shawl_args.push(quote(&url.to_cli()));
+    }
+    if opts.otlp_output {
+        shawl_args.push("--otlp-output".to_string());
+    }
+    if opts.perf_counters {
+        shawl_args.push("--perf-counters".to_string());
+    }
+    if let Some(path) = &opts.status_file {
+        shawl_args.push("--status-file".to_string());
+        shawl_args.push(quote(path));
+    }
+    if let Some(path) = &opts.event_file {
+        shawl_args.push("--event-file".to_string());
+        shawl_args.push(quote(path));
+    }
     if let Some(priority) = opts.priority {
         shawl_args.push("--priority".to_string());
         shawl_args.push(priority.to_cli());
     }
+    if let Some(on_resume) = opts.on_resume {
+        shawl_args.push("--on-resume".to_string());
+        shawl_args.push(on_resume.to_cli());
+    }
+    if let Some(on_session_change) = &opts.on_session_change {
+        shawl_args.push("--on-session-change".to_string());
+        shawl_args.push(join_command_line(&[on_session_change.to_cli()]));
+    }
+    for (code, action) in &opts.on_control {
+        shawl_args.push("--on-control".to_string());
+        shawl_args.push(join_command_line(&[format!("{}={}", code, action.to_cli())]));
+    }
+    for sidecar in &opts.sidecar {
+        shawl_args.push("--sidecar".to_string());
+        shawl_args.push(join_command_line(&[sidecar.to_cli()]));
+    }
+    if let Some(timeout) = opts.wait_for_network {
+        shawl_args.push("--wait-for-network".to_string());
+        if let Some(timeout) = timeout {
+            shawl_args.push(crate::cli::duration_to_cli(timeout));
+        }
+    }
+    for path in &opts.wait_for_path {
+        shawl_args.push("--wait-for-path".to_string());
+        shawl_args.push(quote(path));
+    }
+    if let Some(timeout) = opts.wait_for_path_timeout {
+        shawl_args.push("--wait-for-path-timeout".to_string());
+        shawl_args.push(crate::cli::duration_to_cli(timeout));
+    }
+    for name in &opts.wait_for_service {
+        shawl_args.push("--wait-for-service".to_string());
+        shawl_args.push(quote(name));
+    }
+    if let Some(timeout) = opts.wait_for_service_timeout {
+        shawl_args.push("--wait-for-service-timeout".to_string());
+        shawl_args.push(crate::cli::duration_to_cli(timeout));
+    }
+    for name in &opts.bind_to_service {
+        shawl_args.push("--bind-to-service".to_string());
+        shawl_args.push(quote(name));
+    }
+    for init in &opts.init {
+        shawl_args.push("--init".to_string());
+        shawl_args.push(join_command_line(std::slice::from_ref(init)));
+    }
+    if let Some(script) = &opts.hook_script {
+        shawl_args.push("--hook-script".to_string());
+        shawl_args.push(quote(script));
+    }
+    if let Some(exec) = &opts.hook_exec {
+        shawl_args.push("--hook-exec".to_string());
+        shawl_args.push(quote(exec));
+    }
     shawl_args
 }
 
@@ -148,441 +1056,2043 @@ fn prepare_command(command: &[String]) -> Vec<String> {
     command.iter().map(|x| quote(x)).collect::<Vec<String>>()
 }
 
-fn quote(text: &str) -> String {
-    if text.contains(' ') {
-        format!("\"{}\"", text)
+/// Read the command line of an installed service, split into its arguments.
+pub fn read_service_command_line(name: &str) -> windows_service::Result<Vec<String>> {
+    use windows_service::{
+        service::ServiceAccess,
+        service_manager::{ServiceManager, ServiceManagerAccess},
+    };
+
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+    let service = manager.open_service(name, ServiceAccess::QUERY_CONFIG)?;
+    let config = service.query_config()?;
+    Ok(split_command_line(&config.executable_path.to_string_lossy()))
+}
+
+/// Query the current state of an installed service.
+pub fn query_service_state(name: &str) -> windows_service::Result<windows_service::service::ServiceState> {
+    Ok(query_service_status(name)?.current_state)
+}
+
+fn query_service_status(name: &str) -> windows_service::Result<windows_service::service::ServiceStatus> {
+    use windows_service::{
+        service::ServiceAccess,
+        service_manager::{ServiceManager, ServiceManagerAccess},
+    };
+
+    let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)?;
+    let service = manager.open_service(name, ServiceAccess::QUERY_STATUS)?;
+    service.query_status()
+}
+
+fn describe_state(state: windows_service::service::ServiceState) -> &'static str {
+    use windows_service::service::ServiceState;
+
+    match state {
+        ServiceState::Stopped => "stopped",
+        ServiceState::StartPending => "starting",
+        ServiceState::StopPending => "stopping",
+        ServiceState::Running => "running",
+        ServiceState::ContinuePending => "continuing",
+        ServiceState::PausePending => "pausing",
+        ServiceState::Paused => "paused",
+    }
+}
+
+/// Describe a duration in milliseconds like `1h 2m 3s`.
+fn describe_duration(millis: u128) -> String {
+    let seconds = millis / 1000;
+    let (hours, minutes, seconds) = (seconds / 3600, seconds / 60 % 60, seconds % 60);
+    if hours > 0 {
+        format!("{}h {}m {}s", hours, minutes, seconds)
+    } else if minutes > 0 {
+        format!("{}m {}s", minutes, seconds)
     } else {
-        text.to_owned()
+        format!("{}s", seconds)
     }
 }
 
-#[cfg(test)]
-speculate::speculate! {
-    fn s(text: &str) -> String {
-        text.to_string()
-    }
+/// Format the status response from a service's control pipe as indented lines.
+fn describe_live_status(response: &str) -> Result<Vec<String>, String> {
+    let fields = crate::pipe::parse_response(response).ok_or_else(|| format!("invalid response: {}", response))?;
+    let field = |name: &str| {
+        fields
+            .iter()
+            .find(|(key, value)| key == name && value != "null")
+            .map(|(_, value)| value.as_str())
+    };
+    if field("ok") != Some("true") {
+        return Err(field("error").unwrap_or(response).to_string());
+    }
+    let duration = |name: &str| field(name).and_then(|x| x.parse().ok()).map(describe_duration);
+
+    let mut lines = vec![format!("  state: {}", field("state").unwrap_or("unknown"))];
+    if let Some(pid) = field("pid") {
+        lines.push(format!("  pid: {}", pid));
+    }
+    if let Some(uptime) = duration("uptime_ms") {
+        lines.push(format!("  command uptime: {}", uptime));
+    }
+    if let Some(instances) = field("instances").filter(|x| *x != "1") {
+        lines.push(format!(
+            "  instances running: {} of {}",
+            field("instances_running").unwrap_or("0"),
+            instances
+        ));
+    }
+    if let Some(last_output) = duration("last_output_ms") {
+        lines.push(format!("  last output: {} ago", last_output));
+    }
+    if let Some(code) = field("last_exit_code").and_then(|x| x.parse().ok()) {
+        lines.push(format!("  last exit code: {}", crate::exitcode::describe(code)));
+    }
+    lines.push(format!(
+        "  restarts: {}{}",
+        field("restarts").unwrap_or("0"),
+        if field("restarts_paused") == Some("true") {
+            " (paused)"
+        } else {
+            ""
+        }
+    ));
+    lines.push(format!("  failures: {}", field("failures").unwrap_or("0")));
+    if let Some(uptime) = duration("service_uptime_ms") {
+        lines.push(format!("  service uptime: {}", uptime));
+    }
+    if let Some(uptime) = duration("total_uptime_ms") {
+        lines.push(format!("  total command uptime: {}", uptime));
+    }
+    Ok(lines)
+}
+
+/// Print the state of a service according to the service manager,
+/// and with `live`, the status reported by the running service through its control pipe.
+/// Returns a description of what went wrong if the status is unavailable.
+pub fn show_status(name: &str, live: bool) -> Result<(), String> {
+    let status = query_service_status(name).map_err(|e| format!("Unable to query service {}: {}", name, e))?;
+    println!("{}: {}", name, describe_state(status.current_state));
+
+    if live {
+        let Some(pid) = status
+            .process_id
+            .filter(|_| status.current_state != windows_service::service::ServiceState::Stopped)
+        else {
+            return Err("Unable to get live status because the service is not running".to_string());
+        };
+        let lines = crate::pipe::query(name, pid, r#"{"command":"status"}"#)
+            .map_err(|e| e.to_string())
+            .and_then(|response| describe_live_status(&response))
+            .map_err(|e| format!("Unable to get live status from the service: {}", e))?;
+        for line in lines {
+            println!("{}", line);
+        }
+    }
+    Ok(())
+}
+
+/// Split a command line into arguments, following the usual Windows rules
+/// for quotes and backslashes.
+pub fn split_command_line(command_line: &str) -> Vec<String> {
+    let mut args = vec![];
+    let mut current = String::new();
+    let mut in_arg = false;
+    let mut in_quotes = false;
+    let mut backslashes = 0;
+
+    for c in command_line.chars() {
+        match c {
+            '\\' => {
+                backslashes += 1;
+                in_arg = true;
+            }
+            '"' => {
+                current.push_str(&"\\".repeat(backslashes / 2));
+                if backslashes % 2 == 1 {
+                    current.push('"');
+                } else {
+                    in_quotes = !in_quotes;
+                }
+                backslashes = 0;
+                in_arg = true;
+            }
+            ' ' | '\t' if !in_quotes => {
+                current.push_str(&"\\".repeat(backslashes));
+                backslashes = 0;
+                if in_arg {
+                    args.push(std::mem::take(&mut current));
+                    in_arg = false;
+                }
+            }
+            _ => {
+                current.push_str(&"\\".repeat(backslashes));
+                backslashes = 0;
+                current.push(c);
+                in_arg = true;
+            }
+        }
+    }
+
+    current.push_str(&"\\".repeat(backslashes));
+    if in_arg {
+        args.push(current);
+    }
+
+    args
+}
+
+/// Join arguments into a command line that `split_command_line` will split back
+/// into the same arguments, following the usual Windows quoting rules.
+pub fn join_command_line(args: &[String]) -> String {
+    args.iter()
+        .map(|arg| {
+            if !arg.is_empty() && !arg.contains([' ', '\t', '"']) {
+                return arg.clone();
+            }
+
+            let mut quoted = String::from('"');
+            let mut backslashes = 0;
+            for c in arg.chars() {
+                match c {
+                    '\\' => backslashes += 1,
+                    '"' => {
+                        quoted.push_str(&"\\".repeat(backslashes * 2 + 1));
+                        backslashes = 0;
+                    }
+                    _ => {
+                        quoted.push_str(&"\\".repeat(backslashes));
+                        backslashes = 0;
+                    }
+                }
+                if c != '\\' {
+                    quoted.push(c);
+                }
+            }
+            quoted.push_str(&"\\".repeat(backslashes * 2));
+            quoted.push('"');
+            quoted
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Wrap text in quotes if it has spaces, without escaping anything,
+/// which is how `cmd` and the service manager split arguments.
+pub fn quote(text: &str) -> String {
+    if text.contains(' ') {
+        format!("\"{}\"", text)
+    } else {
+        text.to_owned()
+    }
+}
+
+/// Quote an argument so that `split_command_line` gives back the same text,
+/// even if it contains quotes and backslashes.
+pub fn quote_exactly(text: &str) -> String {
+    let mut quoted = String::from("\"");
+    let mut backslashes = 0;
+    for c in text.chars() {
+        match c {
+            '\\' => {
+                backslashes += 1;
+                continue;
+            }
+            '"' => quoted.push_str(&"\\".repeat(backslashes * 2 + 1)),
+            _ => quoted.push_str(&"\\".repeat(backslashes)),
+        }
+        backslashes = 0;
+        quoted.push(c);
+    }
+    quoted.push_str(&"\\".repeat(backslashes * 2));
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+speculate::speculate! {
+    fn s(text: &str) -> String {
+        text.to_string()
+    }
+
+    describe "construct_shawl_run_args" {
+        it "works with minimal input" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts::default()
+                ),
+                vec!["run", "--name", "shawl"],
+            );
+        }
+
+        it "does not use the command" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        command: vec![s("foo")],
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl"],
+            );
+        }
+
+        it "handles --name with spaces" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("C:/Program Files/shawl"),
+                    &None,
+                    &CommonOpts::default()
+                ),
+                vec!["run", "--name", "\"C:/Program Files/shawl\""],
+            );
+        }
+
+        it "handles --restart" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        restart: true,
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--restart"],
+            );
+        }
+
+        it "handles --no-restart" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        no_restart: true,
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--no-restart"],
+            );
+        }
+
+        it "handles --restart-if with one code" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        restart_if: vec![0.into()],
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--restart-if", "0"],
+            );
+        }
+
+        it "handles --restart-if with multiple codes" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        restart_if: vec![1.into(), 10.into()],
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--restart-if", "1,10"],
+            );
+        }
+
+        it "handles --restart-if-not with one code" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        restart_if_not: vec![0.into()],
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--restart-if-not", "0"],
+            );
+        }
+
+        it "handles --restart-if-not with multiple codes" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        restart_if_not: vec![1.into(), 10.into()],
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--restart-if-not", "1,10"],
+            );
+        }
+
+        it "handles --stop-if" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        stop_if: vec![10.into(), (-1).into()],
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--stop-if", "10,-1"],
+            );
+        }
+
+        it "handles --pass with one code" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        pass: Some(vec![0.into()]),
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--pass", "0"],
+            );
+        }
+
+        it "handles --pass with multiple codes" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        pass: Some(vec![1.into(), 10.into()]),
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--pass", "1,10"],
+            );
+        }
+
+        it "handles --pass with a range" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        pass: Some(vec![crate::cli::ExitCodeRange { start: 0, end: 9 }]),
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--pass", "0-9"],
+            );
+        }
+
+        it "handles --restart-delay" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        restart_delay: Some(1500),
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--restart-delay", "1500"],
+            );
+        }
+
+        it "handles --restart-every" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        restart_every: Some(std::time::Duration::from_secs(6 * 60 * 60)),
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--restart-every", "6h"],
+            );
+        }
+
+        it "handles --interval" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        interval: Some(std::time::Duration::from_secs(15 * 60)),
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--interval", "15m"],
+            );
+        }
+
+        it "handles --instances" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        instances: Some(5),
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--instances", "5"],
+            );
+        }
+
+        it "handles --spawn-retries" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        spawn_retries: Some(5),
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--spawn-retries", "5"],
+            );
+        }
+
+        it "handles --restart-on-update" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        restart_on_update: true,
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--restart-on-update"],
+            );
+        }
+
+        it "handles --watch" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        watch: vec![s("C:/app/config.toml"), s("C:/app data/extra.toml")],
+                        watch_debounce: Some(std::time::Duration::from_secs(5)),
+                        ..Default::default()
+                    }
+                ),
+                vec![
+                    "run", "--name", "shawl",
+                    "--watch", "C:/app/config.toml",
+                    "--watch", "\"C:/app data/extra.toml\"",
+                    "--watch-debounce", "5s",
+                ],
+            );
+        }
+
+        it "handles --stop-timeout" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        stop_timeout: Some(3000),
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--stop-timeout", "3000"],
+            );
+        }
+
+        it "handles --preshutdown-timeout" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        preshutdown_timeout: Some(60000),
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--preshutdown-timeout", "60000"],
+            );
+        }
+
+        it "handles --stop-delay" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        stop_delay: Some(5000),
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--stop-delay", "5000"],
+            );
+        }
+
+        it "handles --kill-method" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        kill_method: Some(crate::cli::KillMethod::Tree),
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--kill-method", "tree"],
+            );
+        }
+
+        it "handles --stop-method" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        stop_method: Some(crate::cli::StopMethod::Kill),
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--stop-method", "kill"],
+            );
+        }
+
+        it "handles --on-duplicate" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        on_duplicate: Some(crate::cli::DuplicateAction::Refuse),
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--on-duplicate", "refuse"],
+            );
+        }
+
+        it "handles --cwd without spaces" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &Some(s("C:/foo")),
+                    &CommonOpts::default()
+                ),
+                vec!["run", "--name", "shawl", "--cwd", "C:/foo"],
+            );
+        }
+
+        it "handles --cwd with spaces" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &Some(s("C:/Program Files/foo")),
+                    &CommonOpts::default()
+                ),
+                vec!["run", "--name", "shawl", "--cwd", "\"C:/Program Files/foo\""],
+            );
+        }
+
+        it "handles --no-log" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        no_log: true,
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--no-log"],
+            );
+        }
+        it "handles --no-log-cmd" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        no_log_cmd: true,
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--no-log-cmd"],
+            );
+        }
+
+        it "handles --log-subdir" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        log_subdir: Some(None),
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--log-subdir"],
+            );
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        log_subdir: Some(Some("apps-{service_name}".to_string())),
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--log-subdir", "apps-{service_name}"],
+            );
+        }
+
+        it "handles --log-private" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        log_subdir: Some(None),
+                        log_private: true,
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--log-subdir", "--log-private"],
+            );
+        }
+
+        it "handles --log-as" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        log_as: Some("foo".to_string()),
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--log-as", "foo"],
+            );
+        }
+
+        it "handles --log-name-pattern" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        log_name_pattern: Some(s("{service_name}-%Y-%m-%d")),
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--log-name-pattern", "{service_name}-%Y-%m-%d"],
+            );
+        }
+
+        it "handles --log-cmd-as" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        log_cmd_as: Some("foo".to_string()),
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--log-cmd-as", "foo"],
+            );
+        }
+
+        it "handles --log-cmd-split" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        log_cmd_as: Some("foo".to_string()),
+                        log_cmd_split: true,
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--log-cmd-as", "foo", "--log-cmd-split"],
+            );
+        }
+
+        it "handles --log-level" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        log_level: Some(crate::cli::LogLevel::Info),
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--log-level", "info"],
+            );
+        }
+
+        it "handles --log-stdout-level and --log-stderr-level" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        log_stdout_level: Some(crate::cli::LogLevel::Info),
+                        log_stderr_level: Some(crate::cli::LogLevel::Error),
+                        ..Default::default()
+                    }
+                ),
+                vec![
+                    "run",
+                    "--name",
+                    "shawl",
+                    "--log-stdout-level",
+                    "info",
+                    "--log-stderr-level",
+                    "error"
+                ],
+            );
+        }
+
+        it "handles --log-filter-include and --log-filter-exclude" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        log_filter_include: vec![s("ERROR")],
+                        log_filter_exclude: vec![s("^heartbeat"), s("a b")],
+                        ..Default::default()
+                    }
+                ),
+                vec![
+                    "run",
+                    "--name",
+                    "shawl",
+                    "--log-filter-include",
+                    "ERROR",
+                    "--log-filter-exclude",
+                    "^heartbeat",
+                    "--log-filter-exclude",
+                    "\"a b\""
+                ],
+            );
+        }
+
+        it "escapes quotes and backslashes in --log-filter-include" {
+            let args = construct_shawl_run_args(
+                &s("shawl"),
+                &None,
+                &CommonOpts {
+                    log_filter_include: vec![s(r#"say "hi""#), s(r"C:\Program Files\")],
+                    ..Default::default()
+                },
+            );
+            assert_eq!(
+                split_command_line(&args.join(" ")),
+                vec!["run", "--name", "shawl", "--log-filter-include", r#"say "hi""#, "--log-filter-include", r"C:\Program Files\"],
+            );
+        }
+
+        it "handles --log-redact" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        log_redact: vec![s("token=\\w+"), s("hunter2")],
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--log-redact", "token=\\w+", "--log-redact", "hunter2"],
+            );
+        }
+
+        it "escapes --log-redact" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        log_redact: vec![s("\"a b\"")],
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--log-redact", "\"\\\"a b\\\"\""],
+            );
+        }
+
+        it "handles --log-redact-secret" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        log_redact_secret: vec![s("my-app/token")],
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--log-redact-secret", "my-app/token"],
+            );
+        }
+
+        it "handles --log-gelf" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        log_gelf: Some(crate::cli::GelfEndpoint {
+                            protocol: crate::cli::GelfProtocol::Tcp,
+                            address: s("graylog:12201"),
+                        }),
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--log-gelf", "tcp://graylog:12201"],
+            );
+        }
+
+        it "handles --log-rate-limit and --log-collapse-repeats" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        log_rate_limit: Some(100),
+                        log_rate_interval: Some(std::time::Duration::from_secs(10)),
+                        log_collapse_repeats: true,
+                        ..Default::default()
+                    }
+                ),
+                vec![
+                    "run",
+                    "--name",
+                    "shawl",
+                    "--log-rate-limit",
+                    "100",
+                    "--log-rate-interval",
+                    "10s",
+                    "--log-collapse-repeats"
+                ],
+            );
+        }
+
+        it "handles --log-group-indented and --log-group-pattern" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        log_group_indented: true,
+                        log_group_pattern: vec![s("^Caused by:")],
+                        ..Default::default()
+                    }
+                ),
+                vec![
+                    "run",
+                    "--name",
+                    "shawl",
+                    "--log-group-indented",
+                    "--log-group-pattern",
+                    "\"^Caused by:\""
+                ],
+            );
+        }
+
+        it "escapes quotes and backslashes in --log-group-pattern" {
+            let args = construct_shawl_run_args(
+                &s("shawl"),
+                &None,
+                &CommonOpts {
+                    log_group_pattern: vec![s(r#"^\s+at "#), s(r#"^"quoted"$"#)],
+                    ..Default::default()
+                },
+            );
+            assert_eq!(
+                split_command_line(&args.join(" ")),
+                vec!["run", "--name", "shawl", "--log-group-pattern", r#"^\s+at "#, "--log-group-pattern", r#"^"quoted"$"#],
+            );
+        }
+
+        it "handles --log-cmd-to" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        log_cmd_to: Some(crate::cli::LogCmdTarget::EventLog),
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--log-cmd-to", "eventlog"],
+            );
+        }
+
+
+        it "handles --echo-output" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        echo_output: true,
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--echo-output"],
+            );
+        }
+
+        it "handles --log-dir without spaces" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        log_dir: Some("C:/foo".to_string()),
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--log-dir", "C:/foo"],
+            );
+        }
+
+        it "handles --log-dir with spaces" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        log_dir: Some("C:/foo bar/hello".to_string()),
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--log-dir", "\"C:/foo bar/hello\""],
+            );
+        }
+
+        it "handles --log-rotate" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        log_rotate: Some(crate::cli::LogRotation::Daily),
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--log-rotate", "daily"],
+            );
+        }
+
+        it "handles --log-rotate with an age and size" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        log_rotate: Some(crate::cli::LogRotation::DailyOrBytes(123)),
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--log-rotate", "daily,bytes=123"],
+            );
+        }
+
+        it "handles --log-retain" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        log_retain: Some(5),
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--log-retain", "5"],
+            );
+        }
+
+        it "handles --log-max-total" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        log_max_total: Some(100),
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--log-max-total", "100"],
+            );
+        }
+
+        it "handles --log-flush-interval" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        log_flush_interval: Some(std::time::Duration::from_millis(500)),
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--log-flush-interval", "500ms"],
+            );
+        }
+
+        it "handles --log-format" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        log_format: Some(crate::cli::LogFormat::Json),
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--log-format", "json"],
+            );
+        }
+
+        it "handles --log-utc and --log-rfc3339" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        log_utc: true,
+                        log_rfc3339: true,
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--log-utc", "--log-rfc3339"],
+            );
+        }
+
+        it "handles --pass-start-args" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        pass_start_args: true,
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--pass-start-args"],
+            );
+        }
+
+        it "handles --command-line" {
+            let command_line = r#"msiexec /i "C:\temp\" A=\"b\" C:\"#;
+            let args = construct_shawl_run_args(
+                &s("shawl"),
+                &None,
+                &CommonOpts {
+                    command_line: Some(s(command_line)),
+                    ..Default::default()
+                }
+            );
+            assert_eq!(
+                args,
+                vec!["run", "--name", "shawl", "--command-line", r#""msiexec /i \"C:\temp\\\" A=\\\"b\\\" C:\\""#],
+            );
+            assert_eq!(split_command_line(&args.join(" "))[4], command_line);
+        }
+
+        it "handles --expand-env" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        expand_env: true,
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--expand-env"],
+            );
+        }
+
+        it "handles --cwd-create" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        cwd_create: true,
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--cwd-create"],
+            );
+        }
+
+        it "handles --env without spaces" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        env: vec![(s("FOO"), s("bar"))],
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--env", "FOO=bar"],
+            );
+        }
+
+        it "handles --env with spaces" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        env: vec![(s("FOO"), s("bar baz"))],
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--env", "\"FOO=bar baz\""],
+            );
+        }
+
+        it "handles --env multiple times" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        env: vec![(s("FOO"), s("1")), (s("BAR"), s("2"))],
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--env", "FOO=1", "--env", "BAR=2"],
+            );
+        }
+
+        it "handles --path without spaces" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        path: vec![s("C:/foo")],
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--path", "C:/foo"],
+            );
+        }
+
+        it "handles --path with spaces" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        path: vec![s("C:/foo bar")],
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--path", "\"C:/foo bar\""],
+            );
+        }
+
+        it "handles --path multiple times" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        path: vec![s("C:/foo"), s("C:/bar")],
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--path", "C:/foo", "--path", "C:/bar"],
+            );
+        }
+
+        it "handles --env-file" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        env_file: Some(s("C:/foo bar/.env")),
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--env-file", "\"C:/foo bar/.env\""],
+            );
+        }
+
+        it "handles --env-secret" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        env_secret: vec![(s("DB_PASSWORD"), s("myapp db"))],
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--env-secret", "\"DB_PASSWORD=myapp db\""],
+            );
+        }
+
+        it "handles --refresh-env" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        refresh_env: true,
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--refresh-env"],
+            );
+        }
+
+        it "handles --load-profile" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        load_profile: true,
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--load-profile"],
+            );
+        }
+
+        it "handles --clean-env" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        clean_env: true,
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--clean-env"],
+            );
+        }
+
+        it "handles --inherit-env" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        clean_env: true,
+                        inherit_env: vec![s("TEMP"), s("APP_*")],
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--clean-env", "--inherit-env", "TEMP", "--inherit-env", "APP_*"],
+            );
+        }
+
+        it "handles --unset-env" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        unset_env: vec![s("JAVA_TOOL_OPTIONS"), s("*_PROXY")],
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--unset-env", "JAVA_TOOL_OPTIONS", "--unset-env", "*_PROXY"],
+            );
+        }
+
+        it "handles --priority" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        priority: Some(crate::cli::Priority::AboveNormal),
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--priority", "above-normal"],
+            );
+        }
+
+        it "handles --hide-window" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        hide_window: true,
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--hide-window"],
+            );
+        }
+
+        it "handles --interactive-session" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        interactive_session: true,
+                        ..Default::default()
+                    }
+                ),
+                vec!["run", "--name", "shawl", "--interactive-session"],
+            );
+        }
 
-    describe "construct_shawl_run_args" {
-        it "works with minimal input" {
+        it "handles --mitigation" {
             assert_eq!(
                 construct_shawl_run_args(
                     &s("shawl"),
                     &None,
-                    &CommonOpts::default()
+                    &CommonOpts {
+                        mitigation: vec![crate::cli::Mitigation::Dep, crate::cli::Mitigation::NoChildProcess],
+                        ..Default::default()
+                    }
                 ),
-                vec!["run", "--name", "shawl"],
+                vec!["run", "--name", "shawl", "--mitigation", "dep", "--mitigation", "no-child-process"],
             );
         }
 
-        it "does not use the command" {
+        it "handles --crash-dump-dir" {
             assert_eq!(
                 construct_shawl_run_args(
                     &s("shawl"),
                     &None,
                     &CommonOpts {
-                        command: vec![s("foo")],
+                        crash_dump_dir: Some(s("C:/app/dumps")),
                         ..Default::default()
                     }
                 ),
-                vec!["run", "--name", "shawl"],
+                vec!["run", "--name", "shawl", "--crash-dump-dir", "C:/app/dumps"],
             );
         }
 
-        it "handles --name with spaces" {
+        it "handles --event-log" {
             assert_eq!(
                 construct_shawl_run_args(
-                    &s("C:/Program Files/shawl"),
+                    &s("shawl"),
                     &None,
-                    &CommonOpts::default()
+                    &CommonOpts {
+                        event_log: true,
+                        ..Default::default()
+                    }
                 ),
-                vec!["run", "--name", "\"C:/Program Files/shawl\""],
+                vec!["run", "--name", "shawl", "--event-log"],
             );
         }
 
-        it "handles --restart" {
+
+        it "handles --notify-slack and --notify-teams" {
             assert_eq!(
                 construct_shawl_run_args(
                     &s("shawl"),
                     &None,
                     &CommonOpts {
-                        restart: true,
+                        notify_slack: Some("https://hooks.slack.com/services/T00/B00/XXX".parse().unwrap()),
+                        notify_teams: Some("http://localhost:8080/hook".parse().unwrap()),
                         ..Default::default()
                     }
                 ),
-                vec!["run", "--name", "shawl", "--restart"],
+                vec![
+                    "run",
+                    "--name",
+                    "shawl",
+                    "--notify-slack",
+                    "https://hooks.slack.com/services/T00/B00/XXX",
+                    "--notify-teams",
+                    "http://localhost:8080/hook",
+                ],
             );
         }
 
-        it "handles --no-restart" {
+        it "handles --heartbeat-url and --heartbeat-interval" {
             assert_eq!(
                 construct_shawl_run_args(
                     &s("shawl"),
                     &None,
                     &CommonOpts {
-                        no_restart: true,
+                        heartbeat_url: Some("https://hc-ping.com/1234".parse().unwrap()),
+                        heartbeat_interval: Some(std::time::Duration::from_secs(300)),
                         ..Default::default()
                     }
                 ),
-                vec!["run", "--name", "shawl", "--no-restart"],
+                vec![
+                    "run",
+                    "--name",
+                    "shawl",
+                    "--heartbeat-url",
+                    "https://hc-ping.com/1234",
+                    "--heartbeat-interval",
+                    "5m",
+                ],
             );
         }
 
-        it "handles --restart-if with one code" {
+        it "handles --otlp-endpoint and --otlp-output" {
             assert_eq!(
                 construct_shawl_run_args(
                     &s("shawl"),
                     &None,
                     &CommonOpts {
-                        restart_if: vec![0],
+                        otlp_endpoint: Some("http://localhost:4318".parse().unwrap()),
+                        otlp_output: true,
                         ..Default::default()
                     }
                 ),
-                vec!["run", "--name", "shawl", "--restart-if", "0"],
+                vec![
+                    "run",
+                    "--name",
+                    "shawl",
+                    "--otlp-endpoint",
+                    "http://localhost:4318/",
+                    "--otlp-output",
+                ],
             );
         }
 
-        it "handles --restart-if with multiple codes" {
+        it "handles --perf-counters" {
             assert_eq!(
                 construct_shawl_run_args(
                     &s("shawl"),
                     &None,
                     &CommonOpts {
-                        restart_if: vec![1, 10],
+                        perf_counters: true,
                         ..Default::default()
                     }
                 ),
-                vec!["run", "--name", "shawl", "--restart-if", "1,10"],
+                vec!["run", "--name", "shawl", "--perf-counters"],
             );
         }
 
-        it "handles --restart-if-not with one code" {
+        it "leaves out --firewall-allow" {
             assert_eq!(
                 construct_shawl_run_args(
                     &s("shawl"),
                     &None,
                     &CommonOpts {
-                        restart_if_not: vec![0],
+                        firewall_allow: vec![
+                            FirewallAllow::Ports { start: 8080, end: 8080, protocol: crate::cli::FirewallProtocol::Tcp },
+                            FirewallAllow::Program,
+                        ],
                         ..Default::default()
                     }
                 ),
-                vec!["run", "--name", "shawl", "--restart-if-not", "0"],
+                vec!["run", "--name", "shawl"],
             );
         }
 
-        it "handles --restart-if-not with multiple codes" {
+        it "leaves out --urlacl" {
             assert_eq!(
                 construct_shawl_run_args(
                     &s("shawl"),
                     &None,
                     &CommonOpts {
-                        restart_if_not: vec![1, 10],
+                        urlacl: vec![s("http://+:8080/api/"), s("https://+:8443/")],
                         ..Default::default()
                     }
                 ),
-                vec!["run", "--name", "shawl", "--restart-if-not", "1,10"],
+                vec!["run", "--name", "shawl"],
             );
         }
 
-        it "handles --pass with one code" {
+        it "handles --profile" {
             assert_eq!(
                 construct_shawl_run_args(
                     &s("shawl"),
                     &None,
                     &CommonOpts {
-                        pass: Some(vec![0]),
+                        profile: Some(s("java-app")),
+                        restart_delay: Some(1500),
                         ..Default::default()
                     }
                 ),
-                vec!["run", "--name", "shawl", "--pass", "0"],
+                vec!["run", "--name", "shawl", "--profile", "java-app", "--restart-delay", "1500"],
             );
         }
 
-        it "handles --pass with multiple codes" {
+        it "keeps --args-file instead of its arguments" {
             assert_eq!(
                 construct_shawl_run_args(
                     &s("shawl"),
                     &None,
                     &CommonOpts {
-                        pass: Some(vec![1, 10]),
+                        args_file: vec![s(r"C:\apps\web args.txt")],
                         ..Default::default()
                     }
                 ),
-                vec!["run", "--name", "shawl", "--pass", "1,10"],
+                vec!["run", "--name", "shawl", "--args-file", r#""C:\apps\web args.txt""#],
             );
         }
 
-        it "handles --restart-delay" {
+        it "handles --defaults-from-env" {
             assert_eq!(
                 construct_shawl_run_args(
                     &s("shawl"),
                     &None,
                     &CommonOpts {
-                        restart_delay: Some(1500),
+                        defaults_from_env: true,
                         ..Default::default()
                     }
                 ),
-                vec!["run", "--name", "shawl", "--restart-delay", "1500"],
+                vec!["run", "--name", "shawl", "--defaults-from-env"],
             );
         }
 
-        it "handles --stop-timeout" {
+        it "handles --status-file" {
             assert_eq!(
                 construct_shawl_run_args(
                     &s("shawl"),
                     &None,
                     &CommonOpts {
-                        stop_timeout: Some(3000),
+                        status_file: Some(s("C:/foo bar/status.json")),
                         ..Default::default()
                     }
                 ),
-                vec!["run", "--name", "shawl", "--stop-timeout", "3000"],
+                vec!["run", "--name", "shawl", "--status-file", "\"C:/foo bar/status.json\""],
             );
         }
 
-        it "handles --cwd without spaces" {
+        it "handles --event-file" {
             assert_eq!(
                 construct_shawl_run_args(
                     &s("shawl"),
-                    &Some(s("C:/foo")),
-                    &CommonOpts::default()
+                    &None,
+                    &CommonOpts {
+                        event_file: Some(s("C:/foo bar/events.jsonl")),
+                        ..Default::default()
+                    }
                 ),
-                vec!["run", "--name", "shawl", "--cwd", "C:/foo"],
+                vec!["run", "--name", "shawl", "--event-file", "\"C:/foo bar/events.jsonl\""],
             );
         }
 
-        it "handles --cwd with spaces" {
+        it "handles --console-codepage" {
             assert_eq!(
                 construct_shawl_run_args(
                     &s("shawl"),
-                    &Some(s("C:/Program Files/foo")),
-                    &CommonOpts::default()
+                    &None,
+                    &CommonOpts {
+                        console_codepage: Some(65001),
+                        ..Default::default()
+                    }
                 ),
-                vec!["run", "--name", "shawl", "--cwd", "\"C:/Program Files/foo\""],
+                vec!["run", "--name", "shawl", "--console-codepage", "65001"],
             );
         }
 
-        it "handles --no-log" {
+        it "handles --cmd-encoding" {
             assert_eq!(
                 construct_shawl_run_args(
                     &s("shawl"),
                     &None,
                     &CommonOpts {
-                        no_log: true,
+                        cmd_encoding: Some(crate::cli::CmdEncoding::CodePage(936)),
                         ..Default::default()
                     }
                 ),
-                vec!["run", "--name", "shawl", "--no-log"],
+                vec!["run", "--name", "shawl", "--cmd-encoding", "936"],
             );
         }
-        it "handles --no-log-cmd" {
+
+        it "handles --capture-raw and --capture-raw-rotate" {
             assert_eq!(
                 construct_shawl_run_args(
                     &s("shawl"),
                     &None,
                     &CommonOpts {
-                        no_log_cmd: true,
+                        capture_raw: Some(s("C:/foo bar/raw.bin")),
+                        capture_raw_rotate: Some(1000),
                         ..Default::default()
                     }
                 ),
-                vec!["run", "--name", "shawl", "--no-log-cmd"],
+                vec![
+                    "run",
+                    "--name",
+                    "shawl",
+                    "--capture-raw",
+                    "\"C:/foo bar/raw.bin\"",
+                    "--capture-raw-rotate",
+                    "1000"
+                ],
             );
         }
 
-        it "handles --log-as" {
+        it "handles --stdin-text" {
             assert_eq!(
                 construct_shawl_run_args(
                     &s("shawl"),
                     &None,
                     &CommonOpts {
-                        log_as: Some("foo".to_string()),
+                        stdin_text: Some(s("Y")),
                         ..Default::default()
                     }
                 ),
-                vec!["run", "--name", "shawl", "--log-as", "foo"],
+                vec!["run", "--name", "shawl", "--stdin-text", "Y"],
             );
         }
 
-        it "handles --log-cmd-as" {
+        it "handles --stdin-file" {
             assert_eq!(
                 construct_shawl_run_args(
                     &s("shawl"),
                     &None,
                     &CommonOpts {
-                        log_cmd_as: Some("foo".to_string()),
+                        stdin_file: Some(s("C:/app/answers.txt")),
                         ..Default::default()
                     }
                 ),
-                vec!["run", "--name", "shawl", "--log-cmd-as", "foo"],
+                vec!["run", "--name", "shawl", "--stdin-file", "C:/app/answers.txt"],
             );
         }
 
-        it "handles --log-dir without spaces" {
+        it "handles --shell" {
             assert_eq!(
                 construct_shawl_run_args(
                     &s("shawl"),
                     &None,
                     &CommonOpts {
-                        log_dir: Some("C:/foo".to_string()),
+                        shell: Some(crate::cli::Shell::Powershell),
                         ..Default::default()
                     }
                 ),
-                vec!["run", "--name", "shawl", "--log-dir", "C:/foo"],
+                vec!["run", "--name", "shawl", "--shell", "powershell"],
             );
         }
 
-        it "handles --log-dir with spaces" {
+        it "handles --no-interpreter" {
             assert_eq!(
                 construct_shawl_run_args(
                     &s("shawl"),
                     &None,
                     &CommonOpts {
-                        log_dir: Some("C:/foo bar/hello".to_string()),
+                        no_interpreter: true,
                         ..Default::default()
                     }
                 ),
-                vec!["run", "--name", "shawl", "--log-dir", "\"C:/foo bar/hello\""],
+                vec!["run", "--name", "shawl", "--no-interpreter"],
             );
         }
 
-        it "handles --log-rotate" {
+        it "handles --on-resume" {
             assert_eq!(
                 construct_shawl_run_args(
                     &s("shawl"),
                     &None,
                     &CommonOpts {
-                        log_rotate: Some(crate::cli::LogRotation::Daily),
+                        on_resume: Some(crate::cli::ResumeAction::Signal),
                         ..Default::default()
                     }
                 ),
-                vec!["run", "--name", "shawl", "--log-rotate", "daily"],
+                vec!["run", "--name", "shawl", "--on-resume", "signal"],
             );
         }
 
-        it "handles --log-retain" {
+        it "handles --on-session-change" {
             assert_eq!(
                 construct_shawl_run_args(
                     &s("shawl"),
                     &None,
                     &CommonOpts {
-                        log_retain: Some(5),
+                        on_session_change: Some(crate::cli::SessionChangeAction::Run(s("notify.bat --user"))),
                         ..Default::default()
                     }
                 ),
-                vec!["run", "--name", "shawl", "--log-retain", "5"],
+                vec!["run", "--name", "shawl", "--on-session-change", "\"run:notify.bat --user\""],
             );
         }
 
-        it "handles --pass-start-args" {
+        it "escapes quotes in --on-session-change" {
+            let args = construct_shawl_run_args(
+                &s("shawl"),
+                &None,
+                &CommonOpts {
+                    on_session_change: Some(crate::cli::SessionChangeAction::Run(s(r#""C:\Program Files\notify.exe" --user"#))),
+                    ..Default::default()
+                },
+            );
+            assert_eq!(
+                split_command_line(&args.join(" ")),
+                vec!["run", "--name", "shawl", "--on-session-change", r#"run:"C:\Program Files\notify.exe" --user"#],
+            );
+        }
+
+        it "handles --on-control multiple times" {
             assert_eq!(
                 construct_shawl_run_args(
                     &s("shawl"),
                     &None,
                     &CommonOpts {
-                        pass_start_args: true,
+                        on_control: vec![
+                            (130, crate::cli::ControlAction::Run(s("app.exe reload"))),
+                            (131, crate::cli::ControlAction::Restart),
+                            (132, crate::cli::ControlAction::LogLevel(crate::cli::LogLevel::Debug)),
+                        ],
                         ..Default::default()
                     }
                 ),
-                vec!["run", "--name", "shawl", "--pass-start-args"],
+                vec![
+                    "run", "--name", "shawl",
+                    "--on-control", "\"130=run:app.exe reload\"",
+                    "--on-control", "131=restart",
+                    "--on-control", "132=log-level:debug",
+                ],
             );
         }
 
-        it "handles --env without spaces" {
+        it "escapes quotes in --on-control" {
+            let args = construct_shawl_run_args(
+                &s("shawl"),
+                &None,
+                &CommonOpts {
+                    on_control: vec![(130, crate::cli::ControlAction::Run(s(r#""C:\Program Files\x.exe" reload"#)))],
+                    ..Default::default()
+                },
+            );
+            assert_eq!(
+                split_command_line(&args.join(" ")),
+                vec!["run", "--name", "shawl", "--on-control", r#"130=run:"C:\Program Files\x.exe" reload"#],
+            );
+        }
+
+        it "handles --sidecar" {
             assert_eq!(
                 construct_shawl_run_args(
                     &s("shawl"),
                     &None,
                     &CommonOpts {
-                        env: vec![(s("FOO"), s("bar"))],
+                        sidecar: vec![crate::cli::Sidecar {
+                            restart: crate::cli::SidecarRestart::Never,
+                            command: s("exporter.exe --port 9100"),
+                        }],
                         ..Default::default()
                     }
                 ),
-                vec!["run", "--name", "shawl", "--env", "FOO=bar"],
+                vec!["run", "--name", "shawl", "--sidecar", "\"never:exporter.exe --port 9100\""],
             );
         }
 
-        it "handles --env with spaces" {
+        it "escapes quotes in --sidecar" {
+            let sidecar = crate::cli::Sidecar {
+                restart: crate::cli::SidecarRestart::OnFailure,
+                command: s(r#""C:\Program Files\exporter.exe" --port 9100"#),
+            };
+            let args = construct_shawl_run_args(
+                &s("shawl"),
+                &None,
+                &CommonOpts {
+                    sidecar: vec![sidecar.clone()],
+                    ..Default::default()
+                },
+            );
+            assert_eq!(
+                split_command_line(&args.join(" ")),
+                vec![s("run"), s("--name"), s("shawl"), s("--sidecar"), sidecar.to_cli()],
+            );
+        }
+
+        it "handles --init" {
             assert_eq!(
                 construct_shawl_run_args(
                     &s("shawl"),
                     &None,
                     &CommonOpts {
-                        env: vec![(s("FOO"), s("bar baz"))],
+                        init: vec![s("migrate.exe --up"), s("warmup.bat")],
                         ..Default::default()
                     }
                 ),
-                vec!["run", "--name", "shawl", "--env", "\"FOO=bar baz\""],
+                vec!["run", "--name", "shawl", "--init", "\"migrate.exe --up\"", "--init", "warmup.bat"],
             );
         }
 
-        it "handles --env multiple times" {
+        it "escapes quotes in --init" {
+            let init = s(r#""C:\Program Files\migrate.exe" --up"#);
+            let args = construct_shawl_run_args(
+                &s("shawl"),
+                &None,
+                &CommonOpts {
+                    init: vec![init.clone()],
+                    ..Default::default()
+                },
+            );
+            assert_eq!(
+                split_command_line(&args.join(" ")),
+                vec![s("run"), s("--name"), s("shawl"), s("--init"), init],
+            );
+        }
+
+        it "handles --hook-script" {
             assert_eq!(
                 construct_shawl_run_args(
                     &s("shawl"),
                     &None,
                     &CommonOpts {
-                        env: vec![(s("FOO"), s("1")), (s("BAR"), s("2"))],
+                        hook_script: Some(s("C:/hooks/policy.ps1")),
                         ..Default::default()
                     }
                 ),
-                vec!["run", "--name", "shawl", "--env", "FOO=1", "--env", "BAR=2"],
+                vec!["run", "--name", "shawl", "--hook-script", "C:/hooks/policy.ps1"],
             );
         }
 
-        it "handles --path without spaces" {
+        it "handles --hook-exec" {
             assert_eq!(
                 construct_shawl_run_args(
                     &s("shawl"),
                     &None,
                     &CommonOpts {
-                        path: vec![s("C:/foo")],
+                        hook_exec: Some(s("C:/hooks/notify.exe")),
                         ..Default::default()
                     }
                 ),
-                vec!["run", "--name", "shawl", "--path", "C:/foo"],
+                vec!["run", "--name", "shawl", "--hook-exec", "C:/hooks/notify.exe"],
             );
         }
 
-        it "handles --path with spaces" {
+        it "handles --wait-for-network without timeout" {
             assert_eq!(
                 construct_shawl_run_args(
                     &s("shawl"),
                     &None,
                     &CommonOpts {
-                        path: vec![s("C:/foo bar")],
+                        wait_for_network: Some(None),
                         ..Default::default()
                     }
                 ),
-                vec!["run", "--name", "shawl", "--path", "\"C:/foo bar\""],
+                vec!["run", "--name", "shawl", "--wait-for-network"],
             );
         }
 
-        it "handles --path multiple times" {
+        it "handles --wait-for-network with timeout" {
             assert_eq!(
                 construct_shawl_run_args(
                     &s("shawl"),
                     &None,
                     &CommonOpts {
-                        path: vec![s("C:/foo"), s("C:/bar")],
+                        wait_for_network: Some(Some(std::time::Duration::from_secs(120))),
                         ..Default::default()
                     }
                 ),
-                vec!["run", "--name", "shawl", "--path", "C:/foo", "--path", "C:/bar"],
+                vec!["run", "--name", "shawl", "--wait-for-network", "2m"],
             );
         }
 
-        it "handles --priority" {
+        it "handles --wait-for-path" {
             assert_eq!(
                 construct_shawl_run_args(
                     &s("shawl"),
                     &None,
                     &CommonOpts {
-                        priority: Some(crate::cli::Priority::AboveNormal),
+                        wait_for_path: vec![s("\\\\server\\share"), s("C:/foo bar")],
+                        wait_for_path_timeout: Some(std::time::Duration::from_secs(30)),
                         ..Default::default()
                     }
                 ),
-                vec!["run", "--name", "shawl", "--priority", "above-normal"],
+                vec![
+                    "run", "--name", "shawl",
+                    "--wait-for-path", "\\\\server\\share",
+                    "--wait-for-path", "\"C:/foo bar\"",
+                    "--wait-for-path-timeout", "30s",
+                ],
+            );
+        }
+
+        it "handles --wait-for-service and --bind-to-service" {
+            assert_eq!(
+                construct_shawl_run_args(
+                    &s("shawl"),
+                    &None,
+                    &CommonOpts {
+                        wait_for_service: vec![s("Dnscache")],
+                        wait_for_service_timeout: Some(std::time::Duration::from_secs(90)),
+                        bind_to_service: vec![s("My Database")],
+                        ..Default::default()
+                    }
+                ),
+                vec![
+                    "run", "--name", "shawl",
+                    "--wait-for-service", "Dnscache",
+                    "--wait-for-service-timeout", "90s",
+                    "--bind-to-service", "\"My Database\"",
+                ],
+            );
+        }
+    }
+
+    describe "effective_options" {
+        it "parses the run command line" {
+            let opts = effective_options(r#""C:\shawl.exe" run --name shawl --event-log -- "C:\app\my app.exe" --port 80"#).unwrap();
+            assert!(opts.event_log);
+            assert_eq!(opts.command, vec![s("C:\\app\\my app.exe"), s("--port"), s("80")]);
+        }
+    }
+
+    describe "split_command_line" {
+        it "splits on whitespace" {
+            assert_eq!(
+                split_command_line("shawl.exe run  --name\tfoo -- bar"),
+                vec![s("shawl.exe"), s("run"), s("--name"), s("foo"), s("--"), s("bar")],
+            );
+        }
+
+        it "handles quoted arguments" {
+            assert_eq!(
+                split_command_line(r#""C:/Program Files/shawl.exe" run --cwd "C:/foo bar" -- "" baz"#),
+                vec![s("C:/Program Files/shawl.exe"), s("run"), s("--cwd"), s("C:/foo bar"), s("--"), s(""), s("baz")],
+            );
+        }
+
+        it "handles backslashes" {
+            assert_eq!(
+                split_command_line(r#"C:\foo\bar.exe "C:\baz\\" a\"b a\\\"b"#),
+                vec![s(r"C:\foo\bar.exe"), s(r"C:\baz\"), s(r#"a"b"#), s(r#"a\"b"#)],
+            );
+        }
+
+        it "round trips prepared commands" {
+            let command = vec![s("cat"), s("some file"), s("C:\\tmp")];
+            assert_eq!(split_command_line(&prepare_command(&command).join(" ")), command);
+        }
+    }
+
+    describe "join_command_line" {
+        it "leaves simple arguments unquoted" {
+            assert_eq!(join_command_line(&[s("cat"), s("file")]), "cat file");
+        }
+
+        it "quotes arguments with spaces, quotes, or no content" {
+            assert_eq!(
+                join_command_line(&[s("cat"), s("some file"), s(r#"a"b"#), s("")]),
+                r#"cat "some file" "a\"b" """#,
             );
         }
+
+        it "round trips through split_command_line" {
+            let command = vec![s(r"C:\Program Files\app.exe"), s(r"C:\foo bar\"), s(r#"a\"b"#), s(r"a\b"), s("")];
+            assert_eq!(split_command_line(&join_command_line(&command)), command);
+        }
     }
 
     describe "prepare_command" {
@@ -600,4 +3110,208 @@ speculate::speculate! {
             );
         }
     }
+
+    describe "describe_state" {
+        it "names each state" {
+            use windows_service::service::ServiceState;
+
+            assert_eq!(describe_state(ServiceState::Running), "running");
+            assert_eq!(describe_state(ServiceState::StartPending), "starting");
+            assert_eq!(describe_state(ServiceState::Stopped), "stopped");
+        }
+    }
+
+    describe "describe_duration" {
+        it "uses the largest units needed" {
+            assert_eq!(describe_duration(999), "0s");
+            assert_eq!(describe_duration(65_000), "1m 5s");
+            assert_eq!(describe_duration(90_061_000), "25h 1m 1s");
+        }
+    }
+
+    describe "describe_live_status" {
+        it "lists the counters" {
+            assert_eq!(
+                describe_live_status(concat!(
+                    r#"{"ok":true,"state":"running","pid":123,"uptime_ms":1500,"last_output_ms":500,"last_exit_code":1,"#,
+                    r#""restarts":47,"restarts_paused":false,"launches":48,"failures":3,"service_uptime_ms":7200000,"total_uptime_ms":7000000}"#,
+                )),
+                Ok(vec![
+                    s("  state: running"),
+                    s("  pid: 123"),
+                    s("  command uptime: 1s"),
+                    s("  last output: 0s ago"),
+                    s("  last exit code: 1"),
+                    s("  restarts: 47"),
+                    s("  failures: 3"),
+                    s("  service uptime: 2h 0m 0s"),
+                    s("  total command uptime: 1h 56m 40s"),
+                ]),
+            );
+        }
+
+        it "skips values that are not known" {
+            assert_eq!(
+                describe_live_status(concat!(
+                    r#"{"ok":true,"state":"waiting","pid":null,"uptime_ms":null,"last_output_ms":null,"last_exit_code":null,"#,
+                    r#""restarts":0,"restarts_paused":true,"launches":0,"failures":0,"service_uptime_ms":null,"total_uptime_ms":0}"#,
+                )),
+                Ok(vec![
+                    s("  state: waiting"),
+                    s("  restarts: 0 (paused)"),
+                    s("  failures: 0"),
+                    s("  total command uptime: 0s"),
+                ]),
+            );
+        }
+
+        it "lists the instances that are running" {
+            assert_eq!(
+                describe_live_status(concat!(
+                    r#"{"ok":true,"state":"running","pid":123,"uptime_ms":1500,"last_output_ms":null,"last_exit_code":null,"#,
+                    r#""restarts":0,"restarts_paused":false,"launches":3,"failures":0,"service_uptime_ms":null,"total_uptime_ms":0,"#,
+                    r#""instances":3,"instances_running":2}"#,
+                )),
+                Ok(vec![
+                    s("  state: running"),
+                    s("  pid: 123"),
+                    s("  command uptime: 1s"),
+                    s("  instances running: 2 of 3"),
+                    s("  restarts: 0"),
+                    s("  failures: 0"),
+                    s("  total command uptime: 0s"),
+                ]),
+            );
+        }
+
+        it "reports errors from the service" {
+            assert_eq!(
+                describe_live_status(r#"{"ok":false,"error":"status is unavailable"}"#),
+                Err(s("status is unavailable")),
+            );
+        }
+    }
+
+    describe "firewall_rule_args" {
+        it "scopes the rule to the program and ports" {
+            assert_eq!(
+                firewall_rule_args(
+                    "web",
+                    Some(std::path::Path::new("C:\\apps\\web\\server.exe")),
+                    FirewallAllow::Ports { start: 5000, end: 5010, protocol: crate::cli::FirewallProtocol::Udp },
+                ),
+                vec![
+                    "advfirewall", "firewall", "add", "rule", "name=web (Shawl)", "dir=in", "action=allow", "enable=yes",
+                    "program=C:\\apps\\web\\server.exe", "protocol=udp", "localport=5000-5010",
+                ],
+            );
+        }
+
+        it "allows any port for the program" {
+            assert_eq!(
+                firewall_rule_args("web", Some(std::path::Path::new("C:\\apps\\web\\server.exe")), FirewallAllow::Program),
+                vec![
+                    "advfirewall", "firewall", "add", "rule", "name=web (Shawl)", "dir=in", "action=allow", "enable=yes",
+                    "program=C:\\apps\\web\\server.exe",
+                ],
+            );
+        }
+
+        it "allows a port without a program" {
+            assert_eq!(
+                firewall_rule_args(
+                    "web",
+                    None,
+                    FirewallAllow::Ports { start: 8080, end: 8080, protocol: crate::cli::FirewallProtocol::Tcp },
+                ),
+                vec![
+                    "advfirewall", "firewall", "add", "rule", "name=web (Shawl)", "dir=in", "action=allow", "enable=yes",
+                    "protocol=tcp", "localport=8080",
+                ],
+            );
+        }
+    }
+
+    describe "firewall_rules_args" {
+        it "selects the rules by the service's rule name" {
+            assert_eq!(
+                firewall_rules_args("web", "delete"),
+                vec!["advfirewall", "firewall", "delete", "rule", "name=web (Shawl)"],
+            );
+        }
+    }
+
+    describe "multi_sz" {
+        it "round-trips a list of strings" {
+            let items = vec![s("http://+:8080/api/"), s("https://+:8443/")];
+            assert_eq!(decode_multi_sz(&encode_multi_sz(&items)), items);
+        }
+
+        it "ends with two nulls" {
+            assert_eq!(encode_multi_sz(&[s("a"), s("b")]), vec![97, 0, 98, 0, 0]);
+            assert_eq!(encode_multi_sz(&[]), vec![0, 0]);
+        }
+    }
+
+    describe "urlacl_args" {
+        it "grants the account by SID" {
+            assert_eq!(
+                urlacl_args("http://+:8080/api/", "S-1-5-20"),
+                vec!["http", "add", "urlacl", "url=http://+:8080/api/", "sddl=D:(A;;GX;;;S-1-5-20)"],
+            );
+            assert_eq!(
+                urlacl_args("https://+:8443/", crate::acl::LOCAL_SYSTEM),
+                vec!["http", "add", "urlacl", "url=https://+:8443/", "sddl=D:(A;;GX;;;SY)"],
+            );
+        }
+    }
+
+    describe "verify_program" {
+        it "finds a program in the working directory" {
+            let dir = env!("CARGO_MANIFEST_DIR");
+            assert_eq!(verify_program("Cargo.toml", Some(dir), &CommonOpts::default()), Ok(()));
+        }
+
+        it "reports a missing program" {
+            let dir = env!("CARGO_MANIFEST_DIR");
+            assert_eq!(
+                verify_program("shawl-missing-program", Some(dir), &CommonOpts::default()),
+                Err(s("Unable to find the command's program: shawl-missing-program")),
+            );
+        }
+
+        it "leaves shell commands to the service" {
+            let opts = CommonOpts {
+                shell: Some(crate::cli::Shell::Cmd),
+                ..Default::default()
+            };
+            assert_eq!(verify_program("echo", None, &opts), Ok(()));
+        }
+
+        it "leaves placeholders to the service" {
+            assert_eq!(verify_program("{service_dir}\\app.exe", None, &CommonOpts::default()), Ok(()));
+        }
+    }
+
+    describe "add_error" {
+        it "classifies errors from sc" {
+            assert_eq!(AddError::from_win32(5), AddError::AccessDenied);
+            assert_eq!(AddError::from_win32(1073), AddError::Duplicate);
+            assert_eq!(AddError::from_win32(1057), AddError::InvalidArguments);
+            assert_eq!(AddError::from_win32(1722), AddError::ScmUnavailable);
+            assert_eq!(AddError::from_win32(1234), AddError::Other);
+        }
+
+        it "has a distinct exit code for each cause" {
+            let codes = [
+                AddError::Other,
+                AddError::InvalidArguments,
+                AddError::AccessDenied,
+                AddError::Duplicate,
+                AddError::ScmUnavailable,
+            ]
+            .map(AddError::exit_code);
+            assert_eq!(codes, [1, 2, 3, 4, 5]);
+        }
+    }
 }
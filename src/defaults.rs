@@ -0,0 +1,573 @@
+use log::warn;
+
+/// Name of the file with default options for `shawl run`.
+pub const FILE_NAME: &str = "shawl.toml";
+
+/// Prefix of environment variables with default options, like `SHAWL_STOP_TIMEOUT`.
+const ENV_PREFIX: &str = "SHAWL_";
+
+/// Options that can come from the environment: the ones that change timing and how logs look,
+/// but can't run other commands, send data elsewhere, or change where files are written.
+const FROM_ENV: [&str; 20] = [
+    "restart-delay",
+    "stop-timeout",
+    "preshutdown-timeout",
+    "stop-delay",
+    "spawn-retries",
+    "watch-debounce",
+    "wait-for-path-timeout",
+    "log-level",
+    "log-stdout-level",
+    "log-stderr-level",
+    "log-rotate",
+    "log-retain",
+    "log-max-total",
+    "log-flush-interval",
+    "log-format",
+    "log-utc",
+    "log-rfc3339",
+    "log-rate-limit",
+    "log-rate-interval",
+    "log-collapse-repeats",
+];
+
+/// Option that lets a service use the defaults from the environment.
+const FROM_ENV_OPTION: &str = "--defaults-from-env";
+
+/// Options from the defaults file, as arguments for `shawl run`.
+#[derive(Debug, Default, Eq, PartialEq)]
+pub struct Defaults {
+    /// Options for every service.
+    pub common: Vec<String>,
+    /// Options from `SHAWL_*` environment variables, for services with `--defaults-from-env`.
+    pub environment: Vec<String>,
+    /// Options from `[profile.<name>]` tables, for services with `--profile <name>`.
+    pub profiles: Vec<(String, Vec<String>)>,
+}
+
+impl Defaults {
+    fn profile(&self, name: &str) -> Result<&[String], String> {
+        self.profiles
+            .iter()
+            .find(|(profile, _)| profile == name)
+            .map(|(_, args)| args.as_slice())
+            .ok_or_else(|| format!("unknown profile: {}", name))
+    }
+}
+
+fn is_valid_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Where to look for the defaults file: next to the executable, then in ProgramData.
+fn candidates() -> Vec<std::path::PathBuf> {
+    let mut paths = vec![];
+    if let Ok(exe) = std::env::current_exe() {
+        paths.push(exe.with_file_name(FILE_NAME));
+    }
+    if let Some(program_data) = std::env::var_os("ProgramData") {
+        paths.push(std::path::PathBuf::from(program_data).join("shawl").join(FILE_NAME));
+    }
+    paths
+}
+
+/// Parse a TOML string, returning it and the rest of the line.
+fn parse_string(text: &str) -> Result<(String, &str), String> {
+    let mut chars = text.char_indices();
+    let quote = match chars.next() {
+        Some((_, c @ ('"' | '\''))) => c,
+        _ => return Err("expected a string".to_string()),
+    };
+    let mut string = String::new();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            c if c == quote => return Ok((string, &text[i + 1..])),
+            // Literal strings in single quotes have no escapes, which is handy for Windows paths.
+            '\\' if quote == '"' => string.push(match chars.next().map(|(_, c)| c) {
+                Some('"') => '"',
+                Some('\\') => '\\',
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('u') => {
+                    let hex: String = chars.by_ref().take(4).map(|(_, c)| c).collect();
+                    u32::from_str_radix(&hex, 16)
+                        .ok()
+                        .and_then(char::from_u32)
+                        .ok_or_else(|| format!("invalid escape: \\u{}", hex))?
+                }
+                Some(c) => return Err(format!("invalid escape: \\{}", c)),
+                None => break,
+            }),
+            c => string.push(c),
+        }
+    }
+    Err("unterminated string".to_string())
+}
+
+/// Parse a single value, returning its text as an argument and the rest of the line.
+fn parse_scalar(text: &str) -> Result<(String, &str), String> {
+    if text.starts_with(['"', '\'']) {
+        return parse_string(text);
+    }
+    let end = text.find([',', ']', '#', ' ', '\t']).unwrap_or(text.len());
+    let (value, rest) = text.split_at(end);
+    if value.is_empty() {
+        return Err("expected a value".to_string());
+    }
+    Ok((value.to_string(), rest))
+}
+
+/// Parse a line's value, which may be an array of values on the same line.
+fn parse_values(text: &str) -> Result<(Vec<String>, &str), String> {
+    let Some(mut rest) = text.strip_prefix('[') else {
+        let (value, rest) = parse_scalar(text)?;
+        return Ok((vec![value], rest));
+    };
+    let mut values = vec![];
+    loop {
+        rest = rest.trim_start();
+        if let Some(after) = rest.strip_prefix(']') {
+            return Ok((values, after));
+        }
+        let (value, after) = parse_scalar(rest)?;
+        values.push(value);
+        rest = after.trim_start();
+        if let Some(after) = rest.strip_prefix(',') {
+            rest = after;
+        } else if !rest.starts_with(']') {
+            return Err("expected `,` or `]` in array".to_string());
+        }
+    }
+}
+
+/// Convert the defaults file into arguments for `shawl run`.
+/// Each key is the name of a long option, like `log-rotate = "daily"` for `--log-rotate daily`.
+/// `true` sets a flag, `false` leaves it unset, and arrays repeat the option for each value.
+/// Keys after a `[profile.<name>]` header belong to that profile instead of every service.
+/// This handles the subset of TOML that options need, without other tables or multi-line values.
+fn parse(content: &str) -> Result<Defaults, String> {
+    let mut defaults = Defaults::default();
+    for (i, line) in content.lines().enumerate() {
+        let error = |e: String| format!("line {}: {}", i + 1, e);
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(table) = line.strip_prefix('[') {
+            let Some((table, rest)) = table.split_once(']') else {
+                return Err(error("unterminated table header".to_string()));
+            };
+            let rest = rest.trim();
+            if !rest.is_empty() && !rest.starts_with('#') {
+                return Err(error(format!("unexpected text after table header: {}", rest)));
+            }
+            let table = table.trim();
+            let name = match table.strip_prefix("profile.") {
+                Some(name) if is_valid_name(name) => name,
+                _ => return Err(error(format!("unknown table (expected [profile.<name>]): {}", table))),
+            };
+            if defaults.profiles.iter().any(|(profile, _)| profile == name) {
+                return Err(error(format!("duplicate profile: {}", name)));
+            }
+            defaults.profiles.push((name.to_string(), vec![]));
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(error("expected `key = value`".to_string()));
+        };
+        let key = key.trim();
+        if !is_valid_name(key) {
+            return Err(error(format!("invalid key: {}", key)));
+        }
+        if key == "profile" {
+            return Err(error("profiles cannot select other profiles".to_string()));
+        }
+        let args = match defaults.profiles.last_mut() {
+            Some((_, args)) => args,
+            None => &mut defaults.common,
+        };
+        let option = format!("--{}", key.replace('_', "-"));
+
+        let (values, rest) = parse_values(value.trim()).map_err(error)?;
+        let rest = rest.trim();
+        if !rest.is_empty() && !rest.starts_with('#') {
+            return Err(error(format!("unexpected text after value: {}", rest)));
+        }
+
+        match values.as_slice() {
+            [flag] if flag == "true" => args.push(option),
+            [flag] if flag == "false" => (),
+            _ => {
+                for value in values {
+                    args.push(format!("{}={}", option, value));
+                }
+            }
+        }
+    }
+    Ok(defaults)
+}
+
+/// Convert `SHAWL_*` environment variables into arguments for `shawl run`,
+/// like `SHAWL_STOP_TIMEOUT=5000` for `--stop-timeout=5000`.
+/// `options` lists each long option and whether it takes a value,
+/// and only the ones in `FROM_ENV` are used.
+/// Flags are set by `1` or `true`, and empty variables are ignored.
+fn from_env(options: &[(String, bool)], var: impl Fn(&str) -> Option<String>) -> Vec<String> {
+    let mut args = vec![];
+    for (option, takes_value) in options {
+        if !FROM_ENV.contains(&option.as_str()) {
+            continue;
+        }
+        let name = format!("{}{}", ENV_PREFIX, option.to_uppercase().replace('-', "_"));
+        let Some(value) = var(&name).filter(|x| !x.is_empty()) else {
+            continue;
+        };
+        if *takes_value {
+            args.push(format!("--{}={}", option, value));
+        } else if value == "1" || value.eq_ignore_ascii_case("true") {
+            args.push(format!("--{}", option));
+        }
+    }
+    args
+}
+
+/// Whether a defaults file can be trusted with the options of services that usually run as Local System.
+/// Anyone who could change it could run their own commands through options like `--hook-exec`.
+fn is_trusted(path: &std::path::Path) -> bool {
+    match crate::acl::is_admin_only(path) {
+        Ok(true) => true,
+        Ok(false) => {
+            warn!(
+                "Ignoring defaults from {} because it must be owned by the administrators or SYSTEM and not writable by other accounts",
+                path.display()
+            );
+            false
+        }
+        Err(e) => {
+            warn!("Ignoring defaults from {}: {}", path.display(), e);
+            false
+        }
+    }
+}
+
+/// Read the first trusted defaults file that exists, returning nothing from it if there isn't one,
+/// along with the defaults from the environment.
+pub fn load() -> Result<Defaults, String> {
+    let environment = from_env(&crate::cli::run_options(), |name| std::env::var(name).ok());
+    let Some(path) = candidates().into_iter().find(|x| x.is_file() && is_trusted(x)) else {
+        return Ok(Defaults {
+            environment,
+            ..Default::default()
+        });
+    };
+    std::fs::read_to_string(&path)
+        .map_err(|e| e.to_string())
+        .and_then(|content| parse(&content))
+        .map(|defaults| Defaults {
+            environment,
+            ..defaults
+        })
+        .map_err(|e| format!("Unable to read defaults from {}: {}", path.display(), e))
+}
+
+/// Find the `--profile` option before the command.
+fn selected_profile(args: &[std::ffi::OsString]) -> Option<String> {
+    let mut args = args.iter().map(|x| x.to_string_lossy()).take_while(|x| x != "--");
+    while let Some(arg) = args.next() {
+        if arg == "--profile" {
+            return args.next().map(|x| x.to_string());
+        } else if let Some(name) = arg.strip_prefix("--profile=") {
+            return Some(name.to_string());
+        }
+    }
+    None
+}
+
+/// The long option of an argument, like `stop-timeout` for `--stop-timeout=5000`.
+fn option_name(arg: &str) -> Option<&str> {
+    let option = arg.strip_prefix("--").filter(|x| !x.is_empty())?;
+    Some(option.split_once('=').map_or(option, |(name, _)| name))
+}
+
+/// Remove the arguments that conflict with any of the later ones, like `--no-restart` before `--restart`,
+/// so that the later ones replace them instead of being rejected.
+fn drop_conflicts(args: &mut Vec<String>, later: &[String], conflicts: &[(String, String)]) {
+    let later: Vec<_> = later.iter().filter_map(|x| option_name(x)).collect();
+    args.retain(|arg| {
+        let Some(option) = option_name(arg) else {
+            return true;
+        };
+        !conflicts
+            .iter()
+            .any(|(a, b)| (a == option && later.contains(&b.as_str())) || (b == option && later.contains(&a.as_str())))
+    });
+}
+
+/// Insert the defaults from the file, the environment (with `--defaults-from-env`),
+/// and then the selected profile before the arguments for `shawl run`,
+/// so that each one overrides the ones before it, even when the options conflict. For `shawl add`, only check that the profile exists.
+/// Other subcommands are left alone.
+pub fn apply(mut args: Vec<std::ffi::OsString>, defaults: &Defaults) -> Result<Vec<std::ffi::OsString>, String> {
+    let profile = match selected_profile(args.get(2..).unwrap_or_default()) {
+        Some(name) => defaults.profile(&name)?,
+        None => &[],
+    };
+    if args.get(1).is_some_and(|x| x == "run") {
+        let conflicts = crate::cli::run_conflicts();
+        let explicit: Vec<_> = args[2..]
+            .iter()
+            .map(|x| x.to_string_lossy().to_string())
+            .take_while(|x| x != "--")
+            .collect();
+        let from_env = defaults
+            .common
+            .iter()
+            .chain(profile)
+            .chain(&explicit)
+            .any(|x| x == FROM_ENV_OPTION);
+        let environment = if from_env { &defaults.environment[..] } else { &[] };
+        let mut inserted = vec![];
+        for layer in [&defaults.common[..], environment, profile, &explicit] {
+            drop_conflicts(&mut inserted, layer, &conflicts);
+            inserted.extend(layer.iter().cloned());
+        }
+        inserted.truncate(inserted.len() - explicit.len());
+        args.splice(2..2, inserted.into_iter().map(std::ffi::OsString::from));
+    }
+    Ok(args)
+}
+
+#[cfg(test)]
+speculate::speculate! {
+    fn s(text: &str) -> String {
+        text.to_string()
+    }
+
+    fn os(args: &[&str]) -> Vec<std::ffi::OsString> {
+        args.iter().map(std::ffi::OsString::from).collect()
+    }
+
+    fn common(args: &[&str]) -> Defaults {
+        Defaults {
+            common: args.iter().map(|x| s(x)).collect(),
+            ..Default::default()
+        }
+    }
+
+    describe "parse" {
+        it "converts keys to options" {
+            assert_eq!(
+                parse(concat!(
+                    "# Shared by all services\n",
+                    "log-dir = 'C:\\Logs\\shawl'\n",
+                    "stop_timeout = 5000  # ctrl-C grace period\n",
+                    "restart-delay = 1000\n",
+                    "\n",
+                    "log-rotate = \"daily\"\n",
+                )),
+                Ok(common(&[
+                    "--log-dir=C:\\Logs\\shawl",
+                    "--stop-timeout=5000",
+                    "--restart-delay=1000",
+                    "--log-rotate=daily",
+                ])),
+            );
+        }
+
+        it "handles flags" {
+            assert_eq!(parse("no-restart = true\nevent-log = false\n"), Ok(common(&["--no-restart"])));
+        }
+
+        it "repeats the option for arrays" {
+            assert_eq!(
+                parse(r#"env = ["A=1", 'B=2',]"#),
+                Ok(common(&["--env=A=1", "--env=B=2"])),
+            );
+            assert_eq!(parse("pass = []"), Ok(common(&[])));
+        }
+
+        it "handles escapes" {
+            assert_eq!(parse(r#"env = "A=\"x\"\u0021""#), Ok(common(&["--env=A=\"x\"!"])));
+        }
+
+        it "collects options for profiles" {
+            assert_eq!(
+                parse(concat!(
+                    "stop-timeout = 5000\n",
+                    "[profile.java-app]  # JVMs take a while to stop\n",
+                    "stop-timeout = 30000\n",
+                    "env = ['JAVA_OPTS=-Xmx1g']\n",
+                    "[ profile.web ]\n",
+                )),
+                Ok(Defaults {
+                    common: vec![s("--stop-timeout=5000")],
+                    profiles: vec![
+                        (s("java-app"), vec![s("--stop-timeout=30000"), s("--env=JAVA_OPTS=-Xmx1g")]),
+                        (s("web"), vec![]),
+                    ],
+                    ..Default::default()
+                }),
+            );
+        }
+
+        it "reports the line with an error" {
+            assert_eq!(
+                parse("\n[service]\n"),
+                Err(s("line 2: unknown table (expected [profile.<name>]): service")),
+            );
+            assert_eq!(parse("[profile.a]\n[profile.a]"), Err(s("line 2: duplicate profile: a")));
+            assert_eq!(parse("[profile.a"), Err(s("line 1: unterminated table header")));
+            assert_eq!(
+                parse("[profile.a]\nprofile = 'b'"),
+                Err(s("line 2: profiles cannot select other profiles")),
+            );
+            assert_eq!(parse("log-dir"), Err(s("line 1: expected `key = value`")));
+            assert_eq!(parse("log-dir = 'C:\\Logs"), Err(s("line 1: unterminated string")));
+            assert_eq!(parse("a b = 1"), Err(s("line 1: invalid key: a b")));
+            assert_eq!(parse("stop-timeout = 5 000"), Err(s("line 1: unexpected text after value: 000")));
+            assert_eq!(parse("env = [\"A=1\" \"B=2\"]"), Err(s("line 1: expected `,` or `]` in array")));
+            assert_eq!(parse("env ="), Err(s("line 1: expected a value")));
+        }
+    }
+
+    describe "from_env" {
+        fn options() -> Vec<(String, bool)> {
+            vec![
+                (s("name"), true),
+                (s("stop-timeout"), true),
+                (s("log-rotate"), true),
+                (s("log-utc"), false),
+                (s("log-rfc3339"), false),
+                (s("log-dir"), true),
+                (s("hook-exec"), true),
+            ]
+        }
+
+        it "converts variables to options" {
+            let args = from_env(&options(), |name| match name {
+                "SHAWL_STOP_TIMEOUT" => Some(s("5000")),
+                "SHAWL_LOG_ROTATE" => Some(s("daily")),
+                "SHAWL_LOG_UTC" => Some(s("TRUE")),
+                "SHAWL_LOG_RFC3339" => Some(s("0")),
+                _ => None,
+            });
+            assert_eq!(args, vec![s("--stop-timeout=5000"), s("--log-rotate=daily"), s("--log-utc")]);
+        }
+
+        it "only allows real options" {
+            let options: Vec<_> = crate::cli::run_options().into_iter().map(|(option, _)| option).collect();
+            for option in FROM_ENV {
+                assert!(options.iter().any(|x| x == option), "{}", option);
+            }
+        }
+
+        it "ignores empty variables and unsafe options" {
+            let args = from_env(&options(), |name| match name {
+                "SHAWL_NAME" => Some(s("app")),
+                "SHAWL_STOP_TIMEOUT" => Some(s("")),
+                "SHAWL_LOG_DIR" => Some(s("C:\\Windows")),
+                "SHAWL_HOOK_EXEC" => Some(s("calc.exe")),
+                _ => None,
+            });
+            assert_eq!(args, Vec::<String>::new());
+        }
+    }
+
+    describe "apply" {
+        it "inserts the defaults after the run subcommand" {
+            assert_eq!(
+                apply(os(&["shawl", "run", "--name", "app", "--", "foo"]), &common(&["--stop-timeout=5000"])),
+                Ok(os(&["shawl", "run", "--stop-timeout=5000", "--name", "app", "--", "foo"])),
+            );
+        }
+
+        it "inserts the selected profile after the defaults" {
+            let defaults = Defaults {
+                common: vec![s("--stop-timeout=5000")],
+                profiles: vec![(s("java-app"), vec![s("--stop-timeout=30000")])],
+                ..Default::default()
+            };
+            assert_eq!(
+                apply(os(&["shawl", "run", "--profile", "java-app", "--", "foo"]), &defaults),
+                Ok(os(&["shawl", "run", "--stop-timeout=5000", "--stop-timeout=30000", "--profile", "java-app", "--", "foo"])),
+            );
+            assert_eq!(
+                apply(os(&["shawl", "run", "--profile=java-app", "--", "foo"]), &defaults),
+                Ok(os(&["shawl", "run", "--stop-timeout=5000", "--stop-timeout=30000", "--profile=java-app", "--", "foo"])),
+            );
+        }
+
+        it "inserts the environment between the defaults and the profile" {
+            let defaults = Defaults {
+                common: vec![s("--stop-timeout=5000")],
+                environment: vec![s("--stop-timeout=10000")],
+                profiles: vec![(s("java-app"), vec![s("--stop-timeout=30000")])],
+            };
+            assert_eq!(
+                apply(os(&["shawl", "run", "--defaults-from-env", "--profile", "java-app", "--", "foo"]), &defaults),
+                Ok(os(&[
+                    "shawl",
+                    "run",
+                    "--stop-timeout=5000",
+                    "--stop-timeout=10000",
+                    "--stop-timeout=30000",
+                    "--defaults-from-env",
+                    "--profile",
+                    "java-app",
+                    "--",
+                    "foo",
+                ])),
+            );
+        }
+
+        it "only uses the environment with --defaults-from-env" {
+            let defaults = Defaults {
+                environment: vec![s("--stop-timeout=10000")],
+                ..Default::default()
+            };
+            assert_eq!(
+                apply(os(&["shawl", "run", "--", "foo", "--defaults-from-env"]), &defaults),
+                Ok(os(&["shawl", "run", "--", "foo", "--defaults-from-env"])),
+            );
+        }
+
+        it "lets later options replace conflicting defaults" {
+            let defaults = Defaults {
+                common: vec![s("--no-restart"), s("--stop-timeout=5000")],
+                profiles: vec![(s("flaky"), vec![s("--restart")])],
+                ..Default::default()
+            };
+            assert_eq!(
+                apply(os(&["shawl", "run", "--profile", "flaky", "--", "foo"]), &defaults),
+                Ok(os(&["shawl", "run", "--stop-timeout=5000", "--restart", "--profile", "flaky", "--", "foo"])),
+            );
+            assert_eq!(
+                apply(os(&["shawl", "run", "--restart-if", "1", "--", "foo", "--restart"]), &common(&["--no-restart"])),
+                Ok(os(&["shawl", "run", "--restart-if", "1", "--", "foo", "--restart"])),
+            );
+        }
+
+        it "ignores --profile in the command" {
+            assert_eq!(
+                apply(os(&["shawl", "run", "--", "foo", "--profile", "x"]), &Defaults::default()),
+                Ok(os(&["shawl", "run", "--", "foo", "--profile", "x"])),
+            );
+        }
+
+        it "rejects an unknown profile" {
+            assert_eq!(
+                apply(os(&["shawl", "run", "--profile", "java-app", "--", "foo"]), &Defaults::default()),
+                Err(s("unknown profile: java-app")),
+            );
+            assert_eq!(
+                apply(os(&["shawl", "add", "--profile", "java-app", "--", "foo"]), &Defaults::default()),
+                Err(s("unknown profile: java-app")),
+            );
+        }
+
+        it "leaves other subcommands alone" {
+            let args = os(&["shawl", "add", "--name", "app", "--", "foo"]);
+            assert_eq!(apply(args.clone(), &common(&["--stop-timeout=5000"])), Ok(args));
+        }
+    }
+}
@@ -0,0 +1,84 @@
+use windows::{
+    core::{HSTRING, PCWSTR},
+    Win32::{
+        Foundation::{CloseHandle, HANDLE},
+        Security::{GetTokenInformation, TokenElevation, TOKEN_ELEVATION, TOKEN_QUERY},
+        System::Threading::{GetCurrentProcess, GetExitCodeProcess, OpenProcessToken, WaitForSingleObject, INFINITE},
+        UI::{
+            Shell::{ShellExecuteExW, SEE_MASK_NOCLOSEPROCESS, SHELLEXECUTEINFOW},
+            WindowsAndMessaging::SW_SHOWNORMAL,
+        },
+    },
+};
+
+/// Win32 error when the user declines the UAC prompt.
+const ERROR_CANCELLED: u32 = 1223;
+
+/// Check whether Shawl is running as an administrator, which `shawl add` needs.
+pub fn is_elevated() -> bool {
+    unsafe {
+        let mut token = HANDLE::default();
+        if OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token).is_err() {
+            return false;
+        }
+        let mut elevation = TOKEN_ELEVATION::default();
+        let mut size = 0;
+        let result = GetTokenInformation(
+            token,
+            TokenElevation,
+            Some(&mut elevation as *mut TOKEN_ELEVATION as *mut _),
+            std::mem::size_of::<TOKEN_ELEVATION>() as u32,
+            &mut size,
+        );
+        let _ = CloseHandle(token);
+        result.is_ok() && elevation.TokenIsElevated != 0
+    }
+}
+
+/// Check whether Shawl is the only process attached to its console,
+/// which means that the console is a window of its own, like after `relaunch`,
+/// and closes as soon as Shawl exits.
+pub fn owns_console() -> bool {
+    use windows::Win32::System::Console::GetConsoleProcessList;
+
+    let mut processes = [0u32; 2];
+    unsafe { GetConsoleProcessList(&mut processes) == 1 }
+}
+
+/// Run Shawl again as an administrator through a UAC prompt,
+/// returning its exit code once it finishes.
+/// The arguments must already be quoted for the command line.
+pub fn relaunch(args: &[String]) -> Result<i32, String> {
+    let exe = std::env::current_exe().map_err(|e| format!("Unable to determine Shawl location: {}", e))?;
+    let cwd = std::env::current_dir().map_err(|e| format!("Unable to determine the current directory: {}", e))?;
+
+    let verb = HSTRING::from("runas");
+    let file = HSTRING::from(exe.as_os_str());
+    let parameters = HSTRING::from(args.join(" "));
+    let directory = HSTRING::from(cwd.as_os_str());
+    let mut info = SHELLEXECUTEINFOW {
+        cbSize: std::mem::size_of::<SHELLEXECUTEINFOW>() as u32,
+        fMask: SEE_MASK_NOCLOSEPROCESS,
+        lpVerb: PCWSTR(verb.as_ptr()),
+        lpFile: PCWSTR(file.as_ptr()),
+        lpParameters: PCWSTR(parameters.as_ptr()),
+        lpDirectory: PCWSTR(directory.as_ptr()),
+        nShow: SW_SHOWNORMAL.0,
+        ..Default::default()
+    };
+
+    unsafe {
+        if let Err(e) = ShellExecuteExW(&mut info) {
+            return Err(match crate::exitcode::win32_from_hresult(e.code().0) {
+                Some(ERROR_CANCELLED) => "The request to run as an administrator was declined".to_string(),
+                _ => format!("Unable to run as an administrator: {}", e),
+            });
+        }
+        WaitForSingleObject(info.hProcess, INFINITE);
+        let mut code = 0;
+        let result = GetExitCodeProcess(info.hProcess, &mut code);
+        let _ = CloseHandle(info.hProcess);
+        result.map_err(|e| format!("Unable to get the result from the elevated Shawl: {}", e))?;
+        Ok(code as i32)
+    }
+}
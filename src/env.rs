@@ -0,0 +1,517 @@
+/// Read and parse a `.env` file.
+pub fn read_env_file(path: &str) -> std::io::Result<Vec<(String, String)>> {
+    let content = std::fs::read_to_string(path)?;
+    parse_env_file(&content).map_err(|e| {
+        std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("Unable to parse env file '{}': {}", path, e),
+        )
+    })
+}
+
+/// Read a secret from a generic credential in the Windows Credential Manager.
+/// The credential must belong to the account that the service runs as.
+#[cfg(windows)]
+pub fn read_credential(name: &str) -> std::io::Result<String> {
+    use windows::Win32::Security::Credentials::{CredFree, CredReadW, CREDENTIALW, CRED_TYPE_GENERIC};
+
+    unsafe {
+        let mut credential: *mut CREDENTIALW = std::ptr::null_mut();
+        CredReadW(
+            &windows::core::HSTRING::from(name),
+            CRED_TYPE_GENERIC,
+            0,
+            &mut credential,
+        )?;
+
+        let blob = (*credential).CredentialBlob;
+        let size = (*credential).CredentialBlobSize as usize;
+        let blob = if blob.is_null() || size == 0 {
+            &[]
+        } else {
+            std::slice::from_raw_parts(blob, size)
+        };
+        let secret = decode_credential_blob(blob);
+
+        CredFree(credential as *const std::ffi::c_void);
+        secret.map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// Decode the secret of a generic credential, which Windows tools like `cmdkey`
+/// and the Credential Manager store as UTF-16, sometimes with a trailing null.
+fn decode_credential_blob(blob: &[u8]) -> Result<String, String> {
+    if !blob.len().is_multiple_of(2) {
+        return Err("The credential's secret is not UTF-16 text".to_string());
+    }
+    let mut wide: Vec<u16> = blob.chunks_exact(2).map(|x| u16::from_le_bytes([x[0], x[1]])).collect();
+    if wide.last() == Some(&0) {
+        wide.pop();
+    }
+    String::from_utf16(&wide).map_err(|_| "The credential's secret is not UTF-16 text".to_string())
+}
+
+/// Build a fresh copy of the system and user environment variables from the registry,
+/// for the account that the service runs as.
+#[cfg(windows)]
+pub fn read_registry_environment() -> std::io::Result<Vec<(String, String)>> {
+    use windows::Win32::{
+        Foundation::{CloseHandle, HANDLE},
+        Security::TOKEN_QUERY,
+        System::Threading::{GetCurrentProcess, OpenProcessToken},
+    };
+
+    unsafe {
+        let mut token = HANDLE::default();
+        OpenProcessToken(GetCurrentProcess(), TOKEN_QUERY, &mut token)?;
+        let vars = read_token_environment(token);
+        let _ = CloseHandle(token);
+        vars
+    }
+}
+
+/// The profile of the account that the service runs as, which stays loaded until dropped.
+#[cfg(windows)]
+pub struct UserProfile {
+    token: windows::Win32::Foundation::HANDLE,
+    profile: windows::Win32::Foundation::HANDLE,
+}
+
+#[cfg(windows)]
+impl Drop for UserProfile {
+    fn drop(&mut self) {
+        use windows::Win32::{Foundation::CloseHandle, UI::Shell::UnloadUserProfile};
+
+        unsafe {
+            let _ = UnloadUserProfile(self.token, self.profile);
+            let _ = CloseHandle(self.token);
+        }
+    }
+}
+
+/// Load the profile of the account that the service runs as,
+/// so that its registry hive and folders like `APPDATA` are available.
+/// Windows does not do this for services running as a named account.
+#[cfg(windows)]
+pub fn load_user_profile() -> std::io::Result<UserProfile> {
+    use windows::{
+        core::PWSTR,
+        Win32::{
+            Foundation::{CloseHandle, HANDLE},
+            Security::{TOKEN_DUPLICATE, TOKEN_IMPERSONATE, TOKEN_QUERY},
+            System::{
+                Threading::{GetCurrentProcess, OpenProcessToken},
+                WindowsProgramming::GetUserNameW,
+            },
+            UI::Shell::{LoadUserProfileW, PROFILEINFOW},
+        },
+    };
+
+    const PI_NOUI: u32 = 0x1;
+
+    unsafe {
+        let mut user_name = [0u16; 257];
+        let mut size = user_name.len() as u32;
+        GetUserNameW(PWSTR(user_name.as_mut_ptr()), &mut size)?;
+
+        let mut token = HANDLE::default();
+        OpenProcessToken(
+            GetCurrentProcess(),
+            TOKEN_QUERY | TOKEN_IMPERSONATE | TOKEN_DUPLICATE,
+            &mut token,
+        )?;
+
+        let mut info = PROFILEINFOW {
+            dwSize: std::mem::size_of::<PROFILEINFOW>() as u32,
+            dwFlags: PI_NOUI,
+            lpUserName: PWSTR(user_name.as_mut_ptr()),
+            ..Default::default()
+        };
+        if let Err(e) = LoadUserProfileW(token, &mut info) {
+            let _ = CloseHandle(token);
+            return Err(e.into());
+        }
+
+        Ok(UserProfile {
+            token,
+            profile: info.hProfile,
+        })
+    }
+}
+
+/// Build the system and user environment variables for the account of a token.
+#[cfg(windows)]
+pub fn read_token_environment(token: windows::Win32::Foundation::HANDLE) -> std::io::Result<Vec<(String, String)>> {
+    use windows::Win32::System::Environment::{CreateEnvironmentBlock, DestroyEnvironmentBlock};
+
+    unsafe {
+        let mut block: *mut std::ffi::c_void = std::ptr::null_mut();
+        CreateEnvironmentBlock(&mut block, token, false)?;
+
+        // The block ends with an empty string, so find the first pair of nulls.
+        let start = block as *const u16;
+        let mut len = 0;
+        while *start.add(len) != 0 || *start.add(len + 1) != 0 {
+            len += 1;
+        }
+        let vars = parse_environment_block(std::slice::from_raw_parts(start, len + 1));
+
+        let _ = DestroyEnvironmentBlock(block);
+        Ok(vars)
+    }
+}
+
+/// Find the executable file for a program.
+/// Paths with a directory component are resolved against the working directory,
+/// while bare names are searched for in the working directory and then in `PATH`.
+/// Names without an extension are tried with each of the given `PATHEXT` extensions.
+pub fn resolve_program(
+    program: &str,
+    cwd: Option<&str>,
+    path: Option<&str>,
+    extensions: &str,
+) -> Option<std::path::PathBuf> {
+    let program_path = std::path::Path::new(program);
+    let has_dir = program_path.is_absolute() || program_path.components().count() > 1;
+    let program_path: std::path::PathBuf = program_path
+        .components()
+        .filter(|x| !matches!(x, std::path::Component::CurDir))
+        .collect();
+
+    let dirs: Vec<std::path::PathBuf> = if has_dir {
+        vec![cwd.map(std::path::PathBuf::from).unwrap_or_default()]
+    } else {
+        cwd.into_iter()
+            .chain(path.unwrap_or_default().split(';'))
+            .filter(|x| !x.is_empty())
+            .map(std::path::PathBuf::from)
+            .collect()
+    };
+
+    dirs.into_iter()
+        .map(|dir| dir.join(&program_path))
+        .flat_map(|base| -> Vec<std::path::PathBuf> {
+            if base.extension().is_some() {
+                vec![base]
+            } else {
+                extensions
+                    .split(';')
+                    .filter(|x| !x.is_empty())
+                    .map(|extension| {
+                        let mut name = base.clone().into_os_string();
+                        name.push(extension.to_lowercase());
+                        std::path::PathBuf::from(name)
+                    })
+                    .collect()
+            }
+        })
+        .find(|x| x.is_file())
+}
+
+/// Check if an environment variable name matches a pattern,
+/// where `*` matches any sequence of characters and `?` matches any single character.
+/// Like environment variable names on Windows, this is case-insensitive.
+pub fn matches_wildcard(pattern: &str, name: &str) -> bool {
+    fn matches(pattern: &[char], name: &[char]) -> bool {
+        match (pattern.first(), name.first()) {
+            (None, None) => true,
+            (Some('*'), _) => matches(&pattern[1..], name) || (!name.is_empty() && matches(pattern, &name[1..])),
+            (Some('?'), Some(_)) => matches(&pattern[1..], &name[1..]),
+            (Some(p), Some(n)) if p.eq_ignore_ascii_case(n) => matches(&pattern[1..], &name[1..]),
+            _ => false,
+        }
+    }
+
+    let pattern: Vec<_> = pattern.chars().collect();
+    let name: Vec<_> = name.chars().collect();
+    matches(&pattern, &name)
+}
+
+/// Parse null-separated `KEY=value` strings from a Windows environment block.
+/// Entries whose names begin with `=`, which track per-drive directories, are skipped.
+pub fn parse_environment_block(block: &[u16]) -> Vec<(String, String)> {
+    block
+        .split(|x| *x == 0)
+        .map(String::from_utf16_lossy)
+        .filter_map(|entry| {
+            let (key, value) = entry.split_once('=')?;
+            if key.is_empty() {
+                return None;
+            }
+            Some((key.to_string(), value.to_string()))
+        })
+        .collect()
+}
+
+/// Apply changes to a list of environment variables, matching names case-insensitively.
+/// A change without a value removes the variable.
+pub fn merge_environment(
+    mut vars: Vec<(String, String)>,
+    changes: &[(String, Option<String>)],
+) -> Vec<(String, String)> {
+    for (key, value) in changes {
+        vars.retain(|(existing, _)| !existing.eq_ignore_ascii_case(key));
+        if let Some(value) = value {
+            vars.push((key.clone(), value.clone()));
+        }
+    }
+    vars
+}
+
+/// Encode variables as a Unicode environment block for `CreateProcess`,
+/// sorted by name as Windows expects.
+pub fn encode_environment_block(vars: &[(String, String)]) -> Vec<u16> {
+    let mut vars: Vec<_> = vars.iter().collect();
+    vars.sort_by_key(|(key, _)| key.to_uppercase());
+
+    let mut block = vec![];
+    for (key, value) in vars {
+        block.extend(format!("{}={}", key, value).encode_utf16());
+        block.push(0);
+    }
+    if block.is_empty() {
+        block.push(0);
+    }
+    block.push(0);
+    block
+}
+
+/// Parse `KEY=value` lines from the contents of a `.env` file.
+///
+/// Blank lines and lines starting with `#` are ignored, and a leading `export` is allowed.
+/// Values may be wrapped in single quotes (taken literally) or double quotes
+/// (supporting `\n`, `\"`, and `\\` escapes). Unquoted values may end with a ` #` comment.
+pub fn parse_env_file(content: &str) -> Result<Vec<(String, String)>, String> {
+    let mut vars = vec![];
+
+    for (index, line) in content.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let line = line.strip_prefix("export ").map(str::trim_start).unwrap_or(line);
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| format!("line {}: expected KEY=value", index + 1))?;
+        let key = key.trim();
+        if key.is_empty() || key.contains(char::is_whitespace) {
+            return Err(format!("line {}: invalid key '{}'", index + 1, key));
+        }
+
+        let value = parse_env_value(value.trim()).map_err(|e| format!("line {}: {}", index + 1, e))?;
+        vars.push((key.to_string(), value));
+    }
+
+    Ok(vars)
+}
+
+fn parse_env_value(value: &str) -> Result<String, String> {
+    let mut chars = value.chars();
+    match chars.next() {
+        Some(quote @ ('"' | '\'')) => {
+            let mut parsed = String::new();
+            loop {
+                match chars.next() {
+                    None => return Err(format!("unterminated {} quote", quote)),
+                    Some(c) if c == quote => break,
+                    Some('\\') if quote == '"' => match chars.next() {
+                        Some('n') => parsed.push('\n'),
+                        Some('r') => parsed.push('\r'),
+                        Some('t') => parsed.push('\t'),
+                        Some(c @ ('"' | '\\')) => parsed.push(c),
+                        Some(c) => {
+                            parsed.push('\\');
+                            parsed.push(c);
+                        }
+                        None => return Err(format!("unterminated {} quote", quote)),
+                    },
+                    Some(c) => parsed.push(c),
+                }
+            }
+            let rest = chars.as_str().trim_start();
+            if !rest.is_empty() && !rest.starts_with('#') {
+                return Err(format!("unexpected text after quoted value: {}", rest));
+            }
+            Ok(parsed)
+        }
+        _ => {
+            let value = match value.find(" #") {
+                Some(comment) => &value[..comment],
+                None => value,
+            };
+            Ok(value.trim_end().to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+speculate::speculate! {
+    fn s(text: &str) -> String {
+        text.to_string()
+    }
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("shawl-env-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("bin")).unwrap();
+        std::fs::create_dir_all(dir.join("app")).unwrap();
+        std::fs::write(dir.join("bin/tool.exe"), "").unwrap();
+        std::fs::write(dir.join("app/server.exe"), "").unwrap();
+        dir
+    }
+
+    describe "decode_credential_blob" {
+        it "decodes UTF-16 text" {
+            let blob: Vec<u8> = "pässword".encode_utf16().flat_map(|x| x.to_le_bytes()).collect();
+            assert_eq!(decode_credential_blob(&blob), Ok(s("pässword")));
+        }
+
+        it "drops a trailing null" {
+            assert_eq!(decode_credential_blob(&[b'a', 0, 0, 0]), Ok(s("a")));
+        }
+
+        it "accepts an empty secret" {
+            assert_eq!(decode_credential_blob(&[]), Ok(s("")));
+        }
+
+        it "rejects other data" {
+            assert!(decode_credential_blob(b"abc").is_err());
+            assert!(decode_credential_blob(&[0x00, 0xD8]).is_err());
+        }
+    }
+
+    describe "matches_wildcard" {
+        it "matches exact names case-insensitively" {
+            assert!(matches_wildcard("JAVA_TOOL_OPTIONS", "java_tool_options"));
+            assert!(!matches_wildcard("JAVA_TOOL_OPTIONS", "JAVA_TOOL"));
+        }
+
+        it "supports wildcards" {
+            assert!(matches_wildcard("*_PROXY", "HTTPS_PROXY"));
+            assert!(matches_wildcard("*_proxy", "no_proxy"));
+            assert!(!matches_wildcard("*_PROXY", "PROXY"));
+            assert!(matches_wildcard("JAVA_*", "JAVA_HOME"));
+            assert!(matches_wildcard("TM?", "TMP"));
+            assert!(!matches_wildcard("TM?", "TEMP"));
+            assert!(matches_wildcard("*", "ANYTHING"));
+        }
+    }
+
+    describe "resolve_program" {
+        it "resolves relative paths against the working directory" {
+            let dir = temp_dir("relative");
+            let cwd = dir.join("app").to_string_lossy().to_string();
+            assert_eq!(
+                resolve_program("./server.exe", Some(&cwd), None, ".EXE"),
+                Some(dir.join("app").join("server.exe")),
+            );
+            assert_eq!(
+                resolve_program("app/server", Some(&dir.to_string_lossy()), None, ".COM;.EXE"),
+                Some(dir.join("app/server.exe")),
+            );
+        }
+
+        it "searches the working directory before PATH" {
+            let dir = temp_dir("search");
+            std::fs::write(dir.join("app/tool.exe"), "").unwrap();
+            let cwd = dir.join("app").to_string_lossy().to_string();
+            let path = format!("{};{}", dir.join("missing").display(), dir.join("bin").display());
+            assert_eq!(
+                resolve_program("tool", Some(&cwd), Some(&path), ".EXE"),
+                Some(dir.join("app").join("tool.exe")),
+            );
+            assert_eq!(
+                resolve_program("tool", None, Some(&path), ".EXE"),
+                Some(dir.join("bin").join("tool.exe")),
+            );
+        }
+
+        it "returns nothing for unknown programs" {
+            let dir = temp_dir("unknown");
+            let path = dir.join("bin").to_string_lossy().to_string();
+            assert_eq!(resolve_program("missing", None, Some(&path), ".EXE"), None);
+            assert_eq!(resolve_program("tool.bat", None, Some(&path), ".EXE"), None);
+        }
+    }
+
+    describe "parse_environment_block" {
+        it "splits entries on nulls" {
+            let block: Vec<u16> = "FOO=bar\0Path=C:\\a;C:\\b\0=C:=C:\\\0EMPTY=\0\0".encode_utf16().collect();
+            assert_eq!(
+                parse_environment_block(&block),
+                vec![(s("FOO"), s("bar")), (s("Path"), s("C:\\a;C:\\b")), (s("EMPTY"), s(""))],
+            );
+        }
+    }
+
+    describe "merge_environment" {
+        it "replaces and removes variables case-insensitively" {
+            assert_eq!(
+                merge_environment(
+                    vec![(s("Path"), s("C:\\a")), (s("FOO"), s("bar")), (s("TEMP"), s("C:\\tmp"))],
+                    &[(s("PATH"), Some(s("C:\\b"))), (s("temp"), None), (s("NEW"), Some(s("1")))],
+                ),
+                vec![(s("FOO"), s("bar")), (s("PATH"), s("C:\\b")), (s("NEW"), s("1"))],
+            );
+        }
+    }
+
+    describe "encode_environment_block" {
+        it "sorts entries and ends with two nulls" {
+            let block = encode_environment_block(&[(s("foo"), s("1")), (s("BAR"), s("2"))]);
+            assert_eq!(String::from_utf16_lossy(&block), "BAR=2\0foo=1\0\0");
+            assert_eq!(parse_environment_block(&block), vec![(s("BAR"), s("2")), (s("foo"), s("1"))]);
+        }
+
+        it "handles an empty environment" {
+            assert_eq!(encode_environment_block(&[]), vec![0, 0]);
+        }
+    }
+
+    describe "parse_env_file" {
+        it "parses simple assignments" {
+            assert_eq!(
+                parse_env_file("FOO=bar\nBAZ = qux\nEMPTY=\n"),
+                Ok(vec![(s("FOO"), s("bar")), (s("BAZ"), s("qux")), (s("EMPTY"), s(""))]),
+            );
+        }
+
+        it "ignores comments and blank lines" {
+            assert_eq!(
+                parse_env_file("# comment\n\n  # indented\nFOO=bar # trailing\nURL=http://x/#anchor\n"),
+                Ok(vec![(s("FOO"), s("bar")), (s("URL"), s("http://x/#anchor"))]),
+            );
+        }
+
+        it "allows a leading export" {
+            assert_eq!(parse_env_file("export FOO=bar"), Ok(vec![(s("FOO"), s("bar"))]));
+        }
+
+        it "handles double quotes with escapes" {
+            assert_eq!(
+                parse_env_file(r#"FOO="a \"b\" # c\nd" # comment"#),
+                Ok(vec![(s("FOO"), s("a \"b\" # c\nd"))]),
+            );
+        }
+
+        it "handles single quotes literally" {
+            assert_eq!(
+                parse_env_file(r"FOO='C:\new\path'"),
+                Ok(vec![(s("FOO"), s(r"C:\new\path"))]),
+            );
+        }
+
+        it "splits on the first equals sign" {
+            assert_eq!(parse_env_file("FOO=a=b"), Ok(vec![(s("FOO"), s("a=b"))]));
+        }
+
+        it "rejects invalid lines" {
+            assert!(parse_env_file("FOO").is_err());
+            assert!(parse_env_file("=bar").is_err());
+            assert!(parse_env_file("FOO BAR=baz").is_err());
+            assert!(parse_env_file("FOO=\"bar").is_err());
+            assert!(parse_env_file("FOO=\"bar\" baz").is_err());
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use crate::{eventlog::Event, logformat::json_string};
+
+static EVENT_FILE: std::sync::OnceLock<EventFile> = std::sync::OnceLock::new();
+
+struct EventFile {
+    service: String,
+    file: std::sync::Mutex<std::fs::File>,
+}
+
+/// Build one line of the file for an event, with details that depend on the kind of event.
+fn line(service: &str, event: &Event, time: std::time::SystemTime) -> String {
+    let mut fields = vec![
+        ("time", crate::statusfile::unix_time(time)),
+        ("service", json_string(service)),
+        ("event", json_string(event.name())),
+    ];
+    match event {
+        Event::Launched { pid } => fields.push(("pid", pid.to_string())),
+        Event::LaunchFailed { error } => fields.push(("error", json_string(error))),
+        Event::Exited { code } => fields.push(("exit_code", code.to_string())),
+        Event::Crashed { code, dump } => {
+            fields.push(("exit_code", code.to_string()));
+            if let Some(dump) = dump {
+                fields.push(("dump", json_string(dump)));
+            }
+        }
+        Event::Restarting { reason } => fields.push(("reason", json_string(reason))),
+        Event::Stopping | Event::Killed | Event::Output { .. } => (),
+    }
+    let fields: Vec<_> = fields
+        .into_iter()
+        .map(|(key, value)| format!("\"{}\":{}", key, value))
+        .collect();
+    format!("{{{}}}\n", fields.join(","))
+}
+
+/// Start appending lifecycle events to the file for `--event-file`.
+pub fn open(service: &str, path: Option<&str>) {
+    let Some(path) = path else {
+        return;
+    };
+    match std::fs::OpenOptions::new().create(true).append(true).open(path) {
+        Ok(file) => {
+            let _ = EVENT_FILE.set(EventFile {
+                service: service.to_string(),
+                file: std::sync::Mutex::new(file),
+            });
+        }
+        Err(e) => log::error!("Unable to open event file {}: {:?}", path, e),
+    }
+}
+
+/// Append a lifecycle event to the file, if it was opened with `open`.
+/// The command's output is left to the logs.
+pub fn report(event: &Event) {
+    use std::io::Write;
+
+    let Some(event_file) = EVENT_FILE.get() else {
+        return;
+    };
+    if matches!(event, Event::Output { .. }) {
+        return;
+    }
+
+    let line = line(&event_file.service, event, std::time::SystemTime::now());
+    if let Ok(mut file) = event_file.file.lock() {
+        if let Err(e) = file.write_all(line.as_bytes()) {
+            log::error!("Unable to write to the event file: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+speculate::speculate! {
+    describe "line" {
+        it "includes the details of the event" {
+            let time = std::time::UNIX_EPOCH + std::time::Duration::from_millis(1_700_000_000_250);
+            assert_eq!(
+                line("app", &Event::Launched { pid: 42 }, time),
+                "{\"time\":1700000000.250,\"service\":\"app\",\"event\":\"launched\",\"pid\":42}\n",
+            );
+            assert_eq!(
+                line("app", &Event::Crashed { code: -1073741819, dump: Some("C:\\dumps\\app.dmp".to_string()) }, time),
+                "{\"time\":1700000000.250,\"service\":\"app\",\"event\":\"crashed\",\"exit_code\":-1073741819,\"dump\":\"C:\\\\dumps\\\\app.dmp\"}\n",
+            );
+            assert_eq!(
+                line("app", &Event::Restarting { reason: "exited with code 1".to_string() }, time),
+                "{\"time\":1700000000.250,\"service\":\"app\",\"event\":\"restarting\",\"reason\":\"exited with code 1\"}\n",
+            );
+        }
+
+        it "has no details for stops and kills" {
+            assert_eq!(
+                line("app", &Event::Killed, std::time::UNIX_EPOCH),
+                "{\"time\":0.000,\"service\":\"app\",\"event\":\"killed\"}\n",
+            );
+        }
+    }
+}
@@ -0,0 +1,274 @@
+use windows::{
+    core::{HSTRING, PCWSTR},
+    Win32::{Foundation::HANDLE, System::EventLog},
+};
+
+static EVENT_LOG: std::sync::OnceLock<Source> = std::sync::OnceLock::new();
+
+/// A lifecycle event of the command, reported to the Application log.
+/// Each kind of event has its own ID so that it can be filtered on.
+#[derive(Debug, Eq, PartialEq)]
+pub enum Event {
+    Launched {
+        pid: u32,
+    },
+    LaunchFailed {
+        error: String,
+    },
+    Exited {
+        code: i32,
+    },
+    Crashed {
+        code: i32,
+        dump: Option<String>,
+    },
+    Restarting {
+        reason: String,
+    },
+    Stopping,
+    Killed,
+    Output {
+        stream: crate::output::Stream,
+        line: String,
+    },
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum Level {
+    Information,
+    Warning,
+    Error,
+}
+
+impl Event {
+    fn id(&self) -> u32 {
+        match self {
+            Self::Launched { .. } => 1,
+            Self::LaunchFailed { .. } => 2,
+            Self::Exited { .. } => 3,
+            Self::Crashed { .. } => 4,
+            Self::Restarting { .. } => 5,
+            Self::Stopping => 6,
+            Self::Killed => 7,
+            Self::Output {
+                stream: crate::output::Stream::Stdout,
+                ..
+            } => 8,
+            Self::Output {
+                stream: crate::output::Stream::Stderr,
+                ..
+            } => 9,
+        }
+    }
+
+    /// A stable name for the kind of event, for machine-readable output.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Launched { .. } => "launched",
+            Self::LaunchFailed { .. } => "launch_failed",
+            Self::Exited { .. } => "exited",
+            Self::Crashed { .. } => "crashed",
+            Self::Restarting { .. } => "restarting",
+            Self::Stopping => "stopping",
+            Self::Killed => "killed",
+            Self::Output { .. } => "output",
+        }
+    }
+
+    pub fn level(&self) -> Level {
+        match self {
+            Self::Launched { .. } | Self::Restarting { .. } | Self::Stopping => Level::Information,
+            Self::Exited { code: 0 } => Level::Information,
+            Self::Exited { .. } | Self::Killed => Level::Warning,
+            Self::LaunchFailed { .. } | Self::Crashed { .. } => Level::Error,
+            Self::Output { stream, .. } => match stream {
+                crate::output::Stream::Stdout => Level::Information,
+                crate::output::Stream::Stderr => Level::Error,
+            },
+        }
+    }
+
+    pub fn message(&self, service: &str) -> String {
+        match self {
+            Self::Launched { pid } => format!("{}: Launched command as process {}", service, pid),
+            Self::LaunchFailed { error } => format!("{}: Unable to launch command: {}", service, error),
+            Self::Exited { code } => format!(
+                "{}: Command exited with code {}",
+                service,
+                crate::exitcode::describe(*code)
+            ),
+            Self::Crashed { code, dump: Some(dump) } => format!(
+                "{}: Command crashed with code {}; dump: {}",
+                service,
+                crate::exitcode::describe(*code),
+                dump
+            ),
+            Self::Crashed { code, dump: None } => format!(
+                "{}: Command crashed with code {}",
+                service,
+                crate::exitcode::describe(*code)
+            ),
+            Self::Restarting { reason } => format!("{}: Restarting command: {}", service, reason),
+            Self::Stopping => format!("{}: Stopping command", service),
+            Self::Killed => format!("{}: Killed command because it did not stop in time", service),
+            Self::Output { stream, line } => format!("{} {}: {}", service, stream.name(), line),
+        }
+    }
+}
+
+/// Registry key under which Windows looks up an event source's settings.
+fn source_key(name: &str) -> String {
+    format!("SYSTEM\\CurrentControlSet\\Services\\EventLog\\Application\\{}", name)
+}
+
+/// Message file whose every event ID is just the first inserted string (`%1`).
+/// It comes with the .NET Framework, which is part of every supported version of Windows,
+/// so Event Viewer can show our messages without Shawl having its own message table.
+const MESSAGE_FILE: &str = "%SystemRoot%\\Microsoft.NET\\Framework\\v4.0.30319\\EventLogMessages.dll";
+
+/// Register the service's name as an event source in the Application log.
+pub fn register_source(name: &str) -> windows::core::Result<()> {
+    use windows::Win32::System::Registry::{
+        RegCloseKey, RegCreateKeyExW, RegSetValueExW, HKEY, HKEY_LOCAL_MACHINE, KEY_SET_VALUE, REG_DWORD,
+        REG_EXPAND_SZ, REG_OPTION_NON_VOLATILE,
+    };
+
+    let types_supported = (EventLog::EVENTLOG_ERROR_TYPE.0
+        | EventLog::EVENTLOG_WARNING_TYPE.0
+        | EventLog::EVENTLOG_INFORMATION_TYPE.0) as u32;
+
+    unsafe {
+        let mut key = HKEY::default();
+        RegCreateKeyExW(
+            HKEY_LOCAL_MACHINE,
+            &HSTRING::from(source_key(name)),
+            0,
+            None,
+            REG_OPTION_NON_VOLATILE,
+            KEY_SET_VALUE,
+            None,
+            &mut key,
+            None,
+        )
+        .ok()?;
+        let message_file: Vec<u8> = MESSAGE_FILE
+            .encode_utf16()
+            .chain(std::iter::once(0))
+            .flat_map(|x| x.to_le_bytes())
+            .collect();
+        let result = RegSetValueExW(
+            key,
+            &HSTRING::from("TypesSupported"),
+            0,
+            REG_DWORD,
+            Some(&types_supported.to_le_bytes()),
+        )
+        .ok()
+        .and_then(|_| {
+            RegSetValueExW(
+                key,
+                &HSTRING::from("EventMessageFile"),
+                0,
+                REG_EXPAND_SZ,
+                Some(&message_file),
+            )
+            .ok()
+        });
+        let _ = RegCloseKey(key);
+        result
+    }
+}
+
+struct Source {
+    handle: HANDLE,
+    name: String,
+}
+
+// The handle is only used for `ReportEventW`, which is thread-safe.
+unsafe impl Send for Source {}
+unsafe impl Sync for Source {}
+
+/// Start reporting events for the service.
+pub fn open(name: &str) -> windows::core::Result<()> {
+    let handle = unsafe { EventLog::RegisterEventSourceW(PCWSTR::null(), &HSTRING::from(name))? };
+    let _ = EVENT_LOG.set(Source {
+        handle,
+        name: name.to_string(),
+    });
+    Ok(())
+}
+
+/// Report an event, if reporting was started with `open`.
+/// Lifecycle events are also sent to any notification webhooks, OpenTelemetry collector, and event file.
+pub fn report(event: Event) {
+    crate::notify::report(&event);
+    crate::otlp::report(&event);
+    crate::eventfile::report(&event);
+
+    let Some(source) = EVENT_LOG.get() else {
+        return;
+    };
+
+    let kind = match event.level() {
+        Level::Information => EventLog::EVENTLOG_INFORMATION_TYPE,
+        Level::Warning => EventLog::EVENTLOG_WARNING_TYPE,
+        Level::Error => EventLog::EVENTLOG_ERROR_TYPE,
+    };
+    let message = HSTRING::from(event.message(&source.name));
+    let strings = [PCWSTR(message.as_ptr())];
+    unsafe {
+        if let Err(e) = EventLog::ReportEventW(
+            source.handle,
+            kind,
+            0,
+            event.id(),
+            windows::Win32::Security::PSID::default(),
+            0,
+            Some(&strings),
+            None,
+        ) {
+            log::error!("Unable to write to the event log: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+speculate::speculate! {
+    describe "event" {
+        it "uses a distinct ID for each kind of event" {
+            let events = [
+                Event::Launched { pid: 1 },
+                Event::LaunchFailed { error: "x".to_string() },
+                Event::Exited { code: 0 },
+                Event::Crashed { code: 0xC0000005_u32 as i32, dump: None },
+                Event::Restarting { reason: "x".to_string() },
+                Event::Stopping,
+                Event::Killed,
+                Event::Output { stream: crate::output::Stream::Stdout, line: "x".to_string() },
+                Event::Output { stream: crate::output::Stream::Stderr, line: "x".to_string() },
+            ];
+            let mut ids: Vec<_> = events.iter().map(|x| x.id()).collect();
+            ids.sort();
+            ids.dedup();
+            assert_eq!(ids.len(), events.len());
+        }
+
+        it "reports failures as warnings or errors" {
+            assert_eq!(Event::Exited { code: 0 }.level(), Level::Information);
+            assert_eq!(Event::Exited { code: 1 }.level(), Level::Warning);
+            assert_eq!(Event::Crashed { code: 0xC0000005_u32 as i32, dump: None }.level(), Level::Error);
+            assert_eq!(
+                Event::Output { stream: crate::output::Stream::Stderr, line: "x".to_string() }.level(),
+                Level::Error,
+            );
+        }
+
+        it "includes the service name and details in the message" {
+            assert_eq!(
+                Event::Crashed { code: 0xC0000005_u32 as i32, dump: Some("C:\\dumps\\app.exe.1.dmp".to_string()) }
+                    .message("app"),
+                "app: Command crashed with code -1073741819 (0xC0000005: STATUS_ACCESS_VIOLATION); dump: C:\\dumps\\app.exe.1.dmp",
+            );
+        }
+    }
+}
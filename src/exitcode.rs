@@ -0,0 +1,98 @@
+/// NTSTATUS values that processes commonly exit with when they crash or fail to load.
+const NTSTATUS_NAMES: &[(u32, &str)] = &[
+    (0x8000_0002, "STATUS_DATATYPE_MISALIGNMENT"),
+    (0x8000_0003, "STATUS_BREAKPOINT"),
+    (0xC000_0005, "STATUS_ACCESS_VIOLATION"),
+    (0xC000_0006, "STATUS_IN_PAGE_ERROR"),
+    (0xC000_0008, "STATUS_INVALID_HANDLE"),
+    (0xC000_000D, "STATUS_INVALID_PARAMETER"),
+    (0xC000_0017, "STATUS_NO_MEMORY"),
+    (0xC000_001D, "STATUS_ILLEGAL_INSTRUCTION"),
+    (0xC000_0022, "STATUS_ACCESS_DENIED"),
+    (0xC000_0025, "STATUS_NONCONTINUABLE_EXCEPTION"),
+    (0xC000_0026, "STATUS_INVALID_DISPOSITION"),
+    (0xC000_007B, "STATUS_INVALID_IMAGE_FORMAT"),
+    (0xC000_008C, "STATUS_ARRAY_BOUNDS_EXCEEDED"),
+    (0xC000_008D, "STATUS_FLOAT_DENORMAL_OPERAND"),
+    (0xC000_008E, "STATUS_FLOAT_DIVIDE_BY_ZERO"),
+    (0xC000_0090, "STATUS_FLOAT_INVALID_OPERATION"),
+    (0xC000_0091, "STATUS_FLOAT_OVERFLOW"),
+    (0xC000_0094, "STATUS_INTEGER_DIVIDE_BY_ZERO"),
+    (0xC000_0095, "STATUS_INTEGER_OVERFLOW"),
+    (0xC000_0096, "STATUS_PRIVILEGED_INSTRUCTION"),
+    (0xC000_00FD, "STATUS_STACK_OVERFLOW"),
+    (0xC000_0135, "STATUS_DLL_NOT_FOUND"),
+    (0xC000_0139, "STATUS_ENTRYPOINT_NOT_FOUND"),
+    (0xC000_013A, "STATUS_CONTROL_C_EXIT"),
+    (0xC000_0142, "STATUS_DLL_INIT_FAILED"),
+    (0xC000_0374, "STATUS_HEAP_CORRUPTION"),
+    (0xC000_0409, "STATUS_STACK_BUFFER_OVERRUN"),
+    (0xC000_0417, "STATUS_INVALID_CRUNTIME_PARAMETER"),
+    (0xC000_041D, "STATUS_FATAL_USER_CALLBACK_EXCEPTION"),
+    (0xC000_0420, "STATUS_ASSERTION_FAILURE"),
+    (0xC000_0602, "STATUS_FAIL_FAST_EXCEPTION"),
+];
+
+/// Get the symbolic name of a well-known NTSTATUS exit code.
+pub fn ntstatus_name(code: i32) -> Option<&'static str> {
+    NTSTATUS_NAMES
+        .iter()
+        .find(|(value, _)| *value == code as u32)
+        .map(|(_, name)| *name)
+}
+
+/// Get the Win32 error code wrapped by an `HRESULT_FROM_WIN32` exit code.
+/// `HRESULT_FROM_WIN32` maps `ERROR_SUCCESS` to 0, so `0x80070000` does not wrap a Win32 error.
+pub fn win32_from_hresult(code: i32) -> Option<u32> {
+    let code = code as u32;
+    (code & 0xFFFF_0000 == 0x8007_0000 && code & 0xFFFF != 0).then_some(code & 0xFFFF)
+}
+
+/// Describe an exit code for logging.
+/// Negative codes are usually NTSTATUS or HRESULT values,
+/// so they are also shown in hex along with their meaning when known.
+pub fn describe(code: i32) -> String {
+    if code >= 0 {
+        return code.to_string();
+    }
+
+    if let Some(name) = ntstatus_name(code) {
+        format!("{} ({:#X}: {})", code, code, name)
+    } else if let Some(win32) = win32_from_hresult(code) {
+        format!("{} ({:#X}: HRESULT for Win32 error {})", code, code, win32)
+    } else {
+        format!("{} ({:#X})", code, code)
+    }
+}
+
+#[cfg(test)]
+speculate::speculate! {
+    describe "describe" {
+        it "leaves ordinary codes alone" {
+            assert_eq!(describe(0), "0");
+            assert_eq!(describe(2), "2");
+        }
+
+        it "names well-known NTSTATUS codes" {
+            assert_eq!(describe(-1073741819), "-1073741819 (0xC0000005: STATUS_ACCESS_VIOLATION)");
+            assert_eq!(describe(0xC0000409_u32 as i32), "-1073740791 (0xC0000409: STATUS_STACK_BUFFER_OVERRUN)");
+        }
+
+        it "recognizes Win32 errors wrapped in an HRESULT" {
+            assert_eq!(describe(0x80070005_u32 as i32), "-2147024891 (0x80070005: HRESULT for Win32 error 5)");
+        }
+
+        it "shows other negative codes in hex" {
+            assert_eq!(describe(-1), "-1 (0xFFFFFFFF)");
+        }
+    }
+
+    describe "win32_from_hresult" {
+        it "extracts the Win32 error" {
+            assert_eq!(win32_from_hresult(0x80070002_u32 as i32), Some(2));
+            assert_eq!(win32_from_hresult(0x80004005_u32 as i32), None);
+            assert_eq!(win32_from_hresult(0x80070000_u32 as i32), None);
+            assert_eq!(win32_from_hresult(2), None);
+        }
+    }
+}
@@ -0,0 +1,249 @@
+use crate::cli::{GelfEndpoint, GelfProtocol};
+
+/// How many messages can wait to be sent before new ones are dropped.
+const QUEUE_SIZE: usize = 1000;
+
+/// How many unsent messages to keep while the server is unavailable.
+const BACKLOG_SIZE: usize = 1000;
+
+/// How long to wait before reconnecting after a failure.
+const RECONNECT_DELAY: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Largest UDP datagram to send. Longer messages are split into chunks.
+const MAX_DATAGRAM_SIZE: usize = 8192;
+
+/// Most chunks that a GELF server will accept for one message.
+const MAX_CHUNKS: usize = 128;
+
+const CHUNK_HEADER_SIZE: usize = 12;
+
+/// Convert a log level to the syslog severity that GELF uses.
+fn syslog_level(level: log::Level) -> u8 {
+    match level {
+        log::Level::Error => 3,
+        log::Level::Warn => 4,
+        log::Level::Info => 6,
+        log::Level::Debug | log::Level::Trace => 7,
+    }
+}
+
+/// Build a GELF message.
+/// `stream` is only set for lines of a command's output.
+fn gelf_message(host: &str, service: &str, timestamp: f64, level: u8, stream: Option<&str>, message: &str) -> String {
+    use crate::logformat::json_string;
+
+    let mut gelf = format!(
+        "{{\"version\":\"1.1\",\"host\":{},\"short_message\":{},\"timestamp\":{:.3},\"level\":{},\"_service\":{}",
+        json_string(host),
+        json_string(message),
+        timestamp,
+        level,
+        json_string(service)
+    );
+    if let Some(stream) = stream {
+        gelf.push_str(&format!(",\"_stream\":{}", json_string(stream)));
+    }
+    gelf.push('}');
+    gelf
+}
+
+/// Split a message into GELF chunks if it is too long for one UDP datagram.
+/// Returns nothing if the message is too long even for the maximum number of chunks.
+fn chunks(message: &[u8], id: [u8; 8]) -> Vec<Vec<u8>> {
+    if message.len() <= MAX_DATAGRAM_SIZE {
+        return vec![message.to_vec()];
+    }
+
+    let parts: Vec<_> = message.chunks(MAX_DATAGRAM_SIZE - CHUNK_HEADER_SIZE).collect();
+    if parts.len() > MAX_CHUNKS {
+        return vec![];
+    }
+    parts
+        .iter()
+        .enumerate()
+        .map(|(i, part)| {
+            let mut chunk = Vec::with_capacity(CHUNK_HEADER_SIZE + part.len());
+            chunk.extend_from_slice(&[0x1e, 0x0f]);
+            chunk.extend_from_slice(&id);
+            chunk.push(i as u8);
+            chunk.push(parts.len() as u8);
+            chunk.extend_from_slice(part);
+            chunk
+        })
+        .collect()
+}
+
+enum Connection {
+    Udp(std::net::UdpSocket),
+    Tcp(std::net::TcpStream),
+}
+
+impl Connection {
+    fn open(endpoint: &GelfEndpoint) -> std::io::Result<Self> {
+        match endpoint.protocol {
+            GelfProtocol::Udp => {
+                let socket = std::net::UdpSocket::bind("0.0.0.0:0")?;
+                socket.connect(&endpoint.address)?;
+                Ok(Self::Udp(socket))
+            }
+            GelfProtocol::Tcp => Ok(Self::Tcp(std::net::TcpStream::connect(&endpoint.address)?)),
+        }
+    }
+
+    fn send(&mut self, message: &str, id: [u8; 8]) -> std::io::Result<()> {
+        use std::io::Write;
+
+        match self {
+            Self::Udp(socket) => {
+                for chunk in chunks(message.as_bytes(), id) {
+                    socket.send(&chunk)?;
+                }
+                Ok(())
+            }
+            Self::Tcp(stream) => {
+                // Messages over TCP are delimited by a null byte.
+                stream.write_all(message.as_bytes())?;
+                stream.write_all(&[0])
+            }
+        }
+    }
+}
+
+/// Send queued messages, keeping a backlog and reconnecting while the server is unavailable.
+/// Errors are not logged since that would feed back into this queue.
+fn run(endpoint: GelfEndpoint, queue: std::sync::mpsc::Receiver<String>) {
+    let mut connection: Option<Connection> = None;
+    let mut retry_at = std::time::Instant::now();
+    let mut backlog = std::collections::VecDeque::new();
+    let mut next_id: u64 = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|x| x.as_nanos() as u64)
+        .unwrap_or_default();
+
+    loop {
+        let disconnected = match queue.recv_timeout(std::time::Duration::from_secs(1)) {
+            Ok(message) => {
+                if backlog.len() >= BACKLOG_SIZE {
+                    backlog.pop_front();
+                }
+                backlog.push_back(message);
+                false
+            }
+            Err(std::sync::mpsc::RecvTimeoutError::Timeout) => false,
+            Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => true,
+        };
+
+        while let Some(message) = backlog.front() {
+            if connection.is_none() {
+                if std::time::Instant::now() < retry_at {
+                    break;
+                }
+                match Connection::open(&endpoint) {
+                    Ok(opened) => connection = Some(opened),
+                    Err(_) => {
+                        retry_at = std::time::Instant::now() + RECONNECT_DELAY;
+                        break;
+                    }
+                }
+            }
+            let Some(open) = connection.as_mut() else {
+                break;
+            };
+            next_id = next_id.wrapping_add(1);
+            match open.send(message, next_id.to_be_bytes()) {
+                Ok(_) => {
+                    backlog.pop_front();
+                }
+                Err(_) => {
+                    connection = None;
+                    retry_at = std::time::Instant::now() + RECONNECT_DELAY;
+                    break;
+                }
+            }
+        }
+
+        if disconnected {
+            return;
+        }
+    }
+}
+
+/// Sends log records to a Graylog server, for `--log-gelf`.
+/// Records are queued for a background thread so that logging never waits on the network.
+pub struct GelfWriter {
+    host: String,
+    queue: std::sync::mpsc::SyncSender<String>,
+}
+
+impl GelfWriter {
+    pub fn new(endpoint: GelfEndpoint) -> Self {
+        let (queue, receiver) = std::sync::mpsc::sync_channel(QUEUE_SIZE);
+        std::thread::spawn(move || run(endpoint, receiver));
+        Self {
+            host: std::env::var("COMPUTERNAME").unwrap_or_else(|_| "localhost".to_string()),
+            queue,
+        }
+    }
+}
+
+impl flexi_logger::writers::LogWriter for GelfWriter {
+    fn write(&self, now: &mut flexi_logger::DeferredNow, record: &log::Record) -> std::io::Result<()> {
+        let (stream, message) = crate::logformat::structured_message(record);
+        let gelf = gelf_message(
+            &self.host,
+            crate::logformat::service(),
+            now.now().timestamp_millis() as f64 / 1000.0,
+            syslog_level(record.level()),
+            stream.as_deref(),
+            &message,
+        );
+        // If the server has been unavailable for long enough to fill the queue,
+        // drop the message rather than blocking the service.
+        let _ = self.queue.try_send(gelf);
+        Ok(())
+    }
+
+    fn flush(&self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+speculate::speculate! {
+    describe "gelf_message" {
+        it "includes the stream for command output" {
+            assert_eq!(
+                gelf_message("pc", "app", 1735689600.5, 7, Some("stderr"), "oops"),
+                r#"{"version":"1.1","host":"pc","short_message":"oops","timestamp":1735689600.500,"level":7,"_service":"app","_stream":"stderr"}"#,
+            );
+        }
+
+        it "omits the stream for Shawl's own messages" {
+            assert_eq!(
+                gelf_message("pc", "app", 1735689600.0, 6, None, "Launching command"),
+                r#"{"version":"1.1","host":"pc","short_message":"Launching command","timestamp":1735689600.000,"level":6,"_service":"app"}"#,
+            );
+        }
+    }
+
+    describe "chunks" {
+        it "keeps short messages whole" {
+            assert_eq!(chunks(b"hello", [0; 8]), vec![b"hello".to_vec()]);
+        }
+
+        it "splits long messages with headers" {
+            let message = vec![b'x'; MAX_DATAGRAM_SIZE + 1];
+            let id = [1, 2, 3, 4, 5, 6, 7, 8];
+            let chunks = chunks(&message, id);
+            assert_eq!(chunks.len(), 2);
+            assert_eq!(chunks[0][..12], [0x1e, 0x0f, 1, 2, 3, 4, 5, 6, 7, 8, 0, 2]);
+            assert_eq!(chunks[1][..12], [0x1e, 0x0f, 1, 2, 3, 4, 5, 6, 7, 8, 1, 2]);
+            assert_eq!(chunks.iter().map(|x| x.len() - 12).sum::<usize>(), message.len());
+        }
+
+        it "drops messages that need too many chunks" {
+            let message = vec![b'x'; (MAX_DATAGRAM_SIZE - CHUNK_HEADER_SIZE) * MAX_CHUNKS + 1];
+            assert!(chunks(&message, [0; 8]).is_empty());
+        }
+    }
+}
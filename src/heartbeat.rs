@@ -0,0 +1,93 @@
+use crate::cli::WebhookUrl;
+
+enum Ping {
+    Fail,
+}
+
+/// Get the URL to ping when the command fails, like healthchecks.io's `/fail` endpoint.
+fn fail_url(url: &WebhookUrl) -> WebhookUrl {
+    let (path, query) = match url.path.split_once('?') {
+        Some((path, query)) => (path, format!("?{}", query)),
+        None => (url.path.as_str(), String::new()),
+    };
+    WebhookUrl {
+        path: format!("{}/fail{}", path.trim_end_matches('/'), query),
+        ..url.clone()
+    }
+}
+
+fn ping(url: &WebhookUrl) {
+    match crate::http::post(url, "") {
+        Ok(status) if status < 300 => (),
+        Ok(status) => log::error!("Heartbeat to {} failed with HTTP status {}", url.host, status),
+        Err(e) => log::error!("Unable to send heartbeat to {}: {:?}", url.host, e),
+    }
+}
+
+/// Pings a monitoring URL for `--heartbeat-url` on a background thread,
+/// so that the monitor notices if the command or the whole host goes down.
+/// Without a URL, it does nothing.
+pub struct Heartbeat {
+    running: std::sync::Arc<std::sync::atomic::AtomicBool>,
+    queue: std::sync::mpsc::Sender<Ping>,
+}
+
+impl Heartbeat {
+    pub fn start(url: Option<WebhookUrl>, interval: std::time::Duration) -> Self {
+        let running = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let (queue, receiver) = std::sync::mpsc::channel();
+        let Some(url) = url else {
+            return Self { running, queue };
+        };
+
+        let running2 = running.clone();
+        std::thread::spawn(move || loop {
+            match receiver.recv_timeout(interval) {
+                Ok(Ping::Fail) => ping(&fail_url(&url)),
+                Err(std::sync::mpsc::RecvTimeoutError::Timeout) => {
+                    if running2.load(std::sync::atomic::Ordering::SeqCst) {
+                        ping(&url);
+                    }
+                }
+                Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => return,
+            }
+        });
+
+        Self { running, queue }
+    }
+
+    /// Set whether the command is running, which is when the URL is pinged.
+    pub fn set_running(&self, running: bool) {
+        self.running.store(running, std::sync::atomic::Ordering::SeqCst);
+    }
+
+    /// Ping the failure URL right away.
+    pub fn fail(&self) {
+        self.set_running(false);
+        let _ = self.queue.send(Ping::Fail);
+    }
+}
+
+#[cfg(test)]
+speculate::speculate! {
+    fn url(path: &str) -> WebhookUrl {
+        WebhookUrl {
+            secure: true,
+            host: "hc-ping.com".to_string(),
+            port: 443,
+            path: path.to_string(),
+        }
+    }
+
+    describe "fail_url" {
+        it "appends /fail to the path" {
+            assert_eq!(fail_url(&url("/1234")).path, "/1234/fail");
+            assert_eq!(fail_url(&url("/1234/")).path, "/1234/fail");
+            assert_eq!(fail_url(&url("/")).path, "/fail");
+        }
+
+        it "keeps the query" {
+            assert_eq!(fail_url(&url("/1234?rid=1")).path, "/1234/fail?rid=1");
+        }
+    }
+}
@@ -0,0 +1,389 @@
+use log::{error, info};
+use std::io::{Read, Write};
+
+/// How long a hook may run before it is killed, so that it can't hold up the service.
+const TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// How often the state is checked for `state-change` hooks.
+const STATE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Event {
+    /// Before each launch of the command.
+    PreStart,
+    /// After the command exits or is terminated.
+    PostExit,
+    /// When the state reported by `shawl status --live` changes.
+    StateChange,
+}
+
+impl Event {
+    fn name(self) -> &'static str {
+        match self {
+            Self::PreStart => "pre-start",
+            Self::PostExit => "post-exit",
+            Self::StateChange => "state-change",
+        }
+    }
+}
+
+/// What a `post-exit` hook decided about restarting the command.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Decision {
+    Restart,
+    NoRestart,
+}
+
+/// Details about the command for the hook.
+#[derive(Clone, Debug, Default)]
+pub struct Context {
+    /// Nothing if the command was terminated.
+    pub exit_code: Option<i32>,
+    pub launches: u32,
+    pub restarts: u32,
+    pub state: &'static str,
+    pub previous_state: Option<&'static str>,
+    /// When the command was last launched.
+    pub launched_at: Option<std::time::SystemTime>,
+}
+
+impl Context {
+    /// Describe the command as the control pipe reports it.
+    pub fn from_status(status: &crate::pipe::Status) -> Self {
+        let now = std::time::Instant::now();
+        Self {
+            exit_code: status.last_exit_code,
+            launches: status.launches,
+            restarts: status.restarts,
+            state: status.state(),
+            previous_state: None,
+            launched_at: status
+                .launched_at
+                .and_then(|x| std::time::SystemTime::now().checked_sub(now.saturating_duration_since(x))),
+        }
+    }
+}
+
+/// Environment variables that describe the event to the hook.
+fn env_vars(service: &str, event: Event, context: &Context) -> Vec<(&'static str, String)> {
+    let mut vars = vec![
+        ("SHAWL_HOOK_EVENT", event.name().to_string()),
+        ("SHAWL_SERVICE", service.to_string()),
+        ("SHAWL_LAUNCHES", context.launches.to_string()),
+        ("SHAWL_RESTARTS", context.restarts.to_string()),
+        ("SHAWL_STATE", context.state.to_string()),
+    ];
+    if let Some(code) = context.exit_code {
+        vars.push(("SHAWL_EXIT_CODE", code.to_string()));
+    }
+    if let Some(state) = context.previous_state {
+        vars.push(("SHAWL_PREVIOUS_STATE", state.to_string()));
+    }
+    vars
+}
+
+/// The JSON object that `--hook-exec` receives on stdin.
+fn payload(service: &str, event: Event, context: &Context, now: std::time::SystemTime) -> String {
+    let optional = |value: Option<String>| value.unwrap_or_else(|| "null".to_string());
+    format!(
+        "{{\"event\":\"{}\",\"service\":{},\"timestamp\":{},\"exit_code\":{},\"launches\":{},\"restarts\":{},\"state\":\"{}\",\"previous_state\":{},\"launched_at\":{}}}\n",
+        event.name(),
+        crate::logformat::json_string(service),
+        crate::statusfile::unix_time(now),
+        optional(context.exit_code.map(|x| x.to_string())),
+        context.launches,
+        context.restarts,
+        context.state,
+        optional(context.previous_state.map(|x| format!("\"{}\"", x))),
+        optional(context.launched_at.map(crate::statusfile::unix_time)),
+    )
+}
+
+/// A hook decides by printing `restart` or `no-restart` as its last line of output.
+fn parse_decision(output: &str) -> Option<Decision> {
+    match output.lines().map(str::trim).rev().find(|x| !x.is_empty())? {
+        "restart" => Some(Decision::Restart),
+        "no-restart" => Some(Decision::NoRestart),
+        _ => None,
+    }
+}
+
+fn read_all(pipe: Option<impl Read + Send + 'static>) -> std::thread::JoinHandle<String> {
+    std::thread::spawn(move || {
+        let mut text = String::new();
+        if let Some(mut pipe) = pipe {
+            let _ = pipe.read_to_string(&mut text);
+        }
+        text
+    })
+}
+
+/// Run a hook program to completion, killing it if it takes too long
+/// or if `cancel` is set because the service is stopping.
+/// Returns its output if it succeeded.
+fn run(
+    mut cmd: std::process::Command,
+    label: &str,
+    input: Option<String>,
+    cancel: &std::sync::atomic::AtomicBool,
+) -> Option<String> {
+    cmd.stdin(if input.is_some() {
+        std::process::Stdio::piped()
+    } else {
+        std::process::Stdio::null()
+    })
+    .stdout(std::process::Stdio::piped())
+    .stderr(std::process::Stdio::piped());
+
+    info!("Running {}", label);
+    let mut child = match cmd.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            error!("Unable to run {}: {:?}", label, e);
+            return None;
+        }
+    };
+    if let (Some(mut stdin), Some(input)) = (child.stdin.take(), input) {
+        // Write from another thread in case the hook doesn't read all of it.
+        std::thread::spawn(move || {
+            let _ = stdin.write_all(input.as_bytes());
+        });
+    }
+    let stdout = read_all(child.stdout.take());
+    let stderr = read_all(child.stderr.take());
+
+    let started = std::time::Instant::now();
+    let status = loop {
+        match child.try_wait() {
+            Ok(Some(status)) => break Some(status),
+            Ok(None) if cancel.load(std::sync::atomic::Ordering::SeqCst) => {
+                info!("{} was killed because the service is stopping", label);
+                let _ = child.kill();
+                let _ = child.wait();
+                break None;
+            }
+            Ok(None) if started.elapsed() < TIMEOUT => std::thread::sleep(std::time::Duration::from_millis(100)),
+            Ok(None) => {
+                error!(
+                    "{} did not finish within {} s, so it was killed",
+                    label,
+                    TIMEOUT.as_secs()
+                );
+                let _ = child.kill();
+                let _ = child.wait();
+                break None;
+            }
+            Err(e) => {
+                error!("Unable to wait for {}: {:?}", label, e);
+                break None;
+            }
+        }
+    };
+
+    let stdout = stdout.join().unwrap_or_default();
+    let stderr = stderr.join().unwrap_or_default();
+    for line in stdout.lines().chain(stderr.lines()).filter(|x| !x.trim().is_empty()) {
+        info!("{}: {}", label, line);
+    }
+
+    match status {
+        Some(status) if status.success() => Some(stdout),
+        Some(status) => {
+            error!("{} failed with code {:?}", label, status.code());
+            None
+        }
+        None => None,
+    }
+}
+
+/// The programs to run on lifecycle events for `--hook-script` and `--hook-exec`.
+#[derive(Clone, Debug)]
+pub struct Hooks {
+    script: Option<String>,
+    exec: Option<String>,
+}
+
+impl Hooks {
+    /// Nothing if there are no hooks.
+    pub fn new(opts: &crate::cli::CommonOpts) -> Option<Self> {
+        let hooks = Self {
+            script: opts.hook_script.clone(),
+            exec: opts.hook_exec.clone(),
+        };
+        (hooks.script.is_some() || hooks.exec.is_some()).then_some(hooks)
+    }
+
+    /// Run the hooks for an event at the same time, returning the hook script's decision if it made one.
+    /// The script gets the event as its argument and details in its environment,
+    /// while the executable gets them as JSON on stdin.
+    /// Both are killed once `cancel` is set.
+    pub fn run(
+        &self,
+        service: &str,
+        event: Event,
+        context: &Context,
+        cancel: &std::sync::atomic::AtomicBool,
+    ) -> Option<Decision> {
+        std::thread::scope(|scope| {
+            let exec = self.exec.as_ref().map(|exec| {
+                let input = payload(service, event, context, std::time::SystemTime::now());
+                scope.spawn(move || {
+                    run(
+                        std::process::Command::new(exec),
+                        &format!("hook executable for {}", event.name()),
+                        Some(input),
+                        cancel,
+                    )
+                })
+            });
+            let decision = self.run_script(service, event, context, cancel);
+            if let Some(exec) = exec {
+                let _ = exec.join();
+            }
+            decision
+        })
+    }
+
+    fn run_script(
+        &self,
+        service: &str,
+        event: Event,
+        context: &Context,
+        cancel: &std::sync::atomic::AtomicBool,
+    ) -> Option<Decision> {
+        self.script.as_ref().and_then(|script| {
+            let args = [std::ffi::OsString::from(event.name())];
+            let mut cmd = match crate::service::script_kind(script) {
+                Some(kind) => crate::service::script_command(kind, script, &args, false),
+                None => {
+                    let mut cmd = std::process::Command::new(script);
+                    cmd.args(&args);
+                    cmd
+                }
+            };
+            cmd.envs(env_vars(service, event, context));
+            let output = run(cmd, &format!("hook script for {}", event.name()), None, cancel)?;
+            parse_decision(&output)
+        })
+    }
+}
+
+/// Runs the `state-change` hook from a background thread,
+/// so that the command is not held up while the hook runs.
+pub struct StateWatcher {
+    stop: std::sync::mpsc::Sender<()>,
+    /// Kills a running hook so that stopping doesn't wait for it.
+    stopping: std::sync::Arc<std::sync::atomic::AtomicBool>,
+    thread: std::thread::JoinHandle<()>,
+}
+
+impl StateWatcher {
+    pub fn start(hooks: Hooks, service: &str, status: std::sync::Arc<std::sync::Mutex<crate::pipe::Status>>) -> Self {
+        let service = service.to_string();
+        let (stop, receiver) = std::sync::mpsc::channel();
+        let stopping = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let cancel = stopping.clone();
+        let thread = std::thread::spawn(move || {
+            let mut previous = None;
+            loop {
+                let snapshot = status.lock().map(|x| x.clone()).unwrap_or_default();
+                let state = snapshot.state();
+                if previous.is_some_and(|x| x != state) {
+                    let context = Context {
+                        previous_state: previous,
+                        ..Context::from_status(&snapshot)
+                    };
+                    hooks.run(&service, Event::StateChange, &context, &cancel);
+                }
+                previous = Some(state);
+
+                match receiver.recv_timeout(STATE_INTERVAL) {
+                    Err(std::sync::mpsc::RecvTimeoutError::Timeout) => (),
+                    _ => return,
+                }
+            }
+        });
+        Self { stop, stopping, thread }
+    }
+
+    pub fn stop(self) {
+        self.stopping.store(true, std::sync::atomic::Ordering::SeqCst);
+        let _ = self.stop.send(());
+        let _ = self.thread.join();
+    }
+}
+
+#[cfg(test)]
+speculate::speculate! {
+    describe "parse_decision" {
+        it "reads the last line" {
+            assert_eq!(parse_decision("checking hours\nrestart\n"), Some(Decision::Restart));
+            assert_eq!(parse_decision("no-restart\r\n\r\n"), Some(Decision::NoRestart));
+        }
+
+        it "ignores other output" {
+            assert_eq!(parse_decision(""), None);
+            assert_eq!(parse_decision("restart\ndone\n"), None);
+        }
+    }
+
+    describe "env_vars" {
+        it "describes the event" {
+            let context = Context {
+                exit_code: Some(12),
+                launches: 3,
+                restarts: 2,
+                state: "waiting",
+                ..Default::default()
+            };
+            assert_eq!(
+                env_vars("web", Event::PostExit, &context),
+                vec![
+                    ("SHAWL_HOOK_EVENT", "post-exit".to_string()),
+                    ("SHAWL_SERVICE", "web".to_string()),
+                    ("SHAWL_LAUNCHES", "3".to_string()),
+                    ("SHAWL_RESTARTS", "2".to_string()),
+                    ("SHAWL_STATE", "waiting".to_string()),
+                    ("SHAWL_EXIT_CODE", "12".to_string()),
+                ],
+            );
+        }
+    }
+
+    describe "payload" {
+        it "describes the event" {
+            let context = Context {
+                exit_code: Some(-1),
+                launches: 3,
+                restarts: 2,
+                state: "waiting",
+                launched_at: Some(std::time::UNIX_EPOCH + std::time::Duration::from_millis(1_000_500)),
+                ..Default::default()
+            };
+            assert_eq!(
+                payload("web", Event::PostExit, &context, std::time::UNIX_EPOCH + std::time::Duration::from_secs(1060)),
+                concat!(
+                    "{\"event\":\"post-exit\",\"service\":\"web\",\"timestamp\":1060.000,\"exit_code\":-1,",
+                    "\"launches\":3,\"restarts\":2,\"state\":\"waiting\",\"previous_state\":null,",
+                    "\"launched_at\":1000.500}\n",
+                ),
+            );
+        }
+
+        it "uses null for missing details" {
+            let context = Context {
+                state: "running",
+                previous_state: Some("paused"),
+                ..Default::default()
+            };
+            assert_eq!(
+                payload("web", Event::StateChange, &context, std::time::UNIX_EPOCH),
+                concat!(
+                    "{\"event\":\"state-change\",\"service\":\"web\",\"timestamp\":0.000,\"exit_code\":null,",
+                    "\"launches\":0,\"restarts\":0,\"state\":\"running\",\"previous_state\":\"paused\",",
+                    "\"launched_at\":null}\n",
+                ),
+            );
+        }
+    }
+}
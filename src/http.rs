@@ -0,0 +1,91 @@
+use crate::cli::WebhookUrl;
+
+/// How long to wait for each step of a request.
+const TIMEOUT_MS: i32 = 10_000;
+
+/// Closes a WinHTTP handle when dropped.
+struct Internet(*mut std::ffi::c_void);
+
+impl Internet {
+    fn new(handle: *mut std::ffi::c_void) -> windows::core::Result<Self> {
+        if handle.is_null() {
+            Err(windows::core::Error::from_win32())
+        } else {
+            Ok(Self(handle))
+        }
+    }
+}
+
+impl Drop for Internet {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = windows::Win32::Networking::WinHttp::WinHttpCloseHandle(self.0);
+        }
+    }
+}
+
+/// Post a JSON body to a URL, returning the HTTP status code.
+/// An empty body is sent without a content type, such as for pings.
+pub fn post(url: &WebhookUrl, body: &str) -> windows::core::Result<u32> {
+    use windows::{
+        core::{HSTRING, PCWSTR},
+        Win32::Networking::WinHttp::*,
+    };
+
+    unsafe {
+        let session = Internet::new(WinHttpOpen(
+            &HSTRING::from(format!("shawl/{}", env!("CARGO_PKG_VERSION"))),
+            WINHTTP_ACCESS_TYPE_DEFAULT_PROXY,
+            PCWSTR::null(),
+            PCWSTR::null(),
+            0,
+        ))?;
+        WinHttpSetTimeouts(session.0, TIMEOUT_MS, TIMEOUT_MS, TIMEOUT_MS, TIMEOUT_MS)?;
+        let connection = Internet::new(WinHttpConnect(
+            session.0,
+            &HSTRING::from(url.host.as_str()),
+            url.port,
+            0,
+        ))?;
+        let request = Internet::new(WinHttpOpenRequest(
+            connection.0,
+            &HSTRING::from("POST"),
+            &HSTRING::from(url.path.as_str()),
+            PCWSTR::null(),
+            PCWSTR::null(),
+            std::ptr::null(),
+            if url.secure {
+                WINHTTP_FLAG_SECURE
+            } else {
+                WINHTTP_OPEN_REQUEST_FLAGS(0)
+            },
+        ))?;
+
+        let headers = HSTRING::from(if body.is_empty() {
+            ""
+        } else {
+            "Content-Type: application/json\r\n"
+        });
+        WinHttpSendRequest(
+            request.0,
+            (!body.is_empty()).then(|| headers.as_wide()),
+            Some(body.as_ptr() as *const std::ffi::c_void),
+            body.len() as u32,
+            body.len() as u32,
+            0,
+        )?;
+        WinHttpReceiveResponse(request.0, std::ptr::null_mut())?;
+
+        let mut status: u32 = 0;
+        let mut size = std::mem::size_of::<u32>() as u32;
+        WinHttpQueryHeaders(
+            request.0,
+            WINHTTP_QUERY_STATUS_CODE | WINHTTP_QUERY_FLAG_NUMBER,
+            PCWSTR::null(),
+            Some(&mut status as *mut u32 as *mut std::ffi::c_void),
+            &mut size,
+            std::ptr::null_mut(),
+        )?;
+        Ok(status)
+    }
+}
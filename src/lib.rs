@@ -0,0 +1,272 @@
+//! Run arbitrary commands as Windows services.
+//!
+//! This is the library behind the `shawl` executable, for programs like installers
+//! that want to add services without shipping a separate copy of Shawl.
+//! The options are the same as on the command line, via [`cli::CommonOpts`].
+//!
+//! The service runs the program that added it with `shawl run` arguments,
+//! so that program must call [`run_service`] when it receives them:
+//!
+//! ```ignore
+//! fn main() {
+//!     if std::env::args().nth(1).as_deref() == Some("run") {
+//!         let cli = shawl_core::cli::evaluate_cli();
+//!         if let shawl_core::cli::Subcommand::Run { name, common, .. } = cli.sub {
+//!             shawl_core::prepare_logging(&name, &common, false).unwrap();
+//!             shawl_core::run_service(name).unwrap();
+//!         }
+//!         return;
+//!     }
+//!
+//!     // The installer's own steps, then:
+//!     let opts = shawl_core::cli::CommonOpts {
+//!         command: vec!["C:/apps/web/server.exe".to_string()],
+//!         restart: true,
+//!         ..Default::default()
+//!     };
+//!     shawl_core::add_service("web".to_string(), None, &[], None, None, false, opts).unwrap();
+//! }
+//! ```
+
+#[cfg(windows)]
+mod acl;
+mod argsfile;
+pub mod cli;
+mod control;
+mod defaults;
+#[cfg(windows)]
+mod elevation;
+mod env;
+#[cfg(windows)]
+mod eventfile;
+#[cfg(windows)]
+mod eventlog;
+mod exitcode;
+mod gelf;
+#[cfg(windows)]
+mod heartbeat;
+#[cfg(windows)]
+mod hook;
+#[cfg(windows)]
+mod http;
+mod logformat;
+mod logprune;
+#[cfg(windows)]
+mod notify;
+#[cfg(windows)]
+mod otlp;
+mod output;
+#[cfg(windows)]
+mod perfcounters;
+#[cfg(windows)]
+mod pipe;
+#[cfg(windows)]
+mod process;
+#[cfg(windows)]
+mod service;
+#[cfg(windows)]
+mod sidecar;
+#[cfg(windows)]
+mod statusfile;
+mod watch;
+mod wizard;
+
+pub use control::{add_service, remove_service, show_status, AddError};
+#[cfg(windows)]
+pub use elevation::owns_console;
+#[cfg(windows)]
+pub use service::{run as run_service, run_foreground, started_outside_service_manager, test as test_service};
+
+/// Simplify local UNC paths since some programs (notably cmd.exe) don't like them.
+pub fn simplify_path(path: &str) -> String {
+    dunce::simplified(std::path::Path::new(path))
+        .to_string_lossy()
+        .to_string()
+}
+
+// The handle needs `&mut` to change where messages are duplicated.
+static LOGGER: std::sync::OnceLock<std::sync::Mutex<flexi_logger::LoggerHandle>> = std::sync::OnceLock::new();
+
+fn with_logger(f: impl FnOnce(&mut flexi_logger::LoggerHandle)) -> bool {
+    match LOGGER.get() {
+        Some(logger) => {
+            let mut logger = logger.lock().unwrap_or_else(|e| e.into_inner());
+            f(&mut logger);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Build a log specification where `level` applies to Shawl's own records,
+/// while the command's output is left to `--log-stdout-level` and `--log-stderr-level`.
+fn log_spec(level: cli::LogLevel) -> flexi_logger::LogSpecification {
+    flexi_logger::LogSpecification::builder()
+        .default(level.to_log().to_level_filter())
+        .module(output::OUTPUT_TARGET, log::LevelFilter::Trace)
+        .module("{shawl-cmd", log::LevelFilter::Trace)
+        .build()
+}
+
+/// Write out any buffered log records, such as before exiting.
+pub fn flush_logs() {
+    with_logger(|logger| logger.flush());
+}
+
+/// Also show Shawl's own messages in the terminal, such as when `run` switches to the foreground.
+pub fn show_logs_in_console() {
+    with_logger(|logger| {
+        let _ = logger.adapt_duplication_to_stderr(flexi_logger::Duplicate::Info);
+    });
+}
+
+/// Change the log level while the service is running.
+pub fn set_log_level(level: cli::LogLevel) {
+    if with_logger(|logger| logger.set_new_spec(log_spec(level))) {
+        log::info!("Changed log level to {}", level.to_cli());
+    } else {
+        log::info!("Unable to change log level because logging is disabled");
+    }
+}
+
+/// Start logging to the files for the service, according to the options.
+/// With `console`, Shawl's own messages are also shown in the terminal.
+pub fn prepare_logging(name: &str, opts: &cli::CommonOpts, console: bool) -> Result<(), Box<dyn std::error::Error>> {
+    logformat::configure(name, opts.log_utc, opts.log_rfc3339, &opts.log_redact)?;
+    let retention = opts.log_retain.unwrap_or(2);
+    let format = opts.log_format.unwrap_or_default();
+
+    let log_dir = simplify_path(&opts.log_directory(name)?.to_string_lossy());
+    if opts.log_subdir.is_some() {
+        std::fs::create_dir_all(&log_dir)?;
+    }
+    // This only applies to the subdirectory, which `--log-private` requires.
+    // When adding a service, this is done once the service's account exists.
+    #[cfg(windows)]
+    if opts.log_private && opts.log_subdir.is_some() && !console {
+        acl::restrict(std::path::Path::new(&log_dir), &acl::current_account_sid()?)?;
+    }
+
+    let rotation = match opts.log_rotate.unwrap_or_default() {
+        cli::LogRotation::Bytes(bytes) => flexi_logger::Criterion::Size(bytes),
+        cli::LogRotation::Daily => flexi_logger::Criterion::Age(flexi_logger::Age::Day),
+        cli::LogRotation::Hourly => flexi_logger::Criterion::Age(flexi_logger::Age::Hour),
+        cli::LogRotation::DailyOrBytes(bytes) => flexi_logger::Criterion::AgeOrSize(flexi_logger::Age::Day, bytes),
+        cli::LogRotation::HourlyOrBytes(bytes) => flexi_logger::Criterion::AgeOrSize(flexi_logger::Age::Hour, bytes),
+    };
+
+    let write_mode = || match opts.log_flush_interval {
+        Some(interval) => flexi_logger::WriteMode::BufferAndFlushWith(flexi_logger::DEFAULT_BUFFER_CAPACITY, interval),
+        None => flexi_logger::WriteMode::Direct,
+    };
+
+    let pattern = opts
+        .log_name_pattern
+        .as_ref()
+        .map(|x| x.replace("{service_name}", name));
+    let spec = {
+        let spec = flexi_logger::FileSpec::default().directory(log_dir.clone());
+
+        if pattern.is_some() {
+            // The whole name comes from the pattern.
+            spec.suppress_basename()
+        } else if let Some(log_as) = &opts.log_as {
+            spec.basename(log_as)
+        } else {
+            spec.discriminant(format!("for_{}", name))
+        }
+    };
+    let naming = match &pattern {
+        // The logger needs the format for as long as it runs.
+        Some(pattern) => flexi_logger::Naming::TimestampsCustomFormat {
+            current_infix: None,
+            format: Box::leak(pattern.clone().into_boxed_str()),
+        },
+        None => flexi_logger::Naming::Timestamps,
+    };
+    let logger = match opts.log_level {
+        Some(level) => flexi_logger::Logger::with(log_spec(level)),
+        None => flexi_logger::Logger::try_with_env_or_str("debug")?,
+    };
+    let logger = match &opts.log_gelf {
+        Some(endpoint) => logger.log_to_file_and_writer(spec, Box::new(gelf::GelfWriter::new(endpoint.clone()))),
+        None => logger.log_to_file(spec),
+    };
+    let logger = logger
+        .write_mode(write_mode())
+        .append()
+        .rotate(rotation, naming, flexi_logger::Cleanup::KeepLogFiles(retention))
+        .format_for_stderr(logformat::console);
+    let mut logger = match format {
+        cli::LogFormat::Text => logger.format_for_files(logformat::text),
+        cli::LogFormat::Json => logger.format_for_files(logformat::json),
+    };
+
+    if console {
+        logger = logger.duplicate_to_stderr(flexi_logger::Duplicate::Info);
+    }
+
+    let mut stems = vec![match &opts.log_as {
+        Some(log_as) => log_as.to_string(),
+        None => format!(
+            "{}_for_{}",
+            std::env::current_exe()?
+                .file_stem()
+                .map(|x| x.to_string_lossy().to_string())
+                .unwrap_or_default(),
+            name
+        ),
+    }];
+
+    if let Some(log_cmd_as) = &opts.log_cmd_as {
+        let writers = if opts.log_cmd_split {
+            vec![
+                ("shawl-cmd-out", format!("{}.out", log_cmd_as)),
+                ("shawl-cmd-err", format!("{}.err", log_cmd_as)),
+            ]
+        } else {
+            vec![("shawl-cmd", log_cmd_as.to_string())]
+        };
+
+        for (target, basename) in writers {
+            stems.push(basename.clone());
+            logger = logger.add_writer(
+                target,
+                Box::new(
+                    flexi_logger::writers::FileLogWriter::builder(
+                        flexi_logger::FileSpec::default()
+                            .directory(log_dir.clone())
+                            .basename(basename),
+                    )
+                    .write_mode(write_mode())
+                    .append()
+                    .rotate(
+                        rotation,
+                        flexi_logger::Naming::Timestamps,
+                        flexi_logger::Cleanup::KeepLogFiles(retention),
+                    )
+                    .format(match format {
+                        cli::LogFormat::Text => logformat::raw,
+                        cli::LogFormat::Json => logformat::json,
+                    })
+                    .try_build()?,
+                ),
+            );
+        }
+    }
+
+    let _ = LOGGER.set(std::sync::Mutex::new(logger.start()?));
+
+    // The logger only cleans up the files that it names itself.
+    if opts.log_max_total.is_some() || pattern.is_some() {
+        logprune::start(
+            std::path::PathBuf::from(log_dir),
+            stems,
+            pattern.as_deref(),
+            opts.log_max_total.map(|x| x.saturating_mul(1024 * 1024)),
+            retention,
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,247 @@
+static SETTINGS: std::sync::OnceLock<Settings> = std::sync::OnceLock::new();
+/// Secrets from `--log-redact-secret`, which are only known once the service has started.
+static SECRETS: std::sync::RwLock<Vec<regex::Regex>> = std::sync::RwLock::new(vec![]);
+
+#[derive(Debug, Default)]
+struct Settings {
+    service: String,
+    utc: bool,
+    rfc3339: bool,
+    redact: Vec<regex::Regex>,
+}
+
+/// Set the service name to include in JSON log records, how to write timestamps,
+/// and which patterns to redact.
+pub fn configure(service: &str, utc: bool, rfc3339: bool, redact: &[String]) -> Result<(), regex::Error> {
+    let _ = SETTINGS.set(Settings {
+        service: service.to_string(),
+        utc,
+        rfc3339,
+        redact: redact.iter().map(|x| regex::Regex::new(x)).collect::<Result<_, _>>()?,
+    });
+    Ok(())
+}
+
+/// Replace each match of the patterns with `***`.
+fn redact_with<'a>(patterns: &[regex::Regex], text: &'a str) -> std::borrow::Cow<'a, str> {
+    let mut text = std::borrow::Cow::Borrowed(text);
+    for pattern in patterns {
+        if let std::borrow::Cow::Owned(redacted) = pattern.replace_all(&text, "***") {
+            text = std::borrow::Cow::Owned(redacted);
+        }
+    }
+    text
+}
+
+/// Hide anything matching `--log-redact` or `--log-redact-secret` before it is logged.
+pub fn redact(text: &str) -> std::borrow::Cow<'_, str> {
+    let text = redact_with(&SETTINGS.get_or_init(Settings::default).redact, text);
+    let secrets = SECRETS.read().unwrap_or_else(|e| e.into_inner());
+    if secrets.is_empty() {
+        return text;
+    }
+    match redact_with(&secrets, &text) {
+        std::borrow::Cow::Owned(redacted) => std::borrow::Cow::Owned(redacted),
+        std::borrow::Cow::Borrowed(_) => text,
+    }
+}
+
+/// Also hide this exact text from now on.
+pub fn redact_secret(secret: &str) {
+    if secret.is_empty() {
+        return;
+    }
+    if let Ok(pattern) = regex::Regex::new(&regex::escape(secret)) {
+        SECRETS.write().unwrap_or_else(|e| e.into_inner()).push(pattern);
+    }
+}
+
+/// Get the `strftime` format for log timestamps.
+fn timestamp_format(utc: bool, rfc3339: bool) -> &'static str {
+    match (utc, rfc3339) {
+        (_, false) => "%Y-%m-%d %H:%M:%S",
+        (false, true) => "%Y-%m-%dT%H:%M:%S%.3f%:z",
+        (true, true) => "%Y-%m-%dT%H:%M:%S%.3fZ",
+    }
+}
+
+/// Format the time of a log record.
+/// JSON records always use RFC 3339 so that they can be parsed reliably.
+fn timestamp(now: &mut flexi_logger::DeferredNow, rfc3339: bool) -> String {
+    let settings = SETTINGS.get_or_init(Settings::default);
+    let format = timestamp_format(settings.utc, rfc3339 || settings.rfc3339);
+    if settings.utc {
+        now.now_utc_owned().format(format).to_string()
+    } else {
+        now.now().format(format).to_string()
+    }
+}
+
+/// Write a log record as a line of text with its timestamp and level.
+pub fn text(
+    w: &mut dyn std::io::Write,
+    now: &mut flexi_logger::DeferredNow,
+    record: &log::Record,
+) -> Result<(), std::io::Error> {
+    write!(
+        w,
+        "{} [{}] {}",
+        timestamp(now, false),
+        record.level(),
+        redact(&record.args().to_string())
+    )
+}
+
+/// Write a log record as-is, for the command's output in `--log-cmd-as`.
+pub fn raw(
+    w: &mut dyn std::io::Write,
+    _now: &mut flexi_logger::DeferredNow,
+    record: &log::Record,
+) -> Result<(), std::io::Error> {
+    write!(w, "{}", redact(&record.args().to_string()))
+}
+
+/// Write a log record with its level, for the console.
+pub fn console(
+    w: &mut dyn std::io::Write,
+    _now: &mut flexi_logger::DeferredNow,
+    record: &log::Record,
+) -> Result<(), std::io::Error> {
+    write!(w, "[{}] {}", record.level(), redact(&record.args().to_string()))
+}
+
+/// Quote and escape a string for JSON.
+pub fn json_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Build a JSON log record.
+/// `stream` is only set for lines of a command's output.
+fn json_record(timestamp: &str, level: &str, service: &str, stream: Option<&str>, message: &str) -> String {
+    let mut record = format!(
+        "{{\"timestamp\":{},\"level\":{},\"service\":{}",
+        json_string(timestamp),
+        json_string(level),
+        json_string(service)
+    );
+    if let Some(stream) = stream {
+        record.push_str(&format!(",\"stream\":{}", json_string(stream)));
+    }
+    record.push_str(&format!(",\"message\":{}}}", json_string(message)));
+    record
+}
+
+/// Get the stream and redacted message of a log record for structured formats.
+/// Lines of a command's output are logged with `stream` and `line` key-values,
+/// which take the place of the formatted message.
+pub fn structured_message(record: &log::Record) -> (Option<String>, String) {
+    let key_values = record.key_values();
+    let stream = key_values.get(log::kv::Key::from_str("stream")).map(|x| x.to_string());
+    let message = match key_values.get(log::kv::Key::from_str("line")) {
+        Some(line) => line.to_string(),
+        None => record.args().to_string(),
+    };
+    let message = redact(&message).into_owned();
+    (stream, message)
+}
+
+/// Get the service name to include in structured log records.
+pub fn service() -> &'static str {
+    &SETTINGS.get_or_init(Settings::default).service
+}
+
+/// Write a log record as a single line of JSON, for `--log-format json`.
+pub fn json(
+    w: &mut dyn std::io::Write,
+    now: &mut flexi_logger::DeferredNow,
+    record: &log::Record,
+) -> Result<(), std::io::Error> {
+    let (stream, message) = structured_message(record);
+    write!(
+        w,
+        "{}",
+        json_record(
+            &timestamp(now, true),
+            record.level().as_str(),
+            service(),
+            stream.as_deref(),
+            &message,
+        )
+    )
+}
+
+#[cfg(test)]
+speculate::speculate! {
+    describe "timestamp_format" {
+        it "keeps the original format by default" {
+            assert_eq!(timestamp_format(false, false), "%Y-%m-%d %H:%M:%S");
+            assert_eq!(timestamp_format(true, false), "%Y-%m-%d %H:%M:%S");
+        }
+
+        it "includes the offset for RFC 3339" {
+            assert_eq!(timestamp_format(false, true), "%Y-%m-%dT%H:%M:%S%.3f%:z");
+            assert_eq!(timestamp_format(true, true), "%Y-%m-%dT%H:%M:%S%.3fZ");
+        }
+    }
+
+    describe "redact_with" {
+        it "replaces every match" {
+            let patterns = vec![
+                regex::Regex::new("token=[a-z0-9]+").unwrap(),
+                regex::Regex::new("hunter2").unwrap(),
+            ];
+            assert_eq!(
+                redact_with(&patterns, "run --env token=abc123 --pass hunter2 token=def"),
+                "run --env *** --pass *** ***",
+            );
+        }
+
+        it "keeps text without matches" {
+            let patterns = vec![regex::Regex::new("secret").unwrap()];
+            assert_eq!(redact_with(&patterns, "nothing to see"), "nothing to see");
+            assert_eq!(redact_with(&[], "secret"), "secret");
+        }
+    }
+
+    describe "json_string" {
+        it "escapes special characters" {
+            assert_eq!(json_string("a \"b\" \\ c"), r#""a \"b\" \\ c""#);
+            assert_eq!(json_string("a\r\n\tb"), r#""a\r\n\tb""#);
+            assert_eq!(json_string("\u{1b}[0m"), r#""\u001b[0m""#);
+        }
+
+        it "keeps other characters as-is" {
+            assert_eq!(json_string("héllo wörld"), r#""héllo wörld""#);
+        }
+    }
+
+    describe "json_record" {
+        it "includes the stream for command output" {
+            assert_eq!(
+                json_record("2025-01-01T00:00:00.000+00:00", "DEBUG", "app", Some("stderr"), "oops"),
+                r#"{"timestamp":"2025-01-01T00:00:00.000+00:00","level":"DEBUG","service":"app","stream":"stderr","message":"oops"}"#,
+            );
+        }
+
+        it "omits the stream for Shawl's own messages" {
+            assert_eq!(
+                json_record("2025-01-01T00:00:00.000+00:00", "INFO", "app", None, "Launching command"),
+                r#"{"timestamp":"2025-01-01T00:00:00.000+00:00","level":"INFO","service":"app","message":"Launching command"}"#,
+            );
+        }
+    }
+}
@@ -0,0 +1,282 @@
+/// How often to check the log files.
+const PRUNE_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct LogFile {
+    path: std::path::PathBuf,
+    size: u64,
+    modified: std::time::SystemTime,
+    /// Whether the logger is still writing to this file.
+    current: bool,
+    /// Whether the file is named by `--log-name-pattern`.
+    patterned: bool,
+}
+
+/// Check if a file name belongs to a log with this stem,
+/// like `shawl_for_app_rCURRENT.log` or `shawl_for_app_r2025-01-01_00-00-00.log`.
+/// Returns whether it is the current file, or `None` if it does not belong to the log.
+fn parse_log_name(name: &str, stem: &str) -> Option<bool> {
+    let rest = name.strip_prefix(stem)?.strip_prefix("_r")?.strip_suffix(".log")?;
+    if rest == "CURRENT" {
+        Some(true)
+    } else if is_rotation_timestamp(rest) {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+/// Check for the timestamp that the logger gives a rotated file, like `2025-01-01_00-00-00`,
+/// including the `.restart-n` suffix that it adds when a name is already taken.
+/// This keeps another service's logs, like `shawl_for_app_r2_rCURRENT.log`, from matching `shawl_for_app`.
+fn is_rotation_timestamp(text: &str) -> bool {
+    let (timestamp, restart) = match text.split_once(".restart-") {
+        Some((timestamp, restart)) => (timestamp, Some(restart)),
+        None => (text, None),
+    };
+    let restart_valid = restart.is_none_or(|x| !x.is_empty() && x.chars().all(|c| c.is_ascii_digit()));
+    let shape = "0000-00-00_00-00-00";
+    restart_valid
+        && timestamp.len() == shape.len()
+        && timestamp.chars().zip(shape.chars()).all(|(c, expected)| {
+            if expected == '0' {
+                c.is_ascii_digit()
+            } else {
+                c == expected
+            }
+        })
+}
+
+/// Convert a `--log-name-pattern` to a regular expression that matches its file names,
+/// including the `.restart-n` suffix that the logger adds when a name is already taken.
+fn pattern_regex(pattern: &str) -> String {
+    let mut regex = "^".to_string();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            regex.push_str(&regex::escape(&c.to_string()));
+            continue;
+        }
+        regex.push_str(match chars.next() {
+            Some('Y') => r"\d{4}",
+            Some('y' | 'm' | 'd' | 'H' | 'M' | 'S') => r"\d{2}",
+            Some('j') => r"\d{3}",
+            Some('%') => "%",
+            _ => ".+?",
+        });
+    }
+    regex.push_str(r"(\.restart-\d+)?\.log$");
+    regex
+}
+
+/// Choose which rotated files to delete, oldest first,
+/// until the total size of all the files is within `max_total` bytes.
+/// Current files count toward the total, but are never deleted.
+fn select_for_removal(mut files: Vec<LogFile>, max_total: u64) -> Vec<std::path::PathBuf> {
+    let mut total: u64 = files.iter().map(|x| x.size).sum();
+    files.sort_by_key(|x| x.modified);
+
+    let mut removals = vec![];
+    for file in files.into_iter().filter(|x| !x.current) {
+        if total <= max_total {
+            break;
+        }
+        total = total.saturating_sub(file.size);
+        removals.push(file.path);
+    }
+    removals
+}
+
+/// Choose which rotated files from a name pattern to delete, oldest first,
+/// so that only the newest `retain` of them are kept.
+/// The logger can't tell which files are its own in that case, so it never deletes them itself.
+fn select_beyond_retention(mut files: Vec<LogFile>, retain: usize) -> Vec<std::path::PathBuf> {
+    files.retain(|x| x.patterned && !x.current);
+    files.sort_by_key(|x| std::cmp::Reverse(x.modified));
+    files.into_iter().skip(retain).map(|x| x.path).collect()
+}
+
+fn find_log_files(dir: &std::path::Path, stems: &[String], pattern: Option<&regex::Regex>) -> Vec<LogFile> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            log::error!("Unable to read log directory {}: {:?}", dir.display(), e);
+            return vec![];
+        }
+    };
+
+    let mut files = vec![];
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let (current, patterned) = match stems.iter().find_map(|stem| parse_log_name(&name, stem)) {
+            Some(current) => (current, false),
+            None if pattern.is_some_and(|x| x.is_match(&name)) => (false, true),
+            None => continue,
+        };
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        if !metadata.is_file() {
+            continue;
+        }
+        files.push(LogFile {
+            path: entry.path(),
+            size: metadata.len(),
+            modified: metadata.modified().unwrap_or(std::time::SystemTime::UNIX_EPOCH),
+            current,
+            patterned,
+        });
+    }
+
+    // Files from a name pattern don't say which one is current, but it's the newest.
+    if let Some(newest) = files.iter_mut().filter(|x| x.patterned).max_by_key(|x| x.modified) {
+        newest.current = true;
+    }
+    files
+}
+
+fn remove(path: &std::path::Path, reason: &str) {
+    match std::fs::remove_file(path) {
+        Ok(_) => log::debug!("Removed old log file to {}: {}", reason, path.display()),
+        Err(e) => log::error!("Unable to remove old log file {}: {:?}", path.display(), e),
+    }
+}
+
+/// Delete the oldest rotated files from a name pattern beyond `retain`,
+/// then the oldest rotated log files until the logs are within `max_total` bytes.
+fn prune(
+    dir: &std::path::Path,
+    stems: &[String],
+    pattern: Option<&regex::Regex>,
+    max_total: Option<u64>,
+    retain: usize,
+) {
+    let mut files = find_log_files(dir, stems, pattern);
+    let removals = select_beyond_retention(files.clone(), retain);
+    for path in &removals {
+        remove(path, "keep the retained count");
+    }
+    files.retain(|x| !removals.contains(&x.path));
+
+    if let Some(max_total) = max_total {
+        for path in select_for_removal(files, max_total) {
+            remove(&path, "stay under size limit");
+        }
+    }
+}
+
+/// Keep the logs with these file stems or `--log-name-pattern` within `max_total` bytes,
+/// and keep only `retain` rotated files from the pattern,
+/// checking now and then periodically on a background thread.
+pub fn start(
+    dir: std::path::PathBuf,
+    stems: Vec<String>,
+    pattern: Option<&str>,
+    max_total: Option<u64>,
+    retain: usize,
+) {
+    let pattern = pattern.and_then(|x| regex::Regex::new(&pattern_regex(x)).ok());
+    prune(&dir, &stems, pattern.as_ref(), max_total, retain);
+    std::thread::spawn(move || loop {
+        std::thread::sleep(PRUNE_INTERVAL);
+        prune(&dir, &stems, pattern.as_ref(), max_total, retain);
+    });
+}
+
+#[cfg(test)]
+speculate::speculate! {
+    fn file(name: &str, size: u64, age: u64, current: bool) -> LogFile {
+        LogFile {
+            path: std::path::PathBuf::from(name),
+            size,
+            modified: std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1000 - age),
+            current,
+            patterned: false,
+        }
+    }
+
+    fn patterned(name: &str, age: u64, current: bool) -> LogFile {
+        LogFile {
+            patterned: true,
+            ..file(name, 10, age, current)
+        }
+    }
+
+    describe "parse_log_name" {
+        it "recognizes current and rotated files" {
+            assert_eq!(parse_log_name("shawl_for_app_rCURRENT.log", "shawl_for_app"), Some(true));
+            assert_eq!(parse_log_name("shawl_for_app_r2025-01-01_00-00-00.log", "shawl_for_app"), Some(false));
+            assert_eq!(
+                parse_log_name("shawl_for_app_r2025-01-01_00-00-00.restart-0001.log", "shawl_for_app"),
+                Some(false)
+            );
+        }
+
+        it "ignores other files" {
+            assert_eq!(parse_log_name("shawl_for_other_rCURRENT.log", "shawl_for_app"), None);
+            assert_eq!(parse_log_name("shawl_for_app_rest_rCURRENT.log", "shawl_for_app"), None);
+            assert_eq!(parse_log_name("shawl_for_app_r2_rCURRENT.log", "shawl_for_app"), None);
+            assert_eq!(parse_log_name("shawl_for_app_r2_r2025-01-01_00-00-00.log", "shawl_for_app"), None);
+            assert_eq!(parse_log_name("shawl_for_app_r2025-01-01_00-00-00.txt", "shawl_for_app"), None);
+        }
+    }
+
+    describe "pattern_regex" {
+        it "matches dates in the pattern" {
+            let regex = regex::Regex::new(&pattern_regex("app-%Y-%m-%d")).unwrap();
+            assert!(regex.is_match("app-2024-05-01.log"));
+            assert!(regex.is_match("app-2024-05-01.restart-0001.log"));
+            assert!(!regex.is_match("app-2024-05.log"));
+            assert!(!regex.is_match("other-app-2024-05-01.log"));
+        }
+
+        it "escapes other characters" {
+            assert_eq!(pattern_regex("a.b_%H%%"), r"^a\.b_\d{2}%(\.restart-\d+)?\.log$");
+        }
+    }
+
+    describe "select_for_removal" {
+        it "removes nothing when under the limit" {
+            let files = vec![file("a", 10, 0, true), file("b", 10, 5, false)];
+            assert_eq!(select_for_removal(files, 20), Vec::<std::path::PathBuf>::new());
+        }
+
+        it "removes the oldest rotated files first" {
+            let files = vec![
+                file("current", 10, 0, true),
+                file("new", 10, 1, false),
+                file("old", 10, 3, false),
+                file("middle", 10, 2, false),
+            ];
+            assert_eq!(
+                select_for_removal(files, 25),
+                vec![std::path::PathBuf::from("old"), std::path::PathBuf::from("middle")],
+            );
+        }
+
+        it "never removes current files" {
+            let files = vec![file("current", 100, 0, true), file("old", 10, 1, false)];
+            assert_eq!(select_for_removal(files, 50), vec![std::path::PathBuf::from("old")]);
+        }
+    }
+
+    describe "select_beyond_retention" {
+        it "keeps the newest rotated files from the pattern" {
+            let files = vec![
+                patterned("app-04.log", 0, true),
+                patterned("app-03.log", 1, false),
+                patterned("app-01.log", 3, false),
+                patterned("app-02.log", 2, false),
+            ];
+            assert_eq!(
+                select_beyond_retention(files, 2),
+                vec![std::path::PathBuf::from("app-01.log")],
+            );
+        }
+
+        it "leaves files from the logger's own naming to the logger" {
+            let files = vec![file("shawl_for_app_rCURRENT.log", 10, 0, true), file("shawl_for_app_r1.log", 10, 5, false)];
+            assert_eq!(select_beyond_retention(files, 0), Vec::<std::path::PathBuf>::new());
+        }
+    }
+}
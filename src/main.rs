@@ -1,150 +1,141 @@
-mod cli;
-mod control;
-#[cfg(windows)]
-mod service;
-
-use crate::cli::{evaluate_cli, Subcommand};
 use log::{debug, error};
-
-/// Simplify local UNC paths since some programs (notably cmd.exe) don't like them.
-pub fn simplify_path(path: &str) -> String {
-    dunce::simplified(std::path::Path::new(path))
-        .to_string_lossy()
-        .to_string()
-}
-
-fn prepare_logging(
-    name: &str,
-    log_dir: Option<&String>,
-    console: bool,
-    rotation: cli::LogRotation,
-    retention: usize,
-    log_as: Option<&String>,
-    log_cmd_as: Option<&String>,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let mut exe_dir = std::env::current_exe()?;
-    exe_dir.pop();
-
-    let log_dir = simplify_path(&match log_dir {
-        Some(log_dir) => log_dir.to_string(),
-        None => exe_dir.to_string_lossy().to_string(),
-    });
-
-    let rotation = match rotation {
-        cli::LogRotation::Bytes(bytes) => flexi_logger::Criterion::Size(bytes),
-        cli::LogRotation::Daily => flexi_logger::Criterion::Age(flexi_logger::Age::Day),
-        cli::LogRotation::Hourly => flexi_logger::Criterion::Age(flexi_logger::Age::Hour),
-    };
-
-    let mut logger = flexi_logger::Logger::try_with_env_or_str("debug")?
-        .log_to_file({
-            let spec = flexi_logger::FileSpec::default().directory(log_dir.clone());
-
-            if let Some(log_as) = log_as {
-                spec.basename(log_as)
-            } else {
-                spec.discriminant(format!("for_{}", name))
-            }
-        })
-        .append()
-        .rotate(
-            rotation,
-            flexi_logger::Naming::Timestamps,
-            flexi_logger::Cleanup::KeepLogFiles(retention),
-        )
-        .format_for_files(|w, now, record| {
-            write!(
-                w,
-                "{} [{}] {}",
-                now.now().format("%Y-%m-%d %H:%M:%S"),
-                record.level(),
-                &record.args()
-            )
-        })
-        .format_for_stderr(|w, _now, record| write!(w, "[{}] {}", record.level(), &record.args()));
-
-    if console {
-        logger = logger.duplicate_to_stderr(flexi_logger::Duplicate::Info);
-    }
-
-    if let Some(log_cmd_as) = log_cmd_as {
-        logger = logger.add_writer(
-            "shawl-cmd",
-            Box::new(
-                flexi_logger::writers::FileLogWriter::builder(
-                    flexi_logger::FileSpec::default()
-                        .directory(log_dir)
-                        .basename(log_cmd_as),
-                )
-                .append()
-                .rotate(
-                    rotation,
-                    flexi_logger::Naming::Timestamps,
-                    flexi_logger::Cleanup::KeepLogFiles(retention),
-                )
-                .format(|w, _now, record| write!(w, "{}", &record.args()))
-                .try_build()?,
-            ),
-        );
-    }
-
-    logger.start()?;
-    Ok(())
-}
+use shawl_core::{
+    cli::{evaluate_cli, Subcommand},
+    flush_logs, prepare_logging,
+};
 
 #[cfg(windows)]
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let cli = evaluate_cli();
-    let console = !matches!(cli.sub, Subcommand::Run { .. });
+    let console = !matches!(cli.sub, Subcommand::Run { foreground: false, .. });
 
-    let should_log = match cli.clone().sub {
-        Subcommand::Add { common: opts, .. } => !opts.no_log,
-        Subcommand::Run { common: opts, .. } => !opts.no_log,
+    let logging = match &cli.sub {
+        Subcommand::Add { name, common, .. } | Subcommand::Run { name, common, .. } => {
+            (!common.no_log).then_some((name, common))
+        }
+        // Only prints to the console.
+        Subcommand::Remove { .. } | Subcommand::Status { .. } => None,
+        // Logs according to the installed service's options once it reads them.
+        Subcommand::Test { .. } => None,
     };
-    if should_log {
-        let (name, common) = match &cli.sub {
-            Subcommand::Add { name, common, .. } | Subcommand::Run { name, common, .. } => (name, common),
-        };
-        prepare_logging(
-            name,
-            common.log_dir.as_ref(),
-            console,
-            common.log_rotate.unwrap_or_default(),
-            common.log_retain.unwrap_or(2),
-            common.log_as.as_ref(),
-            common.log_cmd_as.as_ref(),
-        )?;
+    if let Some((name, common)) = logging {
+        prepare_logging(name, common, console)?;
     }
 
     debug!("********** LAUNCH **********");
-    debug!("{:?}", cli);
+    debug!("{:?}", cli.redacted());
 
     match cli.sub {
         Subcommand::Add {
             name,
             cwd,
             dependencies,
+            no_verify,
+            account,
+            description,
             common: opts,
-        } => match control::add_service(name, cwd, &dependencies, opts) {
+            ..
+        } => match shawl_core::add_service(name, cwd, &dependencies, account, description, no_verify, opts) {
             Ok(_) => (),
-            Err(_) => std::process::exit(1),
+            Err(e) => {
+                flush_logs();
+                pause_if_own_console();
+                std::process::exit(e.exit_code())
+            }
         },
-        Subcommand::Run { name, .. } => match service::run(name) {
+        Subcommand::Run { foreground: true, .. } => run_foreground(),
+        Subcommand::Run { name, .. } => match shawl_core::run_service(name) {
             Ok(_) => (),
+            Err(e) if shawl_core::started_outside_service_manager(&e) => {
+                debug!("Not started by the service manager");
+                if !offer_foreground() {
+                    flush_logs();
+                    std::process::exit(1)
+                }
+                shawl_core::show_logs_in_console();
+                run_foreground();
+            }
             Err(e) => {
                 error!("Failed to run the service:\n{:#?}", e);
                 // We wouldn't have a console if the Windows service manager
                 // ran this, but if we failed here, then it's likely the user
                 // tried to run it directly, so try showing them the error:
                 println!("Failed to run the service:\n{:#?}", e);
+                flush_logs();
+                std::process::exit(1)
+            }
+        },
+        Subcommand::Remove { name } => match shawl_core::remove_service(&name) {
+            Ok(()) => println!("Deleted service {}", name),
+            Err(e) => {
+                eprintln!("{}", e);
                 std::process::exit(1)
             }
         },
+        Subcommand::Status { name, live } => {
+            if let Err(e) = shawl_core::show_status(&name, live) {
+                eprintln!("{}", e);
+                std::process::exit(1)
+            }
+        }
+        Subcommand::Test { name, duration } => {
+            if let Err(e) = shawl_core::test_service(&name, duration.unwrap_or(std::time::Duration::from_secs(10))) {
+                error!("Failed to test the service: {}", e);
+                eprintln!("Failed to test the service: {}", e);
+                flush_logs();
+                std::process::exit(1)
+            }
+        }
     }
     debug!("Finished successfully");
+    flush_logs();
     Ok(())
 }
 
+/// Keep a console window that would close on exit open until the user has read the errors,
+/// such as the window that `--elevate` opens.
+#[cfg(windows)]
+fn pause_if_own_console() {
+    use std::io::BufRead;
+
+    if !shawl_core::owns_console() {
+        return;
+    }
+    println!("Press Enter to close this window");
+    let _ = std::io::stdin().lock().read_line(&mut String::new());
+}
+
+#[cfg(windows)]
+fn run_foreground() {
+    if let Err(e) = shawl_core::run_foreground() {
+        error!("Failed to run in the foreground:\n{:#?}", e);
+        flush_logs();
+        std::process::exit(1)
+    }
+}
+
+/// Explain that `run` is only for the service manager, since this is likely
+/// someone trying it from a terminal, and offer to run in the foreground instead.
+#[cfg(windows)]
+fn offer_foreground() -> bool {
+    use std::io::{BufRead, IsTerminal, Write};
+
+    println!("`shawl run` is meant to be started by the Windows service manager, not directly.");
+    println!("To install a service with these options, use `shawl add` instead.");
+    println!("To try them in this console, use `shawl run --foreground`.");
+    if !std::io::stdin().is_terminal() {
+        return false;
+    }
+
+    print!("Run in the foreground now? [y/N] ");
+    let _ = std::io::stdout().flush();
+    let mut answer = String::new();
+    if std::io::stdin().lock().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
 #[cfg(not(windows))]
 fn main() {
     panic!("This program is only intended to run on Windows.");
@@ -0,0 +1,221 @@
+use crate::{
+    cli::WebhookUrl,
+    eventlog::{Event, Level},
+    logformat::json_string,
+};
+
+static NOTIFIER: std::sync::OnceLock<Notifier> = std::sync::OnceLock::new();
+
+/// Restarts since the service started, to include in notifications.
+static RESTARTS: std::sync::atomic::AtomicU32 = std::sync::atomic::AtomicU32::new(0);
+
+/// Notifications that are queued or being sent.
+static PENDING: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+/// How many notifications can wait to be sent before new ones are dropped.
+const QUEUE_SIZE: usize = 100;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Format {
+    Slack,
+    Teams,
+}
+
+struct Notifier {
+    service: String,
+    host: String,
+    webhooks: Vec<(Format, WebhookUrl)>,
+    queue: std::sync::mpsc::SyncSender<(WebhookUrl, String)>,
+}
+
+/// Details to show with an event, such as the exit code.
+fn facts(service: &str, host: &str, event: &Event, restarts: u32) -> Vec<(&'static str, String)> {
+    let mut facts = vec![("Service", service.to_string()), ("Host", host.to_string())];
+    match event {
+        Event::Launched { pid } => facts.push(("PID", pid.to_string())),
+        Event::Exited { code } | Event::Crashed { code, .. } => {
+            facts.push(("Exit code", crate::exitcode::describe(*code)));
+        }
+        _ => (),
+    }
+    facts.push(("Restarts", restarts.to_string()));
+    facts
+}
+
+/// Build a Slack message with the details as attachment fields.
+fn slack_payload(title: &str, level: &Level, facts: &[(&str, String)]) -> String {
+    let color = match level {
+        Level::Information => "#2eb886",
+        Level::Warning => "#daa038",
+        Level::Error => "#a30200",
+    };
+    let fields: Vec<_> = facts
+        .iter()
+        .map(|(name, value)| {
+            format!(
+                "{{\"title\":{},\"value\":{},\"short\":true}}",
+                json_string(name),
+                json_string(value)
+            )
+        })
+        .collect();
+    format!(
+        "{{\"text\":{},\"attachments\":[{{\"color\":\"{}\",\"fields\":[{}]}}]}}",
+        json_string(title),
+        color,
+        fields.join(",")
+    )
+}
+
+/// Build a Teams message with an Adaptive Card that lists the details.
+fn teams_payload(title: &str, level: &Level, facts: &[(&str, String)]) -> String {
+    let color = match level {
+        Level::Information => "Good",
+        Level::Warning => "Warning",
+        Level::Error => "Attention",
+    };
+    let facts: Vec<_> = facts
+        .iter()
+        .map(|(name, value)| format!("{{\"title\":{},\"value\":{}}}", json_string(name), json_string(value)))
+        .collect();
+    format!(
+        concat!(
+            "{{\"type\":\"message\",\"attachments\":[{{",
+            "\"contentType\":\"application/vnd.microsoft.card.adaptive\",\"content\":{{",
+            "\"$schema\":\"http://adaptivecards.io/schemas/adaptive-card.json\",",
+            "\"type\":\"AdaptiveCard\",\"version\":\"1.4\",\"body\":[",
+            "{{\"type\":\"TextBlock\",\"text\":{},\"weight\":\"Bolder\",\"wrap\":true,\"color\":\"{}\"}},",
+            "{{\"type\":\"FactSet\",\"facts\":[{}]}}",
+            "]}}}}]}}"
+        ),
+        json_string(title),
+        color,
+        facts.join(",")
+    )
+}
+
+/// Start sending notifications for `--notify-slack` and `--notify-teams`.
+/// They are queued for a background thread so that the service never waits on the network.
+pub fn open(service: &str, slack: Option<WebhookUrl>, teams: Option<WebhookUrl>) {
+    let webhooks: Vec<_> = [(Format::Slack, slack), (Format::Teams, teams)]
+        .into_iter()
+        .filter_map(|(format, url)| url.map(|url| (format, url)))
+        .collect();
+    if webhooks.is_empty() {
+        return;
+    }
+
+    let (queue, receiver) = std::sync::mpsc::sync_channel::<(WebhookUrl, String)>(QUEUE_SIZE);
+    std::thread::spawn(move || {
+        for (url, body) in receiver {
+            match crate::http::post(&url, &body) {
+                Ok(status) if status < 300 => (),
+                Ok(status) => log::error!("Notification to {} failed with HTTP status {}", url.host, status),
+                Err(e) => log::error!("Unable to send notification to {}: {:?}", url.host, e),
+            }
+            PENDING.fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+        }
+    });
+
+    let _ = NOTIFIER.set(Notifier {
+        service: service.to_string(),
+        host: std::env::var("COMPUTERNAME").unwrap_or_else(|_| "localhost".to_string()),
+        webhooks,
+        queue,
+    });
+}
+
+/// Update the restart count to include in notifications.
+pub fn set_restarts(count: u32) {
+    RESTARTS.store(count, std::sync::atomic::Ordering::SeqCst);
+}
+
+/// Send a lifecycle event to the webhooks, if notifications were started with `open`.
+pub fn report(event: &Event) {
+    let Some(notifier) = NOTIFIER.get() else {
+        return;
+    };
+    if matches!(event, Event::Output { .. }) {
+        return;
+    }
+
+    let title = event.message(&notifier.service);
+    let level = event.level();
+    let facts = facts(
+        &notifier.service,
+        &notifier.host,
+        event,
+        RESTARTS.load(std::sync::atomic::Ordering::SeqCst),
+    );
+    for (format, url) in &notifier.webhooks {
+        let body = match format {
+            Format::Slack => slack_payload(&title, &level, &facts),
+            Format::Teams => teams_payload(&title, &level, &facts),
+        };
+        PENDING.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+        if notifier.queue.try_send((url.clone(), body)).is_err() {
+            PENDING.fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+            log::error!("Dropped notification to {} because too many are waiting", url.host);
+        }
+    }
+}
+
+/// Give queued notifications a chance to be sent, such as before the service stops.
+pub fn wait(timeout: std::time::Duration) {
+    let deadline = std::time::Instant::now() + timeout;
+    while PENDING.load(std::sync::atomic::Ordering::SeqCst) > 0 && std::time::Instant::now() < deadline {
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+}
+
+#[cfg(test)]
+speculate::speculate! {
+    describe "facts" {
+        it "includes the exit code for exits" {
+            assert_eq!(
+                facts("app", "pc", &Event::Exited { code: 1 }, 2),
+                vec![
+                    ("Service", "app".to_string()),
+                    ("Host", "pc".to_string()),
+                    ("Exit code", "1".to_string()),
+                    ("Restarts", "2".to_string()),
+                ],
+            );
+        }
+
+        it "omits the exit code for other events" {
+            assert_eq!(
+                facts("app", "pc", &Event::Stopping, 0),
+                vec![
+                    ("Service", "app".to_string()),
+                    ("Host", "pc".to_string()),
+                    ("Restarts", "0".to_string()),
+                ],
+            );
+        }
+    }
+
+    describe "slack_payload" {
+        it "formats the details as fields" {
+            assert_eq!(
+                slack_payload("app: Command exited with code 1", &Level::Warning, &[("Service", "app".to_string())]),
+                r##"{"text":"app: Command exited with code 1","attachments":[{"color":"#daa038","fields":[{"title":"Service","value":"app","short":true}]}]}"##,
+            );
+        }
+    }
+
+    describe "teams_payload" {
+        it "formats the details as an Adaptive Card" {
+            assert_eq!(
+                teams_payload("app: Stopping command", &Level::Information, &[("Service", "app".to_string())]),
+                concat!(
+                    r#"{"type":"message","attachments":[{"contentType":"application/vnd.microsoft.card.adaptive","content":{"#,
+                    r#""$schema":"http://adaptivecards.io/schemas/adaptive-card.json","type":"AdaptiveCard","version":"1.4","body":["#,
+                    r#"{"type":"TextBlock","text":"app: Stopping command","weight":"Bolder","wrap":true,"color":"Good"},"#,
+                    r#"{"type":"FactSet","facts":[{"title":"Service","value":"app"}]}"#,
+                    r#"]}}]}"#,
+                ),
+            );
+        }
+    }
+}
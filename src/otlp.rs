@@ -0,0 +1,296 @@
+use crate::{
+    cli::WebhookUrl,
+    eventlog::{Event, Level},
+    logformat::json_string,
+};
+
+static EXPORTER: std::sync::OnceLock<Exporter> = std::sync::OnceLock::new();
+
+/// Records that are queued or being sent.
+static PENDING: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+/// Records dropped because the queue was full, reported with the next batch.
+static DROPPED: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+/// How many records can wait to be sent before new ones are dropped.
+const QUEUE_SIZE: usize = 1000;
+
+/// Most records to send in one request.
+const BATCH_SIZE: usize = 500;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum Value {
+    String(String),
+    Int(i64),
+}
+
+#[derive(Debug, Eq, PartialEq)]
+struct Record {
+    time: std::time::SystemTime,
+    level: Level,
+    body: String,
+    attributes: Vec<(&'static str, Value)>,
+}
+
+struct Exporter {
+    service: String,
+    output: bool,
+    queue: std::sync::mpsc::SyncSender<Record>,
+}
+
+/// Where to post logs for a collector's base URL, following the OTLP/HTTP convention
+/// of adding `/v1/logs`, unless the URL already points there.
+fn logs_url(endpoint: &WebhookUrl) -> WebhookUrl {
+    if endpoint.path.ends_with("/v1/logs") {
+        return endpoint.clone();
+    }
+    WebhookUrl {
+        path: format!("{}/v1/logs", endpoint.path.trim_end_matches('/')),
+        ..endpoint.clone()
+    }
+}
+
+/// Attributes for an event, using the OpenTelemetry semantic conventions where there is one.
+fn event_attributes(event: &Event) -> Vec<(&'static str, Value)> {
+    let mut attributes = vec![("shawl.event", Value::String(event.name().to_string()))];
+    match event {
+        Event::Launched { pid } => attributes.push(("process.pid", Value::Int(i64::from(*pid)))),
+        Event::Exited { code } => attributes.push(("process.exit.code", Value::Int(i64::from(*code)))),
+        Event::Crashed { code, dump } => {
+            attributes.push(("process.exit.code", Value::Int(i64::from(*code))));
+            if let Some(dump) = dump {
+                attributes.push(("shawl.crash_dump", Value::String(dump.clone())));
+            }
+        }
+        Event::Output { stream, .. } => attributes.push(("log.iostream", Value::String(stream.name().to_string()))),
+        _ => (),
+    }
+    attributes
+}
+
+fn attributes_json(attributes: &[(&str, Value)]) -> String {
+    let attributes: Vec<_> = attributes
+        .iter()
+        .map(|(key, value)| {
+            let value = match value {
+                Value::String(x) => format!("{{\"stringValue\":{}}}", json_string(x)),
+                Value::Int(x) => format!("{{\"intValue\":\"{}\"}}", x),
+            };
+            format!("{{\"key\":{},\"value\":{}}}", json_string(key), value)
+        })
+        .collect();
+    format!("[{}]", attributes.join(","))
+}
+
+/// Build an OTLP/HTTP JSON request with the records under one resource for the service.
+fn payload(service: &str, host: &str, records: &[Record]) -> String {
+    let records: Vec<_> = records
+        .iter()
+        .map(|record| {
+            let (severity_number, severity_text) = match record.level {
+                Level::Information => (9, "INFO"),
+                Level::Warning => (13, "WARN"),
+                Level::Error => (17, "ERROR"),
+            };
+            let time = record
+                .time
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|x| x.as_nanos())
+                .unwrap_or_default();
+            format!(
+                "{{\"timeUnixNano\":\"{}\",\"severityNumber\":{},\"severityText\":\"{}\",\"body\":{{\"stringValue\":{}}},\"attributes\":{}}}",
+                time,
+                severity_number,
+                severity_text,
+                json_string(&record.body),
+                attributes_json(&record.attributes)
+            )
+        })
+        .collect();
+    format!(
+        concat!(
+            "{{\"resourceLogs\":[{{\"resource\":{{\"attributes\":{}}},",
+            "\"scopeLogs\":[{{\"scope\":{{\"name\":\"shawl\",\"version\":\"{}\"}},\"logRecords\":[{}]}}]}}]}}"
+        ),
+        attributes_json(&[
+            ("service.name", Value::String(service.to_string())),
+            ("host.name", Value::String(host.to_string())),
+        ]),
+        env!("CARGO_PKG_VERSION"),
+        records.join(",")
+    )
+}
+
+fn send(record: Record) {
+    let Some(exporter) = EXPORTER.get() else {
+        return;
+    };
+    PENDING.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    if exporter.queue.try_send(record).is_err() {
+        PENDING.fetch_sub(1, std::sync::atomic::Ordering::SeqCst);
+        DROPPED.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+/// Start exporting to an OpenTelemetry collector for `--otlp-endpoint`.
+/// Records are batched on a background thread so that the service never waits on the network.
+pub fn open(service: &str, endpoint: Option<WebhookUrl>, output: bool) {
+    let Some(endpoint) = endpoint else {
+        return;
+    };
+    let url = logs_url(&endpoint);
+    let service2 = service.to_string();
+    let host = std::env::var("COMPUTERNAME").unwrap_or_else(|_| "localhost".to_string());
+
+    let (queue, receiver) = std::sync::mpsc::sync_channel::<Record>(QUEUE_SIZE);
+    std::thread::spawn(move || {
+        while let Ok(record) = receiver.recv() {
+            let mut batch = vec![record];
+            while batch.len() < BATCH_SIZE {
+                match receiver.try_recv() {
+                    Ok(record) => batch.push(record),
+                    Err(_) => break,
+                }
+            }
+
+            let dropped = DROPPED.swap(0, std::sync::atomic::Ordering::SeqCst);
+            if dropped > 0 {
+                log::warn!(
+                    "Dropped {} OpenTelemetry log records because too many were waiting",
+                    dropped
+                );
+            }
+
+            match crate::http::post(&url, &payload(&service2, &host, &batch)) {
+                Ok(status) if status < 300 => (),
+                Ok(status) => log::error!(
+                    "OpenTelemetry export to {} failed with HTTP status {}",
+                    url.host,
+                    status
+                ),
+                Err(e) => log::error!("Unable to export to OpenTelemetry collector {}: {:?}", url.host, e),
+            }
+            PENDING.fetch_sub(batch.len(), std::sync::atomic::Ordering::SeqCst);
+        }
+    });
+
+    let _ = EXPORTER.set(Exporter {
+        service: service.to_string(),
+        output,
+        queue,
+    });
+}
+
+/// Export a lifecycle event, if exporting was started with `open`.
+/// Output is exported separately by `output`.
+pub fn report(event: &Event) {
+    let Some(exporter) = EXPORTER.get() else {
+        return;
+    };
+    if matches!(event, Event::Output { .. }) {
+        return;
+    }
+    send(Record {
+        time: std::time::SystemTime::now(),
+        level: event.level(),
+        body: event.message(&exporter.service),
+        attributes: event_attributes(event),
+    });
+}
+
+/// Export a line of the command's output, if `--otlp-output` is set.
+pub fn output(stream: crate::output::Stream, line: &str) {
+    if !EXPORTER.get().is_some_and(|x| x.output) {
+        return;
+    }
+    let event = Event::Output {
+        stream,
+        line: line.to_string(),
+    };
+    send(Record {
+        time: std::time::SystemTime::now(),
+        level: event.level(),
+        body: line.to_string(),
+        attributes: event_attributes(&event),
+    });
+}
+
+/// Give queued records a chance to be sent, such as before the service stops.
+pub fn wait(timeout: std::time::Duration) {
+    let deadline = std::time::Instant::now() + timeout;
+    while PENDING.load(std::sync::atomic::Ordering::SeqCst) > 0 && std::time::Instant::now() < deadline {
+        std::thread::sleep(std::time::Duration::from_millis(50));
+    }
+}
+
+#[cfg(test)]
+speculate::speculate! {
+    fn url(path: &str) -> WebhookUrl {
+        WebhookUrl {
+            secure: false,
+            host: "localhost".to_string(),
+            port: 4318,
+            path: path.to_string(),
+        }
+    }
+
+    describe "logs_url" {
+        it "adds the logs path to the base URL" {
+            assert_eq!(logs_url(&url("/")).path, "/v1/logs");
+            assert_eq!(logs_url(&url("/otel/")).path, "/otel/v1/logs");
+        }
+
+        it "keeps a URL that already has the logs path" {
+            assert_eq!(logs_url(&url("/v1/logs")).path, "/v1/logs");
+        }
+    }
+
+    describe "event_attributes" {
+        it "includes the exit code" {
+            assert_eq!(
+                event_attributes(&Event::Crashed { code: -1073741819, dump: None }),
+                vec![
+                    ("shawl.event", Value::String("crashed".to_string())),
+                    ("process.exit.code", Value::Int(-1073741819)),
+                ],
+            );
+        }
+
+        it "includes the stream for output" {
+            assert_eq!(
+                event_attributes(&Event::Output { stream: crate::output::Stream::Stderr, line: "oops".to_string() }),
+                vec![
+                    ("shawl.event", Value::String("output".to_string())),
+                    ("log.iostream", Value::String("stderr".to_string())),
+                ],
+            );
+        }
+    }
+
+    describe "payload" {
+        it "sets the service name on the resource" {
+            let record = Record {
+                time: std::time::UNIX_EPOCH + std::time::Duration::from_millis(1_735_689_600_500),
+                level: Level::Warning,
+                body: "app: Command exited with code 1".to_string(),
+                attributes: vec![("process.exit.code", Value::Int(1))],
+            };
+            assert_eq!(
+                payload("app", "pc", &[record]),
+                format!(
+                    concat!(
+                        r#"{{"resourceLogs":[{{"resource":{{"attributes":["#,
+                        r#"{{"key":"service.name","value":{{"stringValue":"app"}}}},"#,
+                        r#"{{"key":"host.name","value":{{"stringValue":"pc"}}}}]}},"#,
+                        r#""scopeLogs":[{{"scope":{{"name":"shawl","version":"{}"}},"logRecords":["#,
+                        r#"{{"timeUnixNano":"1735689600500000000","severityNumber":13,"severityText":"WARN","#,
+                        r#""body":{{"stringValue":"app: Command exited with code 1"}},"#,
+                        r#""attributes":[{{"key":"process.exit.code","value":{{"intValue":"1"}}}}]}}"#,
+                        r#"]}}]}}]}}"#,
+                    ),
+                    env!("CARGO_PKG_VERSION"),
+                ),
+            );
+        }
+    }
+}
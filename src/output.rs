@@ -0,0 +1,890 @@
+/// Log target for lines of command output, so that their level can be set apart from Shawl's own records.
+pub const OUTPUT_TARGET: &str = "shawl::output";
+
+/// How many lines can be waiting to be logged before reading pauses.
+#[cfg(windows)]
+const LINE_QUEUE_SIZE: usize = 64;
+
+/// How long to wait for more continuation lines before logging a group.
+#[cfg(windows)]
+const GROUP_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(500);
+
+/// Most lines and bytes to join into one group, so that a continuation pattern
+/// that keeps matching can't hold output back forever.
+const MAX_GROUP_LINES: usize = 1000;
+const MAX_GROUP_BYTES: usize = 64 * 1024;
+
+/// Which of a command's output streams a line came from.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl Stream {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Stdout => "stdout",
+            Self::Stderr => "stderr",
+        }
+    }
+}
+
+/// Splits bytes into lines, even when a line arrives across several reads.
+#[derive(Debug, Default)]
+pub struct LineSplitter {
+    pending: Vec<u8>,
+}
+
+impl LineSplitter {
+    /// Add bytes, calling `emit` for each line that they complete, without its line ending.
+    pub fn push(&mut self, mut bytes: &[u8], mut emit: impl FnMut(&[u8])) {
+        while let Some(end) = bytes.iter().position(|x| *x == b'\n') {
+            self.pending.extend_from_slice(&bytes[..end]);
+            emit(trim_cr(&self.pending));
+            self.pending.clear();
+            bytes = &bytes[end + 1..];
+        }
+        self.pending.extend_from_slice(bytes);
+    }
+
+    /// Emit the final line if it did not end with a line break.
+    pub fn flush(&mut self, mut emit: impl FnMut(&[u8])) {
+        if !self.pending.is_empty() {
+            emit(trim_cr(&self.pending));
+            self.pending.clear();
+        }
+    }
+}
+
+fn trim_cr(line: &[u8]) -> &[u8] {
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+enum Utf16State {
+    #[default]
+    Unknown,
+    NotUtf16,
+    Utf16 {
+        big_endian: bool,
+    },
+}
+
+/// Detects a UTF-16 byte order mark at the start of a stream (as PowerShell may write)
+/// and transcodes the rest of the stream to UTF-8. Other streams are passed through as-is.
+#[derive(Debug, Default)]
+pub struct Utf16Detector {
+    state: Utf16State,
+    pending: Vec<u8>,
+}
+
+impl Utf16Detector {
+    /// Whether the stream turned out to be UTF-16, so the output is UTF-8.
+    pub fn is_utf16(&self) -> bool {
+        matches!(self.state, Utf16State::Utf16 { .. })
+    }
+
+    /// Process the next bytes of the stream, returning any bytes that are ready.
+    pub fn push<'a>(&mut self, bytes: &'a [u8]) -> std::borrow::Cow<'a, [u8]> {
+        match self.state {
+            Utf16State::NotUtf16 => std::borrow::Cow::Borrowed(bytes),
+            Utf16State::Unknown => {
+                self.pending.extend_from_slice(bytes);
+                if self.pending.len() < 2 {
+                    return std::borrow::Cow::Borrowed(&[]);
+                }
+                let pending = std::mem::take(&mut self.pending);
+                let (state, rest) = match pending[..2] {
+                    [0xFF, 0xFE] => (Utf16State::Utf16 { big_endian: false }, &pending[2..]),
+                    [0xFE, 0xFF] => (Utf16State::Utf16 { big_endian: true }, &pending[2..]),
+                    _ => (Utf16State::NotUtf16, &pending[..]),
+                };
+                self.state = state;
+                std::borrow::Cow::Owned(self.push(rest).into_owned())
+            }
+            Utf16State::Utf16 { big_endian } => {
+                self.pending.extend_from_slice(bytes);
+                let mut units: Vec<u16> = self
+                    .pending
+                    .chunks_exact(2)
+                    .map(|x| {
+                        if big_endian {
+                            u16::from_be_bytes([x[0], x[1]])
+                        } else {
+                            u16::from_le_bytes([x[0], x[1]])
+                        }
+                    })
+                    .collect();
+                let mut keep = self.pending.len() % 2;
+                // Wait for the rest of a surrogate pair.
+                if units.last().is_some_and(|x| (0xD800..0xDC00).contains(x)) {
+                    units.pop();
+                    keep += 2;
+                }
+                self.pending.drain(..self.pending.len() - keep);
+                std::borrow::Cow::Owned(String::from_utf16_lossy(&units).into_bytes())
+            }
+        }
+    }
+
+    /// Return whatever is left when the stream ends.
+    pub fn flush(&mut self) -> Vec<u8> {
+        if self.pending.is_empty() {
+            return vec![];
+        }
+        let pending = std::mem::take(&mut self.pending);
+        match self.state {
+            Utf16State::Utf16 { .. } => char::REPLACEMENT_CHARACTER.to_string().into_bytes(),
+            _ => pending,
+        }
+    }
+}
+
+/// Decode a line of output, replacing anything that is not valid in the encoding.
+#[cfg(windows)]
+pub fn decode(line: &[u8], encoding: crate::cli::CmdEncoding) -> std::borrow::Cow<'_, str> {
+    use crate::cli::CmdEncoding;
+    use windows::Win32::Globalization::{GetACP, GetOEMCP, MultiByteToWideChar, MULTI_BYTE_TO_WIDE_CHAR_FLAGS};
+
+    let codepage = match encoding {
+        CmdEncoding::Utf8 => return String::from_utf8_lossy(line),
+        CmdEncoding::Auto => match std::str::from_utf8(line) {
+            Ok(text) => return std::borrow::Cow::Borrowed(text),
+            Err(_) => unsafe { GetOEMCP() },
+        },
+        CmdEncoding::Oem => unsafe { GetOEMCP() },
+        CmdEncoding::Ansi => unsafe { GetACP() },
+        CmdEncoding::CodePage(codepage) => codepage,
+    };
+    if line.is_empty() {
+        return std::borrow::Cow::Borrowed("");
+    }
+
+    let flags = MULTI_BYTE_TO_WIDE_CHAR_FLAGS(0);
+    let len = unsafe { MultiByteToWideChar(codepage, flags, line, None) };
+    if len > 0 {
+        let mut wide = vec![0; len as usize];
+        let len = unsafe { MultiByteToWideChar(codepage, flags, line, Some(&mut wide)) };
+        if len > 0 {
+            return std::borrow::Cow::Owned(String::from_utf16_lossy(&wide[..len as usize]));
+        }
+    }
+    String::from_utf8_lossy(line)
+}
+
+/// Decides which lines of output to log, for `--log-filter-include` and `--log-filter-exclude`.
+#[derive(Debug, Default)]
+pub struct LineFilter {
+    include: Vec<regex::Regex>,
+    exclude: Vec<regex::Regex>,
+}
+
+impl LineFilter {
+    pub fn new(include: &[String], exclude: &[String]) -> Result<Self, regex::Error> {
+        Ok(Self {
+            include: include.iter().map(|x| regex::Regex::new(x)).collect::<Result<_, _>>()?,
+            exclude: exclude.iter().map(|x| regex::Regex::new(x)).collect::<Result<_, _>>()?,
+        })
+    }
+
+    pub fn allows(&self, line: &str) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|x| x.is_match(line)))
+            && !self.exclude.iter().any(|x| x.is_match(line))
+    }
+}
+
+/// Joins continuation lines, such as the frames of a stack trace,
+/// onto the line before them from the same stream,
+/// for `--log-group-indented` and `--log-group-pattern`.
+#[derive(Debug, Default)]
+pub struct LineGrouper {
+    indented: bool,
+    patterns: Vec<regex::Regex>,
+    /// Groups waiting for more lines, with how many lines they have.
+    pending: Vec<(Stream, String, usize)>,
+}
+
+impl LineGrouper {
+    pub fn new(indented: bool, patterns: &[String]) -> Result<Self, regex::Error> {
+        Ok(Self {
+            indented,
+            patterns: patterns
+                .iter()
+                .map(|x| regex::Regex::new(x))
+                .collect::<Result<_, _>>()?,
+            pending: vec![],
+        })
+    }
+
+    fn is_continuation(&self, line: &str) -> bool {
+        (self.indented && line.starts_with([' ', '\t'])) || self.patterns.iter().any(|x| x.is_match(line))
+    }
+
+    /// Add a line, calling `emit` with any group that it completes.
+    pub fn push(&mut self, stream: Stream, line: &str, mut emit: impl FnMut(Stream, &str)) {
+        if !self.indented && self.patterns.is_empty() {
+            emit(stream, line);
+            return;
+        }
+
+        let continuation = self.is_continuation(line);
+        match self.pending.iter().position(|(s, _, _)| *s == stream) {
+            Some(index)
+                if continuation
+                    && self.pending[index].2 < MAX_GROUP_LINES
+                    && self.pending[index].1.len() + line.len() < MAX_GROUP_BYTES =>
+            {
+                let (_, group, lines) = &mut self.pending[index];
+                group.push('\n');
+                group.push_str(line);
+                *lines += 1;
+            }
+            Some(index) => {
+                let (_, group, _) = self.pending.remove(index);
+                emit(stream, &group);
+                self.pending.push((stream, line.to_string(), 1));
+            }
+            None => self.pending.push((stream, line.to_string(), 1)),
+        }
+    }
+
+    /// Emit any groups that are still waiting for more lines.
+    pub fn flush(&mut self, mut emit: impl FnMut(Stream, &str)) {
+        for (stream, group, _) in self.pending.drain(..) {
+            emit(stream, &group);
+        }
+    }
+}
+
+/// Limits how much output is logged, for `--log-rate-limit` and `--log-collapse-repeats`.
+/// Lines that are held back are summarized with a note on the same stream.
+#[derive(Debug, Default)]
+pub struct LineLimiter {
+    /// Most lines to log per interval.
+    rate: Option<(u32, std::time::Duration)>,
+    collapse: bool,
+    window_start: Option<std::time::Instant>,
+    count: u32,
+    dropped: Option<(Stream, u64)>,
+    last: Option<(Stream, String)>,
+    repeats: u64,
+}
+
+impl LineLimiter {
+    pub fn new(rate: Option<(u32, std::time::Duration)>, collapse: bool) -> Self {
+        Self {
+            rate,
+            collapse,
+            ..Default::default()
+        }
+    }
+
+    /// Call `emit` with the line if it should be logged,
+    /// after any notes about lines that were held back before it.
+    pub fn admit(&mut self, now: std::time::Instant, stream: Stream, line: &str, mut emit: impl FnMut(Stream, &str)) {
+        if self.collapse {
+            if self.last.as_ref().is_some_and(|(s, l)| *s == stream && l == line) {
+                self.repeats += 1;
+                return;
+            }
+            self.flush_repeats(&mut emit);
+            self.last = Some((stream, line.to_string()));
+        }
+
+        if let Some((limit, interval)) = self.rate {
+            if self
+                .window_start
+                .is_none_or(|start| now.duration_since(start) >= interval)
+            {
+                self.flush_dropped(&mut emit);
+                self.window_start = Some(now);
+                self.count = 0;
+            }
+            if self.count >= limit {
+                let dropped = self.dropped.map(|(_, n)| n).unwrap_or_default();
+                self.dropped = Some((stream, dropped + 1));
+                return;
+            }
+            self.count += 1;
+        }
+
+        emit(stream, line);
+    }
+
+    /// Emit notes about any lines that are still held back.
+    pub fn finish(&mut self, mut emit: impl FnMut(Stream, &str)) {
+        self.flush_repeats(&mut emit);
+        self.flush_dropped(&mut emit);
+    }
+
+    fn flush_repeats(&mut self, emit: &mut impl FnMut(Stream, &str)) {
+        if self.repeats > 0 {
+            if let Some((stream, _)) = &self.last {
+                emit(
+                    *stream,
+                    &format!("(previous line repeated {} more times)", self.repeats),
+                );
+            }
+            self.repeats = 0;
+        }
+    }
+
+    fn flush_dropped(&mut self, emit: &mut impl FnMut(Stream, &str)) {
+        if let Some((stream, dropped)) = self.dropped.take() {
+            emit(stream, &format!("({} lines dropped by rate limit)", dropped));
+        }
+    }
+}
+
+/// Writes raw output to a file, for `--capture-raw`.
+/// When the file would exceed its size limit, it is renamed with a numbered suffix
+/// (`.1` being the newest) and a new file is started.
+#[derive(Debug)]
+pub struct RawFile {
+    path: std::path::PathBuf,
+    max_bytes: u64,
+    retain: usize,
+    file: Option<std::fs::File>,
+    written: u64,
+}
+
+impl RawFile {
+    pub fn new(path: std::path::PathBuf, max_bytes: u64, retain: usize) -> Self {
+        Self {
+            path,
+            max_bytes,
+            retain,
+            file: None,
+            written: 0,
+        }
+    }
+
+    fn numbered(&self, number: usize) -> std::path::PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", number));
+        path.into()
+    }
+
+    fn rotate(&mut self) -> std::io::Result<()> {
+        self.file = None;
+        if self.retain == 0 {
+            return std::fs::remove_file(&self.path);
+        }
+        let _ = std::fs::remove_file(self.numbered(self.retain));
+        for number in (1..self.retain).rev() {
+            let _ = std::fs::rename(self.numbered(number), self.numbered(number + 1));
+        }
+        std::fs::rename(&self.path, self.numbered(1))
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        use std::io::Write;
+
+        if self.file.is_none() {
+            let file = std::fs::OpenOptions::new().create(true).append(true).open(&self.path)?;
+            self.written = file.metadata()?.len();
+            self.file = Some(file);
+        }
+        if self.written > 0 && self.written + bytes.len() as u64 > self.max_bytes {
+            self.rotate()?;
+            return self.write(bytes);
+        }
+
+        if let Some(file) = self.file.as_mut() {
+            file.write_all(bytes)?;
+            self.written += bytes.len() as u64;
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        use std::io::Write;
+
+        match self.file.as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// How to process captured output before it reaches the sink.
+#[derive(Debug, Default)]
+pub struct CaptureOptions {
+    pub encoding: crate::cli::CmdEncoding,
+    pub grouper: LineGrouper,
+    pub filter: LineFilter,
+    pub limiter: LineLimiter,
+    /// Write stdout to this file as-is instead of splitting it into lines.
+    pub raw_stdout: Option<RawFile>,
+}
+
+#[cfg(windows)]
+trait Pipe: std::io::Read + std::os::windows::io::AsRawHandle + Send {}
+
+#[cfg(windows)]
+impl<T: std::io::Read + std::os::windows::io::AsRawHandle + Send> Pipe for T {}
+
+#[cfg(windows)]
+struct Source {
+    stream: Stream,
+    pipe: Box<dyn Pipe>,
+    raw: Option<RawFile>,
+    detector: Utf16Detector,
+    splitter: LineSplitter,
+    /// Bytes left to read after shutdown was requested.
+    draining: Option<u32>,
+    done: bool,
+}
+
+#[cfg(windows)]
+impl Source {
+    /// Check how many bytes can be read without blocking,
+    /// or `None` if every process with the other end has closed it.
+    fn available(&self) -> Option<u32> {
+        let mut available = 0;
+        unsafe {
+            windows::Win32::System::Pipes::PeekNamedPipe(
+                windows::Win32::Foundation::HANDLE(self.pipe.as_raw_handle()),
+                None,
+                0,
+                None,
+                Some(&mut available),
+                None,
+            )
+        }
+        .ok()?;
+        Some(available)
+    }
+}
+
+/// Captures a command's stdout and stderr, with one thread reading both pipes
+/// and another passing complete lines through the grouper, filter, and limiter to a sink.
+/// Pipes are only read when they have data, so that capturing can be abandoned
+/// when a process that inherited them (such as a grandchild of the command) keeps them open.
+#[cfg(windows)]
+pub struct OutputCapture {
+    shutdown: std::sync::Arc<std::sync::atomic::AtomicBool>,
+    reader: std::thread::JoinHandle<()>,
+    writer: std::thread::JoinHandle<()>,
+}
+
+#[cfg(windows)]
+impl OutputCapture {
+    pub fn start(
+        stdout: Option<impl std::io::Read + std::os::windows::io::AsRawHandle + Send + 'static>,
+        stderr: Option<impl std::io::Read + std::os::windows::io::AsRawHandle + Send + 'static>,
+        options: CaptureOptions,
+        mut sink: impl FnMut(Stream, &str) + Send + 'static,
+    ) -> Self {
+        let CaptureOptions {
+            encoding,
+            mut grouper,
+            filter,
+            mut limiter,
+            raw_stdout,
+        } = options;
+        let shutdown = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let shutdown2 = shutdown.clone();
+        let (line_tx, line_rx) = std::sync::mpsc::sync_channel::<(Stream, bool, Vec<u8>)>(LINE_QUEUE_SIZE);
+        // Buffers come back after their lines are logged so that they can be reused.
+        let (recycle_tx, recycle_rx) = std::sync::mpsc::channel::<Vec<u8>>();
+
+        let mut sources = vec![];
+        if let Some(stdout) = stdout {
+            sources.push(Source {
+                stream: Stream::Stdout,
+                pipe: Box::new(stdout),
+                raw: raw_stdout,
+                detector: Utf16Detector::default(),
+                splitter: LineSplitter::default(),
+                draining: None,
+                done: false,
+            });
+        }
+        if let Some(stderr) = stderr {
+            sources.push(Source {
+                stream: Stream::Stderr,
+                pipe: Box::new(stderr),
+                raw: None,
+                detector: Utf16Detector::default(),
+                splitter: LineSplitter::default(),
+                draining: None,
+                done: false,
+            });
+        }
+
+        let reader = std::thread::spawn(move || {
+            use std::io::Read;
+
+            // Lines from UTF-16 streams are already transcoded to UTF-8.
+            let queue = |stream: Stream, utf8: bool, line: &[u8]| {
+                if line.is_empty() {
+                    return;
+                }
+                let mut buffer = recycle_rx.try_recv().unwrap_or_default();
+                buffer.clear();
+                buffer.extend_from_slice(line);
+                let _ = line_tx.send((stream, utf8, buffer));
+            };
+
+            let mut buffer = [0; 4096];
+            while sources.iter().any(|x| !x.done) {
+                let stopping = shutdown2.load(std::sync::atomic::Ordering::SeqCst);
+                let mut idle = true;
+                for source in sources.iter_mut().filter(|x| !x.done) {
+                    let stream = source.stream;
+                    match source.available() {
+                        None => source.done = true,
+                        Some(available) => {
+                            if stopping && source.draining.is_none() {
+                                source.draining = Some(available);
+                            }
+                            let limit = match source.draining {
+                                Some(left) => left.min(available),
+                                None => available,
+                            };
+                            if source.draining == Some(0) {
+                                source.done = true;
+                            } else if limit > 0 {
+                                let len = buffer.len().min(limit as usize);
+                                match source.pipe.read(&mut buffer[..len]) {
+                                    Ok(0) | Err(_) => source.done = true,
+                                    Ok(read) => {
+                                        idle = false;
+                                        if let Some(left) = source.draining.as_mut() {
+                                            *left = left.saturating_sub(read as u32);
+                                        }
+                                        if let Some(raw) = source.raw.as_mut() {
+                                            if let Err(e) = raw.write(&buffer[..read]) {
+                                                log::error!("Unable to write raw {}: {:?}", stream.name(), e);
+                                            }
+                                        } else {
+                                            let bytes = source.detector.push(&buffer[..read]);
+                                            let utf8 = source.detector.is_utf16();
+                                            source.splitter.push(&bytes, |line| queue(stream, utf8, line));
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                    if source.done {
+                        if let Some(raw) = source.raw.as_mut() {
+                            if let Err(e) = raw.flush() {
+                                log::error!("Unable to flush raw {}: {:?}", stream.name(), e);
+                            }
+                        }
+                        let bytes = source.detector.flush();
+                        let utf8 = source.detector.is_utf16();
+                        source.splitter.push(&bytes, |line| queue(stream, utf8, line));
+                        source.splitter.flush(|line| queue(stream, utf8, line));
+                    }
+                }
+                if idle {
+                    std::thread::sleep(std::time::Duration::from_millis(50));
+                }
+            }
+        });
+
+        let writer = std::thread::spawn(move || {
+            let mut deliver = |stream: Stream, text: &str| {
+                if filter.allows(text) {
+                    limiter.admit(std::time::Instant::now(), stream, text, &mut sink);
+                }
+            };
+            loop {
+                match line_rx.recv_timeout(GROUP_TIMEOUT) {
+                    Ok((stream, utf8, line)) => {
+                        let text = if utf8 {
+                            String::from_utf8_lossy(&line)
+                        } else {
+                            decode(&line, encoding)
+                        };
+                        grouper.push(stream, &text, &mut deliver);
+                        let _ = recycle_tx.send(line);
+                    }
+                    Err(std::sync::mpsc::RecvTimeoutError::Timeout) => grouper.flush(&mut deliver),
+                    Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => break,
+                }
+            }
+            grouper.flush(&mut deliver);
+            limiter.finish(&mut sink);
+        });
+
+        Self {
+            shutdown,
+            reader,
+            writer,
+        }
+    }
+
+    /// Log whatever output is already waiting, then stop capturing.
+    pub fn finish(self) {
+        self.shutdown.store(true, std::sync::atomic::Ordering::SeqCst);
+        if let Err(e) = self.reader.join() {
+            log::error!("Unable to join output reader thread: {:?}", e);
+        }
+        if let Err(e) = self.writer.join() {
+            log::error!("Unable to join output logger thread: {:?}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+speculate::speculate! {
+    fn split(chunks: &[&str]) -> Vec<String> {
+        let mut lines = vec![];
+        let mut splitter = LineSplitter::default();
+        for chunk in chunks {
+            splitter.push(chunk.as_bytes(), |x| lines.push(String::from_utf8_lossy(x).to_string()));
+        }
+        splitter.flush(|x| lines.push(String::from_utf8_lossy(x).to_string()));
+        lines
+    }
+
+    fn filter(include: &[&str], exclude: &[&str]) -> LineFilter {
+        let include: Vec<_> = include.iter().map(|x| x.to_string()).collect();
+        let exclude: Vec<_> = exclude.iter().map(|x| x.to_string()).collect();
+        LineFilter::new(&include, &exclude).unwrap()
+    }
+
+    describe "line_filter" {
+        it "allows everything by default" {
+            assert!(filter(&[], &[]).allows("anything"));
+        }
+
+        it "requires a match with any include pattern" {
+            let filter = filter(&["ERROR", "WARN"], &[]);
+            assert!(filter.allows("WARN: disk is almost full"));
+            assert!(!filter.allows("INFO: started"));
+        }
+
+        it "drops lines matching an exclude pattern" {
+            let filter = filter(&[], &["^heartbeat"]);
+            assert!(!filter.allows("heartbeat 123"));
+            assert!(filter.allows("request handled"));
+        }
+
+        it "prefers exclude over include" {
+            assert!(!filter(&["ERROR"], &["ERROR: retrying"]).allows("ERROR: retrying"));
+        }
+    }
+
+    fn limit(limiter: &mut LineLimiter, lines: &[(u64, Stream, &str)]) -> Vec<String> {
+        let start = std::time::Instant::now();
+        let mut logged = vec![];
+        for (ms, stream, line) in lines {
+            let now = start + std::time::Duration::from_millis(*ms);
+            limiter.admit(now, *stream, line, |s, x| logged.push(format!("{}: {}", s.name(), x)));
+        }
+        limiter.finish(|s, x| logged.push(format!("{}: {}", s.name(), x)));
+        logged
+    }
+
+    fn group(grouper: &mut LineGrouper, lines: &[(Stream, &str)]) -> Vec<String> {
+        let mut logged = vec![];
+        for (stream, line) in lines {
+            grouper.push(*stream, line, |s, x| logged.push(format!("{}: {}", s.name(), x)));
+        }
+        grouper.flush(|s, x| logged.push(format!("{}: {}", s.name(), x)));
+        logged
+    }
+
+    describe "line_grouper" {
+        it "keeps lines separate by default" {
+            let mut grouper = LineGrouper::default();
+            assert_eq!(
+                group(&mut grouper, &[(Stream::Stdout, "a"), (Stream::Stdout, "  b")]),
+                vec!["stdout: a", "stdout:   b"],
+            );
+        }
+
+        it "groups indented lines" {
+            let mut grouper = LineGrouper::new(true, &[]).unwrap();
+            assert_eq!(
+                group(
+                    &mut grouper,
+                    &[
+                        (Stream::Stderr, "Exception in thread main"),
+                        (Stream::Stderr, "\tat Foo.bar(Foo.java:1)"),
+                        (Stream::Stdout, "unrelated"),
+                        (Stream::Stderr, "\tat Foo.main(Foo.java:2)"),
+                        (Stream::Stderr, "done"),
+                    ]
+                ),
+                vec![
+                    "stderr: Exception in thread main\n\tat Foo.bar(Foo.java:1)\n\tat Foo.main(Foo.java:2)",
+                    "stdout: unrelated",
+                    "stderr: done",
+                ],
+            );
+        }
+
+        it "groups lines matching a pattern" {
+            let mut grouper = LineGrouper::new(false, &["^Caused by:".to_string()]).unwrap();
+            assert_eq!(
+                group(
+                    &mut grouper,
+                    &[
+                        (Stream::Stderr, "Error: oops"),
+                        (Stream::Stderr, "Caused by: nope"),
+                        (Stream::Stderr, "  indented"),
+                    ]
+                ),
+                vec!["stderr: Error: oops\nCaused by: nope", "stderr:   indented"],
+            );
+        }
+
+        it "starts a new group once one is too long" {
+            let mut grouper = LineGrouper::new(true, &[]).unwrap();
+            let mut lines = vec![(Stream::Stdout, "start")];
+            lines.extend(std::iter::repeat_n((Stream::Stdout, "  more"), MAX_GROUP_LINES));
+            let logged = group(&mut grouper, &lines);
+            assert_eq!(logged.len(), 2);
+            assert_eq!(logged[0].lines().count(), MAX_GROUP_LINES);
+            assert_eq!(logged[1], "stdout:   more");
+        }
+    }
+
+    describe "line_limiter" {
+        it "logs everything by default" {
+            let mut limiter = LineLimiter::default();
+            assert_eq!(
+                limit(&mut limiter, &[(0, Stream::Stdout, "a"), (0, Stream::Stdout, "a")]),
+                vec!["stdout: a", "stdout: a"],
+            );
+        }
+
+        it "collapses repeated lines" {
+            let mut limiter = LineLimiter::new(None, true);
+            assert_eq!(
+                limit(
+                    &mut limiter,
+                    &[
+                        (0, Stream::Stderr, "oops"),
+                        (0, Stream::Stderr, "oops"),
+                        (0, Stream::Stderr, "oops"),
+                        (0, Stream::Stdout, "oops"),
+                        (0, Stream::Stdout, "done"),
+                        (0, Stream::Stdout, "done"),
+                    ]
+                ),
+                vec![
+                    "stderr: oops",
+                    "stderr: (previous line repeated 2 more times)",
+                    "stdout: oops",
+                    "stdout: done",
+                    "stdout: (previous line repeated 1 more times)",
+                ],
+            );
+        }
+
+        it "drops lines over the rate limit until the next interval" {
+            let mut limiter = LineLimiter::new(Some((2, std::time::Duration::from_secs(1))), false);
+            assert_eq!(
+                limit(
+                    &mut limiter,
+                    &[
+                        (0, Stream::Stdout, "1"),
+                        (100, Stream::Stdout, "2"),
+                        (200, Stream::Stdout, "3"),
+                        (300, Stream::Stderr, "4"),
+                        (1000, Stream::Stdout, "5"),
+                        (1100, Stream::Stdout, "6"),
+                        (1200, Stream::Stdout, "7"),
+                    ]
+                ),
+                vec![
+                    "stdout: 1",
+                    "stdout: 2",
+                    "stderr: (2 lines dropped by rate limit)",
+                    "stdout: 5",
+                    "stdout: 6",
+                    "stdout: (1 lines dropped by rate limit)",
+                ],
+            );
+        }
+    }
+
+    fn s(text: &str) -> String {
+        text.to_string()
+    }
+
+    fn detect(chunks: &[&[u8]]) -> (bool, String) {
+        let mut detector = Utf16Detector::default();
+        let mut bytes = vec![];
+        for chunk in chunks {
+            bytes.extend_from_slice(&detector.push(chunk));
+        }
+        bytes.extend_from_slice(&detector.flush());
+        (detector.is_utf16(), String::from_utf8_lossy(&bytes).to_string())
+    }
+
+    fn utf16le(text: &str) -> Vec<u8> {
+        let mut bytes = vec![0xFF, 0xFE];
+        bytes.extend(text.encode_utf16().flat_map(|x| x.to_le_bytes()));
+        bytes
+    }
+
+    describe "utf16_detector" {
+        it "passes through other output" {
+            assert_eq!(detect(&[b"h", b"ello\n"]), (false, s("hello\n")));
+            assert_eq!(detect(&[b"h"]), (false, s("h")));
+        }
+
+        it "transcodes UTF-16LE with a byte order mark" {
+            assert_eq!(detect(&[&utf16le("héllo\r\nwörld\n")]), (true, s("héllo\r\nwörld\n")));
+        }
+
+        it "transcodes UTF-16BE with a byte order mark" {
+            assert_eq!(detect(&[&[0xFE, 0xFF, 0x00, b'h', 0x00, b'i']]), (true, s("hi")));
+        }
+
+        it "handles characters split across reads" {
+            let bytes = utf16le("a😀b");
+            let chunks: Vec<&[u8]> = bytes.chunks(1).collect();
+            assert_eq!(detect(&chunks), (true, s("a😀b")));
+        }
+    }
+
+    describe "raw_file" {
+        it "rotates before exceeding the size limit" {
+            let dir = std::env::temp_dir().join(format!("shawl-raw-{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            std::fs::create_dir_all(&dir).unwrap();
+            let path = dir.join("raw.bin");
+            let read = |name: &str| std::fs::read(dir.join(name)).ok();
+
+            let mut raw = RawFile::new(path.clone(), 4, 2);
+            for chunk in [&b"\x00\x01"[..], b"\x02\x03", b"\x04", b"\x05\x06\x07\x08", b"\x09"] {
+                raw.write(chunk).unwrap();
+            }
+            raw.flush().unwrap();
+
+            assert_eq!(read("raw.bin"), Some(vec![9]));
+            assert_eq!(read("raw.bin.1"), Some(vec![5, 6, 7, 8]));
+            assert_eq!(read("raw.bin.2"), Some(vec![4]));
+            assert_eq!(read("raw.bin.3"), None);
+            std::fs::remove_dir_all(&dir).unwrap();
+        }
+    }
+
+    describe "line_splitter" {
+        it "splits on line breaks" {
+            assert_eq!(split(&["a\nb\r\nc\n"]), vec!["a", "b", "c"]);
+        }
+
+        it "joins lines that arrive in pieces" {
+            assert_eq!(split(&["he", "llo\r", "\nwor", "ld\n"]), vec!["hello", "world"]);
+        }
+
+        it "keeps a final line without a line break" {
+            assert_eq!(split(&["a\nb"]), vec!["a", "b"]);
+        }
+
+        it "keeps empty lines" {
+            assert_eq!(split(&["\n\n"]), vec!["", ""]);
+        }
+    }
+}
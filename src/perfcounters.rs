@@ -0,0 +1,324 @@
+use windows::{
+    core::{GUID, HSTRING},
+    Win32::{
+        Foundation::{CloseHandle, HANDLE, WIN32_ERROR},
+        System::{
+            Performance::{
+                PerfCreateInstance, PerfSetCounterSetInfo, PerfSetULongLongCounterValue, PerfStartProviderEx,
+                PERF_COUNTERSET_INSTANCE,
+            },
+            ProcessStatus::{GetProcessMemoryInfo, PROCESS_MEMORY_COUNTERS},
+            Threading::{OpenProcess, PROCESS_QUERY_LIMITED_INFORMATION},
+        },
+    },
+};
+
+const PROVIDER: GUID = GUID::from_u128(0x6d70e124_e279_4c94_8754_59e071b85d89);
+const COUNTER_SET: GUID = GUID::from_u128(0x9c9ef5b2_6419_4700_9e60_4d7f19c446d7);
+
+/// Name of the manifest that `lodctr` reads, written next to the executable.
+const MANIFEST_FILE: &str = "shawl-counters.man";
+
+const PERF_COUNTERSET_MULTI_INSTANCES: u32 = 2;
+const PERF_DETAIL_NOVICE: u32 = 100;
+const PERF_COUNTER_LARGE_RAWCOUNT: u32 = 0x0001_0100;
+const PERF_COUNTER_BULK_COUNT: u32 = 0x1041_0500;
+
+const COUNTER_SET_NAME: &str = "Shawl Service";
+const COUNTER_SET_DESCRIPTION: &str = "Commands that Shawl runs as Windows services";
+
+struct Counter {
+    id: u32,
+    name: &'static str,
+    description: &'static str,
+    manifest_type: &'static str,
+    kind: u32,
+}
+
+/// The counters in the order of `values`.
+/// Perfmon reads the names from the string table that `build.rs` embeds in the executable,
+/// which must list the same text starting at ID 1000, alternating between name and description.
+const COUNTERS: [Counter; 4] = [
+    Counter {
+        id: 1,
+        name: "Restarts",
+        description: "Number of times the command has restarted since the service started",
+        manifest_type: "perf_counter_large_rawcount",
+        kind: PERF_COUNTER_LARGE_RAWCOUNT,
+    },
+    Counter {
+        id: 2,
+        name: "Command Uptime",
+        description: "Seconds since the command was launched",
+        manifest_type: "perf_counter_large_rawcount",
+        kind: PERF_COUNTER_LARGE_RAWCOUNT,
+    },
+    Counter {
+        id: 3,
+        name: "Command Working Set",
+        description: "Bytes of memory that the command is using",
+        manifest_type: "perf_counter_large_rawcount",
+        kind: PERF_COUNTER_LARGE_RAWCOUNT,
+    },
+    Counter {
+        id: 4,
+        name: "Output Lines/sec",
+        description: "Rate at which the command writes lines of output",
+        manifest_type: "perf_counter_bulk_count",
+        kind: PERF_COUNTER_BULK_COUNT,
+    },
+];
+
+/// How often the counters are updated.
+const INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+#[repr(C)]
+struct CounterSetInfo {
+    counter_set: GUID,
+    provider: GUID,
+    num_counters: u32,
+    instance_type: u32,
+}
+
+#[repr(C)]
+struct CounterInfo {
+    id: u32,
+    kind: u32,
+    attrib: u64,
+    size: u32,
+    detail_level: u32,
+    scale: i32,
+    offset: u32,
+}
+
+/// Layout of `PERF_COUNTERSET_INFO` followed by its `PERF_COUNTER_INFO` entries.
+#[repr(C)]
+struct Template {
+    info: CounterSetInfo,
+    counters: [CounterInfo; COUNTERS.len()],
+}
+
+fn guid_string(guid: &GUID) -> String {
+    format!("{{{:?}}}", guid)
+}
+
+fn xml_escape(raw: &str) -> String {
+    raw.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Build the instrumentation manifest that describes the counter set to `lodctr`.
+fn manifest(exe: &str) -> String {
+    let counters: Vec<_> = COUNTERS
+        .iter()
+        .enumerate()
+        .map(|(i, counter)| {
+            format!(
+                "          <counter id=\"{}\" uri=\"Shawl.Service.{}\" name=\"{}\" nameID=\"{}\" description=\"{}\" descriptionID=\"{}\" type=\"{}\" detailLevel=\"standard\"/>\n",
+                counter.id,
+                counter.id,
+                xml_escape(counter.name),
+                1002 + i * 2,
+                xml_escape(counter.description),
+                1003 + i * 2,
+                counter.manifest_type,
+            )
+        })
+        .collect();
+    format!(
+        concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<instrumentationManifest xmlns=\"http://schemas.microsoft.com/win/2004/08/events\">\n",
+            "  <instrumentation>\n",
+            "    <counters xmlns=\"http://schemas.microsoft.com/win/2005/12/counters\" schemaVersion=\"2.0\">\n",
+            "      <provider applicationIdentity=\"{}\" providerType=\"userMode\" providerGuid=\"{}\">\n",
+            "        <counterSet guid=\"{}\" uri=\"Shawl.Service\" name=\"{}\" nameID=\"1000\" description=\"{}\" descriptionID=\"1001\" instances=\"multiple\">\n",
+            "{}",
+            "        </counterSet>\n",
+            "      </provider>\n",
+            "    </counters>\n",
+            "  </instrumentation>\n",
+            "</instrumentationManifest>\n"
+        ),
+        xml_escape(exe),
+        guid_string(&PROVIDER),
+        guid_string(&COUNTER_SET),
+        COUNTER_SET_NAME,
+        COUNTER_SET_DESCRIPTION,
+        counters.join(""),
+    )
+}
+
+/// Register the counter set with Windows so that perfmon and other collectors can find it.
+/// The set is shared by all services, which each get their own instance.
+pub fn register() -> Result<(), String> {
+    let exe = std::env::current_exe().map_err(|e| format!("Unable to determine Shawl location: {}", e))?;
+    let path = exe.with_file_name(MANIFEST_FILE);
+    std::fs::write(&path, manifest(&exe.to_string_lossy()))
+        .map_err(|e| format!("Unable to write {}: {}", path.display(), e))?;
+
+    let output = std::process::Command::new("lodctr")
+        .arg(format!("/m:{}", path.display()))
+        .output()
+        .map_err(|e| format!("Unable to run lodctr: {}", e))?;
+    if !output.status.success() {
+        return Err(format!(
+            "lodctr failed with code {:?}: {}",
+            output.status.code(),
+            String::from_utf8_lossy(&output.stdout).trim()
+        ));
+    }
+    Ok(())
+}
+
+/// Current counter values from the service's status.
+fn values(status: &crate::pipe::Status, working_set: Option<u64>, now: std::time::Instant) -> [u64; COUNTERS.len()] {
+    let uptime = match (status.pid, status.launched_at) {
+        (Some(_), Some(launched_at)) => now.saturating_duration_since(launched_at).as_secs(),
+        _ => 0,
+    };
+    [
+        status.restarts as u64,
+        uptime,
+        working_set.unwrap_or(0),
+        status.output_lines,
+    ]
+}
+
+fn working_set(pid: u32) -> Option<u64> {
+    unsafe {
+        let process = OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, false, pid).ok()?;
+        let mut counters = PROCESS_MEMORY_COUNTERS::default();
+        let result = GetProcessMemoryInfo(
+            process,
+            &mut counters,
+            std::mem::size_of::<PROCESS_MEMORY_COUNTERS>() as u32,
+        );
+        let _ = CloseHandle(process);
+        result.ok().map(|_| counters.WorkingSetSize as u64)
+    }
+}
+
+struct Instance {
+    provider: HANDLE,
+    instance: *mut PERF_COUNTERSET_INSTANCE,
+}
+
+// The instance is only updated from the thread that `start` spawns.
+unsafe impl Send for Instance {}
+
+impl Instance {
+    fn create(service: &str) -> windows::core::Result<Self> {
+        let mut template = Template {
+            info: CounterSetInfo {
+                counter_set: COUNTER_SET,
+                provider: PROVIDER,
+                num_counters: COUNTERS.len() as u32,
+                instance_type: PERF_COUNTERSET_MULTI_INSTANCES,
+            },
+            counters: std::array::from_fn(|i| CounterInfo {
+                id: COUNTERS[i].id,
+                kind: COUNTERS[i].kind,
+                attrib: 0,
+                size: std::mem::size_of::<u64>() as u32,
+                detail_level: PERF_DETAIL_NOVICE,
+                scale: 0,
+                offset: (i * std::mem::size_of::<u64>()) as u32,
+            }),
+        };
+
+        unsafe {
+            let mut provider = HANDLE::default();
+            WIN32_ERROR(PerfStartProviderEx(&PROVIDER, None, &mut provider)).ok()?;
+            WIN32_ERROR(PerfSetCounterSetInfo(
+                provider,
+                &mut template as *mut Template as *mut _,
+                std::mem::size_of::<Template>() as u32,
+            ))
+            .ok()?;
+            let instance = PerfCreateInstance(provider, &COUNTER_SET, &HSTRING::from(service), 0);
+            if instance.is_null() {
+                return Err(windows::core::Error::from_win32());
+            }
+            Ok(Self { provider, instance })
+        }
+    }
+
+    fn set(&self, values: &[u64]) {
+        for (counter, value) in COUNTERS.iter().zip(values) {
+            unsafe {
+                PerfSetULongLongCounterValue(self.provider, self.instance, counter.id, *value);
+            }
+        }
+    }
+}
+
+/// Publish the service's counters for `--perf-counters`, updating them on a background thread.
+pub fn start(service: &str, status: std::sync::Arc<std::sync::Mutex<crate::pipe::Status>>) {
+    let instance = match Instance::create(service) {
+        Ok(instance) => instance,
+        Err(e) => {
+            log::error!("Unable to publish performance counters: {:?}", e);
+            return;
+        }
+    };
+
+    std::thread::spawn(move || loop {
+        let status = match status.lock() {
+            Ok(status) => status.clone(),
+            Err(_) => return,
+        };
+        let working_set = status.pid.and_then(working_set);
+        instance.set(&values(&status, working_set, std::time::Instant::now()));
+        std::thread::sleep(INTERVAL);
+    });
+}
+
+#[cfg(test)]
+speculate::speculate! {
+    describe "manifest" {
+        it "points at the executable for the counter names" {
+            let manifest = manifest(r"C:\Tools & Stuff\shawl.exe");
+            assert!(manifest.contains(r#"applicationIdentity="C:\Tools &amp; Stuff\shawl.exe""#));
+            assert!(manifest.contains(r#"providerGuid="{6D70E124-E279-4C94-8754-59E071B85D89}""#));
+        }
+
+        it "lists each counter with its string IDs" {
+            let manifest = manifest("shawl.exe");
+            assert!(manifest.contains(
+                r#"<counter id="1" uri="Shawl.Service.1" name="Restarts" nameID="1002" description="Number of times the command has restarted since the service started" descriptionID="1003" type="perf_counter_large_rawcount" detailLevel="standard"/>"#
+            ));
+            assert!(manifest.contains(
+                r#"<counter id="4" uri="Shawl.Service.4" name="Output Lines/sec" nameID="1008" description="Rate at which the command writes lines of output" descriptionID="1009" type="perf_counter_bulk_count" detailLevel="standard"/>"#
+            ));
+        }
+    }
+
+    describe "values" {
+        it "reports the running command" {
+            let now = std::time::Instant::now();
+            let status = crate::pipe::Status {
+                pid: Some(123),
+                launched_at: Some(now),
+                restarts: 2,
+                output_lines: 50,
+                ..Default::default()
+            };
+            assert_eq!(
+                values(&status, Some(4096), now + std::time::Duration::from_millis(90_500)),
+                [2, 90, 4096, 50],
+            );
+        }
+
+        it "reports no uptime while the command is not running" {
+            let status = crate::pipe::Status {
+                launched_at: Some(std::time::Instant::now()),
+                restarts: 1,
+                ..Default::default()
+            };
+            assert_eq!(values(&status, None, std::time::Instant::now()), [1, 0, 0, 0]);
+        }
+    }
+}
@@ -0,0 +1,640 @@
+use crate::cli::LogLevel;
+
+/// Longest request line to read from a client.
+const MAX_REQUEST_SIZE: u64 = 4096;
+
+/// Name of the pipe that answers status queries and accepts commands for a service.
+pub fn pipe_name(service: &str) -> String {
+    format!(r"\\.\pipe\shawl-{}", service)
+}
+
+/// A command received through the pipe, to be carried out by the service loop.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Command {
+    /// Restart the command, or launch it now if it is waiting to restart.
+    Restart,
+    LogLevel(LogLevel),
+    /// Don't launch the command again after it exits until restarts are resumed.
+    PauseRestarts,
+    ResumeRestarts,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Request {
+    Status,
+    Command(Command),
+}
+
+/// What the service is doing, kept up to date by the service loop for `status` queries.
+#[derive(Clone, Debug, Default)]
+pub struct Status {
+    pub pid: Option<u32>,
+    pub launched_at: Option<std::time::Instant>,
+    /// When the command last wrote a line of output.
+    pub last_output: Option<std::time::Instant>,
+    /// Lines of output from the command since the service started.
+    pub output_lines: u64,
+    pub restarts: u32,
+    /// Exit code from the command's last run, if it exited on its own or when stopped.
+    pub last_exit_code: Option<i32>,
+    pub paused: bool,
+    pub restarts_paused: bool,
+    pub service_started_at: Option<std::time::Instant>,
+    pub launches: u32,
+    /// Runs that failed to launch, exited with a code outside `--pass`, or were terminated.
+    pub failures: u32,
+    /// How long the command ran in all of its finished runs.
+    pub total_uptime: std::time::Duration,
+    pub instances: Instances,
+}
+
+/// The copies of the command beyond the first for `--instances`,
+/// which the pipe includes in its totals.
+#[derive(Clone, Debug, Default)]
+pub struct Instances {
+    pub configured: u32,
+    pub running: u32,
+    pub launches: u32,
+    pub restarts: u32,
+    pub failures: u32,
+}
+
+impl Status {
+    pub fn state(&self) -> &'static str {
+        if self.paused {
+            "paused"
+        } else if self.pid.is_some() || self.instances.running > 0 {
+            "running"
+        } else {
+            "waiting"
+        }
+    }
+
+    fn to_json(&self, now: std::time::Instant) -> String {
+        let optional = |value: Option<u128>| value.map(|x| x.to_string()).unwrap_or_else(|| "null".to_string());
+        let elapsed = |since: Option<std::time::Instant>| since.map(|x| now.saturating_duration_since(x).as_millis());
+        let uptime = elapsed(self.launched_at);
+        format!(
+            concat!(
+                "{{\"ok\":true,\"state\":\"{}\",\"pid\":{},\"uptime_ms\":{},\"last_output_ms\":{},",
+                "\"last_exit_code\":{},\"restarts\":{},\"restarts_paused\":{},\"launches\":{},\"failures\":{},",
+                "\"service_uptime_ms\":{},\"total_uptime_ms\":{},\"instances\":{},\"instances_running\":{}}}"
+            ),
+            self.state(),
+            optional(self.pid.map(u128::from)),
+            optional(uptime),
+            optional(elapsed(self.last_output)),
+            self.last_exit_code
+                .map(|x| x.to_string())
+                .unwrap_or_else(|| "null".to_string()),
+            self.restarts + self.instances.restarts,
+            self.restarts_paused,
+            self.launches + self.instances.launches,
+            self.failures + self.instances.failures,
+            optional(elapsed(self.service_started_at)),
+            self.total_uptime.as_millis() + if self.pid.is_some() { uptime.unwrap_or(0) } else { 0 },
+            self.instances.configured + 1,
+            self.instances.running + u32::from(self.pid.is_some()),
+        )
+    }
+}
+
+fn skip_whitespace(chars: &mut std::iter::Peekable<std::str::Chars>) {
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+}
+
+fn parse_string(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<String> {
+    if chars.next()? != '"' {
+        return None;
+    }
+    let mut string = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return Some(string),
+            '\\' => string.push(match chars.next()? {
+                '"' => '"',
+                '\\' => '\\',
+                '/' => '/',
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                'u' => {
+                    let hex: String = (0..4).map(|_| chars.next()).collect::<Option<_>>()?;
+                    char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?
+                }
+                _ => return None,
+            }),
+            c => string.push(c),
+        }
+    }
+}
+
+/// Parse a number, `true`, `false`, or `null`, keeping its JSON text.
+fn parse_literal(chars: &mut std::iter::Peekable<std::str::Chars>) -> Option<String> {
+    let mut literal = String::new();
+    while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '+' | '.')) {
+        literal.push(c);
+    }
+    (!literal.is_empty()).then_some(literal)
+}
+
+/// Parse a flat JSON object. Values other than strings are only allowed with `literals`.
+fn parse_fields(text: &str, literals: bool) -> Option<Vec<(String, String)>> {
+    let mut chars = text.chars().peekable();
+    let mut fields = vec![];
+
+    skip_whitespace(&mut chars);
+    if chars.next()? != '{' {
+        return None;
+    }
+    skip_whitespace(&mut chars);
+    if chars.next_if_eq(&'}').is_none() {
+        loop {
+            skip_whitespace(&mut chars);
+            let key = parse_string(&mut chars)?;
+            skip_whitespace(&mut chars);
+            if chars.next()? != ':' {
+                return None;
+            }
+            skip_whitespace(&mut chars);
+            let value = if chars.peek() == Some(&'"') {
+                parse_string(&mut chars)?
+            } else if literals {
+                parse_literal(&mut chars)?
+            } else {
+                return None;
+            };
+            skip_whitespace(&mut chars);
+            fields.push((key, value));
+            match chars.next()? {
+                ',' => continue,
+                '}' => break,
+                _ => return None,
+            }
+        }
+    }
+    skip_whitespace(&mut chars);
+    chars.next().is_none().then_some(fields)
+}
+
+/// Parse a JSON object whose values are all strings, which is all that requests need.
+fn parse_object(text: &str) -> Option<Vec<(String, String)>> {
+    parse_fields(text, false)
+}
+
+/// Parse a response from the service, like the status, whose values may also be
+/// numbers, booleans, or `null`. Those are kept as their JSON text.
+pub fn parse_response(text: &str) -> Option<Vec<(String, String)>> {
+    parse_fields(text, true)
+}
+
+/// Parse a request like `{"command": "log-level", "level": "info"}`.
+fn parse_request(line: &str) -> Result<Request, String> {
+    let fields = parse_object(line).ok_or_else(|| "request must be a JSON object of strings".to_string())?;
+    let field = |name: &str| {
+        fields
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    };
+
+    match field("command") {
+        Some("status") => Ok(Request::Status),
+        Some("restart") => Ok(Request::Command(Command::Restart)),
+        Some("log-level") => match field("level") {
+            Some(level) => Ok(Request::Command(Command::LogLevel(level.parse()?))),
+            None => Err("missing level".to_string()),
+        },
+        Some("pause-restarts") => Ok(Request::Command(Command::PauseRestarts)),
+        Some("resume-restarts") => Ok(Request::Command(Command::ResumeRestarts)),
+        Some(command) => Err(format!("unknown command: {}", command)),
+        None => Err("missing command".to_string()),
+    }
+}
+
+/// Answer a request line, passing any command along to the service loop.
+fn respond(line: &str, status: &std::sync::Mutex<Status>, on_command: &impl Fn(Command)) -> String {
+    match parse_request(line.trim()) {
+        Ok(Request::Status) => match status.lock() {
+            Ok(status) => status.to_json(std::time::Instant::now()),
+            Err(_) => error_json("status is unavailable"),
+        },
+        Ok(Request::Command(command)) => {
+            log::info!("Received {:?} command through control pipe", command);
+            on_command(command);
+            "{\"ok\":true}".to_string()
+        }
+        Err(e) => error_json(&e),
+    }
+}
+
+fn error_json(error: &str) -> String {
+    format!("{{\"ok\":false,\"error\":{}}}", crate::logformat::json_string(error))
+}
+
+/// How long a client has to send its request and read the response.
+const CLIENT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// A DACL that only lets Local System, the administrators, and the given accounts use the pipe.
+fn pipe_sddl(sids: &[String]) -> String {
+    let mut sddl = "D:P(A;;GA;;;SY)(A;;GA;;;BA)".to_string();
+    for sid in sids {
+        sddl.push_str(&format!("(A;;GA;;;{})", sid));
+    }
+    sddl
+}
+
+/// The pipe's only instance, which is reused for each client so that another process
+/// never has a chance to create an instance and impersonate the service.
+struct Server(std::os::windows::io::OwnedHandle);
+
+impl Server {
+    /// Create the pipe for the service's own account and its service SID.
+    /// Only local clients are accepted.
+    fn create(name: &str, service: &str) -> Result<Self, String> {
+        use std::os::windows::io::FromRawHandle;
+        use windows::Win32::{
+            Storage::FileSystem::{FILE_FLAG_FIRST_PIPE_INSTANCE, FILE_FLAG_OVERLAPPED, PIPE_ACCESS_DUPLEX},
+            System::Pipes::{
+                CreateNamedPipeW, PIPE_READMODE_BYTE, PIPE_REJECT_REMOTE_CLIENTS, PIPE_TYPE_BYTE, PIPE_WAIT,
+            },
+        };
+
+        let mut sids = vec![crate::acl::current_account_sid()?];
+        // Only services that are actually installed have a service SID.
+        sids.extend(crate::acl::account_sid(&format!(r"NT SERVICE\{}", service)).ok());
+        let handle = crate::acl::with_security_attributes(&pipe_sddl(&sids), |attributes| unsafe {
+            CreateNamedPipeW(
+                &windows::core::HSTRING::from(name),
+                PIPE_ACCESS_DUPLEX | FILE_FLAG_FIRST_PIPE_INSTANCE | FILE_FLAG_OVERLAPPED,
+                PIPE_TYPE_BYTE | PIPE_READMODE_BYTE | PIPE_WAIT | PIPE_REJECT_REMOTE_CLIENTS,
+                1,
+                MAX_REQUEST_SIZE as u32,
+                MAX_REQUEST_SIZE as u32,
+                0,
+                Some(attributes),
+            )
+        })?;
+        if handle.is_invalid() {
+            return Err(windows::core::Error::from_win32().to_string());
+        }
+        Ok(Self(unsafe {
+            std::os::windows::io::OwnedHandle::from_raw_handle(handle.0)
+        }))
+    }
+
+    fn handle(&self) -> windows::Win32::Foundation::HANDLE {
+        use std::os::windows::io::AsRawHandle;
+        windows::Win32::Foundation::HANDLE(self.0.as_raw_handle())
+    }
+
+    /// Start an overlapped operation and wait for it to finish, cancelling it after the timeout.
+    /// Returns the number of bytes transferred.
+    fn wait(
+        &self,
+        timeout: Option<std::time::Duration>,
+        start: impl FnOnce(*mut windows::Win32::System::IO::OVERLAPPED) -> windows::core::Result<()>,
+    ) -> windows::core::Result<u32> {
+        use windows::Win32::{
+            Foundation::{CloseHandle, ERROR_IO_PENDING, WAIT_TIMEOUT, WIN32_ERROR},
+            System::{
+                Threading::{CreateEventW, INFINITE},
+                IO::{CancelIoEx, GetOverlappedResult, GetOverlappedResultEx, OVERLAPPED},
+            },
+        };
+
+        let event = unsafe { CreateEventW(None, true, false, windows::core::PCWSTR::null())? };
+        let mut overlapped = OVERLAPPED {
+            hEvent: event,
+            ..Default::default()
+        };
+        let millis = timeout.map_or(INFINITE, |x| x.as_millis().min((INFINITE - 1) as u128) as u32);
+        let mut transferred = 0;
+        let result = match start(&mut overlapped) {
+            Err(e) if e.code() != ERROR_IO_PENDING.to_hresult() => Err(e),
+            _ => unsafe {
+                match GetOverlappedResultEx(self.handle(), &overlapped, &mut transferred, millis, false) {
+                    Err(e) if e.code() == WIN32_ERROR(WAIT_TIMEOUT.0).to_hresult() => {
+                        // The operation must be finished before `overlapped` goes away.
+                        let _ = CancelIoEx(self.handle(), Some(&overlapped));
+                        let _ = GetOverlappedResult(self.handle(), &overlapped, &mut transferred, true);
+                        Err(e)
+                    }
+                    result => result,
+                }
+            },
+        };
+        unsafe {
+            let _ = CloseHandle(event);
+        }
+        result.map(|_| transferred)
+    }
+
+    /// Wait for a client to connect.
+    fn accept(&self) -> windows::core::Result<()> {
+        use windows::Win32::{Foundation::ERROR_PIPE_CONNECTED, System::Pipes::ConnectNamedPipe};
+
+        match self.wait(None, |overlapped| unsafe {
+            ConnectNamedPipe(self.handle(), Some(overlapped))
+        }) {
+            // The client connected between disconnecting the last one and waiting for this one.
+            Err(e) if e.code() == ERROR_PIPE_CONNECTED.to_hresult() => Ok(()),
+            result => result.map(|_| ()),
+        }
+    }
+
+    fn read(&self, buffer: &mut [u8], deadline: std::time::Instant) -> windows::core::Result<usize> {
+        use windows::Win32::Storage::FileSystem::ReadFile;
+
+        let timeout = deadline.saturating_duration_since(std::time::Instant::now());
+        self.wait(Some(timeout), |overlapped| unsafe {
+            ReadFile(self.handle(), Some(buffer), None, Some(overlapped))
+        })
+        .map(|x| x as usize)
+    }
+
+    fn write_all(&self, mut data: &[u8], deadline: std::time::Instant) -> windows::core::Result<()> {
+        use windows::Win32::Storage::FileSystem::WriteFile;
+
+        while !data.is_empty() {
+            let timeout = deadline.saturating_duration_since(std::time::Instant::now());
+            let written = self.wait(Some(timeout), |overlapped| unsafe {
+                WriteFile(self.handle(), Some(data), None, Some(overlapped))
+            })?;
+            data = &data[written as usize..];
+        }
+        Ok(())
+    }
+
+    /// Read one request line from the connected client and write the response,
+    /// giving up if the client takes too long.
+    fn serve(&self, status: &std::sync::Mutex<Status>, on_command: &impl Fn(Command)) -> windows::core::Result<()> {
+        let deadline = std::time::Instant::now() + CLIENT_TIMEOUT;
+        let mut request = vec![];
+        let mut buffer = [0; 512];
+        while !request.contains(&b'\n') && (request.len() as u64) < MAX_REQUEST_SIZE {
+            match self.read(&mut buffer, deadline)? {
+                0 => break,
+                read => request.extend_from_slice(&buffer[..read]),
+            }
+        }
+        let line = String::from_utf8_lossy(&request);
+        let line = line.split('\n').next().unwrap_or_default();
+        self.write_all(format!("{}\n", respond(line, status, on_command)).as_bytes(), deadline)?;
+        // Wait for the client to read the response and close its end before disconnecting,
+        // which would discard anything that it hasn't read yet.
+        let _ = self.read(&mut buffer, deadline);
+        Ok(())
+    }
+
+    fn disconnect(&self) {
+        unsafe {
+            let _ = windows::Win32::System::Pipes::DisconnectNamedPipe(self.handle());
+        }
+    }
+}
+
+/// Serve the pipe on a background thread, one client at a time.
+pub fn start(
+    service: &str,
+    status: std::sync::Arc<std::sync::Mutex<Status>>,
+    on_command: impl Fn(Command) + Send + 'static,
+) {
+    let name = pipe_name(service);
+    let server = match Server::create(&name, service) {
+        Ok(server) => server,
+        Err(e) => {
+            log::error!("Unable to open control pipe {}: {}", name, e);
+            return;
+        }
+    };
+    log::debug!("Listening on control pipe {}", name);
+    std::thread::spawn(move || loop {
+        match server.accept() {
+            Ok(()) => {
+                if let Err(e) = server.serve(&status, &on_command) {
+                    log::debug!("Control pipe client failed: {:?}", e);
+                }
+            }
+            Err(e) => log::debug!("Control pipe client failed to connect: {:?}", e),
+        }
+        server.disconnect();
+    });
+}
+
+/// Send a request to a running service through its pipe and return the response.
+/// The pipe serves one client at a time, so this waits a few seconds while it is busy.
+/// The response is only trusted if the pipe belongs to the service's process,
+/// and the server may only identify the caller, not act as them.
+pub fn query(service: &str, service_pid: u32, request: &str) -> std::io::Result<String> {
+    use std::io::{BufRead, Write};
+    use std::os::windows::{fs::OpenOptionsExt, io::AsRawHandle};
+    use windows::Win32::{
+        Foundation::{ERROR_PIPE_BUSY, HANDLE},
+        Storage::FileSystem::SECURITY_IDENTIFICATION,
+        System::Pipes::GetNamedPipeServerProcessId,
+    };
+
+    let name = pipe_name(service);
+    let mut attempts = 0;
+    let pipe = loop {
+        // Setting the quality of service flags also sets `SECURITY_SQOS_PRESENT`.
+        match std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .security_qos_flags(SECURITY_IDENTIFICATION.0)
+            .open(&name)
+        {
+            Ok(pipe) => break pipe,
+            Err(e) if e.raw_os_error() == Some(ERROR_PIPE_BUSY.0 as i32) && attempts < 50 => {
+                attempts += 1;
+                std::thread::sleep(std::time::Duration::from_millis(100));
+            }
+            Err(e) => return Err(e),
+        }
+    };
+
+    let mut server_pid = 0;
+    unsafe { GetNamedPipeServerProcessId(HANDLE(pipe.as_raw_handle()), &mut server_pid) }?;
+    if server_pid != service_pid {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::PermissionDenied,
+            format!(
+                "the pipe belongs to process {} instead of the service's process {}",
+                server_pid, service_pid
+            ),
+        ));
+    }
+
+    writeln!(&pipe, "{}", request)?;
+    let mut response = String::new();
+    std::io::BufReader::new(&pipe).read_line(&mut response)?;
+    Ok(response.trim_end().to_string())
+}
+
+#[cfg(test)]
+speculate::speculate! {
+    describe "pipe_sddl" {
+        it "allows only the administrators, Local System, and the given accounts" {
+            assert_eq!(pipe_sddl(&[]), "D:P(A;;GA;;;SY)(A;;GA;;;BA)");
+            assert_eq!(
+                pipe_sddl(&["S-1-5-20".to_string()]),
+                "D:P(A;;GA;;;SY)(A;;GA;;;BA)(A;;GA;;;S-1-5-20)",
+            );
+        }
+    }
+
+    describe "parse_object" {
+        it "parses string fields" {
+            assert_eq!(
+                parse_object(r#" { "command" : "log-level", "level":"info" } "#),
+                Some(vec![
+                    ("command".to_string(), "log-level".to_string()),
+                    ("level".to_string(), "info".to_string()),
+                ]),
+            );
+            assert_eq!(parse_object("{}"), Some(vec![]));
+        }
+
+        it "handles escapes" {
+            assert_eq!(
+                parse_object(r#"{"a\"b":"\u0041\\\n"}"#),
+                Some(vec![("a\"b".to_string(), "A\\\n".to_string())]),
+            );
+        }
+
+        it "rejects other JSON" {
+            assert_eq!(parse_object(r#"{"command":1}"#), None);
+            assert_eq!(parse_object(r#"{"command":"status""#), None);
+            assert_eq!(parse_object(r#"{"command":"status"} x"#), None);
+            assert_eq!(parse_object("status"), None);
+        }
+    }
+
+    describe "parse_response" {
+        it "keeps numbers, booleans, and null as text" {
+            assert_eq!(
+                parse_response(r#"{"ok":true,"pid":null,"restarts":47,"last_exit_code":-1,"state":"running"}"#),
+                Some(vec![
+                    ("ok".to_string(), "true".to_string()),
+                    ("pid".to_string(), "null".to_string()),
+                    ("restarts".to_string(), "47".to_string()),
+                    ("last_exit_code".to_string(), "-1".to_string()),
+                    ("state".to_string(), "running".to_string()),
+                ]),
+            );
+        }
+
+        it "rejects nested values" {
+            assert_eq!(parse_response(r#"{"a":[1]}"#), None);
+            assert_eq!(parse_response(r#"{"a":{}}"#), None);
+        }
+    }
+
+    describe "parse_request" {
+        it "accepts known commands" {
+            assert_eq!(parse_request(r#"{"command":"status"}"#), Ok(Request::Status));
+            assert_eq!(parse_request(r#"{"command":"restart"}"#), Ok(Request::Command(Command::Restart)));
+            assert_eq!(
+                parse_request(r#"{"command":"log-level","level":"trace"}"#),
+                Ok(Request::Command(Command::LogLevel(LogLevel::Trace))),
+            );
+            assert_eq!(
+                parse_request(r#"{"command":"pause-restarts"}"#),
+                Ok(Request::Command(Command::PauseRestarts)),
+            );
+            assert_eq!(
+                parse_request(r#"{"command":"resume-restarts"}"#),
+                Ok(Request::Command(Command::ResumeRestarts)),
+            );
+        }
+
+        it "rejects invalid requests" {
+            assert_eq!(parse_request(r#"{"command":"stop"}"#), Err("unknown command: stop".to_string()));
+            assert_eq!(parse_request(r#"{"level":"info"}"#), Err("missing command".to_string()));
+            assert_eq!(parse_request(r#"{"command":"log-level"}"#), Err("missing level".to_string()));
+            assert_eq!(
+                parse_request(r#"{"command":"log-level","level":"loud"}"#),
+                Err("invalid log level: loud".to_string()),
+            );
+        }
+    }
+
+    describe "respond" {
+        it "reports the status" {
+            let now = std::time::Instant::now();
+            let status = Status {
+                pid: Some(123),
+                launched_at: Some(now),
+                last_output: Some(now + std::time::Duration::from_millis(1000)),
+                restarts: 2,
+                last_exit_code: Some(1),
+                service_started_at: Some(now),
+                launches: 3,
+                failures: 1,
+                total_uptime: std::time::Duration::from_millis(500),
+                ..Default::default()
+            };
+            assert_eq!(
+                status.to_json(now + std::time::Duration::from_millis(1500)),
+                concat!(
+                    r#"{"ok":true,"state":"running","pid":123,"uptime_ms":1500,"last_output_ms":500,"last_exit_code":1,"#,
+                    r#""restarts":2,"restarts_paused":false,"launches":3,"failures":1,"service_uptime_ms":1500,"total_uptime_ms":2000,"#,
+                    r#""instances":1,"instances_running":1}"#,
+                ),
+            );
+            assert_eq!(
+                Status { restarts_paused: true, ..Default::default() }.to_json(now),
+                concat!(
+                    r#"{"ok":true,"state":"waiting","pid":null,"uptime_ms":null,"last_output_ms":null,"last_exit_code":null,"#,
+                    r#""restarts":0,"restarts_paused":true,"launches":0,"failures":0,"service_uptime_ms":null,"total_uptime_ms":0,"#,
+                    r#""instances":1,"instances_running":0}"#,
+                ),
+            );
+        }
+
+        it "includes the other instances in the totals" {
+            let now = std::time::Instant::now();
+            let status = Status {
+                pid: Some(123),
+                launched_at: Some(now),
+                restarts: 1,
+                launches: 2,
+                instances: Instances {
+                    configured: 2,
+                    running: 1,
+                    launches: 3,
+                    restarts: 1,
+                    failures: 2,
+                },
+                ..Default::default()
+            };
+            assert_eq!(
+                status.to_json(now),
+                concat!(
+                    r#"{"ok":true,"state":"running","pid":123,"uptime_ms":0,"last_output_ms":null,"last_exit_code":null,"#,
+                    r#""restarts":2,"restarts_paused":false,"launches":5,"failures":2,"service_uptime_ms":null,"total_uptime_ms":0,"#,
+                    r#""instances":3,"instances_running":2}"#,
+                ),
+            );
+        }
+
+        it "passes commands along" {
+            let received = std::cell::RefCell::new(vec![]);
+            let status = std::sync::Mutex::new(Status::default());
+            assert_eq!(
+                respond("{\"command\":\"restart\"}\r\n", &status, &|x| received.borrow_mut().push(x)),
+                r#"{"ok":true}"#,
+            );
+            assert_eq!(received.into_inner(), vec![Command::Restart]);
+        }
+
+        it "reports errors" {
+            let status = std::sync::Mutex::new(Status::default());
+            assert_eq!(
+                respond("{\"command\":\"stop\"}", &status, &|_| ()),
+                r#"{"ok":false,"error":"unknown command: stop"}"#,
+            );
+        }
+    }
+}
@@ -0,0 +1,492 @@
+use crate::cli::Mitigation;
+use std::os::windows::io::{AsRawHandle, FromRawHandle, OwnedHandle, RawHandle};
+use windows::Win32::Foundation::HANDLE;
+
+// From WinBase.h and WinNT.h.
+const PROC_THREAD_ATTRIBUTE_HANDLE_LIST: usize = 0x20002;
+const PROC_THREAD_ATTRIBUTE_MITIGATION_POLICY: usize = 0x20007;
+const PROC_THREAD_ATTRIBUTE_CHILD_PROCESS_POLICY: usize = 0x2000E;
+const PROCESS_CREATION_MITIGATION_POLICY_DEP_ENABLE: u64 = 0x1;
+const PROCESS_CREATION_MITIGATION_POLICY_FORCE_RELOCATE_IMAGES_ALWAYS_ON: u64 = 0x1 << 8;
+const PROCESS_CREATION_MITIGATION_POLICY_BOTTOM_UP_ASLR_ALWAYS_ON: u64 = 0x1 << 16;
+const PROCESS_CREATION_MITIGATION_POLICY_BLOCK_NON_MICROSOFT_BINARIES_ALWAYS_ON: u64 = 0x1 << 44;
+const PROCESS_CREATION_CHILD_PROCESS_RESTRICTED: u32 = 0x1;
+
+/// The command launched for the service, either through the standard library
+/// or directly through `CreateProcess` when it needs settings that the standard library lacks.
+pub enum CommandProcess {
+    Child(std::process::Child),
+    Raw(RawProcess),
+}
+
+impl CommandProcess {
+    pub fn id(&self) -> u32 {
+        match self {
+            Self::Child(child) => child.id(),
+            Self::Raw(process) => process.id,
+        }
+    }
+
+    pub fn try_wait(&mut self) -> std::io::Result<Option<std::process::ExitStatus>> {
+        match self {
+            Self::Child(child) => child.try_wait(),
+            Self::Raw(process) => process.try_wait(),
+        }
+    }
+
+    pub fn kill(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::Child(child) => child.kill(),
+            Self::Raw(process) => process.kill(),
+        }
+    }
+
+    /// Whether the process shares Shawl's console and can therefore receive ctrl-C.
+    pub fn shares_console(&self) -> bool {
+        match self {
+            Self::Child(_) => true,
+            Self::Raw(process) => process.shares_console,
+        }
+    }
+
+    pub fn take_stdin(&mut self) -> Option<std::process::ChildStdin> {
+        match self {
+            Self::Child(child) => child.stdin.take(),
+            Self::Raw(process) => process.stdin.take(),
+        }
+    }
+
+    pub fn take_stdout(&mut self) -> Option<std::process::ChildStdout> {
+        match self {
+            Self::Child(child) => child.stdout.take(),
+            Self::Raw(process) => process.stdout.take(),
+        }
+    }
+
+    pub fn take_stderr(&mut self) -> Option<std::process::ChildStderr> {
+        match self {
+            Self::Child(child) => child.stderr.take(),
+            Self::Raw(process) => process.stderr.take(),
+        }
+    }
+}
+
+impl AsRawHandle for CommandProcess {
+    fn as_raw_handle(&self) -> RawHandle {
+        match self {
+            Self::Child(child) => child.as_raw_handle(),
+            Self::Raw(process) => process.handle.as_raw_handle(),
+        }
+    }
+}
+
+/// Settings for launching a `RawProcess`.
+#[derive(Default)]
+pub struct LaunchOptions {
+    pub creation_flags: u32,
+    /// Start from the parent's environment (or the user's, for `session`)
+    /// rather than only the variables set on the command.
+    pub inherit_env: bool,
+    /// Launch in the session of the user logged on at the console.
+    /// The process gets its own console there, so its output is not captured.
+    pub session: bool,
+    pub mitigations: Vec<Mitigation>,
+    pub pipe_stdin: bool,
+    pub pipe_output: bool,
+}
+
+/// A process launched directly through `CreateProcess`, or adopted from a previous run.
+pub struct RawProcess {
+    handle: OwnedHandle,
+    id: u32,
+    shares_console: bool,
+    stdin: Option<std::process::ChildStdin>,
+    stdout: Option<std::process::ChildStdout>,
+    stderr: Option<std::process::ChildStderr>,
+}
+
+impl RawProcess {
+    /// Launch the program, arguments, environment, and working directory configured on `cmd`.
+    /// Launching in the console user's session requires Shawl to run as LocalSystem.
+    pub fn spawn(cmd: &std::process::Command, options: &LaunchOptions) -> std::io::Result<Self> {
+        use windows::{
+            core::{HSTRING, PCWSTR, PWSTR},
+            Win32::System::Threading::{
+                CreateProcessAsUserW, CreateProcessW, CREATE_NEW_CONSOLE, CREATE_UNICODE_ENVIRONMENT,
+                EXTENDED_STARTUPINFO_PRESENT, PROCESS_CREATION_FLAGS, PROCESS_INFORMATION, STARTF_USESTDHANDLES,
+                STARTUPINFOEXW, STARTUPINFOW,
+            },
+        };
+
+        let token = if options.session {
+            Some(active_session_token()?)
+        } else {
+            None
+        };
+
+        let base = match (&token, options.inherit_env) {
+            (_, false) => vec![],
+            (Some(token), true) => crate::env::read_token_environment(HANDLE(token.as_raw_handle()))?,
+            (None, true) => std::env::vars_os()
+                .map(|(key, value)| (key.to_string_lossy().to_string(), value.to_string_lossy().to_string()))
+                .collect(),
+        };
+        let changes: Vec<_> = cmd
+            .get_envs()
+            .map(|(key, value)| {
+                (
+                    key.to_string_lossy().to_string(),
+                    value.map(|x| x.to_string_lossy().to_string()),
+                )
+            })
+            .collect();
+        let environment = crate::env::encode_environment_block(&crate::env::merge_environment(base, &changes));
+
+        let mut args = vec![cmd.get_program().to_string_lossy().to_string()];
+        args.extend(cmd.get_args().map(|x| x.to_string_lossy().to_string()));
+        let mut command_line: Vec<u16> = crate::control::join_command_line(&args)
+            .encode_utf16()
+            .chain(Some(0))
+            .collect();
+        let cwd = cmd.get_current_dir().map(|x| HSTRING::from(x.as_os_str()));
+        let mut desktop: Vec<u16> = "winsta0\\default".encode_utf16().chain(Some(0)).collect();
+
+        let mut flags = PROCESS_CREATION_FLAGS(options.creation_flags) | CREATE_UNICODE_ENVIRONMENT;
+        let mut startup = STARTUPINFOEXW::default();
+        startup.StartupInfo.cb = std::mem::size_of::<STARTUPINFOW>() as u32;
+
+        // The child's ends of the pipes need to stay open until the process is created.
+        let mut stdio = None;
+        if options.session {
+            flags |= CREATE_NEW_CONSOLE;
+            startup.StartupInfo.lpDesktop = PWSTR(desktop.as_mut_ptr());
+        } else {
+            let handles = ChildStdio::new(options.pipe_stdin, options.pipe_output)?;
+            startup.StartupInfo.dwFlags |= STARTF_USESTDHANDLES;
+            startup.StartupInfo.hStdInput = HANDLE(handles.child[0].as_raw_handle());
+            startup.StartupInfo.hStdOutput = HANDLE(handles.child[1].as_raw_handle());
+            startup.StartupInfo.hStdError = HANDLE(handles.child[2].as_raw_handle());
+            stdio = Some(handles);
+        }
+
+        let inherited: Vec<HANDLE> = stdio
+            .iter()
+            .flat_map(|x| x.child.iter().map(|handle| HANDLE(handle.as_raw_handle())))
+            .collect();
+        let mitigation_policy = mitigation_policy_flags(&options.mitigations);
+        let child_process_policy = PROCESS_CREATION_CHILD_PROCESS_RESTRICTED;
+        let restrict_children = options.mitigations.contains(&Mitigation::NoChildProcess);
+
+        let count = [!inherited.is_empty(), mitigation_policy != 0, restrict_children]
+            .iter()
+            .filter(|x| **x)
+            .count();
+        let mut attributes = None;
+        if count > 0 {
+            let mut list = AttributeList::new(count as u32)?;
+            unsafe {
+                if !inherited.is_empty() {
+                    list.set(
+                        PROC_THREAD_ATTRIBUTE_HANDLE_LIST,
+                        inherited.as_ptr() as *const std::ffi::c_void,
+                        std::mem::size_of_val(inherited.as_slice()),
+                    )?;
+                }
+                if mitigation_policy != 0 {
+                    list.set(
+                        PROC_THREAD_ATTRIBUTE_MITIGATION_POLICY,
+                        &mitigation_policy as *const u64 as *const std::ffi::c_void,
+                        std::mem::size_of::<u64>(),
+                    )?;
+                }
+                if restrict_children {
+                    list.set(
+                        PROC_THREAD_ATTRIBUTE_CHILD_PROCESS_POLICY,
+                        &child_process_policy as *const u32 as *const std::ffi::c_void,
+                        std::mem::size_of::<u32>(),
+                    )?;
+                }
+            }
+            startup.lpAttributeList = list.as_ptr();
+            flags |= EXTENDED_STARTUPINFO_PRESENT;
+            attributes = Some(list);
+        }
+
+        let mut info = PROCESS_INFORMATION::default();
+        let cwd = cwd.as_ref().map(|x| PCWSTR(x.as_ptr())).unwrap_or(PCWSTR::null());
+        let environment = Some(environment.as_ptr() as *const std::ffi::c_void);
+        unsafe {
+            match &token {
+                Some(token) => CreateProcessAsUserW(
+                    HANDLE(token.as_raw_handle()),
+                    PCWSTR::null(),
+                    PWSTR(command_line.as_mut_ptr()),
+                    None,
+                    None,
+                    false,
+                    flags,
+                    environment,
+                    cwd,
+                    &startup.StartupInfo,
+                    &mut info,
+                )?,
+                None => CreateProcessW(
+                    PCWSTR::null(),
+                    PWSTR(command_line.as_mut_ptr()),
+                    None,
+                    None,
+                    stdio.is_some(),
+                    flags,
+                    environment,
+                    cwd,
+                    &startup.StartupInfo,
+                    &mut info,
+                )?,
+            }
+            drop(OwnedHandle::from_raw_handle(info.hThread.0));
+        }
+        drop(attributes);
+
+        let (stdin, stdout, stderr) = match stdio {
+            Some(stdio) => (stdio.stdin, stdio.stdout, stdio.stderr),
+            None => (None, None, None),
+        };
+        Ok(Self {
+            handle: unsafe { OwnedHandle::from_raw_handle(info.hProcess.0) },
+            id: info.dwProcessId,
+            shares_console: !options.session,
+            stdin,
+            stdout,
+            stderr,
+        })
+    }
+
+    /// Supervise a process that Shawl did not launch itself.
+    /// It does not share Shawl's console, and its output cannot be captured.
+    pub fn adopt(handle: OwnedHandle, id: u32) -> Self {
+        Self {
+            handle,
+            id,
+            shares_console: false,
+            stdin: None,
+            stdout: None,
+            stderr: None,
+        }
+    }
+
+    fn try_wait(&mut self) -> std::io::Result<Option<std::process::ExitStatus>> {
+        use std::os::windows::process::ExitStatusExt;
+        use windows::Win32::{
+            Foundation::{WAIT_OBJECT_0, WAIT_TIMEOUT},
+            System::Threading::{GetExitCodeProcess, WaitForSingleObject},
+        };
+
+        let handle = HANDLE(self.handle.as_raw_handle());
+        match unsafe { WaitForSingleObject(handle, 0) } {
+            WAIT_OBJECT_0 => {
+                let mut code = 0;
+                unsafe { GetExitCodeProcess(handle, &mut code)? };
+                Ok(Some(std::process::ExitStatus::from_raw(code)))
+            }
+            WAIT_TIMEOUT => Ok(None),
+            _ => Err(std::io::Error::last_os_error()),
+        }
+    }
+
+    fn kill(&mut self) -> std::io::Result<()> {
+        unsafe { windows::Win32::System::Threading::TerminateProcess(HANDLE(self.handle.as_raw_handle()), 1)? };
+        Ok(())
+    }
+}
+
+/// Block until any of the objects is signaled or the timeout elapses.
+/// Without a timeout, this waits indefinitely.
+pub fn wait_for_objects(objects: &[&dyn AsRawHandle], timeout: Option<std::time::Duration>) {
+    use windows::Win32::System::Threading::{WaitForMultipleObjects, INFINITE};
+
+    let handles: Vec<_> = objects.iter().map(|x| HANDLE(x.as_raw_handle())).collect();
+    // Round up so that we don't wake just before a deadline and have to wait again.
+    let millis = match timeout {
+        Some(timeout) => timeout.as_nanos().div_ceil(1_000_000).min((INFINITE - 1) as u128) as u32,
+        None => INFINITE,
+    };
+    unsafe {
+        WaitForMultipleObjects(&handles, false, millis);
+    }
+}
+
+/// Get a primary token for the user logged on at the console.
+fn active_session_token() -> std::io::Result<OwnedHandle> {
+    use windows::Win32::{
+        Security::{DuplicateTokenEx, SecurityImpersonation, TokenPrimary, TOKEN_ALL_ACCESS},
+        System::RemoteDesktop::{WTSGetActiveConsoleSessionId, WTSQueryUserToken},
+    };
+
+    let session = unsafe { WTSGetActiveConsoleSessionId() };
+    if session == u32::MAX {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            "No user session is attached to the console",
+        ));
+    }
+
+    unsafe {
+        let mut user_token = HANDLE::default();
+        WTSQueryUserToken(session, &mut user_token)?;
+        let user_token = OwnedHandle::from_raw_handle(user_token.0);
+
+        let mut primary_token = HANDLE::default();
+        DuplicateTokenEx(
+            HANDLE(user_token.as_raw_handle()),
+            TOKEN_ALL_ACCESS,
+            None,
+            SecurityImpersonation,
+            TokenPrimary,
+            &mut primary_token,
+        )?;
+        Ok(OwnedHandle::from_raw_handle(primary_token.0))
+    }
+}
+
+/// Combine the policies that are set through `PROC_THREAD_ATTRIBUTE_MITIGATION_POLICY`.
+fn mitigation_policy_flags(mitigations: &[Mitigation]) -> u64 {
+    mitigations.iter().fold(0, |flags, mitigation| {
+        flags
+            | match mitigation {
+                Mitigation::Dep => PROCESS_CREATION_MITIGATION_POLICY_DEP_ENABLE,
+                Mitigation::ForceAslr => {
+                    PROCESS_CREATION_MITIGATION_POLICY_FORCE_RELOCATE_IMAGES_ALWAYS_ON
+                        | PROCESS_CREATION_MITIGATION_POLICY_BOTTOM_UP_ASLR_ALWAYS_ON
+                }
+                Mitigation::MicrosoftSignedOnly => {
+                    PROCESS_CREATION_MITIGATION_POLICY_BLOCK_NON_MICROSOFT_BINARIES_ALWAYS_ON
+                }
+                Mitigation::NoChildProcess => 0,
+            }
+    })
+}
+
+/// Standard handles for a new process: the inheritable ends for the child,
+/// and the parent's ends of any pipes.
+struct ChildStdio {
+    child: [OwnedHandle; 3],
+    stdin: Option<std::process::ChildStdin>,
+    stdout: Option<std::process::ChildStdout>,
+    stderr: Option<std::process::ChildStderr>,
+}
+
+impl ChildStdio {
+    fn new(pipe_stdin: bool, pipe_output: bool) -> std::io::Result<Self> {
+        let null = || -> std::io::Result<OwnedHandle> {
+            Ok(std::fs::OpenOptions::new().read(true).write(true).open("NUL")?.into())
+        };
+
+        let (stdin, child_stdin) = if pipe_stdin {
+            let (reader, writer) = std::io::pipe()?;
+            (Some(OwnedHandle::from(writer).into()), OwnedHandle::from(reader))
+        } else {
+            (None, null()?)
+        };
+        let (stdout, child_stdout) = if pipe_output {
+            let (reader, writer) = std::io::pipe()?;
+            (Some(OwnedHandle::from(reader).into()), OwnedHandle::from(writer))
+        } else {
+            (None, null()?)
+        };
+        let (stderr, child_stderr) = if pipe_output {
+            let (reader, writer) = std::io::pipe()?;
+            (Some(OwnedHandle::from(reader).into()), OwnedHandle::from(writer))
+        } else {
+            (None, null()?)
+        };
+
+        let child = [child_stdin, child_stdout, child_stderr];
+        for handle in &child {
+            unsafe {
+                windows::Win32::Foundation::SetHandleInformation(
+                    HANDLE(handle.as_raw_handle()),
+                    windows::Win32::Foundation::HANDLE_FLAG_INHERIT.0,
+                    windows::Win32::Foundation::HANDLE_FLAG_INHERIT,
+                )?;
+            }
+        }
+
+        Ok(Self {
+            child,
+            stdin,
+            stdout,
+            stderr,
+        })
+    }
+}
+
+/// A `PROC_THREAD_ATTRIBUTE_LIST` that is deleted when dropped.
+struct AttributeList {
+    buffer: Vec<usize>,
+}
+
+impl AttributeList {
+    fn new(count: u32) -> std::io::Result<Self> {
+        use windows::Win32::System::Threading::{InitializeProcThreadAttributeList, LPPROC_THREAD_ATTRIBUTE_LIST};
+
+        let mut size = 0;
+        // This first call only reports the size, so it is expected to fail.
+        let _ =
+            unsafe { InitializeProcThreadAttributeList(LPPROC_THREAD_ATTRIBUTE_LIST::default(), count, 0, &mut size) };
+
+        let mut buffer = vec![0usize; size.div_ceil(std::mem::size_of::<usize>())];
+        unsafe {
+            InitializeProcThreadAttributeList(
+                LPPROC_THREAD_ATTRIBUTE_LIST(buffer.as_mut_ptr() as *mut std::ffi::c_void),
+                count,
+                0,
+                &mut size,
+            )?;
+        }
+        Ok(Self { buffer })
+    }
+
+    fn as_ptr(&mut self) -> windows::Win32::System::Threading::LPPROC_THREAD_ATTRIBUTE_LIST {
+        windows::Win32::System::Threading::LPPROC_THREAD_ATTRIBUTE_LIST(
+            self.buffer.as_mut_ptr() as *mut std::ffi::c_void
+        )
+    }
+
+    /// The value must stay alive until the process is created.
+    unsafe fn set(&mut self, attribute: usize, value: *const std::ffi::c_void, size: usize) -> std::io::Result<()> {
+        windows::Win32::System::Threading::UpdateProcThreadAttribute(
+            self.as_ptr(),
+            0,
+            attribute,
+            Some(value),
+            size,
+            None,
+            None,
+        )?;
+        Ok(())
+    }
+}
+
+impl Drop for AttributeList {
+    fn drop(&mut self) {
+        unsafe { windows::Win32::System::Threading::DeleteProcThreadAttributeList(self.as_ptr()) };
+    }
+}
+
+#[cfg(test)]
+speculate::speculate! {
+    describe "mitigation_policy_flags" {
+        it "combines policies" {
+            assert_eq!(mitigation_policy_flags(&[]), 0);
+            assert_eq!(
+                mitigation_policy_flags(&[Mitigation::Dep, Mitigation::MicrosoftSignedOnly]),
+                0x1 | (0x1 << 44),
+            );
+            assert_eq!(mitigation_policy_flags(&[Mitigation::ForceAslr]), (0x1 << 8) | (0x1 << 16));
+        }
+
+        it "leaves the child process policy to its own attribute" {
+            assert_eq!(mitigation_policy_flags(&[Mitigation::NoChildProcess]), 0);
+        }
+    }
+}
@@ -1,9 +1,18 @@
-use crate::cli;
+use crate::{
+    cli,
+    eventlog::{self, Event},
+    output::OutputCapture,
+    process::{wait_for_objects, CommandProcess, LaunchOptions, RawProcess},
+};
+use clap::Parser;
 use log::{debug, error, info};
-use std::{io::BufRead, os::windows::process::CommandExt};
+use std::{io::Write, os::windows::process::CommandExt};
 use windows_service::{
     define_windows_service,
-    service::{ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus, ServiceType},
+    service::{
+        PowerEventParam, ServiceControl, ServiceControlAccept, ServiceExitCode, ServiceState, ServiceStatus,
+        ServiceType, SessionChangeReason,
+    },
     service_control_handler::{self, ServiceControlHandlerResult},
     service_dispatcher,
 };
@@ -18,7 +27,122 @@ enum ProcessStatus {
     Terminated,
 }
 
-fn check_process(child: &mut std::process::Child) -> Result<ProcessStatus, Box<dyn std::error::Error>> {
+enum ServiceEvent {
+    Stop,
+    Preshutdown,
+    Pause,
+    Continue,
+    Resume,
+    SessionChange { reason: &'static str, session_id: u32 },
+    Control(u32),
+    ParamChange,
+    Pipe(crate::pipe::Command),
+}
+
+enum StopOutcome {
+    Exited(i32),
+    Terminated,
+    Killed,
+}
+
+/// A Windows event that is set whenever a service event is queued,
+/// so that the main loop can wait for it and for the command to exit at the same time.
+struct EventSignal(std::os::windows::io::OwnedHandle);
+
+impl EventSignal {
+    fn new() -> windows::core::Result<Self> {
+        use std::os::windows::io::FromRawHandle;
+
+        let handle = unsafe {
+            windows::Win32::System::Threading::CreateEventW(None, false, false, windows::core::PCWSTR::null())?
+        };
+        Ok(Self(unsafe {
+            std::os::windows::io::OwnedHandle::from_raw_handle(handle.0)
+        }))
+    }
+
+    fn set(&self) {
+        use std::os::windows::io::AsRawHandle;
+
+        unsafe {
+            let _ =
+                windows::Win32::System::Threading::SetEvent(windows::Win32::Foundation::HANDLE(self.0.as_raw_handle()));
+        }
+    }
+}
+
+/// How long to wait for Shawl's own handler to receive a console control event
+/// that it sent to the command, in case it never arrives.
+const OWN_CTRL_EVENT_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Console control events that Shawl sends to the command, which Shawl's own handler receives too.
+/// The handler runs asynchronously on another thread, so we wait for it to see the event
+/// before we stop ignoring it.
+struct OwnCtrlEvents {
+    ignoring: std::sync::atomic::AtomicBool,
+    received: EventSignal,
+}
+
+impl OwnCtrlEvents {
+    fn new() -> windows::core::Result<Self> {
+        Ok(Self {
+            ignoring: std::sync::atomic::AtomicBool::new(false),
+            received: EventSignal::new()?,
+        })
+    }
+
+    /// Send an event to the command, which Shawl's handler will ignore until `finish` is called.
+    fn send(&self, event: u32) {
+        self.ignoring.store(true, std::sync::atomic::Ordering::SeqCst);
+        if !send_console_ctrl_event(event) {
+            self.ignoring.store(false, std::sync::atomic::Ordering::SeqCst);
+        }
+    }
+
+    /// Called by Shawl's handler. Returns true if the event was one that Shawl sent.
+    fn receive(&self) -> bool {
+        if self.ignoring.load(std::sync::atomic::Ordering::SeqCst) {
+            self.received.set();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Wait for Shawl's handler to receive the event that was sent, then stop ignoring events.
+    fn finish(&self) {
+        if self.ignoring.load(std::sync::atomic::Ordering::SeqCst) {
+            wait_for_objects(&[&self.received.0], Some(OWN_CTRL_EVENT_TIMEOUT));
+            self.ignoring.store(false, std::sync::atomic::Ordering::SeqCst);
+        }
+    }
+}
+
+/// Get the next service event, waiting until one is queued,
+/// the command (if any) exits, or the timeout elapses.
+fn next_event(
+    event_rx: &std::sync::mpsc::Receiver<ServiceEvent>,
+    signal: &EventSignal,
+    child: Option<&CommandProcess>,
+    timeout: Option<std::time::Duration>,
+) -> Option<ServiceEvent> {
+    let receive = || match event_rx.try_recv() {
+        Ok(event) => Some(event),
+        Err(std::sync::mpsc::TryRecvError::Disconnected) => Some(ServiceEvent::Stop),
+        Err(std::sync::mpsc::TryRecvError::Empty) => None,
+    };
+
+    if let Some(event) = receive() {
+        return Some(event);
+    }
+    match child {
+        Some(child) => wait_for_objects(&[&signal.0, child], timeout),
+        None => wait_for_objects(&[&signal.0], timeout),
+    }
+    receive()
+}
+
+fn check_process(child: &mut CommandProcess) -> Result<ProcessStatus, Box<dyn std::error::Error>> {
     match child.try_wait() {
         Ok(None) => Ok(ProcessStatus::Running),
         Ok(Some(status)) => match status.code() {
@@ -33,13 +157,13 @@ fn should_restart_exited_command(
     code: i32,
     restart: bool,
     no_restart: bool,
-    restart_if: &[i32],
-    restart_if_not: &[i32],
+    restart_if: &[cli::ExitCodeRange],
+    restart_if_not: &[cli::ExitCodeRange],
 ) -> bool {
     if !restart_if.is_empty() {
-        restart_if.contains(&code)
+        cli::ExitCodeRange::any_contains(restart_if, code)
     } else if !restart_if_not.is_empty() {
-        !restart_if_not.contains(&code)
+        !cli::ExitCodeRange::any_contains(restart_if_not, code)
     } else {
         restart || !no_restart && code != 0
     }
@@ -49,115 +173,2429 @@ fn should_restart_terminated_command(restart: bool, _no_restart: bool) -> bool {
     restart
 }
 
-pub fn run(name: String) -> windows_service::Result<()> {
-    service_dispatcher::start(name, ffi_service_main)
+/// How long to wait before retrying a failed launch, doubling with each attempt.
+fn spawn_retry_delay(attempt: u32) -> std::time::Duration {
+    let delay = 1000_u64.saturating_mul(1 << attempt.saturating_sub(1).min(16));
+    std::time::Duration::from_millis(delay.min(30_000))
 }
 
-fn service_main(mut arguments: Vec<std::ffi::OsString>) {
+fn describe_session_change(reason: SessionChangeReason) -> &'static str {
+    match reason {
+        SessionChangeReason::SessionLogon => "logon",
+        SessionChangeReason::SessionLogoff => "logoff",
+        SessionChangeReason::SessionLock => "lock",
+        SessionChangeReason::SessionUnlock => "unlock",
+        SessionChangeReason::ConsoleConnect => "console-connect",
+        SessionChangeReason::ConsoleDisconnect => "console-disconnect",
+        SessionChangeReason::RemoteConnect => "remote-connect",
+        SessionChangeReason::RemoteDisconnect => "remote-disconnect",
+        _ => "other",
+    }
+}
+
+/// Run an auxiliary command through `cmd /C` without waiting for it.
+fn run_event_command(label: &'static str, command: &str, env: &[(&str, String)]) {
+    info!("Running {} command: {}", label, command);
+    let mut cmd = std::process::Command::new("cmd");
+    cmd.arg("/C")
+        .raw_arg(command)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null());
+    for (key, value) in env {
+        cmd.env(key, value);
+    }
+
+    match cmd.spawn() {
+        Ok(mut child) => {
+            std::thread::spawn(move || match child.wait() {
+                Ok(status) => info!("The {} command exited with code {:?}", label, status.code()),
+                Err(e) => error!("Unable to wait for {} command: {:?}", label, e),
+            });
+        }
+        Err(e) => error!("Unable to run {} command: {}", label, e),
+    }
+}
+
+fn run_session_change_command(command: &str, reason: &str, session_id: u32) {
+    run_event_command(
+        "session change",
+        command,
+        &[
+            ("SHAWL_SESSION_EVENT", reason.to_string()),
+            ("SHAWL_SESSION_ID", session_id.to_string()),
+        ],
+    );
+}
+
+fn run_control_command(command: &str, code: u32) {
+    run_event_command("control", command, &[("SHAWL_CONTROL_CODE", code.to_string())]);
+}
+
+fn send_console_ctrl_event(event: u32) -> bool {
     unsafe {
-        // Windows services don't start with a console, so we have to
-        // allocate one in order to send ctrl-C to children.
-        if windows::Win32::System::Console::AllocConsole().is_err() {
+        if windows::Win32::System::Console::GenerateConsoleCtrlEvent(event, 0).is_err() {
             error!(
-                "Windows AllocConsole failed with code {:?}",
+                "Windows GenerateConsoleCtrlEvent failed with code {:?}",
                 windows::Win32::Foundation::GetLastError()
             );
+            return false;
         };
     }
-    if !arguments.is_empty() {
-        // first argument is the service name
-        arguments.remove(0);
+    true
+}
+
+/// Send ctrl-break to the command as a notification, without stopping it.
+fn signal_command(ctrl_events: &OwnCtrlEvents) {
+    info!("Sending ctrl-break to command");
+    ctrl_events.send(windows::Win32::System::Console::CTRL_BREAK_EVENT);
+    ctrl_events.finish();
+}
+
+/// A job object that is closed when dropped.
+struct JobObject(windows::Win32::Foundation::HANDLE);
+
+// The handle is owned, so it can move to the thread that supervises an instance.
+unsafe impl Send for JobObject {}
+
+impl JobObject {
+    fn new() -> windows::core::Result<Self> {
+        unsafe { windows::Win32::System::JobObjects::CreateJobObjectW(None, windows::core::PCWSTR::null()).map(Self) }
+    }
+
+    fn assign(&self, child: &CommandProcess) -> windows::core::Result<()> {
+        use std::os::windows::io::AsRawHandle;
+        unsafe {
+            windows::Win32::System::JobObjects::AssignProcessToJobObject(
+                self.0,
+                windows::Win32::Foundation::HANDLE(child.as_raw_handle()),
+            )
+        }
+    }
+
+    fn terminate(&self) -> windows::core::Result<()> {
+        unsafe { windows::Win32::System::JobObjects::TerminateJobObject(self.0, 1) }
     }
-    let _ = run_service(arguments);
 }
 
-#[allow(clippy::cognitive_complexity)]
-pub fn run_service(start_arguments: Vec<std::ffi::OsString>) -> windows_service::Result<()> {
-    let (shutdown_tx, shutdown_rx) = std::sync::mpsc::channel();
-    let cli = cli::evaluate_cli();
-    let (name, cwd, opts) = match cli.sub {
-        cli::Subcommand::Run {
-            name,
-            cwd,
-            common: opts,
-        } => (name, cwd, opts),
-        _ => {
-            // Can't get here.
-            return Ok(());
+impl Drop for JobObject {
+    fn drop(&mut self) {
+        unsafe {
+            let _ = windows::Win32::Foundation::CloseHandle(self.0);
         }
-    };
-    let pass = &opts.pass.unwrap_or_else(|| vec![0]);
-    let stop_timeout = &opts.stop_timeout.unwrap_or(3000_u64);
-    let mut service_exit_code = ServiceExitCode::NO_ERROR;
+    }
+}
 
-    let ignore_ctrlc = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
-    let ignore_ctrlc2 = ignore_ctrlc.clone();
-    ctrlc::set_handler(move || {
-        if !ignore_ctrlc2.load(std::sync::atomic::Ordering::SeqCst) {
-            std::process::abort();
+/// Forcibly ends the command according to `--kill-method`.
+struct Killer {
+    method: cli::KillMethod,
+    job: Option<JobObject>,
+}
+
+impl Killer {
+    /// Prepare to kill a newly launched command,
+    /// placing it into a job object if needed.
+    /// If the command was launched suspended, it's resumed once it's in the job,
+    /// so that any processes it starts are in the job too.
+    fn new(method: cli::KillMethod, child: &mut CommandProcess, suspended: bool) -> Self {
+        let job = match method {
+            cli::KillMethod::Job => match JobObject::new().and_then(|job| job.assign(child).map(|_| job)) {
+                Ok(job) => Some(job),
+                Err(e) => {
+                    error!("Unable to place command into a job object: {:?}", e);
+                    None
+                }
+            },
+            _ => None,
+        };
+        if suspended {
+            if let Err(e) = set_process_suspended(child.id(), false) {
+                error!("Unable to resume command after launching it: {:?}", e);
+                let _ = child.kill();
+            }
         }
-    })
-    .expect("Unable to create ctrl-C handler");
+        Self { method, job }
+    }
 
-    let event_handler = move |control_event| -> ServiceControlHandlerResult {
-        match control_event {
-            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
-            ServiceControl::Stop => {
-                info!("Received stop event");
-                shutdown_tx.send(()).unwrap();
-                ServiceControlHandlerResult::NoError
+    fn kill(&self, child: &mut CommandProcess) {
+        match (self.method, &self.job) {
+            (cli::KillMethod::Job, Some(job)) => {
+                info!("Terminating command's job object");
+                if let Err(e) = job.terminate() {
+                    error!("Unable to terminate job object: {:?}", e);
+                }
             }
-            ServiceControl::Shutdown => {
-                info!("Received shutdown event");
-                shutdown_tx.send(()).unwrap();
-                ServiceControlHandlerResult::NoError
+            (cli::KillMethod::Tree, _) => {
+                info!("Killing command's process tree");
+                if let Err(e) = std::process::Command::new("taskkill")
+                    .args(["/PID", &child.id().to_string(), "/T", "/F"])
+                    .stdin(std::process::Stdio::null())
+                    .stdout(std::process::Stdio::null())
+                    .stderr(std::process::Stdio::null())
+                    .status()
+                {
+                    error!("Unable to run taskkill: {:?}", e);
+                }
             }
-            _ => ServiceControlHandlerResult::NotImplemented,
+            _ => (),
+        }
+        // Make sure the direct child is gone even if the other methods failed.
+        let _ = child.kill();
+    }
+}
+
+/// Send ctrl-C to the command and wait for it to exit, killing it if it
+/// does not exit within the stop timeout.
+/// The command is killed right away if the stop method is `kill`
+/// or if it does not share Shawl's console.
+/// While waiting, `on_wait` is called about once per second with the remaining time.
+fn stop_command(
+    child: &mut CommandProcess,
+    killer: &Killer,
+    stop_method: cli::StopMethod,
+    stop_timeout: u64,
+    ctrl_events: &OwnCtrlEvents,
+    mut on_wait: impl FnMut(std::time::Duration),
+) -> StopOutcome {
+    if !child.shares_console() {
+        info!("Killing command because it is in another session and cannot receive ctrl-C");
+        killer.kill(child);
+        eventlog::report(Event::Killed);
+        return StopOutcome::Killed;
+    }
+    if stop_method == cli::StopMethod::Kill {
+        info!("Killing command");
+        killer.kill(child);
+        eventlog::report(Event::Killed);
+        return StopOutcome::Killed;
+    }
+
+    info!("Sending ctrl-C to command");
+    ctrl_events.send(windows::Win32::System::Console::CTRL_C_EVENT);
+
+    let start_time = std::time::Instant::now();
+    let mut last_wait_report = start_time;
+    let outcome = loop {
+        match check_process(child) {
+            Ok(ProcessStatus::Running) => {
+                if start_time.elapsed().as_millis() < stop_timeout.into() {
+                    let remaining = std::time::Duration::from_millis(stop_timeout).saturating_sub(start_time.elapsed());
+                    if last_wait_report.elapsed() >= std::time::Duration::from_secs(1) {
+                        last_wait_report = std::time::Instant::now();
+                        on_wait(remaining);
+                    }
+                    let next_report = std::time::Duration::from_secs(1).saturating_sub(last_wait_report.elapsed());
+                    wait_for_objects(&[&*child], Some(remaining.min(next_report)));
+                } else {
+                    info!("Killing command because stop timeout expired",);
+                    killer.kill(child);
+                    eventlog::report(Event::Killed);
+                    break StopOutcome::Killed;
+                }
+            }
+            Ok(ProcessStatus::Exited(code)) => {
+                info!(
+                    "Command exited after {:?} ms with code {}",
+                    start_time.elapsed().as_millis(),
+                    crate::exitcode::describe(code)
+                );
+                break StopOutcome::Exited(code);
+            }
+            _ => {
+                info!("Command exited within stop timeout");
+                break StopOutcome::Terminated;
+            }
+        }
+    };
+
+    ctrl_events.finish();
+    outcome
+}
+
+/// Get the full path of the executable that a process is running.
+fn process_image_path(process: &impl std::os::windows::io::AsRawHandle) -> Option<std::path::PathBuf> {
+    use windows::Win32::{
+        Foundation::HANDLE,
+        System::Threading::{QueryFullProcessImageNameW, PROCESS_NAME_WIN32},
+    };
+
+    let mut buffer = vec![0u16; 32768];
+    let mut size = buffer.len() as u32;
+    unsafe {
+        QueryFullProcessImageNameW(
+            HANDLE(process.as_raw_handle()),
+            PROCESS_NAME_WIN32,
+            windows::core::PWSTR(buffer.as_mut_ptr()),
+            &mut size,
+        )
+    }
+    .ok()?;
+    Some(std::path::PathBuf::from(String::from_utf16_lossy(
+        &buffer[..size as usize],
+    )))
+}
+
+/// Where to record the command's process for `--on-duplicate`.
+fn pid_file_path(name: &str, opts: &cli::CommonOpts) -> Option<std::path::PathBuf> {
+    let dir = match &opts.log_dir {
+        Some(dir) => std::path::PathBuf::from(dir),
+        None => std::env::current_exe().ok()?.parent()?.to_path_buf(),
+    };
+    Some(dir.join(format!("shawl_for_{}.pid", name)))
+}
+
+/// The command's process as recorded for `--on-duplicate`.
+#[derive(Debug, PartialEq, Eq)]
+struct PidFile {
+    pid: u32,
+    image: std::path::PathBuf,
+    /// Creation time as a `FILETIME` value.
+    started: u64,
+}
+
+impl PidFile {
+    fn to_file_content(&self) -> String {
+        format!("{}\n{}\n{}\n", self.pid, self.image.display(), self.started)
+    }
+}
+
+/// Parse a PID file, which contains the process ID, executable path,
+/// and creation time on separate lines.
+fn parse_pid_file(content: &str) -> Option<PidFile> {
+    let mut lines = content.lines();
+    let pid = lines.next()?.trim().parse().ok()?;
+    let image = lines.next()?.trim();
+    if image.is_empty() {
+        return None;
+    }
+    let started = lines.next()?.trim().parse().ok()?;
+    Some(PidFile {
+        pid,
+        image: std::path::PathBuf::from(image),
+        started,
+    })
+}
+
+/// Get when a process was created, as a `FILETIME` value.
+fn process_start_time(process: &impl std::os::windows::io::AsRawHandle) -> Option<u64> {
+    use windows::Win32::{
+        Foundation::{FILETIME, HANDLE},
+        System::Threading::GetProcessTimes,
+    };
+
+    let mut created = FILETIME::default();
+    let (mut exited, mut kernel, mut user) = (FILETIME::default(), FILETIME::default(), FILETIME::default());
+    unsafe {
+        GetProcessTimes(
+            HANDLE(process.as_raw_handle()),
+            &mut created,
+            &mut exited,
+            &mut kernel,
+            &mut user,
+        )
+    }
+    .ok()?;
+    Some(((created.dwHighDateTime as u64) << 32) | created.dwLowDateTime as u64)
+}
+
+/// Record the command's process for `--on-duplicate`.
+fn write_pid_file(path: &std::path::Path, child: &CommandProcess) {
+    let Some(image) = process_image_path(child) else {
+        error!("Unable to determine the command's executable path, so it will not be recorded");
+        return;
+    };
+    let Some(started) = process_start_time(child) else {
+        error!("Unable to determine the command's start time, so it will not be recorded");
+        return;
+    };
+    let content = PidFile {
+        pid: child.id(),
+        image,
+        started,
+    }
+    .to_file_content();
+    if let Err(e) = std::fs::write(path, content) {
+        error!("Unable to write PID file {}: {}", path.display(), e);
+    }
+}
+
+/// Find the command from a previous run of the service, if it is still running.
+/// The process must still have the recorded executable path and creation time,
+/// in case its ID has been reused by an unrelated process.
+fn find_previous_command(pid_file: &std::path::Path) -> Option<(u32, std::os::windows::io::OwnedHandle)> {
+    use std::os::windows::io::{AsRawHandle, FromRawHandle, OwnedHandle};
+    use windows::Win32::{
+        Foundation::{HANDLE, WAIT_TIMEOUT},
+        System::Threading::{
+            OpenProcess, WaitForSingleObject, PROCESS_QUERY_LIMITED_INFORMATION, PROCESS_SYNCHRONIZE, PROCESS_TERMINATE,
+        },
+    };
+
+    let PidFile { pid, image, started } = parse_pid_file(&std::fs::read_to_string(pid_file).ok()?)?;
+    let handle = unsafe {
+        OpenProcess(
+            PROCESS_QUERY_LIMITED_INFORMATION | PROCESS_SYNCHRONIZE | PROCESS_TERMINATE,
+            false,
+            pid,
+        )
+    }
+    .ok()?;
+    let handle = unsafe { OwnedHandle::from_raw_handle(handle.0) };
+
+    let running = unsafe { WaitForSingleObject(HANDLE(handle.as_raw_handle()), 0) } == WAIT_TIMEOUT;
+    let current_image = process_image_path(&handle)?;
+    let same_start = process_start_time(&handle) == Some(started);
+    (running
+        && same_start
+        && current_image
+            .to_string_lossy()
+            .eq_ignore_ascii_case(&image.to_string_lossy()))
+    .then_some((pid, handle))
+}
+
+/// Whether an exit code is an NTSTATUS error, such as an access violation (0xC0000005),
+/// rather than a code that the command chose to exit with.
+/// Being stopped by ctrl-C (0xC000013A) does not count.
+fn is_crash_exit_code(code: i32) -> bool {
+    let code = code as u32;
+    (0xC000_0000..=0xCFFF_FFFF).contains(&code) && code != 0xC000_013A
+}
+
+/// Windows Error Reporting settings that write a minidump to a directory when the command crashes.
+/// They apply to every program with the command's image name, so they're marked with the service's name,
+/// and settings that something else made for the same image name are left alone.
+/// The settings are removed again when this is dropped.
+struct CrashDumps {
+    subkey: String,
+    service_name: String,
+}
+
+/// Registry value that marks the `LocalDumps` settings as the service's own.
+const CRASH_DUMPS_OWNER: &str = "ShawlService";
+
+impl CrashDumps {
+    fn configure(service_name: &str, image_name: &str, dir: &str) -> Result<Self, String> {
+        use windows::{
+            core::HSTRING,
+            Win32::System::Registry::{
+                RegCloseKey, RegCreateKeyExW, RegSetValueExW, HKEY, HKEY_LOCAL_MACHINE, KEY_SET_VALUE, REG_DWORD,
+                REG_EXPAND_SZ, REG_OPTION_NON_VOLATILE, REG_SZ,
+            },
+        };
+
+        let dumps = Self {
+            subkey: format!(
+                "SOFTWARE\\Microsoft\\Windows\\Windows Error Reporting\\LocalDumps\\{}",
+                image_name
+            ),
+            service_name: service_name.to_string(),
+        };
+        match dumps.owner() {
+            Some(Some(owner)) if owner == service_name => (),
+            Some(Some(owner)) => return Err(format!("they are already configured for service {}", owner)),
+            Some(None) => return Err("they are already configured outside of Shawl".to_string()),
+            None => (),
+        }
+
+        let wide = |text: &str| -> Vec<u8> {
+            text.encode_utf16()
+                .chain(Some(0))
+                .flat_map(|x| x.to_le_bytes())
+                .collect()
+        };
+        // 1 is a minidump, as opposed to a full dump.
+        let dump_type = 1u32.to_le_bytes();
+
+        unsafe {
+            let mut key = HKEY::default();
+            RegCreateKeyExW(
+                HKEY_LOCAL_MACHINE,
+                &HSTRING::from(&dumps.subkey),
+                0,
+                None,
+                REG_OPTION_NON_VOLATILE,
+                KEY_SET_VALUE,
+                None,
+                &mut key,
+                None,
+            )
+            .ok()
+            .map_err(|e| e.to_string())?;
+            let result = RegSetValueExW(
+                key,
+                &HSTRING::from(CRASH_DUMPS_OWNER),
+                0,
+                REG_SZ,
+                Some(&wide(service_name)),
+            )
+            .ok()
+            .and_then(|_| RegSetValueExW(key, &HSTRING::from("DumpFolder"), 0, REG_EXPAND_SZ, Some(&wide(dir))).ok())
+            .and_then(|_| RegSetValueExW(key, &HSTRING::from("DumpType"), 0, REG_DWORD, Some(&dump_type)).ok());
+            let _ = RegCloseKey(key);
+            result.map_err(|e| e.to_string())?;
+        }
+        Ok(dumps)
+    }
+
+    /// `None` if there are no settings for the image name yet,
+    /// or else the service that made them, if any.
+    fn owner(&self) -> Option<Option<String>> {
+        use windows::{
+            core::HSTRING,
+            Win32::System::Registry::{
+                RegCloseKey, RegGetValueW, RegOpenKeyExW, HKEY, HKEY_LOCAL_MACHINE, KEY_QUERY_VALUE, RRF_RT_REG_SZ,
+            },
+        };
+
+        let subkey = HSTRING::from(&self.subkey);
+        let value = HSTRING::from(CRASH_DUMPS_OWNER);
+        let mut data = [0u16; 257];
+        let mut size = std::mem::size_of_val(&data) as u32;
+        let result = unsafe {
+            RegGetValueW(
+                HKEY_LOCAL_MACHINE,
+                &subkey,
+                &value,
+                RRF_RT_REG_SZ,
+                None,
+                Some(data.as_mut_ptr().cast()),
+                Some(&mut size),
+            )
+        };
+        if result.is_ok() {
+            let len = (size as usize / 2).saturating_sub(1);
+            return Some(Some(String::from_utf16_lossy(&data[..len])));
+        }
+        let mut key = HKEY::default();
+        let exists = unsafe { RegOpenKeyExW(HKEY_LOCAL_MACHINE, &subkey, 0, KEY_QUERY_VALUE, &mut key) }.is_ok();
+        if exists {
+            unsafe {
+                let _ = RegCloseKey(key);
+            }
+        }
+        exists.then_some(None)
+    }
+}
+
+impl Drop for CrashDumps {
+    fn drop(&mut self) {
+        use windows::{
+            core::HSTRING,
+            Win32::System::Registry::{RegDeleteKeyW, HKEY_LOCAL_MACHINE},
+        };
+
+        // Leave the settings alone if something else has replaced them since.
+        if self.owner() != Some(Some(self.service_name.clone())) {
+            return;
+        }
+        let result = unsafe { RegDeleteKeyW(HKEY_LOCAL_MACHINE, &HSTRING::from(&self.subkey)) };
+        if let Err(e) = result.ok() {
+            error!("Unable to remove the crash dump settings: {:?}", e);
+        }
+    }
+}
+
+/// The image name that Windows Error Reporting will know the command by.
+/// Shells and script interpreters are shared with other programs, so there is none for them.
+fn crash_dump_image_name(program: &str, environment: &LaunchEnvironment, opts: &cli::CommonOpts) -> Option<String> {
+    if opts.shell.is_some() {
+        return None;
+    }
+    let extensions = std::env::var("PATHEXT").unwrap_or_else(|_| ".COM;.EXE;.BAT;.CMD".to_string());
+    let resolved = crate::env::resolve_program(
+        program,
+        environment.cwd.as_deref(),
+        environment.path.as_deref(),
+        &extensions,
+    )?;
+    if script_kind(&resolved.to_string_lossy()).is_some() {
+        return None;
+    }
+    resolved.file_name().map(|x| x.to_string_lossy().to_string())
+}
+
+/// Wait for Windows Error Reporting to finish writing a dump, which it may still be doing
+/// after the process has exited. The dump is held open until then, so it can't be opened exclusively.
+fn wait_for_crash_dump(path: &std::path::Path, timeout: std::time::Duration) -> bool {
+    use std::os::windows::fs::OpenOptionsExt;
+
+    let deadline = std::time::Instant::now() + timeout;
+    loop {
+        if std::fs::OpenOptions::new().read(true).share_mode(0).open(path).is_ok() {
+            return true;
         }
+        if std::time::Instant::now() >= deadline {
+            return false;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    }
+}
+
+/// How long to wait for Windows Error Reporting to write a crashed command's dump.
+const CRASH_DUMP_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Where Windows Error Reporting writes the dump for a crashed process.
+fn crash_dump_path(dir: &str, image_name: &str, pid: u32) -> std::path::PathBuf {
+    std::path::Path::new(dir).join(format!("{}.{}.dmp", image_name, pid))
+}
+
+/// Suspend or resume all threads of the process.
+fn set_process_suspended(pid: u32, suspend: bool) -> windows::core::Result<()> {
+    use windows::Win32::{
+        Foundation::CloseHandle,
+        System::{
+            Diagnostics::ToolHelp::{
+                CreateToolhelp32Snapshot, Thread32First, Thread32Next, TH32CS_SNAPTHREAD, THREADENTRY32,
+            },
+            Threading::{OpenThread, ResumeThread, SuspendThread, THREAD_SUSPEND_RESUME},
+        },
     };
 
-    let status_handle = service_control_handler::register(name, event_handler)?;
+    unsafe {
+        let snapshot = CreateToolhelp32Snapshot(TH32CS_SNAPTHREAD, 0)?;
+        let mut entry = THREADENTRY32 {
+            dwSize: std::mem::size_of::<THREADENTRY32>() as u32,
+            ..Default::default()
+        };
+
+        let mut found = Thread32First(snapshot, &mut entry).is_ok();
+        while found {
+            if entry.th32OwnerProcessID == pid {
+                match OpenThread(THREAD_SUSPEND_RESUME, false, entry.th32ThreadID) {
+                    Ok(thread) => {
+                        let previous = if suspend {
+                            SuspendThread(thread)
+                        } else {
+                            ResumeThread(thread)
+                        };
+                        if previous == u32::MAX {
+                            error!(
+                                "Unable to change suspension of thread {}: {:?}",
+                                entry.th32ThreadID,
+                                windows::Win32::Foundation::GetLastError()
+                            );
+                        }
+                        let _ = CloseHandle(thread);
+                    }
+                    Err(e) => error!("Unable to open thread {}: {:?}", entry.th32ThreadID, e),
+                }
+            }
+            found = Thread32Next(snapshot, &mut entry).is_ok();
+        }
+
+        let _ = CloseHandle(snapshot);
+    }
+
+    Ok(())
+}
+
+/// Where to report the service's status. In the foreground,
+/// there is no service manager, so the status is only logged.
+enum StatusHandle {
+    Service(service_control_handler::ServiceStatusHandle),
+    Foreground,
+}
+
+impl StatusHandle {
+    fn set_service_status(&self, status: ServiceStatus) -> windows_service::Result<()> {
+        match self {
+            Self::Service(handle) => handle.set_service_status(status),
+            Self::Foreground => {
+                debug!("Service status: {:?} ({:?})", status.current_state, status.exit_code);
+                Ok(())
+            }
+        }
+    }
+}
 
+fn set_pending(
+    status_handle: &StatusHandle,
+    state: ServiceState,
+    checkpoint: u32,
+    wait_hint: std::time::Duration,
+) -> windows_service::Result<()> {
     status_handle.set_service_status(ServiceStatus {
         service_type: SERVICE_TYPE,
-        current_state: ServiceState::Running,
-        controls_accepted: ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN,
+        current_state: state,
+        controls_accepted: ServiceControlAccept::empty(),
         exit_code: ServiceExitCode::NO_ERROR,
+        checkpoint,
+        wait_hint,
+        process_id: None,
+    })
+}
+
+/// Returns the exit code that was reported.
+fn set_stopped(status_handle: &StatusHandle, exit_code: ServiceExitCode) -> windows_service::Result<ServiceExitCode> {
+    // Windows may end the process once it is stopped.
+    crate::notify::wait(std::time::Duration::from_secs(5));
+    crate::otlp::wait(std::time::Duration::from_secs(5));
+    status_handle.set_service_status(ServiceStatus {
+        service_type: SERVICE_TYPE,
+        current_state: ServiceState::Stopped,
+        controls_accepted: ServiceControlAccept::empty(),
+        exit_code,
         checkpoint: 0,
         wait_hint: std::time::Duration::default(),
         process_id: None,
     })?;
+    Ok(exit_code)
+}
 
-    let mut command = opts.command.into_iter();
-    let program = command.next().unwrap();
-    let mut args: Vec<_> = command.map(std::ffi::OsString::from).collect();
-    if opts.pass_start_args {
-        args.extend(start_arguments);
+/// Report a failing exit code to Windows, passing through Win32 errors wrapped in an HRESULT
+/// so that Windows can show their usual message.
+fn command_exit_code(code: i32) -> ServiceExitCode {
+    match crate::exitcode::win32_from_hresult(code) {
+        Some(win32) => ServiceExitCode::Win32(win32),
+        None => ServiceExitCode::ServiceSpecific(code as u32),
+    }
+}
+
+fn launch_error_exit_code(error: &std::io::Error) -> ServiceExitCode {
+    match error.raw_os_error() {
+        Some(win_code) => ServiceExitCode::Win32(win_code as u32),
+        None => ServiceExitCode::Win32(windows::Win32::Foundation::ERROR_PROCESS_ABORTED.0),
+    }
+}
+
+/// The controls to accept from the service manager, some of which depend on the options,
+/// so that Windows only sends the notifications that Shawl will act on.
+fn accepted_controls(opts: &cli::CommonOpts) -> ServiceControlAccept {
+    let mut controls_accepted = ServiceControlAccept::STOP
+        | ServiceControlAccept::SHUTDOWN
+        | ServiceControlAccept::PAUSE_CONTINUE
+        | ServiceControlAccept::PARAM_CHANGE;
+    if opts.preshutdown_timeout.is_some() {
+        controls_accepted |= ServiceControlAccept::PRESHUTDOWN;
+    }
+    if opts.on_resume.is_some() {
+        controls_accepted |= ServiceControlAccept::POWER_EVENT;
+    }
+    if opts.on_session_change.is_some() {
+        controls_accepted |= ServiceControlAccept::SESSION_CHANGE;
+    }
+    controls_accepted
+}
+
+fn set_service_state(
+    status_handle: &StatusHandle,
+    state: ServiceState,
+    controls_accepted: ServiceControlAccept,
+) -> windows_service::Result<()> {
+    status_handle.set_service_status(ServiceStatus {
+        service_type: SERVICE_TYPE,
+        current_state: state,
+        controls_accepted,
+        exit_code: ServiceExitCode::NO_ERROR,
+        checkpoint: 0,
+        wait_hint: std::time::Duration::default(),
+        process_id: None,
+    })
+}
+
+/// Hand control to the service manager, which runs the service until it stops.
+/// The options are read from the command line, which must use `run`.
+pub fn run(name: String) -> windows_service::Result<()> {
+    service_dispatcher::start(name, ffi_service_main)
+}
+
+/// Run the service in the current console until Ctrl-C, without the service manager.
+/// The options are read from the command line, which must use `run`.
+pub fn run_foreground() -> windows_service::Result<()> {
+    let Some((name, cwd, _, opts)) = run_options() else {
+        return Ok(());
+    };
+    run_configured(
+        name.clone(),
+        name,
+        cwd,
+        opts,
+        Mode::Foreground,
+        vec![],
+        std::sync::Arc::default(),
+    )
+    .map(|_| ())
+}
+
+/// Whether `run` failed because the service manager didn't start it,
+/// such as when someone runs it from a terminal or double-clicks it.
+pub fn started_outside_service_manager(error: &windows_service::Error) -> bool {
+    match error {
+        windows_service::Error::Winapi(e) => {
+            e.raw_os_error() == Some(windows::Win32::Foundation::ERROR_FAILED_SERVICE_CONTROLLER_CONNECT.0 as i32)
+        }
+        _ => false,
+    }
+}
+
+/// Run an installed service's configuration in the current console for a while,
+/// then stop it and report what Windows would have seen.
+/// The service must be stopped so that the two don't compete for the command.
+pub fn test(name: &str, duration: std::time::Duration) -> Result<(), Box<dyn std::error::Error>> {
+    if crate::control::query_service_state(name)? != ServiceState::Stopped {
+        return Err(format!("The service {} must be stopped first", name).into());
+    }
+    let (cwd, opts) = read_installed_options(name)?;
+    let (opts, left_out) = test_options(opts, &std::env::temp_dir().join("shawl-test"));
+    if !opts.no_log {
+        crate::prepare_logging(name, &opts, true)?;
+    }
+
+    let status = std::sync::Arc::new(std::sync::Mutex::new(crate::pipe::Status::default()));
+    let exit_code = run_configured(
+        name.to_string(),
+        name.to_string(),
+        cwd,
+        opts.clone(),
+        Mode::Test(duration),
+        vec![],
+        status.clone(),
+    )?;
+    let status = status.lock().map(|x| x.clone()).unwrap_or_default();
+    println!();
+    for line in test_report(&opts, &status, exit_code) {
+        println!("{}", line);
+    }
+    println!(
+        "The command ran as the current user, {}, rather than the service's account",
+        std::env::var("USERNAME").unwrap_or_else(|_| "unknown".to_string())
+    );
+    if !left_out.is_empty() {
+        println!("Left out for the test: {}", left_out.join(", "));
+    }
+    if !opts.no_log {
+        println!("Logs: {}", opts.log_directory(name)?.display());
+    }
+    Ok(())
+}
+
+/// Adjust the service's options for `shawl test`, returning what was left out.
+/// Anything that other systems or people would notice is left out,
+/// and logs go to a subdirectory of `log_dir` instead of the service's own log files.
+fn test_options(mut opts: cli::CommonOpts, log_dir: &std::path::Path) -> (cli::CommonOpts, Vec<&'static str>) {
+    let mut left_out = vec![];
+    let mut leave_out = |name, configured: bool| {
+        if configured {
+            left_out.push(name);
+        }
+    };
+    leave_out(
+        "notifications",
+        opts.notify_slack.is_some() || opts.notify_teams.is_some(),
+    );
+    leave_out("heartbeats", opts.heartbeat_url.is_some());
+    leave_out("hooks", opts.hook_script.is_some() || opts.hook_exec.is_some());
+    leave_out("OpenTelemetry", opts.otlp_endpoint.is_some());
+    leave_out("GELF", opts.log_gelf.is_some());
+    leave_out("event log", opts.event_log || opts.log_cmd_to.is_some());
+    leave_out("status file", opts.status_file.is_some());
+    leave_out("event file", opts.event_file.is_some());
+    leave_out("performance counters", opts.perf_counters);
+
+    opts.notify_slack = None;
+    opts.notify_teams = None;
+    opts.heartbeat_url = None;
+    opts.hook_script = None;
+    opts.hook_exec = None;
+    opts.otlp_endpoint = None;
+    opts.otlp_output = false;
+    opts.log_gelf = None;
+    opts.event_log = false;
+    opts.log_cmd_to = None;
+    opts.status_file = None;
+    opts.event_file = None;
+    opts.perf_counters = false;
+    opts.log_dir = Some(log_dir.to_string_lossy().to_string());
+    opts.log_subdir = Some(None);
+    opts.log_private = false;
+    (opts, left_out)
+}
+
+fn describe_service_exit_code(code: ServiceExitCode) -> String {
+    match code {
+        ServiceExitCode::Win32(0) => "success".to_string(),
+        ServiceExitCode::Win32(code) => format!("Win32 error {}", code),
+        ServiceExitCode::ServiceSpecific(code) => {
+            format!("service-specific error {}", crate::exitcode::describe(code as i32))
+        }
+    }
+}
+
+/// Summarize a `shawl test` run: what Windows would have seen when the service stopped,
+/// and what Shawl would do if the command exited on its own like it did last.
+fn test_report(opts: &cli::CommonOpts, status: &crate::pipe::Status, exit_code: ServiceExitCode) -> Vec<String> {
+    let mut lines = vec![
+        format!(
+            "Launches: {}, restarts: {}, failures: {}",
+            status.launches, status.restarts, status.failures
+        ),
+        format!("Windows would see: {}", describe_service_exit_code(exit_code)),
+    ];
+    match status.last_exit_code {
+        Some(code) => {
+            let restart = should_restart_exited_command(
+                code,
+                opts.restart,
+                opts.no_restart,
+                &opts.restart_if,
+                &opts.restart_if_not,
+            );
+            lines.push(format!("Last exit code: {}", crate::exitcode::describe(code)));
+            lines.push(format!(
+                "If the command exited on its own with that code, Shawl would {}",
+                if restart { "restart it" } else { "stop the service" }
+            ));
+        }
+        None => lines.push("Last exit code: none".to_string()),
+    }
+    lines
+}
+
+fn service_main(mut arguments: Vec<std::ffi::OsString>) {
+    let mut service_name = None;
+    if !arguments.is_empty() {
+        // first argument is the service name
+        service_name = Some(arguments.remove(0).to_string_lossy().to_string());
+    }
+    let _ = run_service(service_name, arguments);
+}
+
+/// The placeholders that `shawl run` fills in each time it launches the command,
+/// which `shawl add` leaves as-is.
+pub const LAUNCH_PLACEHOLDERS: [&str; 3] = ["{service_name}", "{service_dir}", "{log_dir}"];
+
+/// Values for the placeholders that are filled in when the command launches,
+/// like `{service_name}`, so that many services can share the same arguments.
+/// Without `--cwd` or `--log-dir`, the directory is where Shawl is located.
+fn launch_placeholders(
+    service_name: &str,
+    cwd: &Option<String>,
+    opts: &cli::CommonOpts,
+) -> Vec<(&'static str, String)> {
+    let shawl_dir = || {
+        std::env::current_exe()
+            .ok()
+            .and_then(|x| x.parent().map(|x| x.to_string_lossy().to_string()))
+            .unwrap_or_default()
+    };
+    let service_dir = match cwd {
+        Some(cwd) => expand_path(opts, cwd),
+        None => shawl_dir(),
+    };
+    let log_dir = opts
+        .log_directory(service_name)
+        .map(|x| crate::simplify_path(&x.to_string_lossy()))
+        .unwrap_or_default();
+    LAUNCH_PLACEHOLDERS
+        .into_iter()
+        .zip([service_name.to_string(), service_dir, log_dir])
+        .collect()
+}
+
+/// Split the program from the rest of a `--command-line`, which is kept as-is.
+pub fn split_program(command_line: &str) -> (String, String) {
+    let command_line = command_line.trim_start();
+    let (program, rest) = match command_line.strip_prefix('"') {
+        Some(quoted) => quoted.split_once('"').unwrap_or((quoted, "")),
+        None => command_line.split_once([' ', '\t']).unwrap_or((command_line, "")),
+    };
+    (program.to_string(), rest.trim_start().to_string())
+}
+
+/// Add arguments to a command, without any quoting for `--command-line`.
+fn add_args(cmd: &mut std::process::Command, args: &[std::ffi::OsString], raw: bool) {
+    if raw {
+        for arg in args {
+            cmd.raw_arg(arg);
+        }
+    } else {
+        cmd.args(args);
+    }
+}
+
+/// Build a command that runs a command line through a shell.
+fn shell_command(shell: cli::Shell, program: &str, args: &[std::ffi::OsString]) -> std::process::Command {
+    let command_line = std::iter::once(program.to_string())
+        .chain(args.iter().map(|x| x.to_string_lossy().to_string()))
+        .collect::<Vec<_>>()
+        .join(" ");
+
+    match shell {
+        cli::Shell::Cmd => {
+            let mut cmd = std::process::Command::new("cmd");
+            // With `/S`, cmd strips only the outer quotes and keeps the rest of the line as-is.
+            cmd.args(["/D", "/S", "/C"]).raw_arg(format!("\"{}\"", command_line));
+            cmd
+        }
+        cli::Shell::Powershell => {
+            let mut cmd = std::process::Command::new("powershell");
+            cmd.args(["-NoProfile", "-NonInteractive", "-Command"])
+                .arg(command_line);
+            cmd
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ScriptKind {
+    Batch,
+    PowerShell,
+    Python,
+}
+
+impl ScriptKind {
+    /// The program that runs this kind of script.
+    pub fn interpreter(&self) -> &'static str {
+        match self {
+            Self::Batch => "cmd",
+            Self::PowerShell => "powershell",
+            Self::Python => "py",
+        }
+    }
+}
+
+/// Determine which interpreter a script needs based on its extension.
+pub fn script_kind(program: &str) -> Option<ScriptKind> {
+    let extension = std::path::Path::new(program)
+        .extension()?
+        .to_string_lossy()
+        .to_lowercase();
+    match extension.as_str() {
+        "bat" | "cmd" => Some(ScriptKind::Batch),
+        "ps1" => Some(ScriptKind::PowerShell),
+        "py" | "pyw" => Some(ScriptKind::Python),
+        _ => None,
+    }
+}
+
+/// Build a command that runs a script through its interpreter.
+pub fn script_command(
+    kind: ScriptKind,
+    program: &str,
+    args: &[std::ffi::OsString],
+    raw: bool,
+) -> std::process::Command {
+    match kind {
+        ScriptKind::Batch if raw => shell_command(cli::Shell::Cmd, program, args),
+        ScriptKind::Batch => {
+            let args: Vec<_> = args
+                .iter()
+                .map(|x| std::ffi::OsString::from(crate::control::quote(&x.to_string_lossy())))
+                .collect();
+            shell_command(cli::Shell::Cmd, &crate::control::quote(program), &args)
+        }
+        ScriptKind::PowerShell => {
+            let mut cmd = std::process::Command::new(kind.interpreter());
+            cmd.args([
+                "-NoProfile",
+                "-NonInteractive",
+                "-ExecutionPolicy",
+                "Bypass",
+                "-File",
+                program,
+            ]);
+            add_args(&mut cmd, args, raw);
+            cmd
+        }
+        ScriptKind::Python => {
+            let mut cmd = std::process::Command::new(kind.interpreter());
+            cmd.arg(program);
+            add_args(&mut cmd, args, raw);
+            cmd
+        }
+    }
+}
+
+/// Check whether Shawl needs a console at startup,
+/// either to send ctrl-C or ctrl-break to commands or to configure the console itself.
+fn needs_console(opts: &cli::CommonOpts) -> bool {
+    (opts.stop_method.unwrap_or_default() == cli::StopMethod::CtrlC && !opts.interactive_session)
+        || !opts.sidecar.is_empty()
+        || opts.instances.is_some()
+        || opts.on_resume == Some(cli::ResumeAction::Signal)
+        || opts.console_codepage.is_some()
+}
+
+/// Allocate a console the first time that one is needed.
+/// Windows services don't start with a console, so we have to
+/// allocate one in order to send ctrl-C to children,
+/// and they must be launched after that so that they share it.
+fn ensure_console(opts: &cli::CommonOpts) {
+    static ALLOCATED: std::sync::Once = std::sync::Once::new();
+    ALLOCATED.call_once(|| {
+        unsafe {
+            if windows::Win32::System::Console::AllocConsole().is_err() {
+                error!(
+                    "Windows AllocConsole failed with code {:?}",
+                    windows::Win32::Foundation::GetLastError()
+                );
+                return;
+            };
+        }
+        if opts.hide_window {
+            hide_console_window();
+        }
+        if let Some(codepage) = opts.console_codepage {
+            set_console_codepage(codepage);
+        }
+    });
+}
+
+/// Set the input and output code pages of the console that Shawl allocated.
+fn set_console_codepage(codepage: u32) {
+    info!("Setting console code page to {}", codepage);
+    unsafe {
+        if windows::Win32::System::Console::SetConsoleOutputCP(codepage).is_err()
+            || windows::Win32::System::Console::SetConsoleCP(codepage).is_err()
+        {
+            error!(
+                "Unable to set console code page to {}: {:?}",
+                codepage,
+                windows::Win32::Foundation::GetLastError()
+            );
+        }
+    }
+}
+
+/// Hide the console window that Shawl allocated.
+/// The command shares this console so that it can still receive ctrl-C,
+/// which would not be possible if it were launched with `CREATE_NO_WINDOW`.
+fn hide_console_window() {
+    unsafe {
+        let window = windows::Win32::System::Console::GetConsoleWindow();
+        if window.is_invalid() {
+            return;
+        }
+        let _ = windows::Win32::UI::WindowsAndMessaging::ShowWindow(
+            window,
+            windows::Win32::UI::WindowsAndMessaging::SW_HIDE,
+        );
+    }
+}
+
+/// Expand `%VAR%` references using Shawl's own environment.
+/// Unknown variables are left as-is, matching `cmd`.
+fn expand_env_vars(text: &str) -> String {
+    let source = windows::core::HSTRING::from(text);
+    unsafe {
+        let size = windows::Win32::System::Environment::ExpandEnvironmentStringsW(&source, None);
+        if size == 0 {
+            error!(
+                "Windows ExpandEnvironmentStringsW failed with code {:?}",
+                windows::Win32::Foundation::GetLastError()
+            );
+            return text.to_string();
+        }
+        let mut buffer = vec![0u16; size as usize];
+        let written = windows::Win32::System::Environment::ExpandEnvironmentStringsW(&source, Some(&mut buffer));
+        if written == 0 || written > size {
+            return text.to_string();
+        }
+        // The returned length includes the terminating null.
+        String::from_utf16_lossy(&buffer[..written as usize - 1])
+    }
+}
+
+/// Expand `%VAR%` references if `--expand-env` is enabled.
+fn expand(opts: &cli::CommonOpts, text: &str) -> String {
+    if opts.expand_env {
+        expand_env_vars(text)
+    } else {
+        text.to_string()
+    }
+}
+
+/// Expand `%VAR%` references in a path if `--expand-env` is enabled,
+/// then make the result absolute, since `shawl add` can't do that before expansion.
+/// A path that doesn't exist yet is only expanded.
+fn expand_path(opts: &cli::CommonOpts, path: &str) -> String {
+    let expanded = expand(opts, path);
+    if expanded != path {
+        if let Ok(canonical) = std::fs::canonicalize(&expanded) {
+            return crate::simplify_path(&canonical.to_string_lossy());
+        }
+    }
+    crate::simplify_path(&expanded)
+}
+
+/// Variables that are kept with `--clean-env` because Windows programs
+/// commonly fail without them.
+const CLEAN_ENV_VARS: &[&str] = &["SystemRoot", "SystemDrive"];
+
+/// Select the variables from Shawl's environment that `--inherit-env` keeps with `--clean-env`.
+fn inherited_vars(patterns: &[String], vars: impl Iterator<Item = (String, String)>) -> Vec<(String, String)> {
+    vars.filter(|(key, _)| patterns.iter().any(|x| crate::env::matches_wildcard(x, key)))
+        .collect()
+}
+
+/// Environment variables and working directory for a command launched for the service.
+pub struct LaunchEnvironment {
+    clear: bool,
+    unset: Vec<String>,
+    vars: Vec<(String, String)>,
+    path: Option<String>,
+    cwd: Option<String>,
+}
+
+impl LaunchEnvironment {
+    pub fn apply(&self, cmd: &mut std::process::Command) {
+        if self.clear {
+            cmd.env_clear();
+        }
+        for key in &self.unset {
+            cmd.env_remove(key);
+        }
+        cmd.envs(self.vars.iter().map(|(key, value)| (key, value)));
+        if let Some(path) = &self.path {
+            cmd.env("PATH", path);
+        }
+        if let Some(cwd) = &self.cwd {
+            cmd.current_dir(cwd);
+        }
+    }
+}
+
+/// Determine the environment variables, `PATH` additions, and working directory
+/// that every command launched for the service should receive.
+pub fn prepare_environment(cwd: &Option<String>, opts: &cli::CommonOpts) -> std::io::Result<LaunchEnvironment> {
+    let mut vars = vec![];
+    let mut path_env = std::env::var("PATH").ok();
+
+    if opts.clean_env {
+        path_env = None;
+        for key in CLEAN_ENV_VARS {
+            if let Ok(value) = std::env::var(key) {
+                vars.push((key.to_string(), value));
+            }
+        }
+        let inherited = inherited_vars(
+            &opts.inherit_env,
+            std::env::vars_os()
+                .map(|(key, value)| (key.to_string_lossy().to_string(), value.to_string_lossy().to_string())),
+        );
+        for (key, value) in inherited {
+            debug!("Inheriting environment variable {}", key);
+            if key.eq_ignore_ascii_case("PATH") {
+                path_env = Some(value);
+            } else {
+                vars.push((key, value));
+            }
+        }
+    }
+
+    if opts.refresh_env || opts.load_profile {
+        debug!("Refreshing environment from registry");
+        for (key, value) in crate::env::read_registry_environment()? {
+            if key.eq_ignore_ascii_case("PATH") {
+                path_env = Some(value);
+            } else {
+                vars.push((key, value));
+            }
+        }
+    }
+    let mut unset = vec![];
+    if !opts.unset_env.is_empty() {
+        let should_unset = |key: &str| opts.unset_env.iter().any(|x| crate::env::matches_wildcard(x, key));
+        if !opts.clean_env {
+            unset.extend(std::env::vars_os().filter_map(|(key, _)| {
+                let key = key.to_string_lossy().to_string();
+                should_unset(&key).then_some(key)
+            }));
+        }
+        vars.retain(|(key, _)| !should_unset(key));
+        if should_unset("PATH") {
+            path_env = None;
+        }
+        for key in &unset {
+            debug!("Removing inherited environment variable {}", key);
+        }
+    }
+    if let Some(env_file) = &opts.env_file {
+        let env_file = expand(opts, env_file);
+        debug!("Loading environment from {}", env_file);
+        for (key, value) in crate::env::read_env_file(&env_file)? {
+            let value = expand(opts, &value);
+            vars.push((key, value));
+        }
+    }
+    for (key, value) in &opts.env {
+        vars.push((key.clone(), expand(opts, value)));
+    }
+    for (key, credential) in &opts.env_secret {
+        debug!("Loading {} from credential {}", key, credential);
+        match crate::env::read_credential(credential) {
+            Ok(secret) => vars.push((key.clone(), secret)),
+            Err(e) => {
+                error!("Unable to read credential {}: {}", credential, e);
+                return Err(e);
+            }
+        }
+    }
+    if !opts.path.is_empty() {
+        let simplified: Vec<_> = opts.path.iter().map(|x| expand_path(opts, x)).collect();
+        path_env = match path_env {
+            Some(path) => Some(format!("{};{}", path, simplified.join(";"))),
+            None => Some(simplified.join(";").to_string()),
+        };
+    }
+    if !opts.path_prepend.is_empty() {
+        let simplified: Vec<_> = opts.path_prepend.iter().map(|x| expand_path(opts, x)).collect();
+        path_env = match path_env {
+            Some(path) => Some(format!("{};{}", simplified.join(";"), path)),
+            None => Some(simplified.join(";").to_string()),
+        };
+    }
+    let cwd = match cwd {
+        Some(cwd) => {
+            let expanded = expand(opts, cwd);
+            if opts.cwd_create && !std::path::Path::new(&expanded).exists() {
+                info!("Creating working directory {}", expanded);
+                std::fs::create_dir_all(&expanded)?;
+            }
+            // Resolved after creating it so that a new directory is made absolute too.
+            Some(expand_path(opts, cwd))
+        }
+        None => None,
+    };
+    if let Some(active_cwd) = &cwd {
+        path_env = match path_env {
+            Some(path) => Some(format!("{};{}", path, active_cwd)),
+            None => Some(active_cwd.clone()),
+        };
+    }
+
+    Ok(LaunchEnvironment {
+        clear: opts.clean_env,
+        unset,
+        vars,
+        path: path_env,
+        cwd,
+    })
+}
+
+/// Build the command to launch, resolving the program and choosing how to run it.
+fn launch_command(
+    program: &str,
+    args: &[std::ffi::OsString],
+    environment: &LaunchEnvironment,
+    opts: &cli::CommonOpts,
+) -> std::process::Command {
+    if let Some(shell) = opts.shell {
+        return shell_command(shell, program, args);
+    }
+
+    let extensions = std::env::var("PATHEXT").unwrap_or_else(|_| ".COM;.EXE;.BAT;.CMD".to_string());
+    let program = match crate::env::resolve_program(
+        program,
+        environment.cwd.as_deref(),
+        environment.path.as_deref(),
+        &extensions,
+    ) {
+        Some(resolved) => {
+            info!("Resolved command to {}", resolved.display());
+            resolved.to_string_lossy().to_string()
+        }
+        None => {
+            info!("Unable to resolve command {}, so launching it as-is", program);
+            program.to_string()
+        }
+    };
+
+    let script = if opts.no_interpreter {
+        None
+    } else {
+        script_kind(&program)
+    };
+    match script {
+        Some(kind) => {
+            debug!("Launching {:?} script through its interpreter", kind);
+            script_command(kind, &program, args, opts.command_line.is_some())
+        }
+        None => {
+            let mut cmd = std::process::Command::new(&program);
+            add_args(&mut cmd, args, opts.command_line.is_some());
+            cmd
+        }
+    }
+}
+
+fn priority_flags(opts: &cli::CommonOpts) -> u32 {
+    match opts.priority {
+        Some(x) => x.to_windows().0,
+        None => windows::Win32::System::Threading::INHERIT_CALLER_PRIORITY.0,
+    }
+}
+
+/// Whether to launch the command suspended until `Killer::new` has placed it into a job object.
+fn launch_suspended(opts: &cli::CommonOpts) -> bool {
+    opts.kill_method.unwrap_or_default() == cli::KillMethod::Job
+}
+
+/// Launch the command, or an additional copy of it for `--instances`, with the prepared environment.
+/// With `--kill-method job`, it's launched suspended and must be passed to `Killer::new`.
+fn spawn_command(
+    program: &str,
+    args: &[std::ffi::OsString],
+    environment: &LaunchEnvironment,
+    opts: &cli::CommonOpts,
+    instance: Option<u32>,
+    pipe_stdin: bool,
+    creation_flags: u32,
+) -> std::io::Result<CommandProcess> {
+    let should_log_cmd = !opts.no_log_cmd;
+    let output = if should_log_cmd {
+        std::process::Stdio::piped
+    } else {
+        std::process::Stdio::null
+    };
+    let mut creation_flags = priority_flags(opts) | creation_flags;
+    if launch_suspended(opts) {
+        creation_flags |= windows::Win32::System::Threading::CREATE_SUSPENDED.0;
+    }
+
+    let mut cmd = launch_command(program, args, environment, opts);
+    cmd.creation_flags(creation_flags).stdout(output()).stderr(output());
+    if pipe_stdin {
+        cmd.stdin(std::process::Stdio::piped());
+    }
+    environment.apply(&mut cmd);
+    if let Some(instance) = instance {
+        cmd.env("SHAWL_INSTANCE", instance.to_string());
+    }
+    if opts.interactive_session || !opts.mitigation.is_empty() {
+        let options = LaunchOptions {
+            creation_flags,
+            inherit_env: !environment.clear,
+            session: opts.interactive_session,
+            mitigations: opts.mitigation.clone(),
+            pipe_stdin,
+            pipe_output: should_log_cmd,
+        };
+        RawProcess::spawn(&cmd, &options).map(CommandProcess::Raw)
+    } else {
+        cmd.spawn().map(CommandProcess::Child)
+    }
+}
+
+/// An additional copy of the command for `--instances`, supervised on its own thread.
+/// It runs in its own process group so that it can be stopped separately from the main instance.
+struct Instance {
+    process: CommandProcess,
+    killer: Killer,
+    stop_method: cli::StopMethod,
+    status: std::sync::Arc<std::sync::Mutex<crate::pipe::Status>>,
+}
+
+impl Instance {
+    fn spawn(
+        instance: u32,
+        program: &str,
+        args: &[std::ffi::OsString],
+        cwd: &Option<String>,
+        opts: &cli::CommonOpts,
+        status: std::sync::Arc<std::sync::Mutex<crate::pipe::Status>>,
+    ) -> std::io::Result<Self> {
+        let environment = prepare_environment(cwd, opts)?;
+        info!("Launching instance {}", instance);
+        let mut process = spawn_command(
+            program,
+            args,
+            &environment,
+            opts,
+            Some(instance),
+            false,
+            windows::Win32::System::Threading::CREATE_NEW_PROCESS_GROUP.0,
+        )
+        .inspect_err(|_| update_status(&status, |x| x.instances.failures += 1))?;
+        let killer = Killer::new(
+            opts.kill_method.unwrap_or_default(),
+            &mut process,
+            launch_suspended(opts),
+        );
+        log_output(
+            format!("instance {}", instance),
+            process.take_stdout(),
+            process.take_stderr(),
+        );
+        update_status(&status, |x| {
+            if x.instances.launches >= x.instances.configured {
+                x.instances.restarts += 1;
+            }
+            x.instances.launches += 1;
+            x.instances.running += 1;
+        });
+        Ok(Self {
+            process,
+            killer,
+            stop_method: opts.stop_method.unwrap_or_default(),
+            status,
+        })
+    }
+}
+
+impl crate::sidecar::Supervised for Instance {
+    fn try_wait(&mut self) -> std::io::Result<Option<std::process::ExitStatus>> {
+        let result = self.process.try_wait();
+        if !matches!(result, Ok(None)) {
+            update_status(&self.status, |x| {
+                x.instances.running = x.instances.running.saturating_sub(1)
+            });
+        }
+        result
+    }
+
+    fn stop(&mut self, label: &str, stop_timeout: u64) {
+        if self.stop_method == cli::StopMethod::Kill || !self.process.shares_console() {
+            info!("Killing {}", label);
+            self.killer.kill(&mut self.process);
+        } else {
+            let killer = &self.killer;
+            crate::sidecar::stop(label, &mut self.process, stop_timeout, |process| killer.kill(process));
+        }
+        update_status(&self.status, |x| {
+            x.instances.running = x.instances.running.saturating_sub(1)
+        });
+    }
+
+    fn set_suspended(&mut self, suspended: bool) {
+        if let Err(e) = set_process_suspended(self.process.id(), suspended) {
+            error!("Unable to change suspension of instance: {:?}", e);
+        }
+    }
+}
+
+/// Writes to the command's stdin in order on a separate thread,
+/// so that a command that isn't reading its stdin can't hold up the service.
+/// The stdin is closed once this is dropped and everything has been written.
+struct StdinWriter(std::sync::mpsc::Sender<Vec<u8>>);
+
+impl StdinWriter {
+    fn new(mut stdin: std::process::ChildStdin) -> Self {
+        let (sender, receiver) = std::sync::mpsc::channel::<Vec<u8>>();
+        std::thread::spawn(move || {
+            for content in receiver {
+                if let Err(e) = stdin.write_all(&content).and_then(|_| stdin.flush()) {
+                    error!("Unable to write to command's stdin: {:?}", e);
+                    break;
+                }
+            }
+        });
+        Self(sender)
+    }
+
+    /// Queue the content, returning false if the stdin has already been closed.
+    fn write(&self, content: Vec<u8>) -> bool {
+        self.0.send(content).is_ok()
+    }
+}
+
+/// Write the content from `--stdin-file` or `--stdin-text` to the command's stdin.
+/// If the stdin is needed afterward, the writer is returned;
+/// otherwise, the stdin is closed once the content is written.
+fn provide_stdin(stdin: std::process::ChildStdin, opts: &cli::CommonOpts, keep_stdin: bool) -> Option<StdinWriter> {
+    let content = match (&opts.stdin_file, &opts.stdin_text) {
+        (Some(file), _) => match std::fs::read(expand(opts, file)) {
+            Ok(content) => Some(content),
+            Err(e) => {
+                error!("Unable to read stdin file {}: {}", file, e);
+                Some(vec![])
+            }
+        },
+        (None, Some(text)) => Some(format!("{}\r\n", text).into_bytes()),
+        (None, None) => None,
+    };
+
+    let writer = StdinWriter::new(stdin);
+    if let Some(content) = content {
+        writer.write(content);
+    }
+    keep_stdin.then_some(writer)
+}
+
+/// Log each non-empty line from a command's output in the background.
+pub fn log_output(
+    label: String,
+    stdout: Option<std::process::ChildStdout>,
+    stderr: Option<std::process::ChildStderr>,
+) -> OutputCapture {
+    OutputCapture::start(
+        stdout,
+        stderr,
+        crate::output::CaptureOptions::default(),
+        move |stream, line| debug!(target: crate::output::OUTPUT_TARGET, stream = stream.name(), line = line; "{} {}: {:?}", label, stream.name(), line),
+    )
+}
+
+/// Run an initialization command to completion, reporting start progress while waiting.
+fn run_init_command(
+    index: usize,
+    command_line: &str,
+    cwd: &Option<String>,
+    opts: &cli::CommonOpts,
+    status_handle: &StatusHandle,
+    checkpoint: &mut u32,
+) -> std::io::Result<Option<i32>> {
+    let mut parts = crate::control::split_command_line(&expand(opts, command_line)).into_iter();
+    let mut cmd = std::process::Command::new(parts.next().unwrap_or_default());
+    let output = if opts.no_log_cmd {
+        std::process::Stdio::null
+    } else {
+        std::process::Stdio::piped
+    };
+    cmd.args(parts).stdout(output()).stderr(output());
+    prepare_environment(cwd, opts)?.apply(&mut cmd);
+
+    info!("Running init command {}: {}", index, command_line);
+    let mut child = cmd.spawn()?;
+    let output = log_output(format!("init {}", index), child.stdout.take(), child.stderr.take());
+
+    let mut last_report = std::time::Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+        if last_report.elapsed() >= std::time::Duration::from_secs(1) {
+            last_report = std::time::Instant::now();
+            *checkpoint += 1;
+            if let Err(e) = set_pending(
+                status_handle,
+                ServiceState::StartPending,
+                *checkpoint,
+                std::time::Duration::from_secs(3),
+            ) {
+                error!("Unable to report start progress: {:?}", e);
+            }
+        }
+        std::thread::sleep(std::time::Duration::from_millis(100));
+    };
+
+    output.finish();
+
+    info!("Init command {} exited with code {:?}", index, status.code());
+    Ok(status.code())
+}
+
+/// Poll a start condition with backoff until it is met, reporting start progress while waiting.
+/// Returns false if the timeout expires first.
+fn wait_for_start_condition(
+    label: &str,
+    timeout: Option<std::time::Duration>,
+    status_handle: &StatusHandle,
+    checkpoint: &mut u32,
+    mut check: impl FnMut() -> bool,
+) -> bool {
+    let start_time = std::time::Instant::now();
+    let mut interval = std::time::Duration::from_millis(250);
+    let mut last_report = start_time;
+
+    info!("Waiting for {}", label);
+    loop {
+        if check() {
+            info!(
+                "Finished waiting for {} after {} ms",
+                label,
+                start_time.elapsed().as_millis()
+            );
+            return true;
+        }
+        debug!("Still waiting for {}", label);
+
+        let next_check = std::time::Instant::now() + interval;
+        while std::time::Instant::now() < next_check {
+            if timeout.is_some_and(|timeout| start_time.elapsed() >= timeout) {
+                error!("Timed out waiting for {}", label);
+                return false;
+            }
+            if last_report.elapsed() >= std::time::Duration::from_secs(1) {
+                last_report = std::time::Instant::now();
+                *checkpoint += 1;
+                if let Err(e) = set_pending(
+                    status_handle,
+                    ServiceState::StartPending,
+                    *checkpoint,
+                    std::time::Duration::from_secs(3),
+                ) {
+                    error!("Unable to report start progress: {:?}", e);
+                }
+            }
+            std::thread::sleep(std::time::Duration::from_millis(100));
+        }
+        interval = (interval * 2).min(std::time::Duration::from_secs(5));
+    }
+}
+
+/// Check whether there is a default route, without sending any traffic.
+fn network_is_available() -> bool {
+    [("0.0.0.0:0", "8.8.8.8:53"), ("[::]:0", "[2001:4860:4860::8888]:53")]
+        .iter()
+        .any(|(local, remote)| {
+            std::net::UdpSocket::bind(local)
+                .and_then(|socket| socket.connect(remote))
+                .is_ok()
+        })
+}
+
+fn path_is_available(path: &str) -> bool {
+    std::fs::metadata(path).is_ok()
+}
+
+/// Check whether all of the named services are running.
+fn services_are_running(names: &[String]) -> bool {
+    names
+        .iter()
+        .all(|name| match crate::control::query_service_state(name) {
+            Ok(state) => state == ServiceState::Running,
+            Err(e) => {
+                debug!("Unable to query state of service {}: {:?}", name, e);
+                false
+            }
+        })
+}
+
+/// Re-read Shawl's options from the installed service's command line.
+fn read_installed_options(service_name: &str) -> Result<(Option<String>, cli::CommonOpts), Box<dyn std::error::Error>> {
+    let args = crate::control::read_service_command_line(service_name)?;
+    let args = crate::argsfile::expand(args.into_iter().map(Into::into).collect())?;
+    let args = crate::defaults::apply(args, &crate::defaults::load()?)?;
+    match cli::Cli::try_parse_from(args)?.sub {
+        cli::Subcommand::Run { cwd, common, .. } => Ok((cwd, common)),
+        _ => Err("The service command line does not use `shawl run`".into()),
+    }
+}
+
+fn reload_options(service_name: &str, cwd: &mut Option<String>, opts: &mut cli::CommonOpts) {
+    match read_installed_options(service_name) {
+        Ok((new_cwd, mut new_opts)) => {
+            let placeholders = launch_placeholders(service_name, &new_cwd, &new_opts);
+            new_opts.fill_placeholders(&placeholders);
+            info!("Reloaded service parameters; some changes will only apply to the next launch");
+            *cwd = new_cwd;
+            apply_reloaded_options(opts, new_opts);
+        }
+        Err(e) => error!("Unable to reload service parameters: {:?}", e),
+    }
+}
+
+/// Apply the options that can safely change while the service is running.
+/// Anything else, such as the command or logging, requires a service restart.
+fn apply_reloaded_options(opts: &mut cli::CommonOpts, new: cli::CommonOpts) {
+    opts.pass = new.pass;
+    opts.restart = new.restart;
+    opts.no_restart = new.no_restart;
+    opts.restart_if = new.restart_if;
+    opts.restart_if_not = new.restart_if_not;
+    opts.stop_if = new.stop_if;
+    opts.restart_delay = new.restart_delay;
+    opts.restart_every = new.restart_every;
+    opts.interval = new.interval;
+    opts.spawn_retries = new.spawn_retries;
+    opts.restart_on_update = new.restart_on_update;
+    opts.watch = new.watch;
+    opts.watch_debounce = new.watch_debounce;
+    opts.stop_timeout = new.stop_timeout;
+    opts.preshutdown_timeout = new.preshutdown_timeout;
+    opts.stop_delay = new.stop_delay;
+    opts.stop_method = new.stop_method;
+    opts.kill_method = new.kill_method;
+    opts.shell = new.shell;
+    opts.no_interpreter = new.no_interpreter;
+    opts.interactive_session = new.interactive_session;
+    opts.mitigation = new.mitigation;
+    opts.crash_dump_dir = new.crash_dump_dir;
+    opts.clean_env = new.clean_env;
+    opts.inherit_env = new.inherit_env;
+    opts.unset_env = new.unset_env;
+    opts.stdin_file = new.stdin_file;
+    opts.stdin_text = new.stdin_text;
+    opts.env = new.env;
+    opts.path = new.path;
+    opts.path_prepend = new.path_prepend;
+    opts.priority = new.priority;
+    opts.on_resume = new.on_resume;
+    opts.on_session_change = new.on_session_change;
+    opts.log_stdout_level = new.log_stdout_level;
+    opts.echo_output = new.echo_output;
+    opts.log_stderr_level = new.log_stderr_level;
+    opts.log_filter_include = new.log_filter_include;
+    opts.log_filter_exclude = new.log_filter_exclude;
+    opts.log_rate_limit = new.log_rate_limit;
+    opts.log_rate_interval = new.log_rate_interval;
+    opts.log_collapse_repeats = new.log_collapse_repeats;
+    opts.log_group_indented = new.log_group_indented;
+    opts.log_group_pattern = new.log_group_pattern;
+    opts.cmd_encoding = new.cmd_encoding;
+    opts.capture_raw = new.capture_raw;
+    opts.capture_raw_rotate = new.capture_raw_rotate;
+}
+
+/// Tracks how long the command has run and how often it was restarted,
+/// so that each exit and the service stop can be logged with a summary.
+struct RunStats {
+    started_at: std::time::Instant,
+    launches: u32,
+    total_uptime: std::time::Duration,
+    running_since: Option<std::time::Instant>,
+}
+
+impl RunStats {
+    fn new(started_at: std::time::Instant) -> Self {
+        Self {
+            started_at,
+            launches: 0,
+            total_uptime: std::time::Duration::ZERO,
+            running_since: None,
+        }
+    }
+
+    fn launched(&mut self, at: std::time::Instant) {
+        self.launches += 1;
+        self.running_since = Some(at);
+    }
+
+    fn restarts(&self) -> u32 {
+        self.launches.saturating_sub(1)
+    }
+
+    /// Record that the command is no longer running and return how long it ran.
+    /// Returns nothing if the exit was already recorded.
+    fn exited(&mut self, at: std::time::Instant) -> Option<std::time::Duration> {
+        let uptime = at.saturating_duration_since(self.running_since.take()?);
+        self.total_uptime += uptime;
+        Some(uptime)
+    }
+
+    /// How long the service itself has been running.
+    fn service_uptime(&self, at: std::time::Instant) -> std::time::Duration {
+        at.saturating_duration_since(self.started_at)
+    }
+}
+
+/// Run the `--hook-script` and `--hook-exec` for an event while the command is not running,
+/// returning the script's decision about restarting the command.
+/// Once the service is `stopping`, the hooks are killed so that the stop is handled right away.
+fn run_hook(
+    opts: &cli::CommonOpts,
+    service_name: &str,
+    event: crate::hook::Event,
+    exit_code: Option<i32>,
+    stats: &RunStats,
+    status: &std::sync::Mutex<crate::pipe::Status>,
+    stopping: &std::sync::atomic::AtomicBool,
+) -> Option<crate::hook::Decision> {
+    let hooks = crate::hook::Hooks::new(opts)?;
+    if stopping.load(std::sync::atomic::Ordering::SeqCst) {
+        return None;
+    }
+    let launched_at = status
+        .lock()
+        .map(|x| crate::hook::Context::from_status(&x).launched_at)
+        .unwrap_or_default();
+    let context = crate::hook::Context {
+        exit_code,
+        launches: stats.launches,
+        restarts: stats.restarts(),
+        state: "waiting",
+        previous_state: None,
+        launched_at,
+    };
+    let decision = hooks.run(service_name, event, &context, stopping);
+    if let Some(decision) = decision {
+        info!("Hook script decided: {:?}", decision);
+    }
+    decision
+}
+
+/// Update what the control pipe reports about the service.
+fn update_status(status: &std::sync::Mutex<crate::pipe::Status>, update: impl FnOnce(&mut crate::pipe::Status)) {
+    if let Ok(mut status) = status.lock() {
+        update(&mut status);
+    }
+}
+
+/// Log the summary of the command's run if it hasn't been already.
+fn log_exit_summary(stats: &mut RunStats, exit: &str) {
+    if let Some(uptime) = stats.exited(std::time::Instant::now()) {
+        info!(
+            uptime_ms = uptime.as_millis(),
+            exit = exit,
+            restarts = stats.restarts(),
+            total_uptime_ms = stats.total_uptime.as_millis();
+            "Command run summary"
+        );
+    }
+}
+
+/// Log the summary of the whole time that the service ran.
+fn log_service_summary(stats: &RunStats) {
+    info!(
+        service_uptime_ms = stats.service_uptime(std::time::Instant::now()).as_millis(),
+        launches = stats.launches,
+        restarts = stats.restarts(),
+        total_uptime_ms = stats.total_uptime.as_millis();
+        "Service run summary"
+    );
+}
+
+/// How the service is being run.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Mode {
+    /// Under the service manager.
+    Service,
+    /// In the current console until Ctrl-C, for `run --foreground`.
+    Foreground,
+    /// In the current console until the duration passes, for `shawl test`.
+    Test(std::time::Duration),
+}
+
+/// The `run` options from the command line: the name, `--cwd`, `--foreground`, and the rest.
+fn run_options() -> Option<(String, Option<String>, bool, cli::CommonOpts)> {
+    match cli::evaluate_cli().sub {
+        cli::Subcommand::Run {
+            name,
+            cwd,
+            foreground,
+            common: opts,
+        } => Some((name, cwd, foreground, opts)),
+        _ => None,
+    }
+}
+
+pub fn run_service(
+    service_name: Option<String>,
+    start_arguments: Vec<std::ffi::OsString>,
+) -> windows_service::Result<()> {
+    let Some((name, cwd, foreground, opts)) = run_options() else {
+        // Can't get here.
+        return Ok(());
+    };
+    let service_name = service_name.unwrap_or_else(|| name.clone());
+    let mode = if foreground { Mode::Foreground } else { Mode::Service };
+    run_configured(
+        name,
+        service_name,
+        cwd,
+        opts,
+        mode,
+        start_arguments,
+        std::sync::Arc::default(),
+    )
+    .map(|_| ())
+}
+
+#[allow(clippy::cognitive_complexity)]
+fn run_configured(
+    name: String,
+    service_name: String,
+    mut cwd: Option<String>,
+    mut opts: cli::CommonOpts,
+    mode: Mode,
+    start_arguments: Vec<std::ffi::OsString>,
+    status: std::sync::Arc<std::sync::Mutex<crate::pipe::Status>>,
+) -> windows_service::Result<ServiceExitCode> {
+    let (event_tx, event_rx) = std::sync::mpsc::channel();
+    let signal = std::sync::Arc::new(EventSignal::new().expect("Unable to create service event signal"));
+    let handler_signal = signal.clone();
+    let foreground = mode != Mode::Service;
+    opts.fill_placeholders(&launch_placeholders(&service_name, &cwd, &opts));
+    // In the foreground, the command shares the console that Shawl is already using.
+    let allocate_console = !foreground && needs_console(&opts);
+    if allocate_console {
+        ensure_console(&opts);
+    }
+    if opts.event_log || opts.log_cmd_to.is_some() {
+        if let Err(e) = eventlog::open(&service_name) {
+            error!("Unable to open the event log: {:?}", e);
+        }
+    }
+    crate::notify::open(&service_name, opts.notify_slack.clone(), opts.notify_teams.clone());
+    crate::otlp::open(&service_name, opts.otlp_endpoint.clone(), opts.otlp_output);
+    crate::eventfile::open(&service_name, opts.event_file.as_deref());
+    let heartbeat = crate::heartbeat::Heartbeat::start(
+        opts.heartbeat_url.clone(),
+        opts.heartbeat_interval.unwrap_or(std::time::Duration::from_secs(60)),
+    );
+    // Kept until the service stops so that the profile stays loaded.
+    let _profile = if opts.load_profile {
+        match crate::env::load_user_profile() {
+            Ok(profile) => Some(profile),
+            Err(e) => {
+                error!("Unable to load user profile: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+    // Read after loading the profile, since the credentials may be stored in it.
+    for credential in &opts.log_redact_secret {
+        match crate::env::read_credential(credential) {
+            Ok(secret) => crate::logformat::redact_secret(&secret),
+            Err(e) => error!(
+                "Unable to read credential {}, so its secret will not be redacted: {}",
+                credential, e
+            ),
+        }
+    }
+    let mut pass = opts.pass.clone().unwrap_or_else(|| vec![0.into()]);
+    let mut stop_timeout = opts.stop_timeout.unwrap_or(3000_u64);
+    let mut service_exit_code = ServiceExitCode::NO_ERROR;
+
+    // Set along with queueing a stop, so that hooks can be killed without waiting for the main loop.
+    let stopping = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let ctrl_events = std::sync::Arc::new(OwnCtrlEvents::new().expect("Unable to create ctrl-C event signal"));
+    let handler_ctrl_events = ctrl_events.clone();
+    let ctrlc_tx = std::sync::Mutex::new(foreground.then(|| event_tx.clone()));
+    let ctrlc_signal = signal.clone();
+    let ctrlc_stopping = stopping.clone();
+    ctrlc::set_handler(move || {
+        if handler_ctrl_events.receive() {
+            return;
+        }
+        // In the foreground, the first Ctrl-C stops the service, and another one aborts.
+        match ctrlc_tx.lock().ok().and_then(|mut x| x.take()) {
+            Some(tx) => {
+                info!("Received Ctrl-C");
+                ctrlc_stopping.store(true, std::sync::atomic::Ordering::SeqCst);
+                let _ = tx.send(ServiceEvent::Stop);
+                ctrlc_signal.set();
+            }
+            None => std::process::abort(),
+        }
+    })
+    .expect("Unable to create ctrl-C handler");
+
+    let control_codes: Vec<u32> = opts.on_control.iter().map(|(code, _)| *code).collect();
+    let pipe_tx = event_tx.clone();
+    let pipe_signal = signal.clone();
+    let handler_stopping = stopping.clone();
+    let event_handler = move |control_event| -> ServiceControlHandlerResult {
+        let send = |event| {
+            if matches!(event, ServiceEvent::Stop | ServiceEvent::Preshutdown) {
+                handler_stopping.store(true, std::sync::atomic::Ordering::SeqCst);
+            }
+            event_tx.send(event).unwrap();
+            handler_signal.set();
+        };
+        match control_event {
+            ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+            ServiceControl::Stop => {
+                info!("Received stop event");
+                send(ServiceEvent::Stop);
+                ServiceControlHandlerResult::NoError
+            }
+            ServiceControl::Shutdown => {
+                info!("Received shutdown event");
+                send(ServiceEvent::Stop);
+                ServiceControlHandlerResult::NoError
+            }
+            ServiceControl::Preshutdown => {
+                info!("Received preshutdown event");
+                send(ServiceEvent::Preshutdown);
+                ServiceControlHandlerResult::NoError
+            }
+            ServiceControl::PowerEvent(PowerEventParam::ResumeAutomatic) => {
+                info!("Received power event: system resumed");
+                send(ServiceEvent::Resume);
+                ServiceControlHandlerResult::NoError
+            }
+            ServiceControl::PowerEvent(_) => ServiceControlHandlerResult::NoError,
+            ServiceControl::SessionChange(param) => {
+                let reason = describe_session_change(param.reason);
+                let session_id = param.notification.session_id;
+                info!("Received session change event: {} (session {})", reason, session_id);
+                send(ServiceEvent::SessionChange { reason, session_id });
+                ServiceControlHandlerResult::NoError
+            }
+            ServiceControl::UserEvent(code) if control_codes.contains(&code.to_raw()) => {
+                info!("Received custom control event: {}", code.to_raw());
+                send(ServiceEvent::Control(code.to_raw()));
+                ServiceControlHandlerResult::NoError
+            }
+            ServiceControl::ParamChange => {
+                info!("Received parameter change event");
+                send(ServiceEvent::ParamChange);
+                ServiceControlHandlerResult::NoError
+            }
+            ServiceControl::Pause => {
+                info!("Received pause event");
+                send(ServiceEvent::Pause);
+                ServiceControlHandlerResult::NoError
+            }
+            ServiceControl::Continue => {
+                info!("Received continue event");
+                send(ServiceEvent::Continue);
+                ServiceControlHandlerResult::NoError
+            }
+            _ => ServiceControlHandlerResult::NotImplemented,
+        }
+    };
+
+    let status_handle = if foreground {
+        if let Mode::Test(duration) = mode {
+            info!("Testing for {} ms; press Ctrl-C to stop sooner", duration.as_millis());
+            let tx = pipe_tx.clone();
+            let signal = signal.clone();
+            let stopping = stopping.clone();
+            std::thread::spawn(move || {
+                std::thread::sleep(duration);
+                info!("Finished testing, so stopping");
+                stopping.store(true, std::sync::atomic::Ordering::SeqCst);
+                if tx.send(ServiceEvent::Stop).is_ok() {
+                    signal.set();
+                }
+            });
+        } else {
+            info!("Running in the foreground; press Ctrl-C to stop");
+        }
+        StatusHandle::Foreground
+    } else {
+        StatusHandle::Service(service_control_handler::register(name, event_handler)?)
+    };
+    let mut controls_accepted = accepted_controls(&opts);
+
+    let mut start_checkpoint = 0;
+    if let Some(timeout) = opts.wait_for_network {
+        if !wait_for_start_condition(
+            "network",
+            timeout,
+            &status_handle,
+            &mut start_checkpoint,
+            network_is_available,
+        ) {
+            return set_stopped(
+                &status_handle,
+                ServiceExitCode::Win32(windows::Win32::Foundation::ERROR_TIMEOUT.0),
+            );
+        }
+    }
+    for name in &opts.wait_for_service {
+        if !wait_for_start_condition(
+            &format!("service {}", name),
+            opts.wait_for_service_timeout,
+            &status_handle,
+            &mut start_checkpoint,
+            || services_are_running(std::slice::from_ref(name)),
+        ) {
+            return set_stopped(
+                &status_handle,
+                ServiceExitCode::Win32(windows::Win32::Foundation::ERROR_TIMEOUT.0),
+            );
+        }
+    }
+    for name in &opts.bind_to_service {
+        wait_for_start_condition(
+            &format!("service {}", name),
+            None,
+            &status_handle,
+            &mut start_checkpoint,
+            || services_are_running(std::slice::from_ref(name)),
+        );
+    }
+    for path in &opts.wait_for_path {
+        let path = expand(&opts, path);
+        if !wait_for_start_condition(
+            &format!("path {}", path),
+            opts.wait_for_path_timeout,
+            &status_handle,
+            &mut start_checkpoint,
+            || path_is_available(&path),
+        ) {
+            return set_stopped(
+                &status_handle,
+                ServiceExitCode::Win32(windows::Win32::Foundation::ERROR_TIMEOUT.0),
+            );
+        }
+    }
+
+    let pid_file = opts.on_duplicate.and_then(|_| pid_file_path(&service_name, &opts));
+    let mut adopted = None;
+    if let (Some(action), Some(pid_file)) = (opts.on_duplicate, &pid_file) {
+        if let Some((pid, handle)) = find_previous_command(pid_file) {
+            match action {
+                cli::DuplicateAction::Adopt => {
+                    info!(
+                        "Adopting command from a previous run, which is still running as process {}",
+                        pid
+                    );
+                    adopted = Some(CommandProcess::Raw(RawProcess::adopt(handle, pid)));
+                }
+                cli::DuplicateAction::Kill => {
+                    info!(
+                        "Killing command from a previous run, which is still running as process {}",
+                        pid
+                    );
+                    let handle =
+                        windows::Win32::Foundation::HANDLE(std::os::windows::io::AsRawHandle::as_raw_handle(&handle));
+                    if let Err(e) = unsafe { windows::Win32::System::Threading::TerminateProcess(handle, 1) } {
+                        error!("Unable to kill process {}: {:?}", pid, e);
+                    }
+                }
+                cli::DuplicateAction::Refuse => {
+                    error!(
+                        "Refusing to start because the command from a previous run is still running as process {}",
+                        pid
+                    );
+                    return set_stopped(
+                        &status_handle,
+                        ServiceExitCode::Win32(windows::Win32::Foundation::ERROR_SERVICE_ALREADY_RUNNING.0),
+                    );
+                }
+            }
+        }
+    }
+
+    for (index, command_line) in opts.init.iter().enumerate() {
+        match run_init_command(
+            index + 1,
+            command_line,
+            &cwd,
+            &opts,
+            &status_handle,
+            &mut start_checkpoint,
+        ) {
+            Ok(Some(0)) => (),
+            Ok(Some(code)) => {
+                error!(
+                    "Init command {} failed with code {}",
+                    index + 1,
+                    crate::exitcode::describe(code)
+                );
+                return set_stopped(&status_handle, command_exit_code(code));
+            }
+            Ok(None) => {
+                error!("Init command {} was terminated by a signal", index + 1);
+                return set_stopped(
+                    &status_handle,
+                    ServiceExitCode::Win32(windows::Win32::Foundation::ERROR_PROCESS_ABORTED.0),
+                );
+            }
+            Err(e) => {
+                error!("Unable to run init command {}: {}", index + 1, e);
+                return set_stopped(&status_handle, launch_error_exit_code(&e));
+            }
+        }
+    }
+
+    // Sidecars must all launch before the service is considered running.
+    let mut sidecars = vec![];
+    for (index, sidecar) in opts.sidecar.iter().enumerate() {
+        let sidecar = cli::Sidecar {
+            restart: sidecar.restart,
+            command: expand(&opts, &sidecar.command),
+        };
+        match crate::sidecar::launch(index + 1, sidecar, &cwd, &opts, stop_timeout) {
+            Ok(handle) => sidecars.push(handle),
+            Err(e) => {
+                error!("Unable to launch sidecar {}: {}", index + 1, e);
+                for sidecar in sidecars.into_iter().rev() {
+                    sidecar.stop();
+                }
+                return set_stopped(&status_handle, launch_error_exit_code(&e));
+            }
+        }
+    }
+
+    let (program, mut args) = match &opts.command_line {
+        Some(command_line) => {
+            let (program, rest) = split_program(&expand(&opts, command_line));
+            let args = if rest.is_empty() { vec![] } else { vec![rest.into()] };
+            (program, args)
+        }
+        None => {
+            let mut command = opts.command.iter().map(|x| expand(&opts, x));
+            let program = command.next().unwrap();
+            (program, command.map(std::ffi::OsString::from).collect::<Vec<_>>())
+        }
+    };
+    if opts.pass_start_args {
+        args.extend(start_arguments);
+    }
+
+    // The main loop runs the first instance, while the others are supervised separately.
+    let mut instances = vec![];
+    update_status(&status, |x| x.instances.configured = opts.instances.unwrap_or(1) - 1);
+    for instance in 2..=opts.instances.unwrap_or(1) {
+        let (program, args, cwd, instance_opts) = (program.clone(), args.clone(), cwd.clone(), opts.clone());
+        let restart = opts.clone();
+        let (instance_status, exit_status) = (status.clone(), status.clone());
+        let handle = crate::sidecar::supervise(
+            format!("instance {}", instance),
+            move || Instance::spawn(instance, &program, &args, &cwd, &instance_opts, instance_status.clone()),
+            move |code| {
+                let failed = code.is_none_or(|code| {
+                    !cli::ExitCodeRange::any_contains(restart.pass.as_deref().unwrap_or(&[0.into()]), code)
+                });
+                if failed {
+                    update_status(&exit_status, |x| x.instances.failures += 1);
+                }
+                match code {
+                    Some(code) => {
+                        should_restart_exited_command(
+                            code,
+                            restart.restart,
+                            restart.no_restart,
+                            &restart.restart_if,
+                            &restart.restart_if_not,
+                        ) && !cli::ExitCodeRange::any_contains(&restart.stop_if, code)
+                    }
+                    None => should_restart_terminated_command(restart.restart, restart.no_restart),
+                }
+            },
+            opts.restart_delay,
+            stop_timeout,
+        );
+        match handle {
+            Ok(handle) => instances.push(handle),
+            Err(e) => {
+                error!("Unable to launch instance {}: {}", instance, e);
+                for handle in instances.into_iter().rev().chain(sidecars.into_iter().rev()) {
+                    handle.stop();
+                }
+                return set_stopped(&status_handle, launch_error_exit_code(&e));
+            }
+        }
     }
 
-    let priority = match opts.priority {
-        Some(x) => x.to_windows().0,
-        None => windows::Win32::System::Threading::INHERIT_CALLER_PRIORITY.0,
-    };
+    set_service_state(&status_handle, ServiceState::Running, controls_accepted)?;
 
     let mut restart_after: Option<std::time::Instant> = None;
+    let mut paused = false;
+    let mut waiting_for_bound_services = false;
+    let mut spawn_failures = 0;
+    let mut last_bound_service_check = std::time::Instant::now();
+    let mut stats = RunStats::new(std::time::Instant::now());
+    let mut restarts_paused = false;
+    let mut launch_requested = false;
+
+    update_status(&status, |x| x.service_started_at = Some(stats.started_at));
+    crate::pipe::start(&service_name, status.clone(), move |command| {
+        if pipe_tx.send(ServiceEvent::Pipe(command)).is_ok() {
+            pipe_signal.set();
+        }
+    });
+    if opts.perf_counters {
+        crate::perfcounters::start(&service_name, status.clone());
+    }
+    let status_file = opts
+        .status_file
+        .as_ref()
+        .map(|path| crate::statusfile::StatusFile::start(path.into(), &service_name, status.clone()));
+    let state_watcher = crate::hook::Hooks::new(&opts)
+        .map(|hooks| crate::hook::StateWatcher::start(hooks, &service_name, status.clone()));
 
     debug!("Entering main service loop");
     'outer: loop {
-        if let Some(delay) = restart_after {
-            match shutdown_rx.recv_timeout(std::time::Duration::from_millis(1)) {
-                Ok(_) | Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => {
+        let holding_restarts = restarts_paused && stats.launches > 0 && !launch_requested;
+        if restart_after.is_some() || paused || waiting_for_bound_services || holding_restarts {
+            let now = std::time::Instant::now();
+            let timeout = if paused {
+                None
+            } else {
+                [
+                    restart_after.map(|x| x.saturating_duration_since(now)),
+                    waiting_for_bound_services.then(|| {
+                        (last_bound_service_check + std::time::Duration::from_secs(1)).saturating_duration_since(now)
+                    }),
+                ]
+                .into_iter()
+                .flatten()
+                .min()
+            };
+            match next_event(&event_rx, &signal, None, timeout) {
+                Some(ServiceEvent::Stop | ServiceEvent::Preshutdown) => {
                     info!("Cancelling before launch");
                     break 'outer;
                 }
-                Err(std::sync::mpsc::RecvTimeoutError::Timeout) => (),
+                Some(ServiceEvent::Pause) => {
+                    paused = true;
+                    for instance in &instances {
+                        instance.set_paused(true);
+                    }
+                    update_status(&status, |x| x.paused = true);
+                    set_service_state(&status_handle, ServiceState::Paused, controls_accepted)?;
+                }
+                Some(ServiceEvent::Continue) => {
+                    paused = false;
+                    for instance in &instances {
+                        instance.set_paused(false);
+                    }
+                    update_status(&status, |x| x.paused = false);
+                    set_service_state(&status_handle, ServiceState::Running, controls_accepted)?;
+                }
+                Some(ServiceEvent::Resume) => (),
+                Some(ServiceEvent::ParamChange) => {
+                    reload_options(&service_name, &mut cwd, &mut opts);
+                    pass = opts.pass.clone().unwrap_or_else(|| vec![0.into()]);
+                    stop_timeout = opts.stop_timeout.unwrap_or(3000_u64);
+                    controls_accepted = accepted_controls(&opts);
+                    let state = if paused {
+                        ServiceState::Paused
+                    } else {
+                        ServiceState::Running
+                    };
+                    set_service_state(&status_handle, state, controls_accepted)?;
+                }
+                Some(ServiceEvent::SessionChange { reason, session_id }) => {
+                    if let Some(cli::SessionChangeAction::Run(command)) = &opts.on_session_change {
+                        run_session_change_command(command, reason, session_id);
+                    }
+                }
+                Some(ServiceEvent::Control(code)) => {
+                    for (_, action) in opts.on_control.iter().filter(|(x, _)| *x == code) {
+                        match action {
+                            cli::ControlAction::Run(command) => run_control_command(command, code),
+                            cli::ControlAction::LogLevel(level) => crate::set_log_level(*level),
+                            _ => info!("Ignoring control action while command is not running: {:?}", action),
+                        }
+                    }
+                }
+                Some(ServiceEvent::Pipe(command)) => match command {
+                    crate::pipe::Command::Restart if !paused => {
+                        info!("Launching command now for control pipe");
+                        restart_after = None;
+                        launch_requested = true;
+                    }
+                    crate::pipe::Command::Restart => info!("Ignoring restart while the service is paused"),
+                    crate::pipe::Command::LogLevel(level) => crate::set_log_level(level),
+                    crate::pipe::Command::PauseRestarts => {
+                        info!("Pausing restarts of the command");
+                        restarts_paused = true;
+                        update_status(&status, |x| x.restarts_paused = true);
+                    }
+                    crate::pipe::Command::ResumeRestarts => {
+                        info!("Resuming restarts of the command");
+                        restarts_paused = false;
+                        update_status(&status, |x| x.restarts_paused = false);
+                    }
+                },
+                None => (),
             };
 
-            let now = std::time::Instant::now();
-            if now < delay {
-                let step = (delay - now).min(std::time::Duration::from_millis(50));
-                debug!("Sleeping another {} ms", step.as_millis());
-                std::thread::sleep(step);
+            if paused {
+                continue;
+            }
+
+            if restarts_paused && stats.launches > 0 && !launch_requested {
+                // The command launches as soon as restarts are resumed.
+                restart_after = None;
+                continue;
+            }
+
+            if waiting_for_bound_services {
+                if last_bound_service_check.elapsed() < std::time::Duration::from_secs(1) {
+                    continue;
+                }
+                last_bound_service_check = std::time::Instant::now();
+                if !services_are_running(&opts.bind_to_service) {
+                    continue;
+                }
+                info!("Bound services are running again");
+                waiting_for_bound_services = false;
+            }
+        }
+
+        if let Some(delay) = restart_after {
+            if std::time::Instant::now() < delay {
                 continue;
             } else {
                 info!("Restart delay is complete");
@@ -165,190 +2603,620 @@ pub fn run_service(start_arguments: Vec<std::ffi::OsString>) -> windows_service:
             }
         }
 
+        run_hook(
+            &opts,
+            &service_name,
+            crate::hook::Event::PreStart,
+            None,
+            &stats,
+            &status,
+            &stopping,
+        );
         info!("Launching command");
-        let should_log_cmd = !&opts.no_log_cmd;
-        let mut child_cmd = std::process::Command::new(&program);
-        let mut path_env = std::env::var("PATH").ok();
-
-        child_cmd
-            .args(&args)
-            .creation_flags(priority)
-            .stdout(if should_log_cmd {
-                std::process::Stdio::piped()
-            } else {
-                std::process::Stdio::null()
-            })
-            .stderr(if should_log_cmd {
-                std::process::Stdio::piped()
-            } else {
-                std::process::Stdio::null()
-            });
-        for (key, value) in &opts.env {
-            child_cmd.env(key, value);
-        }
-        if !opts.path.is_empty() {
-            let simplified: Vec<_> = opts.path.iter().map(|x| crate::simplify_path(x)).collect();
-            path_env = match path_env {
-                Some(path) => Some(format!("{};{}", path, simplified.join(";"))),
-                None => Some(simplified.join(";").to_string()),
-            };
+        if allocate_console {
+            ensure_console(&opts);
         }
-        if !opts.path_prepend.is_empty() {
-            let simplified: Vec<_> = opts.path_prepend.iter().map(|x| crate::simplify_path(x)).collect();
-            path_env = match path_env {
-                Some(path) => Some(format!("{};{}", simplified.join(";"), path)),
-                None => Some(simplified.join(";").to_string()),
-            };
-        }
-        if let Some(active_cwd) = &cwd {
-            let active_cwd = crate::simplify_path(active_cwd);
-            child_cmd.current_dir(&active_cwd);
-            path_env = match path_env {
-                Some(path) => Some(format!("{};{}", path, active_cwd)),
-                None => Some(active_cwd),
-            };
+        let keep_stdin = opts
+            .on_control
+            .iter()
+            .any(|(_, action)| matches!(action, cli::ControlAction::Stdin(_)));
+        let needs_stdin = keep_stdin || opts.stdin_file.is_some() || opts.stdin_text.is_some();
+        let environment = prepare_environment(&cwd, &opts);
+        if let Err(e) = &environment {
+            error!("Unable to prepare command environment: {}", e);
         }
-        if let Some(path_env) = path_env {
-            child_cmd.env("PATH", path_env);
+        // Configured before the launch so that an early crash is caught too.
+        let crash_dump_dir = opts.crash_dump_dir.as_ref().map(|x| expand_path(&opts, x));
+        let mut crash_dumps = None;
+        if let (Some(dir), Ok(environment)) = (&crash_dump_dir, &environment) {
+            match crash_dump_image_name(&program, environment, &opts) {
+                Some(image_name) => {
+                    if let Err(e) = std::fs::create_dir_all(dir) {
+                        error!("Unable to create crash dump directory {}: {}", dir, e);
+                    }
+                    match CrashDumps::configure(&service_name, &image_name, dir) {
+                        Ok(dumps) => crash_dumps = Some((dumps, image_name)),
+                        Err(e) => error!("Unable to configure crash dumps for {}: {}", image_name, e),
+                    }
+                }
+                None => error!(
+                    "Crash dumps can only be collected for a command's own executable, not a shell or script interpreter"
+                ),
+            }
         }
+        let was_adopted = adopted.is_some();
+        let spawned = match adopted.take() {
+            Some(process) => Ok(process),
+            None => environment.and_then(|environment| {
+                spawn_command(
+                    &program,
+                    &args,
+                    &environment,
+                    &opts,
+                    opts.instances.map(|_| 1),
+                    needs_stdin,
+                    0,
+                )
+            }),
+        };
 
-        let mut child = match child_cmd.spawn() {
-            Ok(c) => c,
+        let mut child = match spawned {
+            Ok(c) => {
+                spawn_failures = 0;
+                c
+            }
             Err(e) => {
                 error!("Unable to launch command: {}", e);
-                service_exit_code = match e.raw_os_error() {
-                    Some(win_code) => ServiceExitCode::Win32(win_code as u32),
-                    None => ServiceExitCode::Win32(windows::Win32::Foundation::ERROR_PROCESS_ABORTED.0),
-                };
+                eventlog::report(Event::LaunchFailed { error: e.to_string() });
+                update_status(&status, |x| x.failures += 1);
+                heartbeat.fail();
+                service_exit_code = launch_error_exit_code(&e);
+                if spawn_failures < opts.spawn_retries.unwrap_or(0) {
+                    spawn_failures += 1;
+                    let delay = spawn_retry_delay(spawn_failures);
+                    info!(
+                        "Retrying launch in {} ms (attempt {} of {})",
+                        delay.as_millis(),
+                        spawn_failures,
+                        opts.spawn_retries.unwrap_or(0)
+                    );
+                    restart_after = Some(std::time::Instant::now() + delay);
+                    continue;
+                }
                 break;
             }
         };
+        let launched_at = std::time::Instant::now();
+        stats.launched(launched_at);
+        launch_requested = false;
+        update_status(&status, |x| {
+            x.pid = Some(child.id());
+            x.launched_at = Some(launched_at);
+            x.restarts = stats.restarts();
+            x.launches = stats.launches;
+            x.last_output = None;
+        });
+        crate::notify::set_restarts(stats.restarts());
+        heartbeat.set_running(true);
+        eventlog::report(Event::Launched { pid: child.id() });
+        if was_adopted {
+            info!(
+                "Supervising command from a previous run as process {}; its output will not be logged",
+                child.id()
+            );
+        } else if !child.shares_console() {
+            info!(
+                "Launched command in the active user session as process {}; its output will not be logged",
+                child.id()
+            );
+        }
+        let killer = Killer::new(
+            opts.kill_method.unwrap_or_default(),
+            &mut child,
+            !was_adopted && launch_suspended(&opts),
+        );
+        if let Some(pid_file) = &pid_file {
+            write_pid_file(pid_file, &child);
+        }
+        let mut watched: Vec<_> = opts
+            .watch
+            .iter()
+            .map(|x| std::path::PathBuf::from(expand_path(&opts, x)))
+            .collect();
+        if opts.restart_on_update {
+            match process_image_path(&child) {
+                Some(path) => watched.push(path),
+                None => {
+                    error!("Unable to determine the command's executable path, so it will not be watched for updates")
+                }
+            }
+        }
+        let mut watcher = if watched.is_empty() {
+            None
+        } else {
+            for path in &watched {
+                debug!("Watching {} for changes", path.display());
+            }
+            Some(crate::watch::FileWatcher::new(
+                watched,
+                opts.watch_debounce.unwrap_or(std::time::Duration::from_secs(1)),
+            ))
+        };
+        let child_stdin = child
+            .take_stdin()
+            .and_then(|stdin| provide_stdin(stdin, &opts, keep_stdin));
 
-        // Log stdout.
+        // The command's descendants may keep its output open after it exits,
+        // so we stop capturing once we're done with this launch.
         let output_logs_need_target = opts.log_cmd_as.is_some();
-        let stdout_option = child.stdout.take();
-        let stdout_logger = std::thread::spawn(move || {
-            if !should_log_cmd {
-                return;
-            }
-            if let Some(stdout) = stdout_option {
-                std::io::BufReader::new(stdout).lines().for_each(|line| match line {
-                    Ok(ref x) if !x.is_empty() => {
-                        if output_logs_need_target {
-                            debug!(target: "{shawl-cmd}", "{}", x);
-                        } else {
-                            debug!("stdout: {:?}", x);
-                        }
+        let log_cmd_split = opts.log_cmd_split;
+        let log_cmd_to = opts.log_cmd_to;
+        let echo_output = opts.echo_output;
+        let output_status = status.clone();
+        let stdout_level = opts.log_stdout_level.unwrap_or_default().to_log();
+        let stderr_level = opts.log_stderr_level.unwrap_or_default().to_log();
+        let filter =
+            crate::output::LineFilter::new(&opts.log_filter_include, &opts.log_filter_exclude).unwrap_or_else(|e| {
+                error!("Unable to parse output filters, so logging all output: {:?}", e);
+                crate::output::LineFilter::default()
+            });
+        let grouper =
+            crate::output::LineGrouper::new(opts.log_group_indented, &opts.log_group_pattern).unwrap_or_else(|e| {
+                error!(
+                    "Unable to parse output grouping patterns, so only grouping indented lines: {:?}",
+                    e
+                );
+                crate::output::LineGrouper::new(opts.log_group_indented, &[]).unwrap_or_default()
+            });
+        let limiter = crate::output::LineLimiter::new(
+            opts.log_rate_limit.map(|limit| {
+                (
+                    limit,
+                    opts.log_rate_interval.unwrap_or(std::time::Duration::from_secs(1)),
+                )
+            }),
+            opts.log_collapse_repeats,
+        );
+        let output = OutputCapture::start(
+            child.take_stdout(),
+            child.take_stderr(),
+            crate::output::CaptureOptions {
+                encoding: opts.cmd_encoding.unwrap_or_default(),
+                grouper,
+                filter,
+                limiter,
+                raw_stdout: opts.capture_raw.as_ref().map(|path| {
+                    crate::output::RawFile::new(
+                        path.into(),
+                        opts.capture_raw_rotate.unwrap_or(2097152),
+                        opts.log_retain.unwrap_or(2),
+                    )
+                }),
+            },
+            move |stream, line| {
+                let level = match stream {
+                    crate::output::Stream::Stdout => stdout_level,
+                    crate::output::Stream::Stderr => stderr_level,
+                };
+                if output_logs_need_target {
+                    let target = match (log_cmd_split, stream) {
+                        (false, _) => "{shawl-cmd}",
+                        (true, crate::output::Stream::Stdout) => "{shawl-cmd-out}",
+                        (true, crate::output::Stream::Stderr) => "{shawl-cmd-err}",
+                    };
+                    log::log!(target: target, level, stream = stream.name(), line = line; "{}", line);
+                } else {
+                    log::log!(target: crate::output::OUTPUT_TARGET, level, stream = stream.name(), line = line; "{}: {:?}", stream.name(), line);
+                }
+                match (log_cmd_to, stream) {
+                    (Some(cli::LogCmdTarget::EventLog), _)
+                    | (Some(cli::LogCmdTarget::EventLogStderr), crate::output::Stream::Stderr) => {
+                        eventlog::report(Event::Output {
+                            stream,
+                            line: crate::logformat::redact(line).to_string(),
+                        })
                     }
                     _ => (),
+                }
+                crate::otlp::output(stream, &crate::logformat::redact(line));
+                if echo_output {
+                    // There is no stderr when the service manager runs Shawl, so ignore failures.
+                    let _ = writeln!(std::io::stderr(), "{}", crate::logformat::redact(line));
+                }
+                update_status(&output_status, |x| {
+                    x.last_output = Some(std::time::Instant::now());
+                    x.output_lines += 1;
                 });
-            }
-        });
+            },
+        );
 
-        // Log stderr.
-        let stderr_option = child.stderr.take();
-        let stderr_logger = std::thread::spawn(move || {
-            if !should_log_cmd {
-                return;
-            }
-            if let Some(stderr) = stderr_option {
-                std::io::BufReader::new(stderr).lines().for_each(|line| match line {
-                    Ok(ref x) if !x.is_empty() => {
-                        if output_logs_need_target {
-                            debug!(target: "{shawl-cmd}", "{}", x);
-                        } else {
-                            debug!("stderr: {:?}", x);
+        'inner: loop {
+            // Wake up for whichever periodic check is due next.
+            let now = std::time::Instant::now();
+            let timeout = [
+                watcher.as_ref().map(|_| std::time::Duration::from_secs(1)),
+                (!opts.bind_to_service.is_empty()).then(|| {
+                    (last_bound_service_check + std::time::Duration::from_secs(1)).saturating_duration_since(now)
+                }),
+                opts.restart_every
+                    .filter(|_| !paused)
+                    .map(|every| (launched_at + every).saturating_duration_since(now)),
+            ]
+            .into_iter()
+            .flatten()
+            .min();
+            match next_event(&event_rx, &signal, Some(&child), timeout) {
+                Some(ServiceEvent::Pause) => {
+                    if !paused {
+                        info!("Suspending command");
+                        if let Err(e) = set_process_suspended(child.id(), true) {
+                            error!("Unable to suspend command: {:?}", e);
+                        }
+                        paused = true;
+                    }
+                    for instance in &instances {
+                        instance.set_paused(true);
+                    }
+                    update_status(&status, |x| x.paused = true);
+                    heartbeat.set_running(false);
+                    set_service_state(&status_handle, ServiceState::Paused, controls_accepted)?;
+                }
+                Some(ServiceEvent::Continue) => {
+                    if paused {
+                        info!("Resuming command");
+                        if let Err(e) = set_process_suspended(child.id(), false) {
+                            error!("Unable to resume command: {:?}", e);
                         }
+                        paused = false;
+                    }
+                    for instance in &instances {
+                        instance.set_paused(false);
                     }
+                    update_status(&status, |x| x.paused = false);
+                    heartbeat.set_running(true);
+                    set_service_state(&status_handle, ServiceState::Running, controls_accepted)?;
+                }
+                Some(ServiceEvent::Resume) => match opts.on_resume {
+                    Some(cli::ResumeAction::Restart) if !paused => {
+                        info!("Restarting command after system resume");
+                        eventlog::report(Event::Restarting {
+                            reason: "system resumed".to_string(),
+                        });
+                        stop_command(
+                            &mut child,
+                            &killer,
+                            opts.stop_method.unwrap_or_default(),
+                            stop_timeout,
+                            &ctrl_events,
+                            |_| (),
+                        );
+                        service_exit_code = ServiceExitCode::NO_ERROR;
+                        break 'inner;
+                    }
+                    Some(cli::ResumeAction::Signal) if !paused => signal_command(&ctrl_events),
                     _ => (),
-                });
-            }
-        });
+                },
+                Some(ServiceEvent::SessionChange { reason, session_id }) => match &opts.on_session_change {
+                    Some(cli::SessionChangeAction::Restart) if !paused && matches!(reason, "logon" | "logoff") => {
+                        info!("Restarting command after session {}", reason);
+                        eventlog::report(Event::Restarting {
+                            reason: format!("session {}", reason),
+                        });
+                        stop_command(
+                            &mut child,
+                            &killer,
+                            opts.stop_method.unwrap_or_default(),
+                            stop_timeout,
+                            &ctrl_events,
+                            |_| (),
+                        );
+                        service_exit_code = ServiceExitCode::NO_ERROR;
+                        break 'inner;
+                    }
+                    Some(cli::SessionChangeAction::Run(command)) => {
+                        run_session_change_command(command, reason, session_id);
+                    }
+                    _ => (),
+                },
+                Some(ServiceEvent::ParamChange) => {
+                    reload_options(&service_name, &mut cwd, &mut opts);
+                    pass = opts.pass.clone().unwrap_or_else(|| vec![0.into()]);
+                    stop_timeout = opts.stop_timeout.unwrap_or(3000_u64);
+                    controls_accepted = accepted_controls(&opts);
+                    let state = if paused {
+                        ServiceState::Paused
+                    } else {
+                        ServiceState::Running
+                    };
+                    set_service_state(&status_handle, state, controls_accepted)?;
+                }
+                Some(ServiceEvent::Control(code)) => {
+                    let mut restart = false;
+                    for (_, action) in opts.on_control.iter().filter(|(x, _)| *x == code) {
+                        match action {
+                            cli::ControlAction::Restart => restart = true,
+                            cli::ControlAction::Run(command) => run_control_command(command, code),
+                            cli::ControlAction::LogLevel(level) => crate::set_log_level(*level),
+                            cli::ControlAction::Stdin(text) => match &child_stdin {
+                                Some(stdin) => {
+                                    info!("Writing to command's stdin for control {}", code);
+                                    if !stdin.write(format!("{}\r\n", text).into_bytes()) {
+                                        error!("Unable to write to command's stdin because it has been closed");
+                                    }
+                                }
+                                None => error!("Unable to write to command's stdin because it is not available"),
+                            },
+                        }
+                    }
+                    if restart && !paused {
+                        info!("Restarting command for control {}", code);
+                        eventlog::report(Event::Restarting {
+                            reason: format!("control {}", code),
+                        });
+                        stop_command(
+                            &mut child,
+                            &killer,
+                            opts.stop_method.unwrap_or_default(),
+                            stop_timeout,
+                            &ctrl_events,
+                            |_| (),
+                        );
+                        service_exit_code = ServiceExitCode::NO_ERROR;
+                        break 'inner;
+                    }
+                }
+                Some(ServiceEvent::Pipe(command)) => match command {
+                    crate::pipe::Command::Restart if !paused => {
+                        info!("Restarting command for control pipe");
+                        eventlog::report(Event::Restarting {
+                            reason: "control pipe".to_string(),
+                        });
+                        stop_command(
+                            &mut child,
+                            &killer,
+                            opts.stop_method.unwrap_or_default(),
+                            stop_timeout,
+                            &ctrl_events,
+                            |_| (),
+                        );
+                        service_exit_code = ServiceExitCode::NO_ERROR;
+                        launch_requested = true;
+                        break 'inner;
+                    }
+                    crate::pipe::Command::Restart => info!("Ignoring restart while the service is paused"),
+                    crate::pipe::Command::LogLevel(level) => crate::set_log_level(level),
+                    crate::pipe::Command::PauseRestarts => {
+                        info!("Pausing restarts of the command");
+                        restarts_paused = true;
+                        update_status(&status, |x| x.restarts_paused = true);
+                    }
+                    crate::pipe::Command::ResumeRestarts => {
+                        info!("Resuming restarts of the command");
+                        restarts_paused = false;
+                        update_status(&status, |x| x.restarts_paused = false);
+                    }
+                },
+                Some(event @ (ServiceEvent::Stop | ServiceEvent::Preshutdown)) => {
+                    let timeout = match (event, opts.preshutdown_timeout) {
+                        (ServiceEvent::Preshutdown, Some(preshutdown_timeout)) => preshutdown_timeout,
+                        _ => stop_timeout,
+                    };
+                    let stop_delay = opts.stop_delay.unwrap_or(0);
+                    set_pending(
+                        &status_handle,
+                        ServiceState::StopPending,
+                        0,
+                        std::time::Duration::from_millis(stop_delay + timeout + 1000),
+                    )?;
+                    eventlog::report(Event::Stopping);
 
-        'inner: loop {
-            match shutdown_rx.recv_timeout(std::time::Duration::from_secs(1)) {
-                Ok(_) | Err(std::sync::mpsc::RecvTimeoutError::Disconnected) => {
-                    status_handle.set_service_status(ServiceStatus {
-                        service_type: SERVICE_TYPE,
-                        current_state: ServiceState::StopPending,
-                        controls_accepted: ServiceControlAccept::empty(),
-                        exit_code: ServiceExitCode::NO_ERROR,
-                        checkpoint: 0,
-                        wait_hint: std::time::Duration::from_millis(opts.stop_timeout.unwrap_or(3000) + 1000),
-                        process_id: None,
-                    })?;
-
-                    ignore_ctrlc.store(true, std::sync::atomic::Ordering::SeqCst);
-                    info!("Sending ctrl-C to command");
-                    unsafe {
-                        if windows::Win32::System::Console::GenerateConsoleCtrlEvent(
-                            windows::Win32::System::Console::CTRL_C_EVENT,
-                            0,
-                        )
-                        .is_err()
-                        {
-                            error!(
-                                "Windows GenerateConsoleCtrlEvent failed with code {:?}",
-                                windows::Win32::Foundation::GetLastError()
-                            );
-                        };
+                    if paused {
+                        info!("Resuming command before stopping it");
+                        if let Err(e) = set_process_suspended(child.id(), false) {
+                            error!("Unable to resume command: {:?}", e);
+                        }
                     }
 
-                    let start_time = std::time::Instant::now();
-                    loop {
-                        match check_process(&mut child) {
-                            Ok(ProcessStatus::Running) => {
-                                if start_time.elapsed().as_millis() < (*stop_timeout).into() {
-                                    std::thread::sleep(std::time::Duration::from_millis(50))
-                                } else {
-                                    info!("Killing command because stop timeout expired",);
-                                    let _ = child.kill();
-                                    service_exit_code = ServiceExitCode::NO_ERROR;
-                                    break;
-                                }
-                            }
-                            Ok(ProcessStatus::Exited(code)) => {
-                                info!(
-                                    "Command exited after {:?} ms with code {:?}",
-                                    start_time.elapsed().as_millis(),
-                                    code
-                                );
-                                service_exit_code = if pass.contains(&code) {
-                                    ServiceExitCode::NO_ERROR
-                                } else {
-                                    ServiceExitCode::ServiceSpecific(code as u32)
-                                };
+                    let mut checkpoint = 0;
+                    if stop_delay > 0 {
+                        info!("Delaying {} ms before stopping command", stop_delay);
+                        let delay_end = std::time::Instant::now() + std::time::Duration::from_millis(stop_delay);
+                        while let Some(remaining) = delay_end.checked_duration_since(std::time::Instant::now()) {
+                            if remaining.is_zero() || !matches!(check_process(&mut child), Ok(ProcessStatus::Running)) {
                                 break;
                             }
-                            _ => {
-                                info!("Command exited within stop timeout");
-                                break;
+                            checkpoint += 1;
+                            if let Err(e) = set_pending(
+                                &status_handle,
+                                ServiceState::StopPending,
+                                checkpoint,
+                                remaining + std::time::Duration::from_millis(timeout + 1000),
+                            ) {
+                                error!("Unable to report stop progress: {:?}", e);
                             }
+                            wait_for_objects(&[&child], Some(remaining.min(std::time::Duration::from_secs(1))));
                         }
                     }
 
-                    ignore_ctrlc.store(false, std::sync::atomic::Ordering::SeqCst);
+                    let on_wait = |remaining: std::time::Duration| {
+                        checkpoint += 1;
+                        if let Err(e) = set_pending(
+                            &status_handle,
+                            ServiceState::StopPending,
+                            checkpoint,
+                            remaining + std::time::Duration::from_millis(1000),
+                        ) {
+                            error!("Unable to report stop progress: {:?}", e);
+                        }
+                    };
+
+                    service_exit_code = match stop_command(
+                        &mut child,
+                        &killer,
+                        opts.stop_method.unwrap_or_default(),
+                        timeout,
+                        &ctrl_events,
+                        on_wait,
+                    ) {
+                        StopOutcome::Exited(code) => {
+                            log_exit_summary(&mut stats, &crate::exitcode::describe(code));
+                            update_status(&status, |x| x.last_exit_code = Some(code));
+                            if cli::ExitCodeRange::any_contains(&pass, code) {
+                                ServiceExitCode::NO_ERROR
+                            } else {
+                                command_exit_code(code)
+                            }
+                        }
+                        StopOutcome::Terminated => {
+                            log_exit_summary(&mut stats, "terminated");
+                            update_status(&status, |x| x.last_exit_code = None);
+                            service_exit_code
+                        }
+                        StopOutcome::Killed => {
+                            log_exit_summary(&mut stats, "killed");
+                            update_status(&status, |x| x.last_exit_code = None);
+                            ServiceExitCode::NO_ERROR
+                        }
+                    };
                     break 'outer;
                 }
-                Err(std::sync::mpsc::RecvTimeoutError::Timeout) => (),
+                None => (),
             };
 
             match check_process(&mut child) {
-                Ok(ProcessStatus::Running) => (),
+                Ok(ProcessStatus::Running) => {
+                    if !opts.bind_to_service.is_empty()
+                        && last_bound_service_check.elapsed() >= std::time::Duration::from_secs(1)
+                    {
+                        last_bound_service_check = std::time::Instant::now();
+                        if !services_are_running(&opts.bind_to_service) {
+                            info!("Stopping command because a bound service is not running");
+                            if paused {
+                                if let Err(e) = set_process_suspended(child.id(), false) {
+                                    error!("Unable to resume command: {:?}", e);
+                                }
+                            }
+                            stop_command(
+                                &mut child,
+                                &killer,
+                                opts.stop_method.unwrap_or_default(),
+                                stop_timeout,
+                                &ctrl_events,
+                                |_| (),
+                            );
+                            service_exit_code = ServiceExitCode::NO_ERROR;
+                            waiting_for_bound_services = true;
+                            break 'inner;
+                        }
+                    }
+                    if !paused {
+                        if let Some(path) = watcher.as_mut().and_then(|x| x.poll()) {
+                            info!("Restarting command because {} changed", path.display());
+                            eventlog::report(Event::Restarting {
+                                reason: format!("{} changed", path.display()),
+                            });
+                            stop_command(
+                                &mut child,
+                                &killer,
+                                opts.stop_method.unwrap_or_default(),
+                                stop_timeout,
+                                &ctrl_events,
+                                |_| (),
+                            );
+                            service_exit_code = ServiceExitCode::NO_ERROR;
+                            break 'inner;
+                        }
+                    }
+                    if !paused && opts.restart_every.is_some_and(|every| launched_at.elapsed() >= every) {
+                        info!(
+                            "Restarting command after {} ms of uptime",
+                            launched_at.elapsed().as_millis()
+                        );
+                        eventlog::report(Event::Restarting {
+                            reason: "scheduled restart".to_string(),
+                        });
+                        stop_command(
+                            &mut child,
+                            &killer,
+                            opts.stop_method.unwrap_or_default(),
+                            stop_timeout,
+                            &ctrl_events,
+                            |_| (),
+                        );
+                        service_exit_code = ServiceExitCode::NO_ERROR;
+                        break 'inner;
+                    }
+                }
                 Ok(ProcessStatus::Exited(code)) => {
-                    info!("Command exited with code {:?}", code);
-                    service_exit_code = if pass.contains(&code) {
+                    info!("Command exited with code {}", crate::exitcode::describe(code));
+                    log_exit_summary(&mut stats, &crate::exitcode::describe(code));
+                    update_status(&status, |x| x.last_exit_code = Some(code));
+                    if is_crash_exit_code(code) {
+                        let mut dump = None;
+                        if let (Some(dir), Some((_, image_name))) = (&crash_dump_dir, &crash_dumps) {
+                            let path = crash_dump_path(dir, image_name, child.id());
+                            if wait_for_crash_dump(&path, CRASH_DUMP_TIMEOUT) {
+                                error!(
+                                    "Command crashed with code {}; dump: {}",
+                                    crate::exitcode::describe(code),
+                                    path.display()
+                                );
+                                dump = Some(path.to_string_lossy().to_string());
+                            } else {
+                                error!(
+                                    "Command crashed with code {}, but no dump was found in {}",
+                                    crate::exitcode::describe(code),
+                                    dir
+                                );
+                            }
+                        }
+                        eventlog::report(Event::Crashed { code, dump });
+                    } else {
+                        eventlog::report(Event::Exited { code });
+                    }
+                    let decision = run_hook(
+                        &opts,
+                        &service_name,
+                        crate::hook::Event::PostExit,
+                        Some(code),
+                        &stats,
+                        &status,
+                        &stopping,
+                    );
+                    if cli::ExitCodeRange::any_contains(&opts.stop_if, code) {
+                        info!("Stopping service because the command exited with a stop code");
+                        service_exit_code = ServiceExitCode::NO_ERROR;
+                        break 'outer;
+                    }
+                    service_exit_code = if cli::ExitCodeRange::any_contains(&pass, code) {
                         ServiceExitCode::NO_ERROR
                     } else {
-                        ServiceExitCode::ServiceSpecific(code as u32)
+                        update_status(&status, |x| x.failures += 1);
+                        heartbeat.fail();
+                        command_exit_code(code)
                     };
-                    if should_restart_exited_command(
-                        code,
-                        opts.restart,
-                        opts.no_restart,
-                        &opts.restart_if,
-                        &opts.restart_if_not,
-                    ) {
+                    if let Some(interval) = opts.interval {
+                        if !cli::ExitCodeRange::any_contains(&pass, code) {
+                            error!("Command failed with code {}", crate::exitcode::describe(code));
+                        }
+                        info!("Running command again in {} ms", interval.as_millis());
+                        restart_after = Some(std::time::Instant::now() + interval);
+                        break 'inner;
+                    }
+                    let restart = match decision {
+                        Some(decision) => decision == crate::hook::Decision::Restart,
+                        None => should_restart_exited_command(
+                            code,
+                            opts.restart,
+                            opts.no_restart,
+                            &opts.restart_if,
+                            &opts.restart_if_not,
+                        ),
+                    };
+                    if restart {
+                        eventlog::report(Event::Restarting {
+                            reason: format!("exited with code {}", crate::exitcode::describe(code)),
+                        });
                         break 'inner;
                     } else {
                         break 'outer;
@@ -356,8 +3224,35 @@ pub fn run_service(start_arguments: Vec<std::ffi::OsString>) -> windows_service:
                 }
                 Ok(ProcessStatus::Terminated) => {
                     info!("Command was terminated by a signal");
+                    log_exit_summary(&mut stats, "terminated");
+                    update_status(&status, |x| {
+                        x.last_exit_code = None;
+                        x.failures += 1;
+                    });
+                    heartbeat.fail();
                     service_exit_code = ServiceExitCode::Win32(windows::Win32::Foundation::ERROR_PROCESS_ABORTED.0);
-                    if should_restart_terminated_command(opts.restart, opts.no_restart) {
+                    let decision = run_hook(
+                        &opts,
+                        &service_name,
+                        crate::hook::Event::PostExit,
+                        None,
+                        &stats,
+                        &status,
+                        &stopping,
+                    );
+                    if let Some(interval) = opts.interval {
+                        info!("Running command again in {} ms", interval.as_millis());
+                        restart_after = Some(std::time::Instant::now() + interval);
+                        break 'inner;
+                    }
+                    let restart = match decision {
+                        Some(decision) => decision == crate::hook::Decision::Restart,
+                        None => should_restart_terminated_command(opts.restart, opts.no_restart),
+                    };
+                    if restart {
+                        eventlog::report(Event::Restarting {
+                            reason: "terminated".to_string(),
+                        });
                         break 'inner;
                     } else {
                         break 'outer;
@@ -371,12 +3266,14 @@ pub fn run_service(start_arguments: Vec<std::ffi::OsString>) -> windows_service:
             }
         }
 
-        if let Err(e) = stdout_logger.join() {
-            error!("Unable to join stdout logger thread: {:?}", e);
-        }
-        if let Err(e) = stderr_logger.join() {
-            error!("Unable to join stderr logger thread: {:?}", e);
-        }
+        output.finish();
+        log_exit_summary(&mut stats, "stopped for restart");
+        update_status(&status, |x| {
+            x.pid = None;
+            x.launched_at = None;
+            x.total_uptime = stats.total_uptime;
+        });
+        heartbeat.set_running(false);
 
         if let Some(delay) = opts.restart_delay {
             info!("Delaying {delay} ms before restart");
@@ -384,18 +3281,25 @@ pub fn run_service(start_arguments: Vec<std::ffi::OsString>) -> windows_service:
         }
     }
     debug!("Exited main service loop");
+    heartbeat.set_running(false);
+    log_exit_summary(&mut stats, "stopped");
+    log_service_summary(&stats);
+    if let Some(status_file) = status_file {
+        status_file.stop();
+    }
+    if let Some(state_watcher) = state_watcher {
+        state_watcher.stop();
+    }
 
-    status_handle.set_service_status(ServiceStatus {
-        service_type: SERVICE_TYPE,
-        current_state: ServiceState::Stopped,
-        controls_accepted: ServiceControlAccept::empty(),
-        exit_code: service_exit_code,
-        checkpoint: 0,
-        wait_hint: std::time::Duration::default(),
-        process_id: None,
-    })?;
+    if let Some(pid_file) = &pid_file {
+        let _ = std::fs::remove_file(pid_file);
+    }
 
-    Ok(())
+    for handle in instances.into_iter().rev().chain(sidecars.into_iter().rev()) {
+        handle.stop();
+    }
+
+    set_stopped(&status_handle, service_exit_code)
 }
 
 #[cfg(test)]
@@ -410,13 +3314,13 @@ speculate::speculate! {
         }
 
         it "handles --restart-if" {
-            assert!(should_restart_exited_command(0, false, false, &[0], &[]));
-            assert!(!should_restart_exited_command(1, false, false, &[0], &[]));
+            assert!(should_restart_exited_command(0, false, false, &[0.into()], &[]));
+            assert!(!should_restart_exited_command(1, false, false, &[0.into()], &[]));
         }
 
         it "handles --restart-if-not" {
-            assert!(!should_restart_exited_command(0, false, false, &[], &[0]));
-            assert!(should_restart_exited_command(1, false, false, &[], &[0]));
+            assert!(!should_restart_exited_command(0, false, false, &[], &[0.into()]));
+            assert!(should_restart_exited_command(1, false, false, &[], &[0.into()]));
         }
 
         it "restarts nonzero by default" {
@@ -425,6 +3329,233 @@ speculate::speculate! {
         }
     }
 
+    describe "spawn_retry_delay" {
+        it "doubles up to a limit" {
+            assert_eq!(spawn_retry_delay(1), std::time::Duration::from_secs(1));
+            assert_eq!(spawn_retry_delay(2), std::time::Duration::from_secs(2));
+            assert_eq!(spawn_retry_delay(3), std::time::Duration::from_secs(4));
+            assert_eq!(spawn_retry_delay(6), std::time::Duration::from_secs(30));
+            assert_eq!(spawn_retry_delay(100), std::time::Duration::from_secs(30));
+        }
+    }
+
+    describe "parse_pid_file" {
+        it "reads the process ID, executable path, and start time" {
+            assert_eq!(
+                parse_pid_file("1234\nC:\\app\\server.exe\n133000000000000000\n"),
+                Some(PidFile {
+                    pid: 1234,
+                    image: std::path::PathBuf::from("C:\\app\\server.exe"),
+                    started: 133000000000000000,
+                }),
+            );
+        }
+
+        it "round-trips through the file content" {
+            let pid_file = PidFile {
+                pid: 1234,
+                image: std::path::PathBuf::from("C:\\app\\server.exe"),
+                started: 133000000000000000,
+            };
+            assert_eq!(parse_pid_file(&pid_file.to_file_content()), Some(pid_file));
+        }
+
+        it "rejects incomplete content" {
+            assert_eq!(parse_pid_file(""), None);
+            assert_eq!(parse_pid_file("1234\n"), None);
+            assert_eq!(parse_pid_file("1234\nC:\\app\\server.exe\n"), None);
+            assert_eq!(parse_pid_file("abc\nC:\\app\\server.exe\n133000000000000000\n"), None);
+            assert_eq!(parse_pid_file("1234\nC:\\app\\server.exe\nabc\n"), None);
+        }
+    }
+
+    describe "accepted_controls" {
+        it "accepts notifications only for the options that use them" {
+            let base = ServiceControlAccept::STOP
+                | ServiceControlAccept::SHUTDOWN
+                | ServiceControlAccept::PAUSE_CONTINUE
+                | ServiceControlAccept::PARAM_CHANGE;
+            assert_eq!(accepted_controls(&cli::CommonOpts::default()), base);
+            let opts = cli::CommonOpts {
+                preshutdown_timeout: Some(5000),
+                ..Default::default()
+            };
+            assert_eq!(accepted_controls(&opts), base | ServiceControlAccept::PRESHUTDOWN);
+        }
+    }
+
+    describe "test_options" {
+        it "leaves out what others would notice" {
+            let opts = cli::CommonOpts {
+                heartbeat_url: Some("https://hc-ping.com/1234".parse().unwrap()),
+                hook_exec: Some("hook.exe".to_string()),
+                status_file: Some("C:\\status.json".to_string()),
+                log_dir: Some("C:\\logs".to_string()),
+                log_private: true,
+                ..Default::default()
+            };
+            let (opts, left_out) = test_options(opts, std::path::Path::new("C:\\temp\\shawl-test"));
+            assert_eq!(left_out, vec!["heartbeats", "hooks", "status file"]);
+            assert_eq!(opts.heartbeat_url, None);
+            assert_eq!(opts.hook_exec, None);
+            assert_eq!(opts.status_file, None);
+            assert_eq!(opts.log_dir, Some("C:\\temp\\shawl-test".to_string()));
+            assert_eq!(opts.log_subdir, Some(None));
+            assert!(!opts.log_private);
+        }
+    }
+
+    describe "test_report" {
+        it "reports the last exit code and restart decision" {
+            let opts = cli::CommonOpts {
+                restart_if: vec![2.into()],
+                ..Default::default()
+            };
+            let status = crate::pipe::Status {
+                launches: 2,
+                restarts: 1,
+                failures: 1,
+                last_exit_code: Some(2),
+                ..Default::default()
+            };
+            assert_eq!(
+                test_report(&opts, &status, ServiceExitCode::ServiceSpecific(2)),
+                vec![
+                    "Launches: 2, restarts: 1, failures: 1",
+                    "Windows would see: service-specific error 2",
+                    "Last exit code: 2",
+                    "If the command exited on its own with that code, Shawl would restart it",
+                ],
+            );
+        }
+
+        it "handles a command that never exited" {
+            let status = crate::pipe::Status {
+                launches: 1,
+                ..Default::default()
+            };
+            assert_eq!(
+                test_report(&cli::CommonOpts::default(), &status, ServiceExitCode::NO_ERROR),
+                vec![
+                    "Launches: 1, restarts: 0, failures: 0",
+                    "Windows would see: success",
+                    "Last exit code: none",
+                ],
+            );
+        }
+    }
+
+    describe "needs_console" {
+        it "is needed to send ctrl-C by default" {
+            assert!(needs_console(&cli::CommonOpts::default()));
+        }
+
+        it "is not needed when the command is killed" {
+            assert!(!needs_console(&cli::CommonOpts {
+                stop_method: Some(cli::StopMethod::Kill),
+                ..Default::default()
+            }));
+            assert!(!needs_console(&cli::CommonOpts {
+                interactive_session: true,
+                ..Default::default()
+            }));
+        }
+
+        it "is needed for other commands that receive ctrl-break" {
+            assert!(needs_console(&cli::CommonOpts {
+                stop_method: Some(cli::StopMethod::Kill),
+                instances: Some(2),
+                ..Default::default()
+            }));
+        }
+    }
+
+    describe "is_crash_exit_code" {
+        it "recognizes NTSTATUS errors" {
+            assert!(is_crash_exit_code(0xC0000005_u32 as i32));
+            assert!(is_crash_exit_code(0xC0000409_u32 as i32));
+            assert!(!is_crash_exit_code(0));
+            assert!(!is_crash_exit_code(1));
+            assert!(!is_crash_exit_code(-1));
+            assert!(!is_crash_exit_code(0xC000013A_u32 as i32));
+        }
+    }
+
+    describe "crash_dump_path" {
+        it "follows the Windows Error Reporting naming" {
+            assert_eq!(
+                crash_dump_path("C:\\dumps", "app.exe", 1234),
+                std::path::PathBuf::from("C:\\dumps\\app.exe.1234.dmp"),
+            );
+        }
+    }
+
+    describe "script_kind" {
+        it "recognizes script extensions" {
+            assert_eq!(script_kind("run_app.bat"), Some(ScriptKind::Batch));
+            assert_eq!(script_kind("C:\\app\\RUN.CMD"), Some(ScriptKind::Batch));
+            assert_eq!(script_kind("C:/app/run.ps1"), Some(ScriptKind::PowerShell));
+            assert_eq!(script_kind("app.py"), Some(ScriptKind::Python));
+        }
+
+        it "ignores other programs" {
+            assert_eq!(script_kind("app.exe"), None);
+            assert_eq!(script_kind("app"), None);
+        }
+    }
+
+    describe "split_program" {
+        it "keeps the rest of the command line as-is" {
+            assert_eq!(
+                split_program(r#"msiexec /i "C:\temp\app.msi"  INSTALLDIR="C:\Program Files\App""#),
+                ("msiexec".to_string(), r#"/i "C:\temp\app.msi"  INSTALLDIR="C:\Program Files\App""#.to_string()),
+            );
+        }
+
+        it "handles a quoted program" {
+            assert_eq!(
+                split_program(r#""C:\Program Files\Java\bin\java.exe" -Dx="a b" -jar app.jar"#),
+                (r"C:\Program Files\Java\bin\java.exe".to_string(), r#"-Dx="a b" -jar app.jar"#.to_string()),
+            );
+            assert_eq!(split_program("app"), ("app".to_string(), "".to_string()));
+        }
+    }
+
+    describe "inherited_vars" {
+        it "keeps matching variables" {
+            let vars = vec![
+                ("TEMP".to_string(), r"C:\Temp".to_string()),
+                ("app_mode".to_string(), "prod".to_string()),
+                ("USERNAME".to_string(), "svc".to_string()),
+            ];
+            assert_eq!(
+                inherited_vars(&["temp".to_string(), "APP_*".to_string()], vars.into_iter()),
+                vec![
+                    ("TEMP".to_string(), r"C:\Temp".to_string()),
+                    ("app_mode".to_string(), "prod".to_string()),
+                ],
+            );
+        }
+    }
+
+    describe "launch_placeholders" {
+        it "fills in the service context" {
+            let mut opts = cli::CommonOpts {
+                log_dir: Some(r"C:\Logs".to_string()),
+                env: vec![("APP_LOG".to_string(), r"{log_dir}\{service_name}.log".to_string())],
+                command: vec!["app.exe".to_string(), "--id={service_name}".to_string(), r"--data={service_dir}\data".to_string()],
+                ..Default::default()
+            };
+            let placeholders = launch_placeholders("web", &Some(r"C:\Apps\web".to_string()), &opts);
+            opts.fill_placeholders(&placeholders);
+            assert_eq!(opts.env, vec![("APP_LOG".to_string(), r"C:\Logs\web.log".to_string())]);
+            assert_eq!(
+                opts.command,
+                vec!["app.exe".to_string(), "--id=web".to_string(), r"--data=C:\Apps\web\data".to_string()],
+            );
+        }
+    }
+
     describe "should_restart_terminated_command" {
         it "only restarts with --restart" {
             assert!(!should_restart_terminated_command(false, false));
@@ -432,4 +3563,30 @@ speculate::speculate! {
             assert!(!should_restart_terminated_command(false, true));
         }
     }
+
+    describe "run_stats" {
+        it "tracks each run and the whole service" {
+            let start = std::time::Instant::now();
+            let ms = std::time::Duration::from_millis;
+            let mut stats = RunStats::new(start);
+
+            stats.launched(start + ms(100));
+            assert_eq!(stats.exited(start + ms(1100)), Some(ms(1000)));
+            assert_eq!((stats.restarts(), stats.total_uptime), (0, ms(1000)));
+
+            stats.launched(start + ms(1500));
+            assert_eq!(stats.exited(start + ms(2000)), Some(ms(500)));
+            assert_eq!((stats.restarts(), stats.total_uptime), (1, ms(1500)));
+
+            assert_eq!((stats.service_uptime(start + ms(3000)), stats.launches), (ms(3000), 2));
+        }
+
+        it "only records each exit once" {
+            let start = std::time::Instant::now();
+            let mut stats = RunStats::new(start);
+            stats.launched(start);
+            assert!(stats.exited(start).is_some());
+            assert_eq!(stats.exited(start), None);
+        }
+    }
 }
@@ -0,0 +1,218 @@
+use crate::{
+    cli::{CommonOpts, Sidecar, SidecarRestart},
+    process::CommandProcess,
+};
+use log::{error, info};
+use std::os::windows::process::CommandExt;
+
+/// A process that `supervise` can wait for and stop.
+pub trait Supervised: Send + 'static {
+    fn try_wait(&mut self) -> std::io::Result<Option<std::process::ExitStatus>>;
+
+    /// Stop the process, killing it if it does not exit within the timeout.
+    fn stop(&mut self, label: &str, stop_timeout: u64);
+
+    /// Suspend or resume the process while the service is paused.
+    fn set_suspended(&mut self, _suspended: bool) {}
+}
+
+impl Supervised for CommandProcess {
+    fn try_wait(&mut self) -> std::io::Result<Option<std::process::ExitStatus>> {
+        CommandProcess::try_wait(self)
+    }
+
+    fn stop(&mut self, label: &str, stop_timeout: u64) {
+        stop(label, self, stop_timeout, |child| {
+            let _ = child.kill();
+        });
+    }
+}
+
+/// A command supervised on its own thread, such as a sidecar.
+pub struct SidecarHandle {
+    label: String,
+    stopping: std::sync::Arc<std::sync::atomic::AtomicBool>,
+    paused: std::sync::Arc<std::sync::atomic::AtomicBool>,
+    thread: std::thread::JoinHandle<()>,
+}
+
+impl SidecarHandle {
+    /// Stop the command and wait for its supervisor to finish.
+    pub fn stop(self) {
+        info!("Stopping {}", self.label);
+        self.stopping.store(true, std::sync::atomic::Ordering::SeqCst);
+        if let Err(e) = self.thread.join() {
+            error!("Unable to join {} thread: {:?}", self.label, e);
+        }
+    }
+
+    /// Suspend or resume the command, which is not relaunched while paused.
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, std::sync::atomic::Ordering::SeqCst);
+    }
+}
+
+/// Launch a sidecar, returning an error if the first launch fails.
+/// Later relaunches are handled according to the sidecar's restart policy.
+/// Like the main command, it gets the service's working directory and environment.
+pub fn launch(
+    index: usize,
+    sidecar: Sidecar,
+    cwd: &Option<String>,
+    opts: &CommonOpts,
+    stop_timeout: u64,
+) -> std::io::Result<SidecarHandle> {
+    let command = sidecar.clone();
+    let (cwd, opts) = (cwd.clone(), opts.clone());
+    let restart_delay = opts.restart_delay;
+    supervise(
+        format!("sidecar {}", index),
+        move || spawn(index, &command, &cwd, &opts),
+        move |code| match sidecar.restart {
+            SidecarRestart::Always => true,
+            SidecarRestart::OnFailure => code != Some(0),
+            SidecarRestart::Never => false,
+        },
+        restart_delay,
+        stop_timeout,
+    )
+}
+
+/// Launch a command and supervise it on its own thread,
+/// returning an error if the first launch fails.
+/// `should_restart` receives the exit code, or `None` if the command was terminated.
+pub fn supervise<P: Supervised>(
+    label: String,
+    mut spawn: impl FnMut() -> std::io::Result<P> + Send + 'static,
+    should_restart: impl Fn(Option<i32>) -> bool + Send + 'static,
+    restart_delay: Option<u64>,
+    stop_timeout: u64,
+) -> std::io::Result<SidecarHandle> {
+    let mut child = spawn()?;
+    let stopping = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let stopping2 = stopping.clone();
+    let paused = std::sync::Arc::new(std::sync::atomic::AtomicBool::new(false));
+    let paused2 = paused.clone();
+    let label2 = label.clone();
+
+    let thread = std::thread::spawn(move || loop {
+        let label = &label2;
+        let mut suspended = false;
+        let code = loop {
+            if stopping2.load(std::sync::atomic::Ordering::SeqCst) {
+                if suspended {
+                    child.set_suspended(false);
+                }
+                child.stop(label, stop_timeout);
+                return;
+            }
+            let pause = paused2.load(std::sync::atomic::Ordering::SeqCst);
+            if pause != suspended {
+                info!("{} {}", if pause { "Suspending" } else { "Resuming" }, label);
+                child.set_suspended(pause);
+                suspended = pause;
+            }
+            match child.try_wait() {
+                Ok(Some(status)) => break status.code(),
+                Ok(None) => std::thread::sleep(std::time::Duration::from_millis(100)),
+                Err(e) => {
+                    error!("Error trying to determine {} status: {:?}", label, e);
+                    break None;
+                }
+            }
+        };
+        info!("The {} command exited with code {:?}", label, code);
+
+        if !should_restart(code) {
+            return;
+        }
+
+        let restart_after =
+            std::time::Instant::now() + std::time::Duration::from_millis(restart_delay.unwrap_or_default());
+        while std::time::Instant::now() < restart_after || paused2.load(std::sync::atomic::Ordering::SeqCst) {
+            if stopping2.load(std::sync::atomic::Ordering::SeqCst) {
+                return;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(50));
+        }
+
+        child = match spawn() {
+            Ok(child) => child,
+            Err(e) => {
+                error!("Unable to relaunch {}: {}", label, e);
+                return;
+            }
+        };
+    });
+
+    Ok(SidecarHandle {
+        label,
+        stopping,
+        paused,
+        thread,
+    })
+}
+
+fn spawn(index: usize, sidecar: &Sidecar, cwd: &Option<String>, opts: &CommonOpts) -> std::io::Result<CommandProcess> {
+    let mut parts = crate::control::split_command_line(&sidecar.command).into_iter();
+    let program = parts.next().unwrap_or_default();
+
+    let mut cmd = std::process::Command::new(program);
+    crate::service::prepare_environment(cwd, opts)?.apply(&mut cmd);
+
+    info!("Launching sidecar {}: {}", index, sidecar.command);
+    let mut child = cmd
+        .args(parts)
+        // Keep the sidecar out of Shawl's process group so that it does not
+        // receive the ctrl-C meant for the main command.
+        .creation_flags(windows::Win32::System::Threading::CREATE_NEW_PROCESS_GROUP.0)
+        .stdout(std::process::Stdio::piped())
+        .stderr(std::process::Stdio::piped())
+        .spawn()?;
+
+    crate::service::log_output(format!("sidecar {}", index), child.stdout.take(), child.stderr.take());
+
+    Ok(CommandProcess::Child(child))
+}
+
+/// Send ctrl-break to a command in its own process group and wait for it to exit,
+/// calling `kill` if it does not exit within the stop timeout.
+pub fn stop(label: &str, child: &mut CommandProcess, stop_timeout: u64, kill: impl FnOnce(&mut CommandProcess)) {
+    info!("Sending ctrl-break to {}", label);
+    unsafe {
+        if windows::Win32::System::Console::GenerateConsoleCtrlEvent(
+            windows::Win32::System::Console::CTRL_BREAK_EVENT,
+            child.id(),
+        )
+        .is_err()
+        {
+            error!(
+                "Windows GenerateConsoleCtrlEvent failed with code {:?}",
+                windows::Win32::Foundation::GetLastError()
+            );
+        };
+    }
+
+    let start_time = std::time::Instant::now();
+    loop {
+        match child.try_wait() {
+            Ok(None) if start_time.elapsed().as_millis() < stop_timeout.into() => {
+                let remaining = std::time::Duration::from_millis(stop_timeout).saturating_sub(start_time.elapsed());
+                crate::process::wait_for_objects(&[&*child], Some(remaining));
+            }
+            Ok(None) => {
+                info!("Killing {} because stop timeout expired", label);
+                kill(child);
+                return;
+            }
+            Ok(Some(status)) => {
+                info!("The {} command exited with code {:?}", label, status.code());
+                return;
+            }
+            Err(e) => {
+                error!("Error trying to determine {} status: {:?}", label, e);
+                return;
+            }
+        }
+    }
+}
@@ -0,0 +1,144 @@
+use crate::{logformat::json_string, pipe::Status};
+
+/// How often the status file is rewritten.
+const INTERVAL: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// Seconds since the Unix epoch with millisecond precision.
+pub fn unix_time(at: std::time::SystemTime) -> String {
+    let seconds = at
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|x| x.as_secs_f64())
+        .unwrap_or_default();
+    format!("{:.3}", seconds)
+}
+
+/// Build the file's JSON object. Once the service has stopped, there is no PID or uptime.
+fn contents(
+    service: &str,
+    status: &Status,
+    stopped: bool,
+    now: std::time::Instant,
+    wall_now: std::time::SystemTime,
+) -> String {
+    let optional = |value: Option<String>| value.unwrap_or_else(|| "null".to_string());
+    let running = |value: Option<String>| if stopped { None } else { value };
+    let timestamp = |at: std::time::Instant| wall_now.checked_sub(now.saturating_duration_since(at)).map(unix_time);
+    format!(
+        "{{\"service\":{},\"state\":\"{}\",\"pid\":{},\"uptime_ms\":{},\"restarts\":{},\"last_exit_code\":{},\"last_output_at\":{},\"updated_at\":{}}}\n",
+        json_string(service),
+        if stopped { "stopped" } else { status.state() },
+        optional(running(status.pid.map(|x| x.to_string()))),
+        optional(running(
+            status
+                .launched_at
+                .map(|x| now.saturating_duration_since(x).as_millis().to_string())
+        )),
+        status.restarts,
+        optional(status.last_exit_code.map(|x| x.to_string())),
+        optional(status.last_output.and_then(timestamp)),
+        unix_time(wall_now),
+    )
+}
+
+/// Replace the file in one step so that readers never see a partial write.
+fn write(path: &std::path::Path, content: &str) -> std::io::Result<()> {
+    let mut temp = path.as_os_str().to_owned();
+    temp.push(".tmp");
+    std::fs::write(&temp, content)?;
+    std::fs::rename(&temp, path)
+}
+
+/// Keeps the `--status-file` up to date from a background thread.
+pub struct StatusFile {
+    stop: std::sync::mpsc::Sender<()>,
+    thread: std::thread::JoinHandle<()>,
+}
+
+impl StatusFile {
+    pub fn start(path: std::path::PathBuf, service: &str, status: std::sync::Arc<std::sync::Mutex<Status>>) -> Self {
+        let service = service.to_string();
+        let (stop, receiver) = std::sync::mpsc::channel();
+        let thread = std::thread::spawn(move || {
+            let mut stopped = false;
+            let mut failing = false;
+            loop {
+                let snapshot = status.lock().map(|x| x.clone()).unwrap_or_default();
+                let content = contents(
+                    &service,
+                    &snapshot,
+                    stopped,
+                    std::time::Instant::now(),
+                    std::time::SystemTime::now(),
+                );
+                match write(&path, &content) {
+                    Ok(()) => failing = false,
+                    Err(e) => {
+                        // Only log the first failure in a row, since it will likely repeat every interval.
+                        if !failing {
+                            log::error!("Unable to write status file {}: {}", path.display(), e);
+                        }
+                        failing = true;
+                    }
+                }
+                if stopped {
+                    return;
+                }
+                stopped = !matches!(
+                    receiver.recv_timeout(INTERVAL),
+                    Err(std::sync::mpsc::RecvTimeoutError::Timeout)
+                );
+            }
+        });
+        Self { stop, thread }
+    }
+
+    /// Write the final `stopped` state and wait for the thread to finish.
+    pub fn stop(self) {
+        let _ = self.stop.send(());
+        let _ = self.thread.join();
+    }
+}
+
+#[cfg(test)]
+speculate::speculate! {
+    describe "contents" {
+        it "reports the running command" {
+            let now = std::time::Instant::now();
+            let wall_now = std::time::UNIX_EPOCH + std::time::Duration::from_millis(1_735_689_600_500);
+            let status = Status {
+                pid: Some(123),
+                launched_at: Some(now - std::time::Duration::from_millis(1500)),
+                last_output: Some(now - std::time::Duration::from_millis(250)),
+                restarts: 2,
+                last_exit_code: Some(1),
+                ..Default::default()
+            };
+            assert_eq!(
+                contents("app", &status, false, now, wall_now),
+                concat!(
+                    r#"{"service":"app","state":"running","pid":123,"uptime_ms":1500,"restarts":2,"#,
+                    r#""last_exit_code":1,"last_output_at":1735689600.250,"updated_at":1735689600.500}"#,
+                    "\n",
+                ),
+            );
+        }
+
+        it "reports a stopped service" {
+            let now = std::time::Instant::now();
+            let wall_now = std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_735_689_600);
+            let status = Status {
+                pid: Some(123),
+                launched_at: Some(now),
+                ..Default::default()
+            };
+            assert_eq!(
+                contents("app", &status, true, now, wall_now),
+                concat!(
+                    r#"{"service":"app","state":"stopped","pid":null,"uptime_ms":null,"restarts":0,"#,
+                    r#""last_exit_code":null,"last_output_at":null,"updated_at":1735689600.000}"#,
+                    "\n",
+                ),
+            );
+        }
+    }
+}
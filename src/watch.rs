@@ -0,0 +1,87 @@
+type Snapshot = Option<(std::time::SystemTime, u64)>;
+
+fn snapshot(path: &std::path::Path) -> Snapshot {
+    let metadata = std::fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// Detects changes to files by polling their modification time and size.
+pub struct FileWatcher {
+    files: Vec<(std::path::PathBuf, Snapshot)>,
+    debounce: std::time::Duration,
+    pending: Option<(std::path::PathBuf, std::time::Instant)>,
+}
+
+impl FileWatcher {
+    pub fn new(paths: Vec<std::path::PathBuf>, debounce: std::time::Duration) -> Self {
+        Self {
+            files: paths
+                .into_iter()
+                .map(|path| {
+                    let snapshot = snapshot(&path);
+                    (path, snapshot)
+                })
+                .collect(),
+            debounce,
+            pending: None,
+        }
+    }
+
+    /// Check for changes, returning a changed path once there have been
+    /// no further changes for the debounce period.
+    pub fn poll(&mut self) -> Option<std::path::PathBuf> {
+        let now = std::time::Instant::now();
+        for (path, last) in &mut self.files {
+            let current = snapshot(path);
+            if current != *last {
+                *last = current;
+                self.pending = Some((path.clone(), now));
+            }
+        }
+
+        match &self.pending {
+            Some((_, changed_at)) if now.duration_since(*changed_at) >= self.debounce => {
+                self.pending.take().map(|(path, _)| path)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+speculate::speculate! {
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("shawl-watch-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    describe "file_watcher" {
+        it "reports a change after the debounce period" {
+            let dir = temp_dir("change");
+            let file = dir.join("app.toml");
+            std::fs::write(&file, "a").unwrap();
+
+            let mut watcher = FileWatcher::new(vec![file.clone()], std::time::Duration::from_millis(100));
+            assert_eq!(watcher.poll(), None);
+
+            std::fs::write(&file, "ab").unwrap();
+            assert_eq!(watcher.poll(), None);
+            std::thread::sleep(std::time::Duration::from_millis(150));
+            assert_eq!(watcher.poll(), Some(file));
+            assert_eq!(watcher.poll(), None);
+        }
+
+        it "reports a file being created" {
+            let dir = temp_dir("create");
+            let file = dir.join("app.toml");
+
+            let mut watcher = FileWatcher::new(vec![file.clone()], std::time::Duration::ZERO);
+            assert_eq!(watcher.poll(), None);
+
+            std::fs::write(&file, "a").unwrap();
+            assert_eq!(watcher.poll(), Some(file));
+        }
+    }
+}
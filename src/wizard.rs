@@ -0,0 +1,292 @@
+use std::io::{BufRead, Write};
+
+/// Extensions of the programs to offer in the command picker.
+const PROGRAM_EXTENSIONS: [&str; 5] = ["exe", "bat", "cmd", "ps1", "py"];
+
+/// Most programs to list in the command picker.
+const MAX_PROGRAMS: usize = 20;
+
+const RESTART_CHOICES: [(&str, Option<&str>); 3] = [
+    ("Restart the command if it fails", None),
+    ("Always restart the command when it exits", Some("--restart")),
+    ("Never restart the command", Some("--no-restart")),
+];
+
+/// `{name}` is replaced by the service name.
+const ACCOUNT_CHOICES: [(&str, Option<&str>); 4] = [
+    ("Local System, with full access to the computer", None),
+    (
+        "Network Service, with limited access (recommended)",
+        Some(r"NT AUTHORITY\NetworkService"),
+    ),
+    (
+        "Local Service, with limited access and no network credentials",
+        Some(r"NT AUTHORITY\LocalService"),
+    ),
+    ("A virtual account just for this service", Some(r"NT SERVICE\{name}")),
+];
+
+struct Prompter<R, W> {
+    input: R,
+    output: W,
+}
+
+impl<R: BufRead, W: Write> Prompter<R, W> {
+    /// Ask a question, returning the default for an empty answer.
+    fn ask(&mut self, question: &str, default: Option<&str>) -> std::io::Result<String> {
+        match default {
+            Some(default) if !default.is_empty() => write!(self.output, "{} [{}]: ", question, default)?,
+            _ => write!(self.output, "{}: ", question)?,
+        }
+        self.output.flush()?;
+
+        let mut answer = String::new();
+        if self.input.read_line(&mut answer)? == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "no answer was given",
+            ));
+        }
+        Ok(match answer.trim() {
+            "" => default.unwrap_or_default().to_string(),
+            answer => answer.to_string(),
+        })
+    }
+
+    /// Ask the question again until the answer passes the check.
+    fn ask_valid(
+        &mut self,
+        question: &str,
+        default: Option<&str>,
+        check: impl Fn(&str) -> Result<(), String>,
+    ) -> std::io::Result<String> {
+        loop {
+            let answer = self.ask(question, default)?;
+            match check(&answer) {
+                Ok(()) => return Ok(answer),
+                Err(e) => writeln!(self.output, "  {}", e)?,
+            }
+        }
+    }
+
+    /// Ask for one of the numbered choices, returning its index. The first one is the default.
+    fn choose(&mut self, question: &str, choices: &[&str]) -> std::io::Result<usize> {
+        writeln!(self.output, "{}", question)?;
+        for (i, choice) in choices.iter().enumerate() {
+            writeln!(self.output, "  {}. {}", i + 1, choice)?;
+        }
+        let answer = self.ask_valid("Choice", Some("1"), |x| match x.parse::<usize>() {
+            Ok(n) if (1..=choices.len()).contains(&n) => Ok(()),
+            _ => Err(format!("Enter a number from 1 to {}", choices.len())),
+        })?;
+        Ok(answer.parse::<usize>().unwrap_or(1) - 1)
+    }
+}
+
+fn check_name(name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        Err("The service needs a name".to_string())
+    } else if name.contains(['/', '\\']) {
+        Err("The name cannot contain slashes".to_string())
+    } else {
+        Ok(())
+    }
+}
+
+/// Find programs in a directory to offer in the command picker.
+fn find_programs(dir: &std::path::Path) -> Vec<String> {
+    let mut programs: Vec<_> = std::fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| entry.path().is_file())
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .filter(|name| {
+            std::path::Path::new(name)
+                .extension()
+                .is_some_and(|x| PROGRAM_EXTENSIONS.contains(&x.to_string_lossy().to_lowercase().as_str()))
+        })
+        .collect();
+    programs.sort_by_key(|x| x.to_lowercase());
+    programs.truncate(MAX_PROGRAMS);
+    programs
+}
+
+/// Quote an argument for the preview, like it would be typed in a terminal.
+fn display_arg(arg: &str) -> String {
+    if arg.is_empty() || arg.contains([' ', '\t', '"']) {
+        format!("\"{}\"", arg.replace('"', "\\\""))
+    } else {
+        arg.to_string()
+    }
+}
+
+/// Ask for the command, offering the programs in the current directory by number.
+fn ask_command<R: BufRead, W: Write>(
+    prompter: &mut Prompter<R, W>,
+    dir: &std::path::Path,
+    programs: &[String],
+) -> std::io::Result<Vec<String>> {
+    let question = if programs.is_empty() {
+        "Command to run, with its arguments"
+    } else {
+        writeln!(prompter.output, "Programs in {}:", dir.display())?;
+        for (i, program) in programs.iter().enumerate() {
+            writeln!(prompter.output, "  {}. {}", i + 1, program)?;
+        }
+        "Command to run, with its arguments, or a number from the list"
+    };
+    let answer = prompter.ask_valid(question, None, |x| {
+        if crate::control::split_command_line(x).is_empty() {
+            Err("The service needs a command".to_string())
+        } else {
+            Ok(())
+        }
+    })?;
+
+    let picked = answer
+        .parse::<usize>()
+        .ok()
+        .and_then(|x| x.checked_sub(1))
+        .and_then(|x| programs.get(x));
+    match picked {
+        Some(program) => {
+            let mut command = vec![dir.join(program).to_string_lossy().to_string()];
+            let args = prompter.ask("Arguments for the command", None)?;
+            command.extend(crate::control::split_command_line(&args));
+            Ok(command)
+        }
+        None => Ok(crate::control::split_command_line(&answer)),
+    }
+}
+
+/// Walk through the main options for `shawl add`, returning its arguments,
+/// or nothing if the user decides not to add the service after the preview.
+fn wizard<R: BufRead, W: Write>(
+    prompter: &mut Prompter<R, W>,
+    dir: &std::path::Path,
+    programs: &[String],
+) -> std::io::Result<Option<Vec<String>>> {
+    let name = prompter.ask_valid("Service name", None, check_name)?;
+    let description = prompter.ask("Description (optional)", None)?;
+    let command = ask_command(prompter, dir, programs)?;
+
+    let default_cwd = std::path::Path::new(&command[0])
+        .parent()
+        .filter(|x| x.is_absolute())
+        .unwrap_or(dir)
+        .to_string_lossy()
+        .to_string();
+    let cwd = prompter.ask("Working directory", Some(&default_cwd))?;
+
+    let restart = prompter.choose(
+        "When should the command restart?",
+        &RESTART_CHOICES.map(|(label, _)| label),
+    )?;
+    let account = prompter.choose(
+        "Which account should the service run as?",
+        &ACCOUNT_CHOICES.map(|(label, _)| label),
+    )?;
+
+    let mut args = vec!["--name".to_string(), name.clone()];
+    if !description.is_empty() {
+        args.extend(["--description".to_string(), description]);
+    }
+    if let Some(account) = ACCOUNT_CHOICES[account].1 {
+        args.extend(["--account".to_string(), account.replace("{name}", &name)]);
+    }
+    args.extend(["--cwd".to_string(), cwd]);
+    args.extend(RESTART_CHOICES[restart].1.map(String::from));
+    args.push("--".to_string());
+    args.extend(command);
+
+    let preview: Vec<_> = args.iter().map(|x| display_arg(x)).collect();
+    writeln!(
+        prompter.output,
+        "\nThis is the same as running:\n  shawl add {}\n",
+        preview.join(" ")
+    )?;
+    let confirm = prompter.ask_valid("Add the service? (y/n)", Some("y"), |x| {
+        match x.to_lowercase().as_str() {
+            "y" | "yes" | "n" | "no" => Ok(()),
+            _ => Err("Enter y or n".to_string()),
+        }
+    })?;
+    Ok(confirm.to_lowercase().starts_with('y').then_some(args))
+}
+
+/// Prompt for the options for `shawl add --wizard` in the console.
+pub fn run() -> Result<Option<Vec<String>>, String> {
+    let dir = std::env::current_dir().map_err(|e| format!("Unable to determine the current directory: {}", e))?;
+    let programs = find_programs(&dir);
+    let mut prompter = Prompter {
+        input: std::io::stdin().lock(),
+        output: std::io::stdout(),
+    };
+    wizard(&mut prompter, &dir, &programs).map_err(|e| format!("Unable to read the answers: {}", e))
+}
+
+#[cfg(test)]
+speculate::speculate! {
+    fn s(text: &str) -> String {
+        text.to_string()
+    }
+
+    fn answer(answers: &str, programs: &[&str]) -> (Option<Vec<String>>, String) {
+        let mut prompter = Prompter {
+            input: std::io::Cursor::new(answers.as_bytes()),
+            output: vec![],
+        };
+        let programs: Vec<_> = programs.iter().map(|x| s(x)).collect();
+        let args = wizard(&mut prompter, std::path::Path::new(r"C:\apps\web"), &programs).unwrap();
+        (args, String::from_utf8(prompter.output).unwrap())
+    }
+
+    describe "wizard" {
+        it "builds the arguments for add" {
+            let (args, output) = answer(
+                "web\nOur website\nnode server.js --port 80\n\n2\n4\n\n",
+                &[],
+            );
+            assert_eq!(
+                args,
+                Some(vec![
+                    s("--name"), s("web"),
+                    s("--description"), s("Our website"),
+                    s("--account"), s(r"NT SERVICE\web"),
+                    s("--cwd"), s(r"C:\apps\web"),
+                    s("--restart"),
+                    s("--"), s("node"), s("server.js"), s("--port"), s("80"),
+                ]),
+            );
+            assert!(output.contains(
+                r#"shawl add --name web --description "Our website" --account "NT SERVICE\web" --cwd C:\apps\web --restart -- node server.js --port 80"#
+            ));
+        }
+
+        it "picks a program from the list" {
+            let (args, _) = answer("web\n\n2\n--verbose\n\n\n\ny\n", &["app.exe", "run.bat"]);
+            assert_eq!(
+                args,
+                Some(vec![
+                    s("--name"), s("web"),
+                    s("--cwd"), s(r"C:\apps\web"),
+                    s("--"), s(r"C:\apps\web\run.bat"), s("--verbose"),
+                ]),
+            );
+        }
+
+        it "asks again after an invalid answer" {
+            let (args, output) = answer("\nweb\n\n\nnode\n\n9\n1\n\n\n", &[]);
+            assert!(output.contains("The service needs a name"));
+            assert!(output.contains("The service needs a command"));
+            assert!(output.contains("Enter a number from 1 to 3"));
+            assert_eq!(args.unwrap()[..2], [s("--name"), s("web")]);
+        }
+
+        it "adds nothing if declined" {
+            let (args, _) = answer("web\n\nnode\n\n\n\nn\n", &[]);
+            assert_eq!(args, None);
+        }
+    }
+}